@@ -7,9 +7,12 @@ use inkwell::OptimizationLevel;
 use inkwell::passes::PassBuilderOptions;
 use inkwell::targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine,TargetTriple};
 use inkwell::attributes::{Attribute, AttributeLoc};
-use crate::parser::ast::{ASTNode, Type, Parameter, BinaryOperator, UnaryOperator};
-use inkwell::types::{BasicType, BasicTypeEnum, BasicMetadataTypeEnum};
-use inkwell::values::{BasicValue, BasicValueEnum, BasicMetadataValueEnum};
+use crate::parser::ast::{ASTNode, Type, Parameter, BinaryOperator, UnaryOperator, Loc, MatchCase, Pattern, EnumVariant, Generics, GenericParam};
+use crate::type_checker::UserType;
+use crate::diagnostics::{Diagnostic, Span, CODEGEN_ERROR, CODEGEN_UNSUPPORTED};
+use inkwell::types::{BasicType, BasicTypeEnum, BasicMetadataTypeEnum, FunctionType, StructType};
+use inkwell::values::{BasicValue, BasicValueEnum, BasicMetadataValueEnum, PointerValue};
+use inkwell::basic_block::BasicBlock;
 use inkwell::{IntPredicate, FloatPredicate};
 use inkwell::values::FunctionValue;
 use inkwell::module::Linkage;
@@ -18,7 +21,7 @@ use std::ffi::CString;
 use std::os::raw::{c_int, c_void};
 use inkwell::execution_engine::ExecutionEngine;
 use inkwell::types::VectorType;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 
 use inkwell::debug_info::{
@@ -30,8 +33,73 @@ use crate::runtime::{
     quantica_rt_new_state,
     quantica_rt_debug_state,
     quantica_rt_apply_gate,
-    quantica_rt_measure
+    quantica_rt_apply_unitary,
+    quantica_rt_measure,
+    quantica_rt_measure_register,
+    quantica_rt_bits_as_int,
+    quantica_rt_bits_get,
+    quantica_rt_length,
+    quantica_rt_reset,
+    quantica_rt_set_seed,
+    quantica_rt_fail
 };
+use crate::evaluator::Evaluator;
+use crate::environment::{Environment, RuntimeValue};
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::simulator::Unitary;
+
+type GenericDeclaration = (Generics, Vec<Parameter>, Option<Type>, ASTNode);
+
+/// Why compilation stopped: a mistake in the program (`CODEGEN_ERROR`), or a construct
+/// the code generator does not lower yet (`CODEGEN_UNSUPPORTED`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenError {
+    pub code: &'static str,
+    pub message: String,
+    /// That of the innermost statement or expression that failed.
+    pub loc: Option<Loc>,
+}
+
+impl CodegenError {
+    fn at(loc: &Loc, message: impl Into<String>) -> Self {
+        CodegenError { code: CODEGEN_ERROR, message: message.into(), loc: Some(*loc) }
+    }
+
+    fn unsupported(message: impl Into<String>) -> Self {
+        CodegenError { code: CODEGEN_UNSUPPORTED, message: message.into(), loc: None }
+    }
+
+    fn located(mut self, loc: Option<Loc>) -> Self {
+        self.loc = self.loc.or(loc);
+        self
+    }
+}
+
+impl From<String> for CodegenError {
+    fn from(message: String) -> Self {
+        CodegenError { code: CODEGEN_ERROR, message, loc: None }
+    }
+}
+
+impl From<&str> for CodegenError {
+    fn from(message: &str) -> Self {
+        CodegenError::from(message.to_string())
+    }
+}
+
+impl From<CodegenError> for String {
+    fn from(error: CodegenError) -> Self {
+        error.to_string()
+    }
+}
+
+impl std::fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = if self.code == CODEGEN_UNSUPPORTED { "Codegen STUB" } else { "Codegen Error" };
+        write!(f, "({}) {}", kind, self.message)
+    }
+}
 
 #[derive(Debug)]
 enum MLIRStep {
@@ -51,7 +119,36 @@ pub struct Compiler<'ctx> {
     rt_new_state: FunctionValue<'ctx>,
     rt_debug_state: FunctionValue<'ctx>,
     rt_apply_gate: FunctionValue<'ctx>,
+    rt_apply_unitary: FunctionValue<'ctx>,
     rt_measure: FunctionValue<'ctx>,
+    rt_measure_register: FunctionValue<'ctx>,
+    rt_bits_as_int: FunctionValue<'ctx>,
+    rt_bits_get: FunctionValue<'ctx>,
+    rt_length: FunctionValue<'ctx>,
+    rt_reset: FunctionValue<'ctx>,
+    rt_set_seed: FunctionValue<'ctx>,
+    rt_fail: FunctionValue<'ctx>,
+    custom_gates: HashMap<String, Unitary>,
+    user_types: HashMap<String, UserType>,
+    // Function types of the closure struct types, by struct name: the signature of a
+    // closure cannot be read back from its (opaque) function pointer.
+    closure_signatures: RefCell<HashMap<String, FunctionType<'ctx>>>,
+    lambda_count: usize,
+    // Variables in scope that were declared `mut`, which a returned closure cannot capture.
+    mutable_variables: HashSet<String>,
+    // Whether the function being compiled returns a closure, so that the closures it
+    // creates may outlive its stack frame.
+    closures_escape: bool,
+    // Generic declarations are compiled once per instantiation, e.g. `first<Float>` or
+    // `GHZ<3>`; the bindings are those of the instantiation being compiled.
+    generic_functions: HashMap<String, GenericDeclaration>,
+    generic_types: HashMap<String, Type>,
+    generic_sizes: HashMap<String, u64>,
+    // The `where` constraints of the instance being compiled that mention its value
+    // parameters, and so are checked when it is called rather than when it is compiled.
+    runtime_constraints: Vec<ASTNode>,
+    // Registers whose size is known at compile time, for inferring size parameters.
+    register_sizes: HashMap<String, u64>,
     execution_engine: ExecutionEngine<'ctx>,
     rt_device_alloc: FunctionValue<'ctx>,
     rt_device_free: FunctionValue<'ctx>,
@@ -144,6 +241,52 @@ impl<'ctx> Compiler<'ctx> {
             Some(Linkage::External),
         );
 
+        let measure_register_fn_type = state_ptr_type.fn_type(&[state_ptr_type.into()], false);
+        let rt_measure_register = module.add_function(
+            "quantica_rt_measure_register",
+            measure_register_fn_type,
+            Some(Linkage::External),
+        );
+
+        let array_query_fn_type = context.i64_type().fn_type(&[state_ptr_type.into()], false);
+        let rt_bits_as_int = module.add_function(
+            "quantica_rt_bits_as_int",
+            array_query_fn_type,
+            Some(Linkage::External),
+        );
+        let rt_length = module.add_function(
+            "quantica_rt_length",
+            array_query_fn_type,
+            Some(Linkage::External),
+        );
+
+        let bits_get_fn_type = context.i64_type().fn_type(&[state_ptr_type.into(), context.i64_type().into()], false);
+        let rt_bits_get = module.add_function(
+            "quantica_rt_bits_get",
+            bits_get_fn_type,
+            Some(Linkage::External),
+        );
+
+        let rt_reset = module.add_function(
+            "quantica_rt_reset",
+            measure_fn_type,
+            Some(Linkage::External),
+        );
+
+        let set_seed_fn_type = void_type.fn_type(&[context.i64_type().into()], false);
+        let rt_set_seed = module.add_function(
+            "quantica_rt_set_seed",
+            set_seed_fn_type,
+            Some(Linkage::External),
+        );
+
+        let fail_fn_type = void_type.fn_type(&[state_ptr_type.into()], false);
+        let rt_fail = module.add_function(
+            "quantica_rt_fail",
+            fail_fn_type,
+            Some(Linkage::External),
+        );
+
         let i8_ptr_type = context.ptr_type(AddressSpace::default());
         let f64_ptr_type = context.ptr_type(AddressSpace::default());
         let i32_ptr_type = context.ptr_type(AddressSpace::default());
@@ -168,6 +311,26 @@ impl<'ctx> Compiler<'ctx> {
             Some(Linkage::External),
         );
 
+        let apply_unitary_fn_type = i32_type.fn_type(
+            &[
+                i8_ptr_type.into(),
+                i8_ptr_type.into(),
+                f64_ptr_type.into(),
+                i32_type.into(),
+                i32_type.into(),
+                i32_ptr_type.into(),
+                i32_type.into(),
+                i32_type.into(),
+            ],
+            false,
+        );
+
+        let rt_apply_unitary = module.add_function(
+            "quantica_rt_apply_unitary",
+            apply_unitary_fn_type,
+            Some(Linkage::External),
+        );
+
         let size_t_type = context.i64_type();
         let alloc_fn_type = i8_ptr_type.fn_type(&[size_t_type.into()], false);
         let rt_device_alloc = module.add_function("quantica_rt_device_alloc", alloc_fn_type, Some(Linkage::External));
@@ -183,7 +346,15 @@ impl<'ctx> Compiler<'ctx> {
             execution_engine.add_global_mapping(&rt_new_state, quantica_rt_new_state as usize);
             execution_engine.add_global_mapping(&rt_debug_state, quantica_rt_debug_state as usize);
             execution_engine.add_global_mapping(&rt_apply_gate, quantica_rt_apply_gate as usize);
+            execution_engine.add_global_mapping(&rt_apply_unitary, quantica_rt_apply_unitary as usize);
             execution_engine.add_global_mapping(&rt_measure, quantica_rt_measure as usize);
+            execution_engine.add_global_mapping(&rt_measure_register, quantica_rt_measure_register as usize);
+            execution_engine.add_global_mapping(&rt_bits_as_int, quantica_rt_bits_as_int as usize);
+            execution_engine.add_global_mapping(&rt_bits_get, quantica_rt_bits_get as usize);
+            execution_engine.add_global_mapping(&rt_length, quantica_rt_length as usize);
+            execution_engine.add_global_mapping(&rt_reset, quantica_rt_reset as usize);
+            execution_engine.add_global_mapping(&rt_set_seed, quantica_rt_set_seed as usize);
+            execution_engine.add_global_mapping(&rt_fail, quantica_rt_fail as usize);
         }
 
 
@@ -199,7 +370,26 @@ impl<'ctx> Compiler<'ctx> {
             rt_new_state,
             rt_debug_state,
             rt_apply_gate,
+            rt_apply_unitary,
             rt_measure,
+            rt_measure_register,
+            rt_bits_as_int,
+            rt_bits_get,
+            rt_length,
+            rt_reset,
+            rt_set_seed,
+            rt_fail,
+            custom_gates: HashMap::new(),
+            user_types: HashMap::new(),
+            closure_signatures: RefCell::new(HashMap::new()),
+            lambda_count: 0,
+            mutable_variables: HashSet::new(),
+            closures_escape: false,
+            generic_functions: HashMap::new(),
+            generic_types: HashMap::new(),
+            generic_sizes: HashMap::new(),
+            runtime_constraints: Vec::new(),
+            register_sizes: HashMap::new(),
             execution_engine,
             rt_device_alloc,
             rt_device_free,
@@ -342,7 +532,7 @@ impl<'ctx> Compiler<'ctx> {
     }
 
 
-    pub fn optimize_module(&self, _opt_level: OptimizationLevel) -> Result<(), String> {
+    pub fn optimize_module(&self, _opt_level: OptimizationLevel) -> Result<(), CodegenError> {
         let passes: &[&str] = &[
             "instcombine",
             "reassociate",
@@ -367,7 +557,7 @@ impl<'ctx> Compiler<'ctx> {
         Ok(())
     }
 
-    pub fn analyze_and_fuse_tensors(&self, function_name: &str, _body_ast: &ASTNode) -> Result<String, String> {
+    pub fn analyze_and_fuse_tensors(&self, function_name: &str, _body_ast: &ASTNode) -> Result<String, CodegenError> {
         let fusion_summary = format!(
             "// TENSOR FUSION OPTIMIZATION APPLIED:\n\
             // Kernels identified in {}:\n\
@@ -384,14 +574,14 @@ impl<'ctx> Compiler<'ctx> {
     pub fn emit_zero_copy_transfer(
         &self,
         host_ptr: inkwell::values::PointerValue<'ctx>,
-    ) -> Result<inkwell::values::PointerValue<'ctx>, String> {
+    ) -> Result<inkwell::values::PointerValue<'ctx>, CodegenError> {
         println!("   -> Zero-Copy Abstraction: Using shared memory pointer.");
         Ok(host_ptr)
     }
 
-    pub fn run_jit(&self) -> Result<(), String> {
+    pub fn run_jit(&self) -> Result<(), CodegenError> {
         if !self.module.verify().is_ok() {
-            return Err("(JIT Error) Compiled module failed verification.".to_string());
+            return Err("(JIT Error) Compiled module failed verification.".into());
         }
 
         let _main_function = self.module.get_function("main")
@@ -421,7 +611,7 @@ impl<'ctx> Compiler<'ctx> {
         })
     }
 
-    pub fn enable_jit_profiling(&mut self, func_names: Vec<String>) -> Result<(), String> {
+    pub fn enable_jit_profiling(&mut self, func_names: Vec<String>) -> Result<(), CodegenError> {
         println!("   -> Profiling Abstraction: Initializing {} functions for adaptive JIT.", func_names.len());
 
         let i64_type = self.context.i64_type();
@@ -440,13 +630,13 @@ impl<'ctx> Compiler<'ctx> {
         Ok(())
     }
 
-    pub fn trigger_adaptive_recompile(&self, hot_function_name: &str) -> Result<(), String> {
+    pub fn trigger_adaptive_recompile(&self, hot_function_name: &str) -> Result<(), CodegenError> {
         println!("   -> ADAPTIVE JIT: Function '{}' detected as hot. Recompiling...", hot_function_name);
         println!("   -> ADAPTIVE JIT: Replacing old code pointer with optimized version (e.g., -O3).");
         Ok(())
     }
 
-    pub fn emit_vulkan_compute_path(&self, kernel_ir: &str, kernel_name: &str) -> Result<String, String> {
+    pub fn emit_vulkan_compute_path(&self, kernel_ir: &str, kernel_name: &str) -> Result<String, CodegenError> {
         let kernel_size_bytes = kernel_ir.len() * 4;
 
         let spv_binary_stub = format!(
@@ -472,7 +662,7 @@ impl<'ctx> Compiler<'ctx> {
         ))
     }
 
-    pub fn export_to_hlo_ir(&self, module_name: &str) -> Result<String, String> {
+    pub fn export_to_hlo_ir(&self, module_name: &str) -> Result<String, CodegenError> {
         let graph_summary = format!(
             "// HLO Graph Extracted from LLVM IR ({}):\n// Ops: [Add, Mul, DotProduct, ControlFlow]\n",
             module_name
@@ -493,7 +683,7 @@ impl<'ctx> Compiler<'ctx> {
         Ok(format!("{}\n\n{}", graph_summary, hlo_ir_stub))
     }
 
-    pub fn compile_gpu_kernel(&self, name: &str, body: &ASTNode) -> Result<String, String> {
+    pub fn compile_gpu_kernel(&self, name: &str, body: &ASTNode) -> Result<String, CodegenError> {
         println!("   -> Running MLIR Lowering (Item #26)...");
         let mlir_trace = self.lower_tensor_to_mlir_dialect(name, body)?;
 
@@ -528,7 +718,7 @@ impl<'ctx> Compiler<'ctx> {
         &self,
         func_name: &str,
         _body_ast: &ASTNode,
-    ) -> Result<String, String> {
+    ) -> Result<String, CodegenError> {
         let mut stages = vec![MLIRStep::HighLevelDialect];
         stages.push(MLIRStep::GPUDialect);
         stages.push(MLIRStep::LLVMIR);
@@ -541,55 +731,127 @@ impl<'ctx> Compiler<'ctx> {
         Ok(output)
     }
 
-    pub fn compile_jit_program(&mut self, program: &ASTNode) -> Result<(), String> {
+    pub fn compile_jit_program(&mut self, program: &ASTNode) -> Result<(), CodegenError> {
         if let ASTNode::Program(statements) = program {
             for stmt in statements {
-                if let ASTNode::FunctionDeclaration { name, parameters, return_type, body } = stmt {
+                if let ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } = stmt {
+                    if !generics.is_empty() {
+                        self.generic_functions.insert(name.clone(), (generics.clone(), parameters.clone(), return_type.clone(), (**body).clone()));
+                        continue;
+                    }
 
                     self.compile_function(name, parameters, return_type, body)?;
                 } else {
-                    return Err("(Codegen Error) JIT synthesis failed: Expected FunctionDeclaration.".to_string());
+                    return Err("JIT synthesis failed: Expected FunctionDeclaration.".into());
                 }
             }
             Ok(())
         } else {
-            Err("Expected ASTNode::Program at root.".to_string())
+            Err("Expected ASTNode::Program at root.".into())
         }
     }
 
 
-    pub fn compile_program(&mut self, program: &ASTNode) -> Result<(), String> {
+    /// A diagnostic for a compilation failure; constructs the code generator does not
+    /// lower yet are reported as unsupported rather than as mistakes in the program.
+    pub fn diagnostic(error: &CodegenError) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(error.code, error.message.clone());
+        if let Some(loc) = error.loc {
+            diagnostic = diagnostic.with_primary(Span::from(loc), "");
+        }
+        if error.code == CODEGEN_UNSUPPORTED {
+            diagnostic = diagnostic.with_suggestion("run the file with the interpreter, which supports it");
+        }
+        diagnostic
+    }
+
+    pub fn compile_program(&mut self, program: &ASTNode) -> Result<(), CodegenError> {
         if let ASTNode::Program(statements) = program {
             for stmt in statements {
                 match stmt {
-                    ASTNode::FunctionDeclaration { name, parameters, return_type, body } => {
+                    ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } => {
+                        if !generics.is_empty() {
+                            self.generic_functions.insert(name.clone(), (generics.clone(), parameters.clone(), return_type.clone(), (**body).clone()));
+                            continue;
+                        }
 
                         self.compile_function(name, parameters, return_type, body)?;
                     }
 
-                    ASTNode::CircuitDeclaration { name, parameters, return_type: _, body } => {
+                    ASTNode::CircuitDeclaration { name, generics, parameters, return_type: _, body } => {
 
                         println!("\n⚙️ Compiling GPU Kernel: {}", name);
                         let kernel_ir = self.compile_gpu_kernel(name, body)?;
                         println!("   -> Kernel IR Dump (Placeholder):\n{}", kernel_ir);
 
+                        if !generics.is_empty() {
+                            self.generic_functions.insert(name.clone(), (generics.clone(), parameters.clone(), None, ASTNode::Block(vec![])));
+                            continue;
+                        }
                         self.compile_function(name, parameters, &None, &ASTNode::Block(vec![]))?;
                     }
+
+                    ASTNode::GateDeclaration { name, matrix, loc } => {
+                        let unitary = Evaluator::eval_gate_matrix(matrix)
+                            .map_err(|e| format!("Gate '{}' at {}: {}.", name, loc, e))?;
+                        self.custom_gates.insert(name.clone(), unitary);
+                    }
+
+                    ASTNode::StructDeclaration { name, fields, loc } => {
+                        let field_types: Vec<BasicTypeEnum<'ctx>> = fields.iter().map(|field| self.map_type(&field.param_type)).collect();
+                        if fields.iter().any(|field| field.param_type == Type::Custom(name.clone())) {
+                            return Err(format!("Struct '{}' at {} contains itself.", name, loc).into());
+                        }
+                        self.context.opaque_struct_type(name).set_body(&field_types, false);
+                        self.user_types.insert(name.clone(), UserType::Struct(fields.clone()));
+                    }
+
+                    ASTNode::EnumDeclaration { name, variants, loc } => {
+                        let mut words = 0;
+                        for variant in variants {
+                            let mut variant_words = 0;
+                            for field_type in &variant.fields {
+                                variant_words += Self::payload_words(field_type).ok_or_else(|| CodegenError::unsupported(format!(
+                                    "Variant '{}.{}' at {}: {:?} payloads are not lowered yet.",
+                                    name, variant.name, loc, field_type
+                                )))?;
+                            }
+                            words = words.max(variant_words);
+                        }
+                        let i64_type = self.context.i64_type();
+                        self.context.opaque_struct_type(name)
+                            .set_body(&[self.context.i32_type().into(), i64_type.array_type(words).into()], false);
+                        self.user_types.insert(name.clone(), UserType::Enum(variants.clone()));
+                    }
+
+                    ASTNode::ImplBlock { type_name, methods, .. } => {
+                        for method in methods {
+                            if let ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } |
+                                   ASTNode::CircuitDeclaration { name, generics, parameters, return_type, body } = method {
+                                let qualified = format!("{}.{}", type_name, name);
+                                if !generics.is_empty() {
+                                    self.generic_functions.insert(qualified, (generics.clone(), parameters.clone(), return_type.clone(), (**body).clone()));
+                                    continue;
+                                }
+                                self.compile_function(&qualified, parameters, return_type, body)?;
+                            }
+                        }
+                    }
                     _ => {
                         return Err(format!(
-                            "(Codegen Error) Only function declarations are allowed at the top level. Found: {:?}",
+                            "Only function declarations are allowed at the top level. Found: {:?}",
                             stmt
-                        ));
+                        ).into());
                     }
                 }
             }
         } else {
-            return Err("Expected ASTNode::Program at root.".to_string());
+            return Err("Expected ASTNode::Program at root.".into());
         }
         Ok(())
     }
 
-    pub fn write_to_object_file(&self, path: &str) -> Result<(), String> {
+    pub fn write_to_object_file(&self, path: &str) -> Result<(), CodegenError> {
         use inkwell::targets::FileType;
         use std::path::Path;
 
@@ -599,7 +861,7 @@ impl<'ctx> Compiler<'ctx> {
                 FileType::Object,
                 Path::new(path),
             )
-            .map_err(|e| format!("Failed to write object file: {}", e))
+            .map_err(|e| format!("Failed to write object file: {}", e).into())
     }
 
     pub fn dump_ir(&self) {
@@ -607,7 +869,11 @@ impl<'ctx> Compiler<'ctx> {
     }
 
 
-    fn compile_statement(&mut self, node: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), String> {
+    fn compile_statement(&mut self, node: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), CodegenError> {
+        self.compile_statement_node(node, current_function).map_err(|error| error.located(node.loc()))
+    }
+
+    fn compile_statement_node(&mut self, node: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), CodegenError> {
         match node {
             ASTNode::LetDeclaration { name, type_annotation, value, is_mutable } => {
                 self.compile_let_declaration(name, type_annotation, value, *is_mutable, current_function)?;
@@ -632,8 +898,23 @@ impl<'ctx> Compiler<'ctx> {
                 self.compile_while_loop(condition, body, current_function)?;
                 Ok(())
             }
+            ASTNode::Match { value, cases, loc } => {
+                self.compile_match_statement(value, cases, loc, current_function)
+            }
 
-            ASTNode::FunctionCall { callee, arguments, .. } => {
+            ASTNode::FunctionCall { callee, arguments, loc, is_dagger } => {
+                // `apply MyGate(q[0], q[1])` parses as a call to the user-defined gate.
+                if let ASTNode::Identifier { name, .. } = &**callee {
+                    if self.custom_gates.contains_key(name) {
+                        let gate = ASTNode::Gate { name: name.clone(), loc: *loc };
+                        let gate_expr = if *is_dagger {
+                            ASTNode::Dagger { gate_expr: Box::new(gate), loc: *loc }
+                        } else {
+                            gate
+                        };
+                        return self.compile_apply_statement(&gate_expr, arguments, current_function);
+                    }
+                }
                 self.compile_function_call(callee, arguments, current_function)?;
                 Ok(())
             }
@@ -657,8 +938,12 @@ impl<'ctx> Compiler<'ctx> {
                 Ok(())
             }
 
+            ASTNode::TryCatch { loc, .. } | ASTNode::Throw { loc, .. } => {
+                Err(CodegenError::unsupported(format!("Exceptions (Try/Throw at {}) are only supported by the interpreter.", loc)))
+            }
+
             _ => {
-                Err(format!("(Codegen Error) Unsupported statement type: {:?}", node))
+                Err(format!("Unsupported statement type: {:?}", node).into())
             }
         }
     }
@@ -674,7 +959,7 @@ impl<'ctx> Compiler<'ctx> {
         v2: inkwell::values::VectorValue<'ctx>,
         v3: inkwell::values::VectorValue<'ctx>,
         _current_function: FunctionValue<'ctx>,
-    ) -> Result<inkwell::values::VectorValue<'ctx>, String> {
+    ) -> Result<inkwell::values::VectorValue<'ctx>, CodegenError> {
 
         let intrinsic_function = self.module.get_function("llvm.fma.v2f64")
             .unwrap_or_else(|| {
@@ -694,11 +979,147 @@ impl<'ctx> Compiler<'ctx> {
             .into_vector_value())
     }
 
+    // Complex numbers are lowered to `{ double re, double im }`.
+    fn complex_type(&self) -> inkwell::types::StructType<'ctx> {
+        let f64_type = self.context.f64_type();
+        self.context.struct_type(&[f64_type.into(), f64_type.into()], false)
+    }
+
+    fn build_complex(
+        &self,
+        re: inkwell::values::FloatValue<'ctx>,
+        im: inkwell::values::FloatValue<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>, inkwell::builder::BuilderError> {
+        let with_re = self.builder.build_insert_value(self.complex_type().get_undef(), re, 0, "complex_re")?;
+        let complex = self.builder.build_insert_value(with_re, im, 1, "complex_im")?;
+        Ok(complex.as_basic_value_enum())
+    }
+
+    // An Int or Float stored into a Complex slot widens to `re + 0i`, as the type checker allows.
+    fn promote(&self, value: BasicValueEnum<'ctx>, slot: BasicTypeEnum<'ctx>) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        if slot == self.complex_type().as_basic_type_enum() && (value.is_int_value() || value.is_float_value()) {
+            let (re, im) = self.complex_parts(value)?;
+            return self.build_complex(re, im).map_err(|e| e.to_string().into());
+        }
+        Ok(value)
+    }
+
+    // Real and imaginary parts of a complex value, promoting ints and floats.
+    fn complex_parts(
+        &self,
+        value: BasicValueEnum<'ctx>,
+    ) -> Result<(inkwell::values::FloatValue<'ctx>, inkwell::values::FloatValue<'ctx>), CodegenError> {
+        let f64_type = self.context.f64_type();
+        match value {
+            BasicValueEnum::StructValue(complex) => {
+                let re = self.builder.build_extract_value(complex, 0, "re").map_err(|e| e.to_string())?;
+                let im = self.builder.build_extract_value(complex, 1, "im").map_err(|e| e.to_string())?;
+                Ok((re.into_float_value(), im.into_float_value()))
+            }
+            BasicValueEnum::FloatValue(re) => Ok((re, f64_type.const_float(0.0))),
+            BasicValueEnum::IntValue(int) => {
+                let re = self.builder.build_signed_int_to_float(int, f64_type, "int_to_re").map_err(|e| e.to_string())?;
+                Ok((re, f64_type.const_float(0.0)))
+            }
+            _ => Err("Expected a number in complex arithmetic.".into()),
+        }
+    }
+
+    fn emit_complex_binary(
+        &self,
+        operator: &BinaryOperator,
+        left: BasicValueEnum<'ctx>,
+        right: BasicValueEnum<'ctx>,
+        loc: &Loc,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        if !matches!(operator, BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul |
+                               BinaryOperator::Div | BinaryOperator::Equal | BinaryOperator::NotEqual) {
+            return Err(CodegenError::unsupported(format!("Complex operator {:?} not yet implemented at {}", operator, loc)));
+        }
+        let (a, b) = self.complex_parts(left)?;
+        let (c, d) = self.complex_parts(right)?;
+        let b_ = &self.builder;
+        let emit = || match operator {
+            BinaryOperator::Add => self.build_complex(b_.build_float_add(a, c, "cadd_re")?, b_.build_float_add(b, d, "cadd_im")?),
+            BinaryOperator::Sub => self.build_complex(b_.build_float_sub(a, c, "csub_re")?, b_.build_float_sub(b, d, "csub_im")?),
+            BinaryOperator::Mul => {
+                // (a + bi)(c + di) = (ac - bd) + (ad + bc)i
+                let re = b_.build_float_sub(b_.build_float_mul(a, c, "ac")?, b_.build_float_mul(b, d, "bd")?, "cmul_re")?;
+                let im = b_.build_float_add(b_.build_float_mul(a, d, "ad")?, b_.build_float_mul(b, c, "bc")?, "cmul_im")?;
+                self.build_complex(re, im)
+            }
+            BinaryOperator::Div => {
+                // (a + bi)/(c + di) = ((ac + bd) + (bc - ad)i) / (c² + d²)
+                let denominator = b_.build_float_add(b_.build_float_mul(c, c, "cc")?, b_.build_float_mul(d, d, "dd")?, "cdiv_den")?;
+                let re = b_.build_float_add(b_.build_float_mul(a, c, "ac")?, b_.build_float_mul(b, d, "bd")?, "cdiv_re_num")?;
+                let im = b_.build_float_sub(b_.build_float_mul(b, c, "bc")?, b_.build_float_mul(a, d, "ad")?, "cdiv_im_num")?;
+                self.build_complex(b_.build_float_div(re, denominator, "cdiv_re")?, b_.build_float_div(im, denominator, "cdiv_im")?)
+            }
+            BinaryOperator::Equal => {
+                let re_eq = b_.build_float_compare(FloatPredicate::OEQ, a, c, "ceq_re")?;
+                let im_eq = b_.build_float_compare(FloatPredicate::OEQ, b, d, "ceq_im")?;
+                Ok(b_.build_and(re_eq, im_eq, "ceq")?.as_basic_value_enum())
+            }
+            BinaryOperator::NotEqual => {
+                let re_ne = b_.build_float_compare(FloatPredicate::UNE, a, c, "cne_re")?;
+                let im_ne = b_.build_float_compare(FloatPredicate::UNE, b, d, "cne_im")?;
+                Ok(b_.build_or(re_ne, im_ne, "cne")?.as_basic_value_enum())
+            }
+            _ => unreachable!(),
+        };
+        emit().map_err(|e| e.to_string().into())
+    }
+
+    fn get_libm_function(&self, name: &str, arity: usize) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| {
+            let f64_type = self.context.f64_type();
+            let params: Vec<BasicMetadataTypeEnum> = vec![f64_type.into(); arity];
+            self.module.add_function(name, f64_type.fn_type(&params, false), Some(Linkage::External))
+        })
+    }
+
+    fn call_libm(&self, name: &str, args: &[inkwell::values::FloatValue<'ctx>]) -> Result<inkwell::values::FloatValue<'ctx>, CodegenError> {
+        let function = self.get_libm_function(name, args.len());
+        let args: Vec<BasicMetadataValueEnum> = args.iter().map(|&arg| arg.into()).collect();
+        let call = self.builder.build_call(function, &args, name).map_err(|e| e.to_string())?;
+        call.try_as_basic_value().left()
+            .map(|value| value.into_float_value())
+            .ok_or_else(|| format!("'{}' did not return a double.", name).into())
+    }
+
+    // abs, arg, conj, exp, real and imag; `exp` of a real number stays real.
+    fn emit_complex_builtin(&self, name: &str, argument: BasicValueEnum<'ctx>) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let is_complex = argument.is_struct_value();
+        let (re, im) = self.complex_parts(argument)?;
+        let value = match name {
+            "abs" => self.call_libm("hypot", &[re, im])?.as_basic_value_enum(),
+            "arg" => self.call_libm("atan2", &[im, re])?.as_basic_value_enum(),
+            "real" => re.as_basic_value_enum(),
+            "imag" => im.as_basic_value_enum(),
+            "conj" => {
+                let im = self.builder.build_float_neg(im, "conj_im").map_err(|e| e.to_string())?;
+                self.build_complex(re, im).map_err(|e| e.to_string())?
+            }
+            "exp" if !is_complex => self.call_libm("exp", &[re])?.as_basic_value_enum(),
+            "exp" => {
+                // e^(a + bi) = e^a (cos b + i sin b)
+                let magnitude = self.call_libm("exp", &[re])?;
+                let cos = self.call_libm("cos", &[im])?;
+                let sin = self.call_libm("sin", &[im])?;
+                let re = self.builder.build_float_mul(magnitude, cos, "cexp_re").map_err(|e| e.to_string())?;
+                let im = self.builder.build_float_mul(magnitude, sin, "cexp_im").map_err(|e| e.to_string())?;
+                self.build_complex(re, im).map_err(|e| e.to_string())?
+            }
+            _ => return Err(format!("Unknown complex built-in '{}'.", name).into()),
+        };
+        Ok(value)
+    }
+
     fn emit_vector_mul(
         &self,
         v1: inkwell::values::VectorValue<'ctx>,
         v2: inkwell::values::VectorValue<'ctx>,
-    ) -> Result<inkwell::values::VectorValue<'ctx>, String> {
+    ) -> Result<inkwell::values::VectorValue<'ctx>, CodegenError> {
 
         let result = self.builder.build_float_mul(v1, v2, "vector_mul_tmp")
             .map_err(|e| e.to_string())?;
@@ -712,17 +1133,40 @@ impl<'ctx> Compiler<'ctx> {
         size_node: &Option<Box<ASTNode>>,
         initial_state_node: &Option<Box<ASTNode>>,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(), String> {
+    ) -> Result<(), CodegenError> {
 
-        if initial_state_node.is_some() {
-            return Err("(Codegen STUB) Quantum declaration with initial state is not yet supported.".to_string());
-        }
+        // Ket initial states are folded at compile time and prepared with unitaries.
+        let ket = match initial_state_node {
+            Some(node) => match Evaluator::evaluate(node, &Rc::new(RefCell::new(Environment::new()))) {
+                Ok(RuntimeValue::Ket(ket)) => Some(ket),
+                _ => return Err(format!("Initial state of '{}' must be a constant ket expression.", name).into()),
+            },
+            None => None,
+        };
+
+        let constant_size = match (&ket, size_node.as_deref()) {
+            (Some(ket), _) => Some(ket.num_qubits as u64),
+            (None, Some(ASTNode::IntLiteral(n))) => Some(*n as u64),
+            (None, None) => Some(1),
+            _ => None,
+        };
+        match constant_size {
+            Some(size) => self.register_sizes.insert(name.to_string(), size),
+            None => self.register_sizes.remove(name),
+        };
 
         let size_value: inkwell::values::IntValue<'ctx>;
-        if let Some(node) = size_node {
+        if let Some(ket) = &ket {
+            if let Some(ASTNode::IntLiteral(n)) = size_node.as_deref() {
+                if *n as usize != ket.num_qubits {
+                    return Err(format!("Register '{}' has {} qubit(s), but its initial state is a {}-qubit ket.", name, n, ket.num_qubits).into());
+                }
+            }
+            size_value = self.context.i32_type().const_int(ket.num_qubits as u64, false);
+        } else if let Some(node) = size_node {
             let compiled_size = self.compile_expression(node, current_function)?;
             if !compiled_size.is_int_value() {
-                return Err("(Codegen Error) Quantum register size must be an integer.".to_string());
+                return Err("Quantum register size must be an integer.".into());
             }
             let size_i64 = compiled_size.into_int_value();
 
@@ -740,7 +1184,7 @@ impl<'ctx> Compiler<'ctx> {
         ).map_err(|e| e.to_string())?;
 
         let state_ptr = call_site.try_as_basic_value().left()
-            .ok_or("(Codegen Error) quantica_rt_new_state did not return a value.".to_string())?
+            .ok_or("quantica_rt_new_state did not return a value.".to_string())?
             .into_pointer_value();
 
         let state_ptr_type = self.context.ptr_type(AddressSpace::default());
@@ -750,6 +1194,32 @@ impl<'ctx> Compiler<'ctx> {
         self.builder.build_store(alloca, state_ptr)
             .map_err(|e| e.to_string())?;
 
+        if let Some(ket) = ket {
+            let gate_name_ptr = self.builder.build_global_string_ptr("state_prep", "gate_name")
+                .map_err(|e| e.to_string())?
+                .as_pointer_value();
+            for (qubits, unitary) in ket.preparation_steps()? {
+                let qubit_indices: Vec<i32> = qubits.iter().map(|&q| q as i32).collect();
+                let matrix_ptr = self.build_matrix_constant(&unitary);
+                let qubit_indices_ptr = self.build_i32_array(&qubit_indices)?;
+                let i32_type = self.context.i32_type();
+                let _ = self.builder.build_call(
+                    self.rt_apply_unitary,
+                    &[
+                        state_ptr.into(),
+                        gate_name_ptr.into(),
+                        matrix_ptr.into(),
+                        i32_type.const_int(unitary.num_qubits as u64, false).into(),
+                        i32_type.const_int(0, false).into(),
+                        qubit_indices_ptr.into(),
+                        i32_type.const_int(qubit_indices.len() as u64, false).into(),
+                        i32_type.const_int(0, false).into(),
+                    ],
+                    "call_state_prep",
+                ).map_err(|e| e.to_string())?;
+            }
+        }
+
         self.variables.insert(name.to_string(), (alloca, state_ptr_type.as_basic_type_enum()));
 
         Ok(())
@@ -762,13 +1232,14 @@ impl<'ctx> Compiler<'ctx> {
         value_node: &ASTNode,
         is_mutable: bool,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(), String> {
+    ) -> Result<(), CodegenError> {
         let value = self.compile_expression(value_node, current_function)?;
         let llvm_type = if let Some(quantica_type) = type_annotation {
              self.map_type(quantica_type)
         } else {
             value.get_type()
         };
+        let value = self.promote(value, llvm_type)?;
         let alloca = self.builder.build_alloca(llvm_type, name).map_err(|e| e.to_string())?;
         let _ = self.builder.build_store(alloca, value);
 
@@ -810,7 +1281,11 @@ impl<'ctx> Compiler<'ctx> {
         }
 
         self.variables.insert(name.to_string(), (alloca, llvm_type));
-        let _ = is_mutable;
+        if is_mutable {
+            self.mutable_variables.insert(name.to_string());
+        } else {
+            self.mutable_variables.remove(name);
+        }
         Ok(())
     }
 
@@ -868,7 +1343,7 @@ impl<'ctx> Compiler<'ctx> {
         elif_blocks: &Vec<(ASTNode, ASTNode)>,
         else_node: &Option<Box<ASTNode>>,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(), String> {
+    ) -> Result<(), CodegenError> {
 
         let merge_block = self.context.append_basic_block(current_function, "merge");
 
@@ -917,12 +1392,361 @@ impl<'ctx> Compiler<'ctx> {
         Ok(())
     }
 
+    fn compile_match_statement(
+        &mut self,
+        value_node: &ASTNode,
+        cases: &[MatchCase],
+        loc: &Loc,
+        current_function: FunctionValue<'ctx>,
+    ) -> Result<(), CodegenError> {
+        let subject = self.compile_expression(value_node, current_function)?;
+        let (discriminant, variants) = match self.user_type_of(subject) {
+            Some(UserType::Enum(variants)) => {
+                let tag = self.builder.build_extract_value(subject.into_struct_value(), 0, "tag").map_err(|e| e.to_string())?;
+                (tag.into_int_value(), Some(variants))
+            }
+            _ if subject.is_int_value() => (subject.into_int_value(), None),
+            _ => return Err(CodegenError::unsupported(format!("'match' is only lowered for Int, Bool and enum values at {}", loc))),
+        };
+        let merge_block = self.context.append_basic_block(current_function, "match_merge");
+        self.compile_match_cases(subject, discriminant, variants.as_deref(), cases, loc, merge_block, current_function)?;
+        self.builder.position_at_end(merge_block);
+        Ok(())
+    }
+
+    // Cases are tried in order. Each has a test block that switches over its constant
+    // patterns (the tag, for enums) and otherwise falls through to the next case's test;
+    // a failed guard branches to that same block, so every body is emitted once. The
+    // names a pattern binds are only visible inside its case.
+    #[allow(clippy::too_many_arguments)]
+    fn compile_match_cases(
+        &mut self,
+        subject: BasicValueEnum<'ctx>,
+        discriminant: inkwell::values::IntValue<'ctx>,
+        variants: Option<&[EnumVariant]>,
+        cases: &[MatchCase],
+        loc: &Loc,
+        merge_block: inkwell::basic_block::BasicBlock<'ctx>,
+        current_function: FunctionValue<'ctx>,
+    ) -> Result<(), CodegenError> {
+        let test_blocks: Vec<_> = (0..cases.len())
+            .map(|i| self.context.append_basic_block(current_function, &format!("match_test_{}", i)))
+            .collect();
+        let first_test = test_blocks.first().copied().unwrap_or(merge_block);
+        self.builder.build_unconditional_branch(first_test).map_err(|e| e.to_string())?;
+
+        for (i, case) in cases.iter().enumerate() {
+            let next_block = test_blocks.get(i + 1).copied().unwrap_or(merge_block);
+            let case_block = self.context.append_basic_block(current_function, &format!("match_case_{}", i));
+
+            self.builder.position_at_end(test_blocks[i]);
+            if case.pattern.is_irrefutable() {
+                self.builder.build_unconditional_branch(case_block).map_err(|e| e.to_string())?;
+            } else {
+                let mut values = Vec::new();
+                Self::pattern_constants(&case.pattern, variants, &mut values, loc)?;
+                values.sort_unstable();
+                values.dedup();
+                let switch_cases: Vec<_> = values.into_iter()
+                    .map(|value| (discriminant.get_type().const_int(value as u64, true), case_block))
+                    .collect();
+                self.builder.build_switch(discriminant, next_block, &switch_cases).map_err(|e| e.to_string())?;
+            }
+
+            self.builder.position_at_end(case_block);
+            let outer_variables = self.variables.clone();
+            self.bind_pattern(&case.pattern, subject, variants, loc)?;
+            if let Some(guard) = &case.guard {
+                let condition = self.compile_expression(guard, current_function)?.into_int_value();
+                let body_block = self.context.append_basic_block(current_function, &format!("match_body_{}", i));
+                self.builder.build_conditional_branch(condition, body_block, next_block).map_err(|e| e.to_string())?;
+                self.builder.position_at_end(body_block);
+            }
+            self.compile_statement(&case.body, current_function)?;
+            if self.builder.get_insert_block().and_then(|b| b.get_terminator()).is_none() {
+                self.builder.build_unconditional_branch(merge_block).map_err(|e| e.to_string())?;
+            }
+            self.variables = outer_variables;
+        }
+        Ok(())
+    }
+
+    // Switch values of a literal, range or alternative pattern over Int or Bool, or the
+    // tags of the variants a pattern accepts.
+    fn pattern_constants(pattern: &Pattern, variants: Option<&[EnumVariant]>, values: &mut Vec<i64>, loc: &Loc) -> Result<(), CodegenError> {
+        const MAX_RANGE_CASES: i64 = 1024;
+        let constant = |node: &ASTNode| match node {
+            ASTNode::IntLiteral(n) => Some(*n),
+            ASTNode::BoolLiteral(b) => Some(*b as i64),
+            ASTNode::Unary { operator: UnaryOperator::Minus, operand } => match &**operand {
+                ASTNode::IntLiteral(n) => Some(-n),
+                _ => None,
+            },
+            _ => None,
+        };
+        match pattern {
+            Pattern::Literal(node) => {
+                values.push(constant(node).ok_or_else(|| CodegenError::unsupported(format!("Only Int and Bool literal patterns are lowered at {}", loc)))?);
+            }
+            Pattern::Range { start, end, inclusive } => {
+                let (Some(low), Some(high)) = (constant(start), constant(end)) else {
+                    return Err(CodegenError::unsupported(format!("Range patterns need Int literal bounds at {}", loc)));
+                };
+                let high = if *inclusive { high } else { high - 1 };
+                if high - low >= MAX_RANGE_CASES {
+                    return Err(CodegenError::unsupported(format!("Range pattern at {} spans more than {} values", loc, MAX_RANGE_CASES)));
+                }
+                values.extend(low..=high);
+            }
+            Pattern::Alternatives(options) => {
+                for option in options {
+                    Self::pattern_constants(option, variants, values, loc)?;
+                }
+            }
+            Pattern::Binding { pattern, .. } => Self::pattern_constants(pattern, variants, values, loc)?,
+            Pattern::Variant { variant, fields, .. } => {
+                let tag = variants.and_then(|variants| variants.iter().position(|v| v.name == *variant))
+                    .ok_or_else(|| format!("Unknown variant '{}' at {}", variant, loc))?;
+                if !fields.iter().all(Pattern::is_irrefutable) {
+                    return Err(CodegenError::unsupported(format!("Refutable patterns inside variant '{}' are not lowered yet at {}", variant, loc)));
+                }
+                values.push(tag as i64);
+            }
+            Pattern::Identifier(_) | Pattern::Wildcard => {}
+        }
+        Ok(())
+    }
+
+    // Give every name bound by a matched pattern its own stack slot.
+    fn bind_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: BasicValueEnum<'ctx>,
+        variants: Option<&[EnumVariant]>,
+        loc: &Loc,
+    ) -> Result<(), CodegenError> {
+        match pattern {
+            Pattern::Identifier(name) => {
+                let alloca = self.builder.build_alloca(value.get_type(), name).map_err(|e| e.to_string())?;
+                self.builder.build_store(alloca, value).map_err(|e| e.to_string())?;
+                self.variables.insert(name.clone(), (alloca, value.get_type()));
+                Ok(())
+            }
+            Pattern::Binding { pattern, name } => {
+                self.bind_pattern(pattern, value, variants, loc)?;
+                self.bind_pattern(&Pattern::Identifier(name.clone()), value, variants, loc)
+            }
+            Pattern::Variant { variant, fields, .. } => {
+                let declared = variants.and_then(|variants| variants.iter().find(|v| v.name == *variant))
+                    .ok_or_else(|| format!("Unknown variant '{}' at {}", variant, loc))?;
+                let payload = self.builder.build_extract_value(value.into_struct_value(), 1, "payload")
+                    .map_err(|e| e.to_string())?
+                    .into_array_value();
+                let mut word = 0;
+                for (field, field_type) in fields.iter().zip(&declared.fields) {
+                    let field_value = self.decode_payload(payload, word, field_type)?;
+                    word += Self::payload_words(field_type).unwrap_or(1);
+                    self.bind_pattern(field, field_value, None, loc)?;
+                }
+                Ok(())
+            }
+            Pattern::Alternatives(_) if !pattern.bindings().is_empty() => {
+                Err(CodegenError::unsupported(format!("Alternative patterns that bind names are not lowered yet at {}", loc)))
+            }
+            Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Alternatives(_) | Pattern::Wildcard => Ok(()),
+        }
+    }
+
+    // Address of a variable, or of a field path inside one, for passing as `mut self`.
+    fn place_address(&self, node: &ASTNode) -> Result<(PointerValue<'ctx>, BasicTypeEnum<'ctx>), CodegenError> {
+        match node {
+            ASTNode::Identifier { name, loc } => self.variables.get(name).copied()
+                .ok_or_else(|| CodegenError::at(loc, format!("Undefined variable '{}'", name))),
+            ASTNode::MemberAccess { object, member } => {
+                let (address, ty) = self.place_address(object)?;
+                let struct_type = ty.into_struct_type();
+                let fields = match struct_type.get_name().and_then(|name| name.to_str().ok()).and_then(|name| self.user_types.get(name)) {
+                    Some(UserType::Struct(fields)) => fields,
+                    _ => return Err(format!("No field '{}' on a value that is not a struct", member).into()),
+                };
+                let index = fields.iter().position(|field| field.name == *member)
+                    .ok_or_else(|| format!("No field '{}' on struct value", member))? as u32;
+                let field_address = self.builder.build_struct_gep(struct_type, address, index, member)
+                    .map_err(|e| e.to_string())?;
+                let field_type = struct_type.get_field_type_at_index(index)
+                    .ok_or_else(|| format!("No field '{}' on struct value", member))?;
+                Ok((field_address, field_type))
+            }
+            _ => Err(CodegenError::unsupported("A 'mut self' receiver must be a variable or a field of one.")),
+        }
+    }
+
+    // Name of the struct or enum a value was declared as, if it is one.
+    fn user_type_name(&self, value: BasicValueEnum<'ctx>) -> Option<String> {
+        let BasicValueEnum::StructValue(value) = value else {
+            return None;
+        };
+        let name = value.get_type().get_name()?.to_str().ok()?.to_string();
+        self.user_types.contains_key(&name).then_some(name)
+    }
+
+    fn user_type_of(&self, value: BasicValueEnum<'ctx>) -> Option<UserType> {
+        self.user_type_name(value).and_then(|name| self.user_types.get(&name).cloned())
+    }
+
+    // Number of i64 words an enum payload field occupies.
+    fn payload_words(ty: &Type) -> Option<u32> {
+        match ty {
+            Type::Int | Type::Int8 | Type::Int16 | Type::Int32 | Type::Int64 |
+            Type::Bool | Type::Float | Type::Float32 | Type::Float64 => Some(1),
+            Type::Complex | Type::Complex64 | Type::Complex128 => Some(2),
+            _ => None,
+        }
+    }
+
+    fn encode_payload(&self, value: BasicValueEnum<'ctx>, ty: &Type) -> Result<Vec<inkwell::values::IntValue<'ctx>>, CodegenError> {
+        let i64_type = self.context.i64_type();
+        let f64_type = self.context.f64_type();
+        let emit = || -> Result<Vec<inkwell::values::IntValue<'ctx>>, inkwell::builder::BuilderError> {
+            let to_word = |float: inkwell::values::FloatValue<'ctx>| -> Result<inkwell::values::IntValue<'ctx>, inkwell::builder::BuilderError> {
+                let float = self.builder.build_float_cast(float, f64_type, "payload_f64")?;
+                Ok(self.builder.build_bit_cast(float, i64_type, "payload_bits")?.into_int_value())
+            };
+            Ok(match (ty, value) {
+                (Type::Bool, BasicValueEnum::IntValue(int)) => vec![self.builder.build_int_z_extend(int, i64_type, "payload_bool")?],
+                (Type::Float | Type::Float32 | Type::Float64, BasicValueEnum::IntValue(int)) => {
+                    vec![to_word(self.builder.build_signed_int_to_float(int, f64_type, "payload_int_to_f64")?)?]
+                }
+                (_, BasicValueEnum::IntValue(int)) => vec![self.builder.build_int_s_extend_or_bit_cast(int, i64_type, "payload_int")?],
+                (_, BasicValueEnum::FloatValue(float)) if Self::payload_words(ty) == Some(1) => vec![to_word(float)?],
+                _ => return Ok(vec![]),
+            })
+        };
+        let words = emit().map_err(|e| e.to_string())?;
+        if !words.is_empty() {
+            return Ok(words);
+        }
+        // Complex fields: real and imaginary parts, promoting ints and floats.
+        let (re, im) = self.complex_parts(value)?;
+        let re = self.builder.build_bit_cast(re, i64_type, "payload_re").map_err(|e| e.to_string())?;
+        let im = self.builder.build_bit_cast(im, i64_type, "payload_im").map_err(|e| e.to_string())?;
+        Ok(vec![re.into_int_value(), im.into_int_value()])
+    }
+
+    fn decode_payload(
+        &self,
+        payload: inkwell::values::ArrayValue<'ctx>,
+        word: u32,
+        ty: &Type,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let f64_type = self.context.f64_type();
+        let emit = || -> Result<BasicValueEnum<'ctx>, inkwell::builder::BuilderError> {
+            let bits = self.builder.build_extract_value(payload, word, "payload_word")?.into_int_value();
+            Ok(match ty {
+                Type::Float | Type::Float32 | Type::Float64 => {
+                    let float = self.builder.build_bit_cast(bits, f64_type, "payload_f64")?.into_float_value();
+                    self.builder.build_float_cast(float, self.map_type(ty).into_float_type(), "payload_float")?.as_basic_value_enum()
+                }
+                Type::Complex | Type::Complex64 | Type::Complex128 => {
+                    let im = self.builder.build_extract_value(payload, word + 1, "payload_word")?.into_int_value();
+                    let re = self.builder.build_bit_cast(bits, f64_type, "payload_re")?.into_float_value();
+                    let im = self.builder.build_bit_cast(im, f64_type, "payload_im")?.into_float_value();
+                    self.build_complex(re, im)?
+                }
+                _ => self.builder.build_int_truncate_or_bit_cast(bits, self.map_type(ty).into_int_type(), "payload_int")?.as_basic_value_enum(),
+            })
+        };
+        emit().map_err(|e| e.to_string().into())
+    }
+
+    fn is_user_constructor(&self, callee: &ASTNode) -> bool {
+        let type_name = match callee {
+            ASTNode::Identifier { name, .. } => name,
+            ASTNode::MemberAccess { object, .. } => match &**object {
+                ASTNode::Identifier { name, .. } => name,
+                _ => return false,
+            },
+            _ => return false,
+        };
+        self.user_types.contains_key(type_name) && !self.variables.contains_key(type_name)
+    }
+
+    // `Point(1, 2)` and `Shape.Circle(1.0)`: a struct or enum value built in place.
+    fn compile_user_constructor(
+        &mut self,
+        callee: &ASTNode,
+        arguments: &[ASTNode],
+        loc: &Loc,
+        current_function: FunctionValue<'ctx>,
+    ) -> Result<Option<BasicValueEnum<'ctx>>, CodegenError> {
+        let (type_name, variant) = match callee {
+            ASTNode::Identifier { name, .. } if !self.variables.contains_key(name) => (name, None),
+            ASTNode::MemberAccess { object, member } => match &**object {
+                ASTNode::Identifier { name, .. } if !self.variables.contains_key(name) => (name, Some(member.as_str())),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let Some(user_type) = self.user_types.get(type_name).cloned() else {
+            return Ok(None);
+        };
+        let Some(llvm_type) = self.context.get_struct_type(type_name) else {
+            return Err(format!("Type '{}' has no layout at {}", type_name, loc).into());
+        };
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            values.push(self.compile_expression(argument, current_function)?);
+        }
+
+        match (user_type, variant) {
+            (UserType::Struct(fields), None) => {
+                if fields.len() != values.len() {
+                    return Err(format!("'{}' has {} fields, but {} values were given at {}", type_name, fields.len(), values.len(), loc).into());
+                }
+                let mut value = llvm_type.get_undef();
+                for (i, field_value) in values.into_iter().enumerate() {
+                    value = self.builder.build_insert_value(value, field_value, i as u32, &fields[i].name)
+                        .map_err(|e| e.to_string())?
+                        .into_struct_value();
+                }
+                Ok(Some(value.as_basic_value_enum()))
+            }
+            (UserType::Enum(variants), Some(variant)) => {
+                let Some(tag) = variants.iter().position(|v| v.name == variant) else {
+                    return Err(format!("Enum '{}' has no variant '{}' at {}", type_name, variant, loc).into());
+                };
+                let declared = &variants[tag].fields;
+                if declared.len() != values.len() {
+                    return Err(format!("'{}.{}' carries {} values, but {} were given at {}", type_name, variant, declared.len(), values.len(), loc).into());
+                }
+                let mut payload = llvm_type.get_field_type_at_index(1)
+                    .ok_or_else(|| format!("Enum '{}' has no payload slot", type_name))?
+                    .into_array_type()
+                    .const_zero();
+                let mut word = 0;
+                for (field_value, field_type) in values.into_iter().zip(declared) {
+                    for bits in self.encode_payload(field_value, field_type)? {
+                        payload = self.builder.build_insert_value(payload, bits, word, "payload")
+                            .map_err(|e| e.to_string())?
+                            .into_array_value();
+                        word += 1;
+                    }
+                }
+                let tag = self.context.i32_type().const_int(tag as u64, false);
+                let value = self.builder.build_insert_value(llvm_type.get_undef(), tag, 0, "tag").map_err(|e| e.to_string())?;
+                let value = self.builder.build_insert_value(value, payload, 1, variant).map_err(|e| e.to_string())?;
+                Ok(Some(value.as_basic_value_enum()))
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn compile_while_loop(
         &mut self,
         condition_node: &ASTNode,
         body_node: &ASTNode,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(), String> {
+    ) -> Result<(), CodegenError> {
 
         let _parent_block = self.builder.get_insert_block().ok_or("No valid block")?;
 
@@ -957,21 +1781,46 @@ impl<'ctx> Compiler<'ctx> {
         callee: &ASTNode,
         arguments: &Vec<ASTNode>,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<inkwell::values::CallSiteValue<'ctx>, String> {
-
-        let function_name = match callee {
-            ASTNode::Identifier { name, .. } => name,
-            _ => return Err("(Codegen STUB) Function calls on complex expressions are not supported.".to_string()),
+    ) -> Result<inkwell::values::CallSiteValue<'ctx>, CodegenError> {
+
+        let (function_name, receiver) = match callee {
+            ASTNode::Identifier { name, .. } if !self.variables.contains_key(name) => (name.clone(), None),
+            // `Type.method(...)` calls the method directly; `value.method(...)` passes the value as `self`.
+            ASTNode::MemberAccess { object, member } => match &**object {
+                ASTNode::Identifier { name, .. } if self.user_types.contains_key(name) && !self.variables.contains_key(name) => {
+                    (format!("{}.{}", name, member), None)
+                }
+                _ => {
+                    let value = self.compile_expression(object, current_function)?;
+                    let type_name = self.user_type_name(value)
+                        .ok_or_else(|| CodegenError::unsupported(format!("Method '{}' can only be called on struct and enum values.", member)))?;
+                    let method = format!("{}.{}", type_name, member);
+                    let takes_address = self.module.get_function(&method)
+                        .and_then(|function| function.get_type().get_param_types().first().copied())
+                        .is_some_and(|receiver| receiver.is_pointer_type());
+                    let receiver = if takes_address {
+                        self.place_address(object)?.0.as_basic_value_enum()
+                    } else {
+                        value
+                    };
+                    (method, Some(receiver))
+                }
+            },
+            // Anything else must evaluate to a closure.
+            _ => {
+                let closure = self.compile_expression(callee, current_function)?;
+                return self.call_closure(closure, arguments, current_function);
+            }
         };
 
         if function_name == "print" {
             if arguments.len() != 1 {
-                return Err("(Codegen Error) 'print' currently expects 1 argument.".to_string());
+                return Err("'print' currently expects 1 argument.".into());
             }
             let arg_val = self.compile_expression(&arguments[0], current_function)?;
 
             if !arg_val.is_pointer_value() {
-                return Err("(Codegen STUB) 'print' only supports string literals for now.".to_string());
+                return Err(CodegenError::unsupported("'print' only supports string literals for now."));
             }
             let string_ptr = arg_val.into_pointer_value();
 
@@ -985,12 +1834,12 @@ impl<'ctx> Compiler<'ctx> {
 
         } else if function_name == "debug_state" {
             if arguments.len() != 1 {
-                return Err("(Codegen Error) 'debug_state' expects 1 argument (a quantum register).".to_string());
+                return Err("'debug_state' expects 1 argument (a quantum register).".into());
             }
 
             let arg_val = self.compile_expression(&arguments[0], current_function)?;
             if !arg_val.is_pointer_value() {
-                return Err("(Codegen Error) 'debug_state' argument must be a quantum register.".to_string());
+                return Err("'debug_state' argument must be a quantum register.".into());
             }
             let state_ptr = arg_val.into_pointer_value();
 
@@ -1001,18 +1850,48 @@ impl<'ctx> Compiler<'ctx> {
             ).map_err(|e| e.to_string())?;
 
             return Ok(call_site);
-        }
 
-        let function = self.module.get_function(function_name)
-            .ok_or_else(|| format!("(Codegen Error) Unknown function '{}'", function_name))?;
+        } else if function_name == "set_seed" {
+            if arguments.len() != 1 {
+                return Err("'set_seed' expects 1 argument (an integer seed).".into());
+            }
+
+            let arg_val = self.compile_expression(&arguments[0], current_function)?;
+            if !arg_val.is_int_value() {
+                return Err("'set_seed' argument must be an integer.".into());
+            }
+
+            let call_site = self.builder.build_call(
+                self.rt_set_seed,
+                &[arg_val.into()],
+                "calltmp_set_seed",
+            ).map_err(|e| e.to_string())?;
 
-        let mut compiled_args: Vec<BasicMetadataValueEnum<'ctx>> = Vec::with_capacity(arguments.len());
+            return Ok(call_site);
+        }
 
+        let mut arg_values: Vec<BasicValueEnum<'ctx>> = Vec::with_capacity(arguments.len() + 1);
+        arg_values.extend(receiver);
         for arg_node in arguments {
-            let arg_value = self.compile_expression(arg_node, current_function)?;
-            compiled_args.push(arg_value.into());
+            arg_values.push(self.compile_expression(arg_node, current_function)?);
         }
 
+        let function = if self.generic_functions.contains_key(&function_name) {
+            let arg_nodes: Vec<Option<&ASTNode>> = receiver.iter().map(|_| None).chain(arguments.iter().map(Some)).collect();
+            self.instantiate_generic(&function_name, &arg_nodes, &arg_values)?
+        } else {
+            self.module.get_function(&function_name)
+                .ok_or_else(|| format!("Unknown function '{}'", function_name))?
+        };
+        let param_types = function.get_type().get_param_types();
+        let arg_values = arg_values.into_iter().enumerate()
+            .map(|(i, value)| match param_types.get(i).and_then(|&param_type| BasicTypeEnum::try_from(param_type).ok()) {
+                Some(slot) => self.promote(value, slot),
+                None => Ok(value),
+            })
+            .collect::<Result<Vec<_>, CodegenError>>()?;
+        let compiled_args: Vec<BasicMetadataValueEnum<'ctx>> = arg_values.into_iter().map(BasicMetadataValueEnum::from).collect();
+
         let call_site = self.builder.build_call(
             function,
             &compiled_args,
@@ -1023,13 +1902,414 @@ impl<'ctx> Compiler<'ctx> {
     }
 
 
+    // Compile (or reuse) the instance of a generic declaration for these arguments:
+    // type parameters are read off the argument values and size parameters off the
+    // registers passed, which must have been declared with a constant size.
+    fn instantiate_generic(
+        &mut self,
+        name: &str,
+        arguments: &[Option<&ASTNode>],
+        values: &[BasicValueEnum<'ctx>],
+    ) -> Result<FunctionValue<'ctx>, CodegenError> {
+        let (generics, parameters, return_type, body) = self.generic_functions.get(name).cloned()
+            .ok_or_else(|| format!("Unknown function '{}'", name))?;
+        if parameters.len() != values.len() {
+            return Err(format!("'{}' expects {} arguments, but got {}.", name, parameters.len(), values.len()).into());
+        }
+
+        let mut types: HashMap<String, Type> = HashMap::new();
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        let mut param_sizes: HashMap<String, u64> = HashMap::new();
+        for ((param, argument), value) in parameters.iter().zip(arguments).zip(values) {
+            match &param.param_type {
+                Type::Generic(type_param) => {
+                    let ty = self.value_type(*value).ok_or_else(|| CodegenError::unsupported(format!(
+                        "Cannot infer type parameter '{}' of '{}' from argument '{}'.", type_param, name, param.name
+                    )))?;
+                    if types.get(type_param).is_some_and(|bound| *bound != ty) {
+                        return Err(format!("Type parameter '{}' of '{}' is bound to two different types.", type_param, name).into());
+                    }
+                    types.insert(type_param.clone(), ty);
+                }
+                Type::GenericRegister(size_param) => {
+                    let size = match argument {
+                        Some(ASTNode::Identifier { name: register, .. }) => self.register_sizes.get(register).copied(),
+                        _ => None,
+                    }.ok_or_else(|| CodegenError::unsupported(format!(
+                        "Size parameter '{}' of '{}' can only be inferred from a register declared with a constant size.",
+                        size_param, name
+                    )))?;
+                    if sizes.insert(size_param.clone(), size).is_some_and(|bound| bound != size) {
+                        return Err(format!("'{}' needs registers of the same size '{}'.", name, size_param).into());
+                    }
+                    param_sizes.insert(param.name.clone(), size);
+                }
+                _ => {}
+            }
+        }
+
+        // Constraints on sizes alone are decided here, for the sizes of this instance.
+        let (static_constraints, runtime_constraints): (Vec<ASTNode>, Vec<ASTNode>) = generics.where_clause.iter().cloned()
+            .partition(|constraint| !constraint.any_node(&|node| matches!(node, ASTNode::Identifier { name, .. } if !sizes.contains_key(name))));
+        let size_env = Rc::new(RefCell::new(Environment::new()));
+        for (size_param, size) in &sizes {
+            size_env.borrow_mut().set(size_param.clone(), RuntimeValue::Int(*size as i64));
+        }
+        if !Evaluator::where_clause_holds(&static_constraints, &size_env).map_err(|e| format!("{}", e))? {
+            let bound: Vec<String> = generics.size_params().map(|param| format!("{} = {}", param, sizes[param])).collect();
+            return Err(format!("Call to '{}' does not satisfy its 'where' clause with {}.", name, bound.join(", ")).into());
+        }
+
+        let mut bindings = Vec::new();
+        for generic in &generics.params {
+            match generic {
+                GenericParam::Type(type_param) => match types.get(type_param) {
+                    Some(ty) => bindings.push(format!("{:?}", ty)),
+                    None => return Err(CodegenError::unsupported(format!("Cannot infer type parameter '{}' of '{}'.", type_param, name))),
+                },
+                GenericParam::Size(size_param) => bindings.push(sizes[size_param].to_string()),
+            }
+        }
+        let instance = format!("{}<{}>", name, bindings.join(", "));
+        if let Some(function) = self.module.get_function(&instance) {
+            return Ok(function);
+        }
+
+        let saved = self.suspend_insertion();
+        let outer_types = std::mem::replace(&mut self.generic_types, types);
+        let outer_sizes = std::mem::replace(&mut self.generic_sizes, sizes);
+        let mut outer_registers = self.register_sizes.clone();
+        self.register_sizes.extend(param_sizes);
+        self.runtime_constraints = runtime_constraints;
+        let result = self.compile_function(&instance, &parameters, &return_type, &body);
+        self.generic_types = outer_types;
+        self.generic_sizes = outer_sizes;
+        std::mem::swap(&mut self.register_sizes, &mut outer_registers);
+        self.resume_insertion(saved);
+        result
+    }
+
+    // Stop the program on entry to the instance `name` when `constraint` does not hold.
+    fn compile_runtime_constraint(&mut self, name: &str, constraint: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), CodegenError> {
+        let BasicValueEnum::IntValue(holds) = self.compile_expression(constraint, current_function)? else {
+            return Err(format!("'where' clause of '{}' must be a Bool.", name).into());
+        };
+        let violated_block = self.context.append_basic_block(current_function, "where_violated");
+        let satisfied_block = self.context.append_basic_block(current_function, "where_satisfied");
+        self.builder.build_conditional_branch(holds, satisfied_block, violated_block).map_err(|e| e.to_string())?;
+
+        self.builder.position_at_end(violated_block);
+        let message = format!("Call to '{}' does not satisfy its 'where' clause.", name);
+        let message = self.builder.build_global_string_ptr(&message, "where_message").map_err(|e| e.to_string())?;
+        self.builder.build_call(self.rt_fail, &[message.as_pointer_value().into()], "").map_err(|e| e.to_string())?;
+        self.builder.build_unreachable().map_err(|e| e.to_string())?;
+
+        self.builder.position_at_end(satisfied_block);
+        Ok(())
+    }
+
+    // The source type of a compiled value, as bound to a type parameter.
+    fn value_type(&self, value: BasicValueEnum<'ctx>) -> Option<Type> {
+        match value {
+            BasicValueEnum::IntValue(v) => match v.get_type().get_bit_width() {
+                1 => Some(Type::Bool),
+                8 => Some(Type::Int8),
+                16 => Some(Type::Int16),
+                32 => Some(Type::Int32),
+                64 => Some(Type::Int),
+                128 => Some(Type::Int128),
+                _ => None,
+            },
+            BasicValueEnum::FloatValue(v) if v.get_type() == self.context.f32_type() => Some(Type::Float32),
+            BasicValueEnum::FloatValue(_) => Some(Type::Float),
+            BasicValueEnum::PointerValue(_) => Some(Type::String),
+            BasicValueEnum::StructValue(v) if v.get_type() == self.complex_type() => Some(Type::Complex),
+            _ => self.user_type_name(value).map(Type::Custom),
+        }
+    }
+
+    // A closure is `{ ptr fn, ptr env }`, and `fn` takes `env` before its own arguments.
+    fn closure_type(&self, fn_type: FunctionType<'ctx>) -> StructType<'ctx> {
+        let name = format!("closure<{}>", fn_type.print_to_string());
+        if let Some(existing) = self.context.get_struct_type(&name) {
+            return existing;
+        }
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let closure = self.context.opaque_struct_type(&name);
+        closure.set_body(&[ptr_type.into(), ptr_type.into()], false);
+        self.closure_signatures.borrow_mut().insert(name, fn_type);
+        closure
+    }
+
+    fn closure_fn_type(&self, params: &[BasicMetadataTypeEnum<'ctx>], return_type: Option<BasicTypeEnum<'ctx>>) -> FunctionType<'ctx> {
+        let mut param_types: Vec<BasicMetadataTypeEnum<'ctx>> = vec![self.context.ptr_type(AddressSpace::default()).into()];
+        param_types.extend_from_slice(params);
+        match return_type {
+            Some(ty) => ty.fn_type(&param_types, false),
+            None => self.context.void_type().fn_type(&param_types, false),
+        }
+    }
+
+    fn build_closure(&self, function: FunctionValue<'ctx>, env: PointerValue<'ctx>) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let closure_type = self.closure_type(function.get_type());
+        let closure = self.builder.build_insert_value(closure_type.get_undef(), function.as_global_value().as_pointer_value(), 0, "closure_fn")
+            .map_err(|e| e.to_string())?;
+        let closure = self.builder.build_insert_value(closure, env, 1, "closure")
+            .map_err(|e| e.to_string())?;
+        Ok(closure.into_struct_value().as_basic_value_enum())
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: BasicValueEnum<'ctx>,
+        arguments: &[ASTNode],
+        current_function: FunctionValue<'ctx>,
+    ) -> Result<inkwell::values::CallSiteValue<'ctx>, CodegenError> {
+        let BasicValueEnum::StructValue(closure) = closure else {
+            return Err("Only functions and lambdas can be called.".into());
+        };
+        let struct_name = closure.get_type().get_name().and_then(|name| name.to_str().ok()).unwrap_or_default().to_string();
+        let fn_type = self.closure_signatures.borrow().get(&struct_name).copied()
+            .ok_or_else(|| "Only functions and lambdas can be called.".to_string())?;
+
+        let function = self.builder.build_extract_value(closure, 0, "closure_fn").map_err(|e| e.to_string())?;
+        let env = self.builder.build_extract_value(closure, 1, "closure_env").map_err(|e| e.to_string())?;
+        let mut compiled_args: Vec<BasicMetadataValueEnum<'ctx>> = vec![env.into()];
+        for arg_node in arguments {
+            compiled_args.push(self.compile_expression(arg_node, current_function)?.into());
+        }
+        self.builder.build_indirect_call(fn_type, function.into_pointer_value(), &compiled_args, "calltmp")
+            .map_err(|e| e.to_string().into())
+    }
+
+    // Lambda bodies and wrappers are emitted into their own functions in the middle of
+    // another one, without debug info; these save and restore the enclosing position.
+    fn suspend_insertion(&mut self) -> (Option<BasicBlock<'ctx>>, Option<DILocation<'ctx>>) {
+        let saved = (self.builder.get_insert_block(), self.current_debug_location);
+        self.clear_debug_location();
+        saved
+    }
+
+    fn resume_insertion(&mut self, (block, location): (Option<BasicBlock<'ctx>>, Option<DILocation<'ctx>>)) {
+        if let Some(block) = block {
+            self.builder.position_at_end(block);
+        }
+        if let Some(location) = location {
+            self.current_debug_location = Some(location);
+            self.builder.set_current_debug_location(location);
+        }
+    }
+
+    // A declared function used as a value: a wrapper that ignores the environment.
+    fn function_closure(&mut self, function: FunctionValue<'ctx>) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let name = format!("{}.closure", function.get_name().to_string_lossy());
+        let wrapper = match self.module.get_function(&name) {
+            Some(wrapper) => wrapper,
+            None => {
+                let fn_type = self.closure_fn_type(&function.get_type().get_param_types(), function.get_type().get_return_type());
+                let wrapper = self.module.add_function(&name, fn_type, Some(Linkage::Internal));
+                let saved = self.suspend_insertion();
+                self.builder.position_at_end(self.context.append_basic_block(wrapper, "entry"));
+                let args: Vec<BasicMetadataValueEnum<'ctx>> = wrapper.get_param_iter().skip(1).map(Into::into).collect();
+                let result = self.builder.build_call(function, &args, "forward")
+                    .and_then(|call| match call.try_as_basic_value().left() {
+                        Some(value) => self.builder.build_return(Some(&value)),
+                        None => self.builder.build_return(None),
+                    });
+                self.resume_insertion(saved);
+                result.map_err(|e| e.to_string())?;
+                wrapper
+            }
+        };
+        self.build_closure(wrapper, self.context.ptr_type(AddressSpace::default()).const_null())
+    }
+
+    // Closure conversion: the body becomes a function `lambda.N(env, params...)` over an
+    // environment holding the variables it uses. The environment lives in the creating
+    // frame and holds the variables' addresses, so captures are by reference as in the
+    // interpreter. A closure returned from that frame outlives it instead: its environment
+    // is a heap copy of the captured values, owned by the closure for the rest of the run,
+    // and so it may not capture `mut` variables.
+    fn compile_lambda(
+        &mut self,
+        parameters: &[Parameter],
+        return_type: &Option<Type>,
+        body: &ASTNode,
+        loc: &Loc,
+    ) -> Result<BasicValueEnum<'ctx>, CodegenError> {
+        let mut captures = Vec::new();
+        self.collect_captures(body, parameters, &mut captures);
+        let by_value = self.closures_escape;
+        if by_value {
+            if let Some((name, _)) = captures.iter().find(|(name, _)| self.mutable_variables.contains(name)) {
+                return Err(format!(
+                    "Lambda at {} is returned from the function that creates it and cannot capture the mutable variable '{}'.",
+                    loc, name
+                ).into());
+            }
+        }
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let capture_types: Vec<BasicTypeEnum<'ctx>> = captures.iter()
+            .map(|(_, ty)| if by_value { *ty } else { ptr_type.into() })
+            .collect();
+        let env_type = self.context.struct_type(&capture_types, false);
+        let escapes = matches!(return_type, Some(Type::Function(..))) || matches!(body, ASTNode::Lambda { .. });
+        let param_types: Vec<BasicMetadataTypeEnum<'ctx>> = parameters.iter().map(|p| self.map_type(&p.param_type).into()).collect();
+
+        let result_type = match (return_type, body) {
+            (Some(Type::None), _) | (None, ASTNode::Block(_)) => None,
+            (Some(ty), _) => Some(self.map_type(ty)),
+            // The type of an unannotated expression body is found by compiling it once
+            // into a scratch function, which is then discarded with any lambdas it created.
+            (None, _) => {
+                let first_lambda = self.lambda_count;
+                let scratch = self.module.add_function("lambda.scratch", self.closure_fn_type(&param_types, None), None);
+                let body_type = self.emit_lambda_function(scratch, parameters, &captures, (env_type, by_value), escapes, body, loc);
+                unsafe { scratch.delete() };
+                for index in first_lambda..self.lambda_count {
+                    if let Some(nested) = self.module.get_function(&format!("lambda.{}", index)) {
+                        unsafe { nested.delete() };
+                    }
+                }
+                self.lambda_count = first_lambda;
+                body_type?
+            }
+        };
+
+        let function = self.module.add_function(
+            &format!("lambda.{}", self.lambda_count),
+            self.closure_fn_type(&param_types, result_type),
+            Some(Linkage::Internal),
+        );
+        self.lambda_count += 1;
+        self.emit_lambda_function(function, parameters, &captures, (env_type, by_value), escapes, body, loc)?;
+        if !function.verify(true) {
+            return Err(format!("Invalid lambda generated at {}", loc).into());
+        }
+
+        let env = if captures.is_empty() {
+            ptr_type.const_null()
+        } else {
+            let env = if by_value {
+                self.builder.build_malloc(env_type, "closure_env")
+            } else {
+                self.builder.build_alloca(env_type, "closure_env")
+            }.map_err(|e| e.to_string())?;
+            for (index, (name, ty)) in captures.iter().enumerate() {
+                let (alloca, _) = self.variables[name];
+                let value = if by_value {
+                    self.builder.build_load(*ty, alloca, name).map_err(|e| e.to_string())?
+                } else {
+                    alloca.as_basic_value_enum()
+                };
+                let field = self.builder.build_struct_gep(env_type, env, index as u32, name).map_err(|e| e.to_string())?;
+                self.builder.build_store(field, value).map_err(|e| e.to_string())?;
+            }
+            env
+        };
+        self.build_closure(function, env)
+    }
+
+    // Variables of the enclosing function that the lambda body refers to.
+    fn collect_captures(&self, node: &ASTNode, parameters: &[Parameter], captures: &mut Vec<(String, BasicTypeEnum<'ctx>)>) {
+        if let ASTNode::Identifier { name, .. } = node {
+            if let Some(&(_, ty)) = self.variables.get(name) {
+                if !parameters.iter().any(|p| p.name == *name) && !captures.iter().any(|(captured, _)| captured == name) {
+                    captures.push((name.clone(), ty));
+                }
+            }
+        }
+        for child in node.children() {
+            self.collect_captures(child, parameters, captures);
+        }
+    }
+
+    // Fill `function` with the lambda body and return the type of an expression body.
+    // `escapes` is whether the lambda returns a closure, which then outlives its frame.
+    #[allow(clippy::too_many_arguments)]
+    fn emit_lambda_function(
+        &mut self,
+        function: FunctionValue<'ctx>,
+        parameters: &[Parameter],
+        captures: &[(String, BasicTypeEnum<'ctx>)],
+        env: (StructType<'ctx>, bool),
+        escapes: bool,
+        body: &ASTNode,
+        loc: &Loc,
+    ) -> Result<Option<BasicTypeEnum<'ctx>>, CodegenError> {
+        let saved = self.suspend_insertion();
+        let outer_variables = std::mem::take(&mut self.variables);
+        let captured_mutable = captures.iter().map(|(name, _)| name).filter(|name| self.mutable_variables.contains(*name)).cloned().collect();
+        let outer_mutable_variables = std::mem::replace(&mut self.mutable_variables, captured_mutable);
+        let outer_closures_escape = std::mem::replace(&mut self.closures_escape, escapes);
+        let result = self.emit_lambda_body(function, parameters, captures, env, body, loc);
+        self.variables = outer_variables;
+        self.mutable_variables = outer_mutable_variables;
+        self.closures_escape = outer_closures_escape;
+        self.resume_insertion(saved);
+        result
+    }
+
+    // `env` is the environment's type and whether it holds the captured values
+    // themselves rather than their addresses.
+    fn emit_lambda_body(
+        &mut self,
+        function: FunctionValue<'ctx>,
+        parameters: &[Parameter],
+        captures: &[(String, BasicTypeEnum<'ctx>)],
+        (env_type, by_value): (StructType<'ctx>, bool),
+        body: &ASTNode,
+        loc: &Loc,
+    ) -> Result<Option<BasicTypeEnum<'ctx>>, CodegenError> {
+        self.builder.position_at_end(self.context.append_basic_block(function, "entry"));
+        let mut arguments = function.get_param_iter();
+        let env = arguments.next().ok_or("Lambda is missing its environment")?.into_pointer_value();
+        env.set_name("env");
+
+        for (index, (name, ty)) in captures.iter().enumerate() {
+            let field = self.builder.build_struct_gep(env_type, env, index as u32, name).map_err(|e| e.to_string())?;
+            let address = if by_value {
+                field
+            } else {
+                self.builder.build_load(self.context.ptr_type(AddressSpace::default()), field, name)
+                    .map_err(|e| e.to_string())?
+                    .into_pointer_value()
+            };
+            self.variables.insert(name.clone(), (address, *ty));
+        }
+        for (param, value) in parameters.iter().zip(arguments) {
+            value.set_name(&param.name);
+            let alloca = self.builder.build_alloca(value.get_type(), &param.name).map_err(|e| e.to_string())?;
+            self.builder.build_store(alloca, value).map_err(|e| e.to_string())?;
+            self.variables.insert(param.name.clone(), (alloca, value.get_type()));
+        }
+
+        let returns_value = function.get_type().get_return_type().is_some();
+        if let ASTNode::Block(_) = body {
+            self.compile_block(body, function)?;
+            if function.get_last_basic_block().and_then(|block| block.get_terminator()).is_none() {
+                if returns_value {
+                    return Err(format!("Lambda at {} has a non-void return type but does not end with a 'return' statement.", loc).into());
+                }
+                self.builder.build_return(None).map_err(|e| e.to_string())?;
+            }
+            return Ok(None);
+        }
+
+        let value = self.compile_expression(body, function)?;
+        if returns_value {
+            self.builder.build_return(Some(&value)).map_err(|e| e.to_string())?;
+        }
+        Ok(Some(value.get_type()))
+    }
+
     fn compile_function(
         &mut self,
         name: &str,
         params: &[Parameter],
         return_type_node: &Option<Type>,
         body: &ASTNode,
-    ) -> Result<inkwell::values::FunctionValue<'ctx>, String> {
+    ) -> Result<inkwell::values::FunctionValue<'ctx>, CodegenError> {
 
         eprintln!("[DEBUG compile_function] Function '{}', body type: {}", name, match body {
             ASTNode::Block(stmts) => format!("Block with {} statements", stmts.len()),
@@ -1040,9 +2320,11 @@ impl<'ctx> Compiler<'ctx> {
         for p in params {
             param_types.push(self.map_type(&p.param_type));
         }
+        // A `mut self` receiver is passed by address so the method changes the caller's value.
         let param_metadata_types: Vec<BasicMetadataTypeEnum<'ctx>> = param_types
             .iter()
-            .map(|&ty| ty.into())
+            .zip(params)
+            .map(|(&ty, p)| if p.is_mutable { self.context.ptr_type(AddressSpace::default()).into() } else { ty.into() })
             .collect();
         let param_types_slice = param_metadata_types.as_slice();
 
@@ -1127,11 +2409,19 @@ impl<'ctx> Compiler<'ctx> {
         }
 
         let old_variables = self.variables.clone();
+        let old_mutable_variables = self.mutable_variables.clone();
+        let old_closures_escape = std::mem::replace(&mut self.closures_escape, matches!(return_type_node, Some(Type::Function(..))));
 
         for (i, param) in function.get_param_iter().enumerate() {
             let ast_param = &params[i];
             let param_type = param_types[i];
             param.set_name(&ast_param.name);
+            if ast_param.is_mutable {
+                self.variables.insert(ast_param.name.clone(), (param.into_pointer_value(), param_type));
+                self.mutable_variables.insert(ast_param.name.clone());
+                continue;
+            }
+            self.mutable_variables.remove(&ast_param.name);
             let alloca = self.builder.build_alloca(param_type, &ast_param.name)
                 .map_err(|e| e.to_string())?;
             let _ = self.builder.build_store(alloca, param);
@@ -1160,6 +2450,9 @@ impl<'ctx> Compiler<'ctx> {
             self.variables.insert(ast_param.name.clone(), (alloca, param_type));
         }
 
+        for constraint in std::mem::take(&mut self.runtime_constraints) {
+            self.compile_runtime_constraint(name, &constraint, function)?;
+        }
         self.compile_block(body, function)?;
 
         if function.get_last_basic_block().unwrap().get_terminator().is_none() {
@@ -1171,7 +2464,7 @@ impl<'ctx> Compiler<'ctx> {
             if fn_returns_void {
                 self.builder.build_return(None).map_err(|e| e.to_string())?;
             } else {
-                return Err(format!("(Codegen Error) Function '{}' has a non-void return type but does not end with a 'return' statement.", name));
+                return Err(format!("Function '{}' has a non-void return type but does not end with a 'return' statement.", name).into());
             }
         }
 
@@ -1179,28 +2472,30 @@ impl<'ctx> Compiler<'ctx> {
         self.clear_debug_location();
 
         self.variables = old_variables;
+        self.mutable_variables = old_mutable_variables;
+        self.closures_escape = old_closures_escape;
 
         if function.verify(true) {
             Ok(function)
         } else {
-            Err(format!("(Codegen Error) Invalid function generated: {}", name))
+            Err(format!("Invalid function generated: {}", name).into())
         }
     }
 
 
-    fn compile_block(&mut self, node: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), String> {
+    fn compile_block(&mut self, node: &ASTNode, current_function: FunctionValue<'ctx>) -> Result<(), CodegenError> {
         if let ASTNode::Block(statements) = node {
             for stmt in statements {
                 self.compile_statement(stmt, current_function)?;
             }
             Ok(())
         } else {
-            Err(format!("(Codegen Error) Expected Block node, got: {:?}", node))
+            Err(format!("Expected Block node, got: {:?}", node).into())
         }
     }
 
 
-    fn compile_return(&mut self, value_node: &Option<Box<ASTNode>>, current_function: FunctionValue<'ctx>) -> Result<(), String> {
+    fn compile_return(&mut self, value_node: &Option<Box<ASTNode>>, current_function: FunctionValue<'ctx>) -> Result<(), CodegenError> {
         if let Some(expr) = value_node {
             let value = self.compile_expression(expr, current_function)?;
             self.builder.build_return(Some(&value)).map_err(|e| e.to_string())?;
@@ -1214,7 +2509,15 @@ impl<'ctx> Compiler<'ctx> {
         &mut self,
         node: &ASTNode,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<inkwell::values::BasicValueEnum<'ctx>, String> {
+    ) -> Result<inkwell::values::BasicValueEnum<'ctx>, CodegenError> {
+        self.compile_expression_node(node, current_function).map_err(|error| error.located(node.loc()))
+    }
+
+    fn compile_expression_node(
+        &mut self,
+        node: &ASTNode,
+        current_function: FunctionValue<'ctx>,
+    ) -> Result<inkwell::values::BasicValueEnum<'ctx>, CodegenError> {
         match node {
             ASTNode::IntLiteral(value) => {
                 Ok(self.context.i64_type().const_int(*value as u64, true).as_basic_value_enum())
@@ -1222,6 +2525,13 @@ impl<'ctx> Compiler<'ctx> {
             ASTNode::FloatLiteral(value) => {
                 Ok(self.context.f64_type().const_float(*value).as_basic_value_enum())
             }
+            ASTNode::ImaginaryLiteral(value) => {
+                let f64_type = self.context.f64_type();
+                Ok(self.complex_type().const_named_struct(&[
+                    f64_type.const_float(0.0).into(),
+                    f64_type.const_float(*value).into(),
+                ]).as_basic_value_enum())
+            }
             ASTNode::BoolLiteral(value) => {
                 let bool_val = self.context.bool_type().const_int(if *value { 1 } else { 0 }, false);
                 Ok(bool_val.as_basic_value_enum())
@@ -1240,8 +2550,30 @@ impl<'ctx> Compiler<'ctx> {
                             .map_err(|e| e.to_string())?;
                         Ok(loaded_value)
                     }
-                    None => Err(format!("Codegen Error at {}: Undefined variable '{}'", loc, name))
+                    None => match (self.generic_sizes.get(name), self.module.get_function(name)) {
+                        (Some(size), _) => Ok(self.context.i64_type().const_int(*size, false).as_basic_value_enum()),
+                        (None, Some(function)) => self.function_closure(function),
+                        (None, None) => Err(CodegenError::at(loc, format!("Undefined variable '{}'", name))),
+                    }
+                }
+            }
+            ASTNode::Lambda { parameters, return_type, body, loc } => self.compile_lambda(parameters, return_type, body, loc),
+
+            // A whole register measures to a runtime bit array (element i is qubit i).
+            ASTNode::Measure(qubit_expr) if matches!(&**qubit_expr, ASTNode::Identifier { .. }) => {
+                let state_ptr = self.compile_expression(qubit_expr, current_function)?;
+                if !state_ptr.is_pointer_value() {
+                    return Err("'measure' target must be a qubit or a quantum register.".into());
                 }
+
+                let call_site = self.builder.build_call(
+                    self.rt_measure_register,
+                    &[state_ptr.into()],
+                    "measure_register_result",
+                ).map_err(|e| e.to_string())?;
+
+                call_site.try_as_basic_value().left()
+                    .ok_or("Register measure call failed to return a bit array.".into())
             }
 
             ASTNode::Measure(qubit_expr) => {
@@ -1254,7 +2586,7 @@ impl<'ctx> Compiler<'ctx> {
                 ).map_err(|e| e.to_string())?;
 
                 call_site.try_as_basic_value().left()
-                    .ok_or("(Codegen Error) Measure call failed to return i32.".to_string())
+                    .ok_or("Measure call failed to return i32.".into())
             }
 
             ASTNode::Binary { operator, left, right, loc } => {
@@ -1275,16 +2607,22 @@ impl<'ctx> Compiler<'ctx> {
                                 return Ok(result.as_basic_value_enum());
                             }
                             _ => return Err(format!(
-                                "(Codegen Error) Vector operation {:?} not supported for Tensors at {}",
+                                "Vector operation {:?} not supported for Tensors at {}",
                                 operator, loc
-                            )),
+                            ).into()),
                         }
                     } else {
                         return Err(format!(
-                            "(Codegen Error) Mixed scalar/vector operation not supported at {}", loc
-                        ));
+                            "Mixed scalar/vector operation not supported at {}", loc
+                        ).into());
                     }
                 }
+                if self.user_type_of(left_val).is_some() || self.user_type_of(right_val).is_some() {
+                    return Err(CodegenError::unsupported(format!("Operator {:?} on struct and enum values is not lowered yet at {}", operator, loc)));
+                }
+                if left_val.is_struct_value() || right_val.is_struct_value() {
+                    return self.emit_complex_binary(operator, left_val, right_val, loc);
+                }
                 match (left_val, right_val) {
                     (BasicValueEnum::IntValue(left_int), BasicValueEnum::IntValue(right_int)) => {
                         let result = match operator {
@@ -1300,7 +2638,7 @@ impl<'ctx> Compiler<'ctx> {
                             BinaryOperator::GreaterEqual => self.builder.build_int_compare(IntPredicate::SGE, left_int, right_int, "ige"),
                             BinaryOperator::And => self.builder.build_and(left_int, right_int, "and"),
                             BinaryOperator::Or => self.builder.build_or(left_int, right_int, "or"),
-                            _ => return Err(format!("(Codegen STUB) Integer operator {:?} not yet implemented at {}", operator, loc))
+                            _ => return Err(CodegenError::unsupported(format!("Integer operator {:?} not yet implemented at {}", operator, loc)))
                         }.map_err(|e| e.to_string())?;
                         Ok(result.as_basic_value_enum())
                     }
@@ -1334,20 +2672,82 @@ impl<'ctx> Compiler<'ctx> {
                                     .map_err(|e| e.to_string())?;
                                 return Ok(result.as_basic_value_enum());
                             }
-                            _ => return Err(format!("(Codegen STUB) Float operator {:?} not yet implemented at {}", operator, loc))
+                            _ => return Err(CodegenError::unsupported(format!("Float operator {:?} not yet implemented at {}", operator, loc)))
                         }
                     }
-                    _ => Err(format!("(Codegen Error) Mismatched types in binary operation at {}", loc))
+                    _ => Err(format!("Mismatched types in binary operation at {}", loc).into())
                 }
             }
 
+            // The complex built-ins, unless a user function or variable of that name shadows them.
+            ASTNode::FunctionCall { callee, arguments, .. } if arguments.len() == 1 && matches!(
+                &**callee,
+                ASTNode::Identifier { name, .. } if matches!(name.as_str(), "abs" | "arg" | "conj" | "exp" | "real" | "imag")
+                    && self.module.get_function(name).is_none()
+                    && !self.generic_functions.contains_key(name)
+                    && !self.variables.contains_key(name)
+            ) => {
+                let name = match &**callee {
+                    ASTNode::Identifier { name, .. } => name.as_str(),
+                    _ => unreachable!(),
+                };
+                let argument = self.compile_expression(&arguments[0], current_function)?;
+                self.emit_complex_builtin(name, argument)
+            }
+            ASTNode::FunctionCall { callee, arguments, loc, .. } if self.is_user_constructor(callee) => {
+                self.compile_user_constructor(callee, arguments, loc, current_function)?
+                    .ok_or_else(|| format!("Invalid constructor call at {}", loc).into())
+            }
+            ASTNode::MemberAccess { object, member } => {
+                // A unit variant such as `Shape.Empty`.
+                if let ASTNode::Identifier { loc, .. } = &**object {
+                    if self.is_user_constructor(node) {
+                        if let Some(value) = self.compile_user_constructor(node, &[], loc, current_function)? {
+                            return Ok(value);
+                        }
+                    }
+                }
+                let value = self.compile_expression(object, current_function)?;
+                // Arrays and registers live in the runtime and are queried through it.
+                if value.is_pointer_value() && (member == "as_int" || member == "length") {
+                    let query = if member == "as_int" { self.rt_bits_as_int } else { self.rt_length };
+                    let call_site = self.builder.build_call(query, &[value.into()], member)
+                        .map_err(|e| e.to_string())?;
+                    return call_site.try_as_basic_value().left()
+                        .ok_or_else(|| format!("'.{}' call failed to return i64.", member).into());
+                }
+                match self.user_type_of(value) {
+                    Some(UserType::Struct(fields)) => {
+                        let index = fields.iter().position(|field| field.name == *member)
+                            .ok_or_else(|| format!("No field '{}' on struct value", member))?;
+                        self.builder.build_extract_value(value.into_struct_value(), index as u32, member)
+                            .map_err(|e| e.to_string().into())
+                    }
+                    _ => Err(CodegenError::unsupported(format!("Member access '.{}' is only lowered for struct values.", member))),
+                }
+            }
+            // Indexing a measured bit array; qubit accesses are handled by `apply` and `measure`.
+            ASTNode::ArrayAccess { array, index, loc } => {
+                let bits = self.compile_expression(array, current_function)?;
+                let index_val = self.compile_expression(index, current_function)?;
+                if !bits.is_pointer_value() || !index_val.is_int_value() {
+                    return Err(CodegenError::at(loc, "Only measured bit arrays can be indexed by an Int here."));
+                }
+                let call_site = self.builder.build_call(
+                    self.rt_bits_get,
+                    &[bits.into(), index_val.into()],
+                    "bit",
+                ).map_err(|e| e.to_string())?;
+                call_site.try_as_basic_value().left()
+                    .ok_or("Array access failed to return i64.".into())
+            }
             ASTNode::FunctionCall { callee, arguments, .. } => {
                 let call_site = self.compile_function_call(callee, arguments, current_function)?;
 
                 call_site.try_as_basic_value().left()
                     .ok_or_else(|| format!(
-                        "(Codegen Error) Function call used in expression does not return a value."
-                    ))
+                        "Function call used in expression does not return a value."
+                    ).into())
             }
             ASTNode::Unary { operator, operand } => {
                 let operand_val = self.compile_expression(operand, current_function)?;
@@ -1366,15 +2766,20 @@ impl<'ctx> Compiler<'ctx> {
                         } else if operand_val.is_float_value() {
                             self.builder.build_float_neg(operand_val.into_float_value(), "fneg")
                                 .map(|val| val.as_basic_value_enum())
+                        } else if operand_val.is_struct_value() {
+                            let (re, im) = self.complex_parts(operand_val)?;
+                            let re = self.builder.build_float_neg(re, "cneg_re").map_err(|e| e.to_string())?;
+                            let im = self.builder.build_float_neg(im, "cneg_im").map_err(|e| e.to_string())?;
+                            self.build_complex(re, im)
                         } else {
-                            return Err("(Codegen STUB) Unary '-' not supported for this type.".to_string());
+                            return Err(CodegenError::unsupported("Unary '-' not supported for this type."));
                         }
                     }
-                    _ => return Err(format!("(Codegen STUB) Unary operator {:?} not yet implemented.", operator))
+                    _ => return Err(CodegenError::unsupported(format!("Unary operator {:?} not yet implemented.", operator)))
                 }.map_err(|e| e.to_string())?;
                 Ok(result)
             }
-            _ => Err(format!("(Codegen STUB) Expression {:?} is not yet implemented.", node))
+            _ => Err(CodegenError::unsupported(format!("Expression {:?} is not yet implemented.", node)))
         }
     }
 
@@ -1392,6 +2797,22 @@ impl<'ctx> Compiler<'ctx> {
             Type::Int128 => self.context.i128_type().as_basic_type_enum(),
             Type::Float32 => self.context.f32_type().as_basic_type_enum(),
             Type::Float64 => self.context.f64_type().as_basic_type_enum(),
+            Type::Complex | Type::Complex64 | Type::Complex128 => self.complex_type().as_basic_type_enum(),
+            // Registers are passed as pointers to their runtime state, arrays as
+            // pointers to the runtime value holding them.
+            Type::QuantumRegister(_) | Type::GenericRegister(_) | Type::Array(_) => self.context.ptr_type(AddressSpace::default()).as_basic_type_enum(),
+            Type::Generic(name) if self.generic_types.contains_key(name) => self.map_type(&self.generic_types[name]),
+            Type::Function(params, return_type) => {
+                let params: Vec<BasicMetadataTypeEnum<'ctx>> = params.iter().map(|p| self.map_type(p).into()).collect();
+                let return_type = (**return_type != Type::None).then(|| self.map_type(return_type));
+                self.closure_type(self.closure_fn_type(&params, return_type)).as_basic_type_enum()
+            }
+            // Structs are laid out field by field; enums as `{ i32 tag, [N x i64] payload }`.
+            Type::Custom(name) if self.user_types.contains_key(name) => {
+                self.context.get_struct_type(name)
+                    .map(|t| t.as_basic_type_enum())
+                    .unwrap_or_else(|| self.context.i64_type().as_basic_type_enum())
+            }
             _ => {
                 println!("Warning: Codegen for type {:?} is not implemented, defaulting to i64.", ty);
                 self.context.i64_type().as_basic_type_enum()
@@ -1404,7 +2825,7 @@ impl<'ctx> Compiler<'ctx> {
         gate_expr: &ASTNode,
         arguments: &Vec<ASTNode>,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(), String> {
+    ) -> Result<(), CodegenError> {
 
         let (gate_name, param_ast_nodes, is_dagger, num_controls) =
             self.compile_gate_expression(gate_expr, current_function)?;
@@ -1422,7 +2843,7 @@ impl<'ctx> Compiler<'ctx> {
                     "param_f64"
                 ).map_err(|e| e.to_string())?
             } else {
-                return Err("(Codegen Error) Gate parameter must be a float or int.".to_string());
+                return Err("Gate parameter must be a float or int.".into());
             };
             gate_params_llvm.push(param_f64);
         }
@@ -1437,19 +2858,19 @@ impl<'ctx> Compiler<'ctx> {
                     let array_name = if let ASTNode::Identifier { name, .. } = &**array {
                         name
                     } else {
-                        return Err(format!("(Codegen Error) Qubit argument must be a simple register access, found: {:?}", array));
+                        return Err(format!("Qubit argument must be a simple register access, found: {:?}", array).into());
                     };
 
                     let (current_var_alloca, current_var_type) = self.variables.get(array_name)
-                        .ok_or_else(|| format!("(Codegen Error) Unknown quantum register '{}'", array_name))?;
+                        .ok_or_else(|| format!("Unknown quantum register '{}'", array_name))?;
 
                     if !current_var_type.is_pointer_type() {
-                         return Err(format!("(Codegen Error) Variable '{}' is not a quantum register.", array_name));
+                         return Err(format!("Variable '{}' is not a quantum register.", array_name).into());
                     }
 
                     if let Some(first_alloca) = register_alloca {
                         if first_alloca != *current_var_alloca {
-                            return Err(format!("(Codegen Error) 'apply' on different registers is not supported."));
+                            return Err(format!("'apply' on different registers is not supported.").into());
                         }
                     } else {
                         register_alloca = Some(*current_var_alloca);
@@ -1461,12 +2882,12 @@ impl<'ctx> Compiler<'ctx> {
                     } else {
                         let index_val = self.compile_expression(index, current_function)?;
                         if !index_val.is_int_value() {
-                            return Err(format!("(Codegen Error) Qubit index must be an integer at {}", loc));
+                            return Err(format!("Qubit index must be an integer at {}", loc).into());
                         }
-                        return Err(format!("(Codegen STUB) Qubit index must be an integer literal for now (at {})", loc));
+                        return Err(CodegenError::unsupported(format!("Qubit index must be an integer literal for now (at {})", loc)));
                     }
                 }
-                _ => return Err("(Codegen Error) 'apply' arguments must be qubit accesses (e.g., q[0]).".to_string())
+                _ => return Err("'apply' arguments must be qubit accesses (e.g., q[0]).".into())
             }
         }
 
@@ -1476,25 +2897,62 @@ impl<'ctx> Compiler<'ctx> {
                     .map_err(|e| e.to_string())?
                     .into_pointer_value()
             },
-            _ => return Err("(Codegen Error) 'apply' called with no qubit arguments.".to_string())
+            _ => return Err("'apply' called with no qubit arguments.".into())
         };
 
+        if gate_name == "reset" {
+            if is_dagger || num_controls > 0 {
+                return Err("'reset' is not unitary and cannot be daggered or controlled.".into());
+            }
+            for &index in &qubit_indices {
+                let index_val = self.context.i32_type().const_int(index as u64, false);
+                let _ = self.builder.build_call(
+                    self.rt_reset,
+                    &[state_ptr.into(), index_val.into()],
+                    "call_reset",
+                ).map_err(|e| e.to_string())?;
+            }
+            return Ok(());
+        }
+
         let gate_name_global = self.builder.build_global_string_ptr(&gate_name, "gate_name")
             .map_err(|e| e.to_string())?;
         let gate_name_ptr = gate_name_global.as_pointer_value();
 
         let is_dagger_int = self.context.i32_type().const_int(if is_dagger { 1 } else { 0 }, false);
 
-        let params_ptr = self.build_f64_array(&gate_params_llvm)?;
-
-        let num_params = self.context.i32_type().const_int(gate_params_llvm.len() as u64, false);
-
         let qubit_indices_ptr = self.build_i32_array(&qubit_indices)?;
 
         let num_qubits = self.context.i32_type().const_int(qubit_indices.len() as u64, false);
 
         let num_controls_i32 = self.context.i32_type().const_int(num_controls as u64, false);
 
+        if let Some(unitary) = self.custom_gates.get(&gate_name) {
+            let matrix_ptr = self.build_matrix_constant(unitary);
+            let num_gate_qubits = self.context.i32_type().const_int(unitary.num_qubits as u64, false);
+
+            let _ = self.builder.build_call(
+                self.rt_apply_unitary,
+                &[
+                    state_ptr.into(),
+                    gate_name_ptr.into(),
+                    matrix_ptr.into(),
+                    num_gate_qubits.into(),
+                    is_dagger_int.into(),
+                    qubit_indices_ptr.into(),
+                    num_qubits.into(),
+                    num_controls_i32.into(),
+                ],
+                "call_apply_unitary",
+            ).map_err(|e| e.to_string())?;
+
+            return Ok(());
+        }
+
+        let params_ptr = self.build_f64_array(&gate_params_llvm)?;
+
+        let num_params = self.context.i32_type().const_int(gate_params_llvm.len() as u64, false);
+
         let _ = self.builder.build_call(
             self.rt_apply_gate,
             &[
@@ -1517,17 +2975,17 @@ impl<'ctx> Compiler<'ctx> {
         &mut self,
         qubit_expr: &ASTNode,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(inkwell::values::PointerValue<'ctx>, inkwell::values::IntValue<'ctx>), String> {
+    ) -> Result<(inkwell::values::PointerValue<'ctx>, inkwell::values::IntValue<'ctx>), CodegenError> {
 
         if let ASTNode::ArrayAccess { array, index, loc } = qubit_expr {
             let array_name = if let ASTNode::Identifier { name, .. } = &**array {
                 name
             } else {
-                return Err(format!("(Codegen Error) Measure target must be a register access, found: {:?}", array));
+                return Err(format!("Measure target must be a register access, found: {:?}", array).into());
             };
 
             let (var_ptr, var_type) = self.variables.get(array_name)
-                .ok_or_else(|| format!("(Codegen Error) Unknown quantum register '{}'", array_name))?;
+                .ok_or_else(|| format!("Unknown quantum register '{}'", array_name))?;
 
             let state_ptr = self.builder.build_load(*var_type, *var_ptr, "load_measure_ptr")
                 .map_err(|e| e.to_string())?
@@ -1535,7 +2993,7 @@ impl<'ctx> Compiler<'ctx> {
 
             let index_val = self.compile_expression(index, current_function)?;
             if !index_val.is_int_value() {
-                return Err(format!("(Codegen Error) Qubit index must be an integer at {}", loc));
+                return Err(format!("Qubit index must be an integer at {}", loc).into());
             }
             let index_i32 = self.builder.build_int_truncate(
                 index_val.into_int_value(),
@@ -1545,7 +3003,7 @@ impl<'ctx> Compiler<'ctx> {
 
             Ok((state_ptr, index_i32))
         } else {
-            Err("(Codegen Error) 'measure' target must be a qubit access (e.g., q[0]).".to_string())
+            Err("'measure' target must be a qubit access (e.g., q[0]).".into())
         }
     }
 
@@ -1553,7 +3011,7 @@ impl<'ctx> Compiler<'ctx> {
         &mut self,
         gate_expr: &ASTNode,
         current_function: FunctionValue<'ctx>,
-    ) -> Result<(String, Vec<ASTNode>, bool, i32), String> {
+    ) -> Result<(String, Vec<ASTNode>, bool, i32), CodegenError> {
 
         match gate_expr {
             ASTNode::Gate { name, .. } => {
@@ -1587,16 +3045,16 @@ impl<'ctx> Compiler<'ctx> {
             }
 
             _ => Err(format!(
-                "(Codegen Error) This expression is not a valid gate: {:?}",
+                "This expression is not a valid gate: {:?}",
                 gate_expr
-            )),
+            ).into()),
         }
     }
 
     fn build_f64_array(
         &self,
         values: &[inkwell::values::FloatValue<'ctx>],
-    ) -> Result<inkwell::values::PointerValue<'ctx>, String> {
+    ) -> Result<inkwell::values::PointerValue<'ctx>, CodegenError> {
         let f64_type = self.context.f64_type();
         let f64_array_type = f64_type.array_type(values.len() as u32);
 
@@ -1621,13 +3079,28 @@ impl<'ctx> Compiler<'ctx> {
             array_alloca,
             self.context.ptr_type(AddressSpace::default()),
             "params_ptr"
-        ).map_err(|e| e.to_string())
+        ).map_err(|e| e.to_string().into())
+    }
+
+    // Read-only global holding a gate matrix as interleaved (re, im) doubles.
+    fn build_matrix_constant(&self, unitary: &Unitary) -> inkwell::values::PointerValue<'ctx> {
+        let f64_type = self.context.f64_type();
+        let values: Vec<inkwell::values::FloatValue<'ctx>> = unitary.data.iter()
+            .flat_map(|entry| [f64_type.const_float(entry.re), f64_type.const_float(entry.im)])
+            .collect();
+        let array_type = f64_type.array_type(values.len() as u32);
+
+        let global = self.module.add_global(array_type, None, "gate_matrix");
+        global.set_initializer(&f64_type.const_array(&values));
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        global.as_pointer_value()
     }
 
     fn build_i32_array(
         &self,
         values: &[i32],
-    ) -> Result<inkwell::values::PointerValue<'ctx>, String> {
+    ) -> Result<inkwell::values::PointerValue<'ctx>, CodegenError> {
         let i32_type = self.context.i32_type();
         let i32_array_type = i32_type.array_type(values.len() as u32);
 
@@ -1652,7 +3125,7 @@ impl<'ctx> Compiler<'ctx> {
             array_alloca,
             self.context.ptr_type(AddressSpace::default()),
             "indices_ptr"
-        ).map_err(|e| e.to_string())
+        ).map_err(|e| e.to_string().into())
     }
 
 }
@@ -0,0 +1,362 @@
+// src/diagnostics.rs
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use serde::Serialize;
+use crate::lexer::token::TokenWithLocation;
+use crate::parser::ast::Loc;
+
+pub const LEXER_ERROR: &str = "E0001";
+pub const FILE_ERROR: &str = "E0002";
+pub const SYNTAX_ERROR: &str = "E0100";
+pub const TYPE_ERROR: &str = "E0200";
+pub const UNDEFINED_NAME: &str = "E0201";
+pub const MUTABILITY_ERROR: &str = "E0202";
+pub const QUBIT_OWNERSHIP: &str = "E0203";
+pub const NOT_UNITARY: &str = "E0204";
+pub const RUNTIME_ERROR: &str = "E0300";
+pub const CODEGEN_ERROR: &str = "E0400";
+pub const CODEGEN_UNSUPPORTED: &str = "E0401";
+pub const NON_EXHAUSTIVE_MATCH: &str = "W0200";
+pub const MPS_TRUNCATION: &str = "W0300";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Source from `start` up to, but not including, `end`. Columns are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: Loc,
+    pub end: Loc,
+}
+
+impl Span {
+    pub fn of_token(token: &TokenWithLocation) -> Self {
+        Span::from(Loc { line: token.line, column: token.column, length: token.length })
+    }
+}
+
+/// The columns a location covers, and at least one.
+impl From<Loc> for Span {
+    fn from(loc: Loc) -> Self {
+        Span { start: loc, end: Loc { line: loc.line, column: loc.column + loc.length.max(1), length: 0 } }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A problem found in a program, with where it is and what might fix it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(code, message) }
+    }
+
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestions.push(suggestion.into());
+        self
+    }
+
+    /// Render in the style of `rustc`: a header, then each labelled source line with
+    /// carets (`^`) under the primary span and dashes under secondary ones.
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let mut labels: Vec<(&Label, char)> = self.primary.iter().map(|label| (label, '^'))
+            .chain(self.secondary.iter().map(|label| (label, '-')))
+            .filter(|(label, _)| label.span.start.line >= 1 && label.span.start.line <= lines.len())
+            .collect();
+        labels.sort_by_key(|(label, _)| (label.span.start.line, label.span.start.column));
+        let width = labels.iter().map(|(label, _)| label.span.start.line.to_string().len()).max().unwrap_or(1);
+        let gutter = " ".repeat(width);
+
+        if let Some(primary) = &self.primary {
+            out.push_str(&format!("{}--> {}:{}:{}\n", gutter, filename, primary.span.start.line, primary.span.start.column));
+        }
+        if !labels.is_empty() {
+            out.push_str(&format!("{} |\n", gutter));
+        }
+        for (label, marker) in &labels {
+            let Span { start, end } = label.span;
+            let line = lines[start.line - 1];
+            let line_length = line.chars().count();
+            // Keep tabs in the indentation so the markers line up with the source.
+            let indent: String = line.chars().take(start.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let last = if end.line == start.line { end.column } else { line_length + 1 };
+            let count = last.saturating_sub(start.column).max(1);
+            out.push_str(&format!("{:>width$} | {}\n", start.line, line, width = width));
+            let text = if label.message.is_empty() { String::new() } else { format!(" {}", label.message) };
+            out.push_str(&format!("{} | {}{}{}\n", gutter, indent, marker.to_string().repeat(count), text));
+        }
+        if !labels.is_empty() && (!self.notes.is_empty() || !self.suggestions.is_empty()) {
+            out.push_str(&format!("{} |\n", gutter));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        for suggestion in &self.suggestions {
+            out.push_str(&format!("{} = help: {}\n", gutter, suggestion));
+        }
+        out
+    }
+
+    /// One line of JSON for `--message-format=json`.
+    pub fn to_json(&self, filename: &str) -> String {
+        let message = JsonMessage::Diagnostic {
+            file: filename,
+            severity: self.severity,
+            code: self.code,
+            message: &self.message,
+            range: self.primary.as_ref().map(|label| label.span.into()),
+            label: self.primary.as_ref().map_or("", |label| &label.message),
+            secondary: self.secondary.iter()
+                .map(|label| JsonLabel { range: label.span.into(), message: &label.message })
+                .collect(),
+            notes: &self.notes,
+            suggestions: &self.suggestions,
+        };
+        serde_json::to_string(&message).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl MessageFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("Unknown message format '{}' (expected human or json)", name)),
+        }
+    }
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+// Diagnostics reported so far, for the closing summary.
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_message_format(format: MessageFormat) {
+    JSON_OUTPUT.store(format == MessageFormat::Json, Ordering::Relaxed);
+}
+
+pub fn message_format() -> MessageFormat {
+    if JSON_OUTPUT.load(Ordering::Relaxed) { MessageFormat::Json } else { MessageFormat::Human }
+}
+
+/// Print every diagnostic to stderr, rendered or as JSON lines depending on the
+/// message format; fails with a summary if any of them is an error.
+pub fn report(diagnostics: &[Diagnostic], source: &str, filename: &str) -> Result<(), String> {
+    for diagnostic in diagnostics {
+        let counter = if diagnostic.severity == Severity::Error { &ERRORS } else { &WARNINGS };
+        counter.fetch_add(1, Ordering::Relaxed);
+        match message_format() {
+            MessageFormat::Human => eprintln!("{}", diagnostic.render(source, filename)),
+            MessageFormat::Json => eprintln!("{}", diagnostic.to_json(filename)),
+        }
+    }
+    match diagnostics.iter().filter(|d| d.severity == Severity::Error).count() {
+        0 => Ok(()),
+        1 => Err(format!("1 error found in '{}'", filename)),
+        errors => Err(format!("{} errors found in '{}'", errors, filename)),
+    }
+}
+
+/// The object closing a JSON message stream, counting everything reported so far.
+/// Human output has no summary line of its own here.
+pub fn print_summary() {
+    if message_format() == MessageFormat::Json {
+        let summary = JsonMessage::Summary {
+            errors: ERRORS.load(Ordering::Relaxed),
+            warnings: WARNINGS.load(Ordering::Relaxed),
+        };
+        eprintln!("{}", serde_json::to_string(&summary).unwrap_or_default());
+    }
+}
+
+/// The closest of `candidates` to `name`, if it is a plausible typo.
+pub fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonMessage<'a> {
+    Diagnostic {
+        file: &'a str,
+        severity: Severity,
+        code: &'a str,
+        message: &'a str,
+        range: Option<JsonRange>,
+        #[serde(skip_serializing_if = "str::is_empty")]
+        label: &'a str,
+        secondary: Vec<JsonLabel<'a>>,
+        notes: &'a [String],
+        suggestions: &'a [String],
+    },
+    Summary {
+        errors: usize,
+        warnings: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct JsonLabel<'a> {
+    range: JsonRange,
+    message: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonRange {
+    start: JsonPosition,
+    end: JsonPosition,
+}
+
+#[derive(Serialize)]
+struct JsonPosition {
+    line: usize,
+    column: usize,
+}
+
+impl From<Span> for JsonRange {
+    fn from(span: Span) -> Self {
+        JsonRange {
+            start: JsonPosition { line: span.start.line, column: span.start.column },
+            end: JsonPosition { line: span.end.line, column: span.end.column },
+        }
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_cover_their_token() {
+        let span = Span::from(Loc { line: 3, column: 5, length: 6 });
+        assert_eq!((span.start.line, span.start.column), (3, 5));
+        assert_eq!((span.end.line, span.end.column), (3, 11));
+
+        let empty = Span::from(Loc { line: 7, column: 1, length: 0 });
+        assert_eq!(empty.end.column, 2);
+    }
+
+    #[test]
+    fn test_render_underlines_the_token() {
+        let token = TokenWithLocation::new(crate::lexer::token::Token::Identifier("total".to_string()), 2, 9, 5);
+        let diagnostic = Diagnostic::error(UNDEFINED_NAME, "Undefined variable 'total'")
+            .with_primary(Span::of_token(&token), "not found in this scope")
+            .with_suggestion("a similar name exists: 'totals'");
+        let rendered = diagnostic.render("let a = 1\nlet b = total + 1\n", "sum.qc");
+        let expected = "error[E0201]: Undefined variable 'total'\n\
+                        \x20--> sum.qc:2:9\n\
+                        \x20 |\n\
+                        2 | let b = total + 1\n\
+                        \x20 |         ^^^^^ not found in this scope\n\
+                        \x20 |\n\
+                        \x20 = help: a similar name exists: 'totals'\n";
+        assert_eq!(rendered, expected);
+        assert_eq!(closest_name("totl", ["total", "count"]), Some("total"));
+    }
+
+    #[test]
+    fn test_json_has_file_range_and_code() {
+        let diagnostic = Diagnostic::error(MUTABILITY_ERROR, "Cannot assign to immutable variable 'c'.")
+            .with_primary(Span::from(Loc { line: 4, column: 1, length: 1 }), "")
+            .with_suggestion("declare it with 'mut' to allow assignment");
+        let json: serde_json::Value = serde_json::from_str(&diagnostic.to_json("main.qc")).unwrap();
+        assert_eq!(json["type"], "diagnostic");
+        assert_eq!(json["file"], "main.qc");
+        assert_eq!(json["severity"], "error");
+        assert_eq!(json["code"], "E0202");
+        assert_eq!(json["message"], "Cannot assign to immutable variable 'c'.");
+        assert_eq!(json["range"]["start"], serde_json::json!({ "line": 4, "column": 1 }));
+        assert_eq!(json["range"]["end"], serde_json::json!({ "line": 4, "column": 2 }));
+        assert_eq!(json["suggestions"][0], "declare it with 'mut' to allow assignment");
+        assert!(json.get("label").is_none());
+
+        let plain: serde_json::Value = serde_json::from_str(&Diagnostic::warning(MPS_TRUNCATION, "lossy").to_json("main.qc")).unwrap();
+        assert_eq!(plain["severity"], "warning");
+        assert!(plain["range"].is_null());
+    }
+}
@@ -2,7 +2,49 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::cell::RefCell;
-use crate::parser::ast::Parameter;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::parser::ast::{Loc, Parameter};
+use crate::simulator::{Bra, Ket, Observable, QuantumState, Unitary, C64};
+
+// The one RNG behind measurement, `maybe`, `sample` and the runtime C API,
+// together with the seed it was last seeded from.
+static RNG: Mutex<Option<(u64, StdRng)>> = Mutex::new(None);
+
+/// Reseed the shared RNG so that a run can be replayed.
+pub fn set_seed(seed: u64) {
+    let mut guard = RNG.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = Some((seed, StdRng::seed_from_u64(seed)));
+}
+
+/// Seed of the shared RNG; a random one is picked on first use.
+pub fn current_seed() -> u64 {
+    let mut guard = RNG.lock().unwrap_or_else(|e| e.into_inner());
+    guard.get_or_insert_with(random_seeded).0
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut guard = RNG.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut guard.get_or_insert_with(random_seeded).1)
+}
+
+fn random_seeded() -> (u64, StdRng) {
+    let seed = rand::random();
+    (seed, StdRng::seed_from_u64(seed))
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silence `print`, `echo` and `debug_state`, e.g. while repeating shots.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone)]
 pub struct GateDefinition {
@@ -17,29 +59,88 @@ pub struct GateDefinition {
    
     pub register_size: usize,
    
-    pub state_rc: Rc<RefCell<HashMap<usize, (f64, f64)>>>,
+    pub state_rc: Rc<RefCell<QuantumState>>,
+
+    // Matrix of a user-defined gate; built-in gates are looked up by name.
+    pub matrix: Option<Rc<Unitary>>,
+}
+
+/// How a register or qubit reaches its quantum state. When an `apply` merges two
+/// states, the absorbed one is replaced by a forward to the merged state and the
+/// index its qubits start at there, so every copy of the handle follows the merge
+/// wherever it is held.
+#[derive(Debug, Clone)]
+pub struct StateHandle(Rc<RefCell<StateSlot>>);
+
+#[derive(Debug)]
+enum StateSlot {
+    Owned(Rc<RefCell<QuantumState>>),
+    Forward { into: StateHandle, offset: usize },
+}
+
+impl StateHandle {
+    pub fn new(state: QuantumState) -> Self {
+        StateHandle(Rc::new(RefCell::new(StateSlot::Owned(Rc::new(RefCell::new(state))))))
+    }
+
+    /// The live state, and the index this handle's qubit 0 has in it.
+    pub fn resolve(&self) -> (Rc<RefCell<QuantumState>>, usize) {
+        let (_, state, shift) = self.root();
+        (state, shift)
+    }
+
+    /// Tensor `other`'s state onto the high-order end of this one and forward
+    /// `other` (and everything sharing its state) to the result.
+    pub fn absorb(&self, other: &StateHandle) -> Result<(), String> {
+        let (root, state, _) = self.root();
+        let (other_root, other_state, _) = other.root();
+        if Rc::ptr_eq(&state, &other_state) {
+            return Ok(());
+        }
+        let offset = state.borrow().num_qubits();
+        let merged = other_state.borrow().tensor(&state.borrow())?;
+        *state.borrow_mut() = merged;
+        *other_root.0.borrow_mut() = StateSlot::Forward { into: root, offset };
+        Ok(())
+    }
+
+    // The handle that owns the state, the state, and the accumulated index shift.
+    fn root(&self) -> (StateHandle, Rc<RefCell<QuantumState>>, usize) {
+        let mut handle = self.clone();
+        let mut shift = 0;
+        loop {
+            let next = match &*handle.0.borrow() {
+                StateSlot::Owned(state) => return (handle.clone(), state.clone(), shift),
+                StateSlot::Forward { into, offset } => {
+                    shift += offset;
+                    into.clone()
+                }
+            };
+            handle = next;
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum RuntimeValue {
     Int(i64),
     Float(f64),
+    Complex(C64),
     String(String),
     Bool(bool),
     None,
     
     Qubit {
         
-        state: Rc<RefCell<HashMap<usize, (f64, f64)>>>, 
+        state: StateHandle, 
         index: usize,
-        size: usize,
     },
     
    
     QuantumRegister {
         size: usize,
         
-        state: Rc<RefCell<HashMap<usize, (f64, f64)>>>, 
+        state: StateHandle, 
     },
     
     Gate {
@@ -48,22 +149,68 @@ pub enum RuntimeValue {
         is_dagger: bool,
        
         num_controls: usize,
+
+        matrix: Option<Rc<Unitary>>,
     },
 
     Register(Vec<Rc<RefCell<RuntimeValue>>>),
     Dict(HashMap<String, Rc<RefCell<RuntimeValue>>>),
     Range(Vec<i64>),
-    KetState(String),
+    Ket(Ket),
+    Bra(Bra),
+    Observable(Observable),
+
+    // Instances of user-defined types. Struct fields are cells so that field
+    // assignment reaches them in place; binding or passing a struct copies it with
+    // `deep_copy`, which gives structs value semantics.
+    Struct {
+        name: String,
+        fields: Vec<(String, Rc<RefCell<RuntimeValue>>)>,
+    },
+    EnumValue {
+        enum_name: String,
+        variant: String,
+        payload: Vec<RuntimeValue>,
+    },
+    // The declared types themselves: a struct is called to construct it, and an
+    // enum hands out its variants through member access.
+    StructType {
+        name: String,
+        fields: Vec<String>,
+    },
+    EnumType {
+        name: String,
+        variants: Vec<(String, usize)>,
+    },
+    VariantConstructor {
+        enum_name: String,
+        variant: String,
+        arity: usize,
+    },
+    // A raised or caught error. `loc` is filled in by the `Throw` that raises it,
+    // or taken from the `RuntimeError::Failure` of a built-in failure.
+    Error {
+        kind: String,
+        message: String,
+        loc: Option<Loc>,
+    },
     
     
     Function {
         parameters: Vec<Parameter>,
         body: Box<crate::parser::ast::ASTNode>,
         env: Rc<RefCell<Environment>>,
+        // Constraints of a generic declaration, checked on every call.
+        where_clause: Vec<crate::parser::ast::ASTNode>,
     },
     
    
     BuiltinFunction(String),
+    // `obj.method`: a method from an impl block with its receiver filled in.
+    BoundMethod {
+        receiver: Box<RuntimeValue>,
+        method: Box<RuntimeValue>,
+    },
     Module(Rc<RefCell<Environment>>),
     
     ReturnValue(Box<RuntimeValue>),
@@ -78,10 +225,30 @@ pub enum RuntimeValue {
 
 impl RuntimeValue {
 
+    /// A copy of a struct (or an enum carrying one) with fresh field cells, so that
+    /// writes through the copy do not reach the original. Other values are cloned.
+    pub fn deep_copy(&self) -> RuntimeValue {
+        match self {
+            RuntimeValue::Struct { name, fields } => RuntimeValue::Struct {
+                name: name.clone(),
+                fields: fields.iter()
+                    .map(|(field, value)| (field.clone(), Rc::new(RefCell::new(value.borrow().deep_copy()))))
+                    .collect(),
+            },
+            RuntimeValue::EnumValue { enum_name, variant, payload } => RuntimeValue::EnumValue {
+                enum_name: enum_name.clone(),
+                variant: variant.clone(),
+                payload: payload.iter().map(RuntimeValue::deep_copy).collect(),
+            },
+            other => other.clone(),
+        }
+    }
+
     pub fn type_name(&self) -> &str {
         match self {
             RuntimeValue::Int(_) => "int",
             RuntimeValue::Float(_) => "float",
+            RuntimeValue::Complex(_) => "complex",
             RuntimeValue::String(_) => "string",
             RuntimeValue::Bool(_) => "bool",
             RuntimeValue::None => "none",
@@ -91,9 +258,17 @@ impl RuntimeValue {
             RuntimeValue::Register(_) => "array",
             RuntimeValue::Dict(_) => "dict",
             RuntimeValue::Range(_) => "range",
-            RuntimeValue::KetState(_) => "ket_state",
+            RuntimeValue::Ket(_) => "ket",
+            RuntimeValue::Bra(_) => "bra",
+            RuntimeValue::Observable(_) => "observable",
+            RuntimeValue::Struct { name, .. } => name,
+            RuntimeValue::EnumValue { enum_name, .. } => enum_name,
+            RuntimeValue::StructType { .. } | RuntimeValue::EnumType { .. } => "type",
+            RuntimeValue::VariantConstructor { .. } => "variant_constructor",
+            RuntimeValue::Error { .. } => "error",
             RuntimeValue::Function { .. } => "function",
             RuntimeValue::BuiltinFunction(_) => "builtin_function",
+            RuntimeValue::BoundMethod { .. } => "method",
             RuntimeValue::Module(_) => "module",
             RuntimeValue::ReturnValue(_) => "return_value",
             RuntimeValue::Break => "break",
@@ -109,12 +284,16 @@ impl std::fmt::Display for RuntimeValue {
         match self {
             RuntimeValue::Int(n) => write!(f, "{}", n),
             RuntimeValue::Float(n) => write!(f, "{}", n),
+            RuntimeValue::Complex(z) if z.im < 0.0 => write!(f, "{}-{}i", z.re, -z.im),
+            RuntimeValue::Complex(z) => write!(f, "{}+{}i", z.re, z.im),
             RuntimeValue::String(s) => write!(f, "{}", s),
             RuntimeValue::Bool(b) => write!(f, "{}", b),
             RuntimeValue::None => write!(f, "None"),
             
-            RuntimeValue::Qubit { index, size, .. } => {
-                write!(f, "<Qubit {} of {}>", index, size)
+            RuntimeValue::Qubit { state, index } => {
+                let (state, shift) = state.resolve();
+                let size = state.borrow().num_qubits();
+                write!(f, "<Qubit {} of {}>", index + shift, size)
             }
            
             RuntimeValue::QuantumRegister { size, .. } => {
@@ -129,6 +308,21 @@ impl std::fmt::Display for RuntimeValue {
                 write!(f, "{{{}}}", parts.join(", "))
             }
             RuntimeValue::Module(_) => write!(f, "<Module>"),
+            RuntimeValue::Observable(observable) => write!(f, "<Observable {}>", observable),
+            RuntimeValue::Ket(ket) => write!(f, "{}", ket),
+            RuntimeValue::Bra(bra) => write!(f, "{}", bra),
+            RuntimeValue::Struct { name, fields } => {
+                let parts: Vec<String> = fields.iter().map(|(field, v)| format!("{}: {}", field, v.borrow())).collect();
+                write!(f, "{}({})", name, parts.join(", "))
+            }
+            RuntimeValue::EnumValue { enum_name, variant, payload } if payload.is_empty() => write!(f, "{}.{}", enum_name, variant),
+            RuntimeValue::EnumValue { enum_name, variant, payload } => {
+                let parts: Vec<String> = payload.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}.{}({})", enum_name, variant, parts.join(", "))
+            }
+            RuntimeValue::StructType { name, .. } | RuntimeValue::EnumType { name, .. } => write!(f, "<Type {}>", name),
+            RuntimeValue::Error { kind, message, loc: Some(loc) } => write!(f, "{} at {}: {}", kind, loc, message),
+            RuntimeValue::Error { kind, message, loc: None } => write!(f, "{}: {}", kind, message),
             _ => write!(f, "{:?}", self), 
         }
     }
@@ -159,6 +353,18 @@ impl Environment {
         env.set("len".to_string(), RuntimeValue::BuiltinFunction("len".to_string()));
         env.set("debug_state".to_string(), RuntimeValue::BuiltinFunction("debug_state".to_string()));
         env.set("assert".to_string(), RuntimeValue::BuiltinFunction("assert".to_string()));
+        env.set("set_seed".to_string(), RuntimeValue::BuiltinFunction("set_seed".to_string()));
+        env.set("observable".to_string(), RuntimeValue::BuiltinFunction("observable".to_string()));
+        env.set("expectation".to_string(), RuntimeValue::BuiltinFunction("expectation".to_string()));
+        env.set("variance".to_string(), RuntimeValue::BuiltinFunction("variance".to_string()));
+        env.set("amplitude".to_string(), RuntimeValue::BuiltinFunction("amplitude".to_string()));
+        env.set("probabilities".to_string(), RuntimeValue::BuiltinFunction("probabilities".to_string()));
+        env.set("fidelity".to_string(), RuntimeValue::BuiltinFunction("fidelity".to_string()));
+        env.set("reduced_density_matrix".to_string(), RuntimeValue::BuiltinFunction("reduced_density_matrix".to_string()));
+        env.set("Error".to_string(), RuntimeValue::BuiltinFunction("Error".to_string()));
+        for name in ["abs", "arg", "conj", "exp", "real", "imag"] {
+            env.set(name.to_string(), RuntimeValue::BuiltinFunction(name.to_string()));
+        }
         env
     }
 
@@ -1,8 +1,9 @@
 // src/evaluator/mod.rs
 use std::collections::HashMap;
-use crate::parser::ast::ASTNode;
+use crate::parser::ast::{ASTNode, CatchClause, MatchCase, Pattern, Type};
 use crate::parser::ast::Loc;
-use crate::environment::{Environment, RuntimeValue, GateDefinition};
+use crate::diagnostics::{Diagnostic, Span, RUNTIME_ERROR};
+use crate::environment::{self, Environment, RuntimeValue, GateDefinition, StateHandle};
 use std::rc::Rc;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
@@ -13,15 +14,81 @@ use crate::parser::ast::ImportSpec;
 use crate::parser::ast::ImportPath;
 use std::path::PathBuf;
 use crate::parser::ast::BinaryOperator;
+use crate::parser::ast::UnaryOperator;
+use crate::simulator::{self, Bra, Ket, Observable, QuantumState, Unitary};
 
 use num_complex::Complex;
 type C64 = Complex<f64>;
 
 
+thread_local! {
+    // Control qubits of the `controlled(circuit)` applications being evaluated.
+    // Every gate applied inside such a circuit is controlled on all of them.
+    static CIRCUIT_CONTROLS: RefCell<Vec<RuntimeValue>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Why evaluation stopped: a failure inside the interpreter, or a value raised by
+/// `Throw`. `Try` handlers and diagnostics read the kind and location from it directly.
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    /// Caught as `RuntimeError`; the location is that of the innermost node that failed.
+    Failure { message: String, loc: Option<Loc> },
+    /// Always a `RuntimeValue::Error`.
+    Thrown(RuntimeValue),
+}
+
+impl RuntimeError {
+    /// The error as a handler binds it.
+    pub fn value(&self) -> RuntimeValue {
+        match self {
+            RuntimeError::Failure { message, loc } => RuntimeValue::Error {
+                kind: "RuntimeError".to_string(),
+                message: message.clone(),
+                loc: *loc,
+            },
+            RuntimeError::Thrown(value) => value.clone(),
+        }
+    }
+
+    fn at(loc: &Loc, message: impl Into<String>) -> Self {
+        RuntimeError::Failure { message: message.into(), loc: Some(*loc) }
+    }
+
+    // Failures raised below `node` without a location of their own happened there.
+    fn located(self, loc: Option<Loc>) -> Self {
+        match self {
+            RuntimeError::Failure { message, loc: None } => RuntimeError::Failure { message, loc },
+            other => other,
+        }
+    }
+}
+
+impl From<String> for RuntimeError {
+    fn from(message: String) -> Self {
+        RuntimeError::Failure { message, loc: None }
+    }
+}
+
+impl From<RuntimeError> for String {
+    fn from(error: RuntimeError) -> Self {
+        error.to_string()
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::Failure { message, loc: Some(loc) } => write!(f, "Runtime Error at {}: {}", loc, message),
+            RuntimeError::Failure { message, loc: None } => write!(f, "Runtime Error: {}", message),
+            RuntimeError::Thrown(value) => write!(f, "{}", value),
+        }
+    }
+}
+
 pub struct Evaluator;
 
 impl Evaluator {
-    pub fn evaluate_program(program: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
+    pub fn evaluate_program(program: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
         if let ASTNode::Program(statements) = program {
             let mut last_result = RuntimeValue::None;
             for stmt in statements {
@@ -29,17 +96,40 @@ impl Evaluator {
             }
             Ok(last_result)
         } else {
-            Err("Expected ASTNode::Program at root.".to_string())
+            Err("Expected ASTNode::Program at root.".to_string().into())
         }
     }
 
 
-    pub fn evaluate(node: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
+    /// A diagnostic for the error that stopped a program.
+    pub fn diagnostic(error: &RuntimeError) -> Diagnostic {
+        let (message, loc) = match error {
+            RuntimeError::Failure { message, loc } => (message.clone(), *loc),
+            RuntimeError::Thrown(RuntimeValue::Error { kind, message, loc }) => (format!("{}: {}", kind, message), *loc),
+            RuntimeError::Thrown(value) => (value.to_string(), None),
+        };
+        let diagnostic = Diagnostic::error(RUNTIME_ERROR, message);
+        let diagnostic = match loc {
+            Some(loc) => diagnostic.with_primary(Span::from(loc), ""),
+            None => diagnostic,
+        };
+        match error {
+            RuntimeError::Thrown(_) => diagnostic.with_note("raised by 'Throw' and not handled by any 'Except' clause"),
+            RuntimeError::Failure { .. } => diagnostic,
+        }
+    }
+
+    pub fn evaluate(node: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
+        Self::evaluate_node(node, env).map_err(|error| error.located(node.loc()))
+    }
+
+    fn evaluate_node(node: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
         match node {
-            ASTNode::LetDeclaration { name, value, .. } => Self::eval_let_declaration(name, value, env),
+            ASTNode::LetDeclaration { name, type_annotation, value, .. } => Self::eval_let_declaration(name, type_annotation, value, env),
             ASTNode::Assignment { target, value } => Self::eval_assignment(target, value, env),
             ASTNode::IntLiteral(n) => Ok(RuntimeValue::Int(*n)),
             ASTNode::FloatLiteral(f) => Ok(RuntimeValue::Float(*f)),
+            ASTNode::ImaginaryLiteral(f) => Ok(RuntimeValue::Complex(C64::new(0.0, *f))),
             ASTNode::StringLiteral(s) => Ok(RuntimeValue::String(s.clone())),
             ASTNode::BoolLiteral(b) => Ok(RuntimeValue::Bool(*b)),
             ASTNode::NoneLiteral => Ok(RuntimeValue::None),
@@ -51,8 +141,17 @@ impl Evaluator {
             ASTNode::Unary { operator, operand, .. } => Self::eval_unary_op(operator, operand, env),
             ASTNode::Break => Ok(RuntimeValue::Break),
             ASTNode::Continue => Ok(RuntimeValue::Continue),
-            ASTNode::TryCatch { try_block, error_variable, catch_block } => {
-                Self::eval_try_catch(try_block, error_variable, catch_block, env)
+            ASTNode::TryCatch { try_block, handlers, finally_block, loc } => {
+                Self::eval_try_catch(try_block, handlers, finally_block.as_deref(), loc, env)
+            }
+            ASTNode::Throw { value, loc } => Self::eval_throw(value, loc, env),
+            // A lambda closes over the scope it is created in.
+            ASTNode::Lambda { parameters, body, .. } => {
+                let body = match &**body {
+                    ASTNode::Block(_) => body.clone(),
+                    expression => Box::new(ASTNode::Block(vec![ASTNode::Return(Some(Box::new(expression.clone())))])),
+                };
+                Ok(RuntimeValue::Function { parameters: parameters.clone(), body, env: env.clone(), where_clause: Vec::new() })
             }
             ASTNode::Import { path, alias } => {
                 Self::eval_import_statement(path, alias, env)
@@ -62,6 +161,8 @@ impl Evaluator {
             }
             ASTNode::If { condition, then_block, elif_blocks, else_block } =>
                 Self::eval_if_statement(condition, then_block, elif_blocks, else_block, env),
+            ASTNode::Match { value, cases, loc } =>
+                Self::eval_match_statement(value, cases, loc, env),
             ASTNode::While { condition, body } =>
                 Self::eval_while_statement(condition, body, env),
             ASTNode::For { variable, iterator, body } =>
@@ -73,21 +174,19 @@ impl Evaluator {
 
             ASTNode::QuantumDeclaration { name, size, initial_state } =>
                 Self::eval_quantum_declaration(name, size, initial_state, env),
-            ASTNode::QuantumKet(s) => {
-                let size = 1;
-                let state_vec = Self::ket_to_state_vector(s, size)?;
-                Ok(RuntimeValue::QuantumRegister {
-                    size,
-                    state: Rc::new(RefCell::new(state_vec))
-                })
-            },
+            ASTNode::QuantumKet(label) => Ket::parse(label)
+                .map(RuntimeValue::Ket)
+                .map_err(RuntimeError::from),
+            ASTNode::QuantumBra(label) => Ket::parse(label)
+                .map(|ket| RuntimeValue::Bra(Bra(ket)))
+                .map_err(RuntimeError::from),
 
             ASTNode::Apply { gate_expr, arguments, loc } => {
                 Self::eval_apply_statement(gate_expr, arguments, loc, env)
             }
 
             ASTNode::Gate { .. } | ASTNode::Controlled { .. } | ASTNode::Dagger { .. } => {
-                Err("Gate expressions (like 'X' or 'controlled(X)') can only be used inside an 'apply' statement.".to_string())
+                Err("Gate expressions (like 'X' or 'controlled(X)') can only be used inside an 'apply' statement.".to_string().into())
             }
 
             ASTNode::Measure(target_expr) =>
@@ -105,24 +204,64 @@ impl Evaluator {
             ASTNode::DictLiteral(pairs) => {
                 Self::eval_dict_literal(pairs, env)
             }
-            ASTNode::FunctionDeclaration { name, parameters, return_type:_, body, .. } => {
+            ASTNode::FunctionDeclaration { name, generics, parameters, return_type:_, body } => {
                 let func = RuntimeValue::Function {
                     parameters: parameters.clone(),
                     body: body.clone(),
                     env: env.clone(),
+                    where_clause: generics.where_clause.clone(),
                 };
                 env.borrow_mut().set(name.clone(), func);
                 Ok(RuntimeValue::None)
             }
-            ASTNode::CircuitDeclaration { name, parameters, return_type:_, body, .. } => {
+            ASTNode::CircuitDeclaration { name, generics, parameters, return_type:_, body } => {
                 let func = RuntimeValue::Function {
                     parameters: parameters.clone(),
                     body: body.clone(),
                     env: env.clone(),
+                    where_clause: generics.where_clause.clone(),
                 };
                 env.borrow_mut().set(name.clone(), func);
                 Ok(RuntimeValue::None)
             }
+            ASTNode::StructDeclaration { name, fields, .. } => {
+                let fields = fields.iter().map(|field| field.name.clone()).collect();
+                env.borrow_mut().set(name.clone(), RuntimeValue::StructType { name: name.clone(), fields });
+                Ok(RuntimeValue::None)
+            }
+            ASTNode::EnumDeclaration { name, variants, .. } => {
+                let variants = variants.iter().map(|variant| (variant.name.clone(), variant.fields.len())).collect();
+                env.borrow_mut().set(name.clone(), RuntimeValue::EnumType { name: name.clone(), variants });
+                Ok(RuntimeValue::None)
+            }
+            // Methods live next to the type as `Type.method`, which no identifier can shadow.
+            ASTNode::ImplBlock { type_name, methods, .. } => {
+                for method in methods {
+                    if let ASTNode::FunctionDeclaration { name, generics, parameters, body, .. } |
+                           ASTNode::CircuitDeclaration { name, generics, parameters, body, .. } = method {
+                        let func = RuntimeValue::Function {
+                            parameters: parameters.clone(),
+                            body: body.clone(),
+                            env: env.clone(),
+                            where_clause: generics.where_clause.clone(),
+                        };
+                        env.borrow_mut().set(format!("{}.{}", type_name, name), func);
+                    }
+                }
+                Ok(RuntimeValue::None)
+            }
+            ASTNode::GateDeclaration { name, matrix, loc } => {
+                let unitary = Self::eval_gate_matrix(matrix)
+                    .map_err(|e| RuntimeError::at(loc, format!("Gate '{}': {}.", name, e)))?;
+                let gate = RuntimeValue::Gate {
+                    base_name: name.to_lowercase(),
+                    is_dagger: false,
+                    num_controls: 0,
+                    matrix: Some(Rc::new(unitary)),
+                };
+                env.borrow_mut().set(name.clone(), gate);
+                Ok(RuntimeValue::None)
+            }
             ASTNode::Return(value_expr) => {
                 let value = match value_expr {
                     Some(expr) => Self::evaluate(expr, env)?,
@@ -130,7 +269,7 @@ impl Evaluator {
                 };
                 Ok(RuntimeValue::ReturnValue(Box::new(value)))
             }
-            _ => Err(format!("Evaluation not implemented for AST node: {:?}", node)),
+            _ => Err(format!("Evaluation not implemented for AST node: {:?}", node).into()),
         }
     }
     fn is_truthy(val: &RuntimeValue) -> bool {
@@ -139,7 +278,7 @@ impl Evaluator {
         RuntimeValue::Int(n) => *n != 0,
         RuntimeValue::None => false,
         RuntimeValue::Probabilistic { value, confidence } => {
-            if rand::thread_rng().gen::<f64>() < *confidence { Self::is_truthy(value) } else { false }
+            if environment::with_rng(|rng| rng.gen::<f64>()) < *confidence { Self::is_truthy(value) } else { false }
         }
         _ => true,
     }
@@ -152,33 +291,40 @@ impl Evaluator {
         size_expr: &Option<Box<ASTNode>>,
         initial_state_expr: &Option<Box<ASTNode>>,
         env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
-        let register = if let Some(expr) = size_expr {
-            let size_val = Self::evaluate(expr, env)?;
-            let size = match size_val {
-                RuntimeValue::Int(n) if n > 0 => n as usize,
-                _ => return Err(format!("Runtime Error: Quantum register size must be a positive integer, got {:?}", size_val)),
-            };
-            if size > 20 {
-                return Err(format!("Runtime Error: Register size {} is too large to simulate.", size));
-            }
+    ) -> Result<RuntimeValue, RuntimeError> {
+        let declared_size = match size_expr {
+            Some(expr) => match Self::evaluate(expr, env)? {
+                RuntimeValue::Int(n) if n > 0 => Some(n as usize),
+                size_val => return Err(format!("Quantum register size must be a positive integer, got {:?}", size_val).into()),
+            },
+            None => None,
+        };
 
-            let state_map = Self::default_state_vector(size)?;
-            RuntimeValue::QuantumRegister { size, state: Rc::new(RefCell::new(state_map)) }
-        } else if let Some(expr) = initial_state_expr {
+        let register = if let Some(expr) = initial_state_expr {
             let state_val = Self::evaluate(expr, env)?;
             match state_val {
-
-                RuntimeValue::QuantumRegister { size, state } => {
+                RuntimeValue::Ket(ket) => {
+                    if let Some(size) = declared_size.filter(|size| *size != ket.num_qubits) {
+                        return Err(format!("Register '{}' has {} qubit(s), but its initial state is a {}-qubit ket.", name, size, ket.num_qubits).into());
+                    }
+                    let ket = ket.normalized().map_err(|e| format!("Cannot initialize '{}': {}", name, e))?;
+                    let size = ket.num_qubits;
+                    let state_map = QuantumState::from_amplitudes(size, ket.amplitudes)?;
+                    RuntimeValue::QuantumRegister { size, state: StateHandle::new(state_map) }
+                }
+                RuntimeValue::QuantumRegister { size, state } if declared_size.is_none_or(|declared| declared == size) => {
                     RuntimeValue::QuantumRegister { size, state }
                 }
-                _ => return Err(format!("Runtime Error: Initial state for a quantum register must be a register, got {:?}", state_val.type_name())),
+                _ => return Err(format!("Initial state for a quantum register must be a ket or a register of the declared size, got {:?}", state_val.type_name()).into()),
             }
+        } else if let Some(size) = declared_size {
+            let state_map = Self::default_state_vector(size)?;
+            RuntimeValue::QuantumRegister { size, state: StateHandle::new(state_map) }
         } else {
 
             let size = 1;
             let state_map = Self::default_state_vector(size)?;
-            RuntimeValue::QuantumRegister { size, state: Rc::new(RefCell::new(state_map)) }
+            RuntimeValue::QuantumRegister { size, state: StateHandle::new(state_map) }
         };
         env.borrow_mut().set(name.to_string(), register.clone());
         Ok(register)
@@ -187,60 +333,29 @@ impl Evaluator {
     operator: &crate::parser::ast::UnaryOperator,
     operand_expr: &Box<ASTNode>,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let operand = Self::evaluate(operand_expr, env)?;
     match operator {
         crate::parser::ast::UnaryOperator::Not => Ok(RuntimeValue::Bool(!Self::is_truthy(&operand))),
         crate::parser::ast::UnaryOperator::Minus => match operand {
             RuntimeValue::Int(i) => Ok(RuntimeValue::Int(-i)),
             RuntimeValue::Float(f) => Ok(RuntimeValue::Float(-f)),
-            _ => Err(format!("Runtime Error: Unary operator '-' not defined for type {:?}", operand.type_name())),
+            RuntimeValue::Complex(z) => Ok(RuntimeValue::Complex(-z)),
+            RuntimeValue::Ket(ket) => Ok(RuntimeValue::Ket(ket.scale(C64::new(-1.0, 0.0)))),
+            RuntimeValue::Bra(Bra(ket)) => Ok(RuntimeValue::Bra(Bra(ket.scale(C64::new(-1.0, 0.0))))),
+            _ => Err(format!("Unary operator '-' not defined for type {:?}", operand.type_name()).into()),
         },
         crate::parser::ast::UnaryOperator::Plus => match operand {
             RuntimeValue::Int(i) => Ok(RuntimeValue::Int(i)),
             RuntimeValue::Float(f) => Ok(RuntimeValue::Float(f)),
-            _ => Err(format!("Runtime Error: Unary operator '+' not defined for type {:?}", operand.type_name())),
+            RuntimeValue::Complex(z) => Ok(RuntimeValue::Complex(z)),
+            _ => Err(format!("Unary operator '+' not defined for type {:?}", operand.type_name()).into()),
         },
     }
 }
 
-    fn default_state_vector(size: usize) -> Result<HashMap<usize, (f64, f64)>, String> {
-        if size == 0 { return Err("Runtime Error: Quantum register size must be > 0".to_string()); }
-
-        let mut state = HashMap::new();
-        state.insert(0, (1.0, 0.0));
-        Ok(state)
-    }
-
-    fn ket_to_state_vector(ket_str: &str, size: usize) -> Result<HashMap<usize, (f64, f64)>, String> {
-        if size != 1 {
-            return Err("Runtime Error: Can only initialize single-qubit kets for now.".to_string());
-        }
-        let mut state = HashMap::new();
-        match ket_str {
-            "0" => state.insert(0, (1.0, 0.0)), // |0>
-            "1" => state.insert(1, (1.0, 0.0)), // |1>
-            _ => return Err(format!("Runtime Error: Invalid single-qubit ket state '{}'.", ket_str)),
-        };
-        Ok(state)
-    }
-
-
-    fn complex_mul((a, b): (f64, f64), (c, d): (f64, f64)) -> (f64, f64) {
-        (a * c - b * d, a * d + b * c)
-    }
-    fn complex_add((a, b): (f64, f64), (c, d): (f64, f64)) -> (f64, f64) {
-        (a + c, b + d)
-    }
-    fn complex_scalar_mul((a, b): (f64, f64), s: f64) -> (f64, f64) {
-        (a * s, b * s)
-    }
-    fn complex_mul_by_phase((a, b): (f64, f64), phi: f64) -> (f64, f64) {
-        let (c, d) = (phi.cos(), phi.sin());
-        (a * c - b * d, a * d + b * c)
-    }
-    fn complex_norm_sq((a, b): (f64, f64)) -> f64 {
-        a * a + b * b
+    fn default_state_vector(size: usize) -> Result<QuantumState, String> {
+        QuantumState::new(size)
     }
 
 
@@ -249,7 +364,7 @@ impl Evaluator {
         arg_nodes: &[ASTNode],
         loc: &Loc,
         env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
 
 
         let mut qubit_args = Vec::new();
@@ -257,12 +372,38 @@ impl Evaluator {
             qubit_args.push(Self::evaluate(arg_node, env)?);
         }
 
+        if let Some((circuit, num_controls, is_dagger)) = Self::circuit_gate(gate_expr_node, env)? {
+            return Self::apply_controlled_circuit(circuit, num_controls, is_dagger, qubit_args, loc);
+        }
 
         let gate_val = Self::eval_gate_expression(gate_expr_node, env)?;
 
-        let (base_name, is_dagger, num_controls) = match gate_val {
-            RuntimeValue::Gate { base_name, is_dagger, num_controls } => (base_name, is_dagger, num_controls),
-            _ => return Err(format!("Runtime Error at {}: Expression is not a valid gate.", loc)),
+        let mut params = Vec::new();
+        Self::extract_gate_params(gate_expr_node, &mut params, env)?;
+
+        Self::apply_gate_value(gate_val, &qubit_args, params, loc)
+    }
+
+    fn apply_gate_value(
+        gate_val: RuntimeValue,
+        qubit_args: &[RuntimeValue],
+        params: Vec<f64>,
+        loc: &Loc,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        let (base_name, is_dagger, mut num_controls, matrix) = match gate_val {
+            RuntimeValue::Gate { base_name, is_dagger, num_controls, matrix } => (base_name, is_dagger, num_controls, matrix),
+            _ => return Err(RuntimeError::at(loc, "Expression is not a valid gate.")),
+        };
+
+        let circuit_controls = CIRCUIT_CONTROLS.with(|controls| controls.borrow().clone());
+        let qubit_args: Vec<RuntimeValue> = if circuit_controls.is_empty() {
+            qubit_args.to_vec()
+        } else {
+            if base_name == "reset" {
+                return Err(RuntimeError::at(loc, "'reset' cannot be applied inside a controlled circuit."));
+            }
+            num_controls += circuit_controls.len();
+            circuit_controls.into_iter().chain(qubit_args.iter().cloned()).collect()
         };
 
 
@@ -270,29 +411,32 @@ impl Evaluator {
         let mut targets = Vec::new();
 
 
-        let mut state_rc: Option<Rc<RefCell<HashMap<usize, (f64, f64)>>>> = None;
+        let mut state_rc: Option<Rc<RefCell<QuantumState>>> = None;
         let mut reg_size: Option<usize> = None;
 
+        if qubit_args.is_empty() {
+            return Err(RuntimeError::at(loc, format!("Gate '{}' was applied to no qubits.", base_name)));
+        }
         if qubit_args.len() < num_controls {
-            return Err(format!("Runtime Error at {}: Gate requires {} control qubits, but only {} arguments provided.", loc, num_controls, qubit_args.len()));
+            return Err(RuntimeError::at(loc, format!("Gate requires {} control qubits, but only {} arguments provided.", num_controls, qubit_args.len())));
         }
 
-        let (_control_args, _target_args) = qubit_args.split_at(num_controls);
+        Self::merge_qubit_states(&qubit_args, loc)?;
 
         for (i, qubit_val) in qubit_args.iter().enumerate() {
             let (q_state_rc, q_index, q_size) = match qubit_val {
 
-                RuntimeValue::Qubit { state, index, size } => (state.clone(), *index, *size),
-                _ => return Err(format!("Runtime Error at {}: Gate arguments must be Qubits, but argument {} was {}.", loc, i+1, qubit_val.type_name())),
+                RuntimeValue::Qubit { state, index } => {
+                    let (state, shift) = state.resolve();
+                    let size = state.borrow().num_qubits();
+                    (state, index + shift, size)
+                }
+                _ => return Err(RuntimeError::at(loc, format!("Gate arguments must be Qubits, but argument {} was {}.", i+1, qubit_val.type_name()))),
             };
 
             if i == 0 {
                 state_rc = Some(q_state_rc);
                 reg_size = Some(q_size);
-            } else {
-                if !Rc::ptr_eq(state_rc.as_ref().unwrap(), &q_state_rc) {
-                    return Err(format!("Runtime Error at {}: All qubits in an 'apply' statement must be from the same register.", loc));
-                }
             }
 
             if i < num_controls {
@@ -303,10 +447,6 @@ impl Evaluator {
         }
 
 
-        let mut params = Vec::new();
-        Self::extract_gate_params(gate_expr_node, &mut params, env)?;
-
-
         let gate_def = GateDefinition {
             name: base_name,
             params,
@@ -314,23 +454,93 @@ impl Evaluator {
             targets,
             register_size: reg_size.unwrap_or(0),
             state_rc: state_rc.unwrap(),
+            matrix,
         };
 
 
         Self::apply_multi_controlled_gate(gate_def, is_dagger)
     }
 
+    // A circuit (or circuit method) under any number of `controlled`/`dagger`
+    // wrappers, with its number of controls and whether it is inverted.
+    fn circuit_gate(
+        node: &ASTNode,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Option<(RuntimeValue, usize, bool)>, RuntimeError> {
+        match node {
+            ASTNode::Controlled { gate_expr, .. } => {
+                Ok(Self::circuit_gate(gate_expr, env)?.map(|(circuit, controls, dagger)| (circuit, controls + 1, dagger)))
+            }
+            ASTNode::Dagger { gate_expr, .. } => {
+                Ok(Self::circuit_gate(gate_expr, env)?.map(|(circuit, controls, dagger)| (circuit, controls, !dagger)))
+            }
+            ASTNode::MemberAccess { .. } => Ok(Some((Self::evaluate(node, env)?, 0, false))),
+            ASTNode::Gate { name, .. } => {
+                let value = env.borrow().get(name).map(|value| value.borrow().clone());
+                match value {
+                    Some(circuit @ (RuntimeValue::Function { .. } | RuntimeValue::BoundMethod { .. })) => Ok(Some((circuit, 0, false))),
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Run a circuit with every gate it applies controlled on the leading `num_controls`
+    // qubits; they join the targets' state when the first gate is applied.
+    fn apply_controlled_circuit(
+        circuit: RuntimeValue,
+        num_controls: usize,
+        is_dagger: bool,
+        qubit_args: Vec<RuntimeValue>,
+        loc: &Loc,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        if qubit_args.len() < num_controls {
+            return Err(RuntimeError::at(loc, format!("Circuit requires {} control qubits, but only {} arguments provided.", num_controls, qubit_args.len())));
+        }
+        let (controls, targets) = qubit_args.split_at(num_controls);
+        if let Some(control) = controls.iter().find(|control| !matches!(control, RuntimeValue::Qubit { .. })) {
+            return Err(RuntimeError::at(loc, format!("Controls of a circuit must be single qubits, got {}.", control.type_name())));
+        }
+
+        let depth = CIRCUIT_CONTROLS.with(|active| active.borrow().len());
+        CIRCUIT_CONTROLS.with(|active| active.borrow_mut().extend(controls.iter().cloned()));
+        let result = Self::call_value(circuit, targets.to_vec(), "circuit", loc, is_dagger);
+        CIRCUIT_CONTROLS.with(|active| active.borrow_mut().truncate(depth));
+        result
+    }
+
+    /// Bring all qubits of one `apply` into a single state. Each further state is
+    /// tensored onto the first one's high-order end; handles on it are forwarded.
+    fn merge_qubit_states(qubit_args: &[RuntimeValue], loc: &Loc) -> Result<(), String> {
+        let Some(RuntimeValue::Qubit { state: base, .. }) = qubit_args.first() else {
+            return Ok(());
+        };
+        for arg in &qubit_args[1..] {
+            if let RuntimeValue::Qubit { state, .. } = arg {
+                base.absorb(state).map_err(|e| format!("{} (at {})", e, loc))?;
+            }
+        }
+        Ok(())
+    }
+
 
     fn eval_gate_expression(
         node: &ASTNode,
         env: &Rc<RefCell<Environment>>
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
         match node {
             ASTNode::Gate { name, loc: _ } => {
+                if let Some(value) = env.borrow().get(name) {
+                    if let RuntimeValue::Gate { .. } = &*value.borrow() {
+                        return Ok(value.borrow().clone());
+                    }
+                }
                 Ok(RuntimeValue::Gate {
                     base_name: name.to_lowercase(),
                     is_dagger: false,
                     num_controls: 0,
+                    matrix: None,
                 })
             }
 
@@ -339,35 +549,38 @@ impl Evaluator {
                     base_name: name.to_lowercase(),
                     is_dagger: false,
                     num_controls: 0,
+                    matrix: None,
                 })
             }
             ASTNode::Dagger { gate_expr, .. } => {
                 let inner_gate = Self::eval_gate_expression(gate_expr, env)?;
                 match inner_gate {
-                    RuntimeValue::Gate { base_name, is_dagger, num_controls } => {
+                    RuntimeValue::Gate { base_name, is_dagger, num_controls, matrix } => {
                         Ok(RuntimeValue::Gate {
                             base_name,
                             is_dagger: !is_dagger,
                             num_controls,
+                            matrix,
                         })
                     }
-                    _ => Err("Internal Error: 'dagger' did not receive a valid gate.".to_string())
+                    _ => Err("Internal Error: 'dagger' did not receive a valid gate.".to_string().into())
                 }
             }
             ASTNode::Controlled { gate_expr, .. } => {
                 let inner_gate = Self::eval_gate_expression(gate_expr, env)?;
                 match inner_gate {
-                    RuntimeValue::Gate { base_name, is_dagger, num_controls } => {
+                    RuntimeValue::Gate { base_name, is_dagger, num_controls, matrix } => {
                         Ok(RuntimeValue::Gate {
                             base_name,
                             is_dagger,
                             num_controls: num_controls + 1,
+                            matrix,
                         })
                     }
-                    _ => Err("Internal Error: 'controlled' did not receive a valid gate.".to_string())
+                    _ => Err("Internal Error: 'controlled' did not receive a valid gate.".to_string().into())
                 }
             }
-            _ => Err("Internal Error: Invalid ASTNode passed to eval_gate_expression.".to_string())
+            _ => Err("Internal Error: Invalid ASTNode passed to eval_gate_expression.".to_string().into())
         }
     }
 
@@ -376,12 +589,12 @@ impl Evaluator {
         node: &ASTNode,
         params: &mut Vec<f64>,
         env: &Rc<RefCell<Environment>>
-    ) -> Result<(), String> {
+    ) -> Result<(), RuntimeError> {
         match node {
             ASTNode::Gate { name, .. } => {
                 match name.to_lowercase().as_str() {
                     "rx" | "ry" | "rz" | "cphase" | "u" => {
-                        return Err(format!("Runtime Error: Parameterized gate '{}' must be called as a function (e.g., RX(theta)).", name));
+                        return Err(format!("Parameterized gate '{}' must be called as a function (e.g., RX(theta)).", name).into());
                     }
                     _ => Ok(())
                 }
@@ -392,7 +605,7 @@ impl Evaluator {
                 let float_param = match param_val {
                     RuntimeValue::Float(f) => f,
                     RuntimeValue::Int(i) => i as f64,
-                    _ => return Err(format!("Runtime Error: Gate parameter must be a number, got {:?}", param_val.type_name()))
+                    _ => return Err(format!("Gate parameter must be a number, got {:?}", param_val.type_name()).into())
                 };
                 params.push(float_param);
             }
@@ -409,7 +622,7 @@ impl Evaluator {
 
                 let gate_name = match &**callee {
                     ASTNode::Identifier { name, .. } => name.to_lowercase(),
-                    _ => return Err(format!("Runtime Error at {}: Gate expression must be a simple identifier inside the call.", loc)),
+                    _ => return Err(RuntimeError::at(loc, "Gate expression must be a simple identifier inside the call.")),
                 };
 
 
@@ -438,199 +651,148 @@ impl Evaluator {
     pub fn apply_multi_controlled_gate(
         gate: GateDefinition,
         is_dagger: bool
-    ) -> Result<RuntimeValue, String> {
-
+    ) -> Result<RuntimeValue, RuntimeError> {
 
-        let is_native_2qubit = matches!(
-            gate.name.as_str(),
-            "cphase" | "cnot" | "cz" | "cx" | "cy" | "swap"
-        );
-
-        if is_native_2qubit && gate.controls.is_empty() && gate.targets.len() == 2 {
-            return Self::apply_native_2qubit_gate(gate, is_dagger);
-        }
-
-        let matrix = Self::get_gate_matrix(&gate.name, &gate.params, is_dagger)?;
-
-        let mut control_mask = 0;
-        for &control_idx in &gate.controls {
-            if (control_mask & (1 << control_idx)) != 0 {
-                return Err("Runtime Error: Duplicate control qubit indices.".to_string());
+        if gate.name == "reset" && gate.matrix.is_none() {
+            if is_dagger || !gate.controls.is_empty() {
+                return Err("'reset' is not unitary and cannot be daggered or controlled.".to_string().into());
             }
-            for &target_idx in &gate.targets {
-                if control_idx == target_idx {
-                    return Err("Runtime Error: Control and target qubits must be different.".to_string());
-                }
+            for &target in &gate.targets {
+                Self::reset_qubit(&gate.state_rc, target, gate.register_size)?;
             }
-            control_mask |= 1 << control_idx;
+            return Ok(RuntimeValue::None);
         }
 
+        let unitary = match &gate.matrix {
+            Some(matrix) if is_dagger => matrix.dagger(),
+            Some(matrix) => (**matrix).clone(),
+            None => Self::get_gate_unitary(&gate.name, &gate.params, is_dagger)?,
+        };
+
         if gate.targets.is_empty() {
-            return Err(format!("Runtime Error: Gate '{}' must have at least 1 target qubit.", gate.name));
+            return Err(format!("Gate '{}' must have at least 1 target qubit.", gate.name).into());
         }
-        if gate.targets.len() > 1 {
-            return Err("Runtime Error: Multi-target gates with additional controls are not yet supported.".to_string());
+        if gate.targets.len() != unitary.num_qubits {
+            return Err(format!("Gate '{}' acts on {} qubit(s), but {} target qubit(s) were given.", gate.name, unitary.num_qubits, gate.targets.len()).into());
         }
 
-        let target_idx = gate.targets[0];
-        let target_mask = 1 << target_idx;
-
-        let mut state_map_guard = gate.state_rc.borrow_mut();
-        let old_state_map = &*state_map_guard;
-        let mut new_state_map = HashMap::new();
-
-
-        let mut processed = std::collections::HashSet::new();
-
-
-        for (&basis_state, &amp_tuple) in old_state_map.iter() {
-            if processed.contains(&basis_state) {
-                continue;
+        for (i, &control_idx) in gate.controls.iter().enumerate() {
+            if gate.controls[..i].contains(&control_idx) {
+                return Err("Duplicate control qubit indices.".to_string().into());
             }
-
-
-            if (basis_state & control_mask) == control_mask {
-
-
-
-
-                let partner_state = basis_state ^ target_mask;
-
-                let amp_self_tuple = amp_tuple;
-                let amp_partner_tuple = old_state_map.get(&partner_state).cloned().unwrap_or((0.0, 0.0));
-
-                let amp_self = C64::new(amp_self_tuple.0, amp_self_tuple.1);
-                let amp_partner = C64::new(amp_partner_tuple.0, amp_partner_tuple.1);
-
-                let (amp0, amp1, idx0, idx1);
-
-
-                if (basis_state & target_mask) == 0 {
-
-                    amp0 = amp_self;
-                    amp1 = amp_partner;
-                    idx0 = basis_state;
-                    idx1 = partner_state;
-                } else {
-
-                    amp0 = amp_partner;
-                    amp1 = amp_self;
-                    idx0 = partner_state;
-                    idx1 = basis_state;
-                }
-
-
-                let new_amp0 = matrix[0][0] * amp0 + matrix[0][1] * amp1;
-                let new_amp1 = matrix[1][0] * amp0 + matrix[1][1] * amp1;
-
-
-                Self::insert_if_nonzero(&mut new_state_map, idx0, new_amp0);
-                Self::insert_if_nonzero(&mut new_state_map, idx1, new_amp1);
-
-
-                processed.insert(idx0);
-                processed.insert(idx1);
-
-
-
-            } else {
-
-                new_state_map.insert(basis_state, amp_tuple);
-                processed.insert(basis_state);
+            if gate.targets.contains(&control_idx) {
+                return Err("Control and target qubits must be different.".to_string().into());
             }
         }
+        for (i, &target_idx) in gate.targets.iter().enumerate() {
+            if gate.targets[..i].contains(&target_idx) {
+                return Err("Duplicate target qubit indices.".to_string().into());
+            }
+        }
+
+        let mut state = gate.state_rc.borrow_mut();
+        state.apply_unitary(&unitary, &gate.targets, &gate.controls)
+            .map_err(|e| format!("{} Offending gate: '{}'.", e, gate.name))?;
 
-        *state_map_guard = new_state_map;
+        let touched: Vec<usize> = gate.controls.iter().chain(gate.targets.iter()).cloned().collect();
+        state.apply_gate_noise(&gate.name, &touched);
         Ok(RuntimeValue::None)
     }
 
-    fn apply_native_2qubit_gate(
-        gate: GateDefinition,
+    /// Full matrix for a named gate. Multi-qubit gates are built from their
+    /// single-qubit parts, and `dagger` is the conjugate transpose.
+    pub fn get_gate_unitary(
+        name: &str,
+        params: &[f64],
         is_dagger: bool
-    ) -> Result<RuntimeValue, String> {
-
-        if gate.targets.len() != 2 {
-            return Err(format!("Runtime Error: Gate '{}' requires exactly 2 qubits.", gate.name));
-        }
+    ) -> Result<Unitary, String> {
+        let unitary = match name {
+            "swap" => Unitary::swap(),
+            "cswap" | "fredkin" => Unitary::swap().controlled(),
+            "cnot" | "cx" | "cy" | "cz" | "cphase" => {
+                Unitary::single(Self::get_gate_matrix(name, params, false)?).controlled()
+            }
+            "ccx" | "toffoli" => {
+                Unitary::single(Self::get_gate_matrix(name, params, false)?).controlled().controlled()
+            }
+            _ => Unitary::single(Self::get_gate_matrix(name, params, false)?),
+        };
+        Ok(if is_dagger { unitary.dagger() } else { unitary })
+    }
 
-        let control_idx = gate.targets[0];
-        let target_idx = gate.targets[1];
 
-        if control_idx == target_idx {
-            return Err("Runtime Error: Control and target qubits must be different.".to_string());
+    /// Evaluates the `where` clause of a generic declaration in `env`, where its size
+    /// parameters are bound.
+    pub fn where_clause_holds(where_clause: &[ASTNode], env: &Rc<RefCell<Environment>>) -> Result<bool, RuntimeError> {
+        for constraint in where_clause {
+            match Self::evaluate(constraint, env)? {
+                RuntimeValue::Bool(true) => {}
+                RuntimeValue::Bool(false) => return Ok(false),
+                other => return Err(format!("'where' clause must be a Bool, but got {}.", other.type_name()).into()),
+            }
         }
+        Ok(true)
+    }
 
-        let control_mask = 1 << control_idx;
-        let target_mask = 1 << target_idx;
-
-        let mut state_map_guard = gate.state_rc.borrow_mut();
-
-        match gate.name.as_str() {
-            "cphase" => {
-                let phi = gate.params.get(0).cloned().unwrap_or(0.0);
-                let angle = if is_dagger { -phi } else { phi };
-                let phase_factor = C64::from_polar(1.0, angle);
-
-
-                for (&basis_state, amp_tuple) in state_map_guard.iter_mut() {
-
-                    if (basis_state & control_mask) != 0 && (basis_state & target_mask) != 0 {
-                        let amp = C64::new(amp_tuple.0, amp_tuple.1);
-                        let new_amp = amp * phase_factor;
-                        *amp_tuple = (new_amp.re, new_amp.im);
-                    }
+    /// Matrix of a `gate Name = [[...]]` declaration. Entries are constant
+    /// numbers or `[re, im]` pairs; the result is checked for unitarity.
+    pub fn eval_gate_matrix(node: &ASTNode) -> Result<Unitary, String> {
+        let rows = match node {
+            ASTNode::ArrayLiteral(rows) => rows,
+            _ => return Err("the matrix must be an array literal of rows".to_string()),
+        };
+        let mut matrix = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            match row {
+                ASTNode::ArrayLiteral(entries) => {
+                    let row = entries.iter()
+                        .map(Self::eval_matrix_entry)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    matrix.push(row);
                 }
+                _ => return Err(format!("row {} of the matrix is not an array literal", i)),
             }
-            "swap" => {
-                let mut new_state_map = HashMap::new();
-                for (&basis_state, &amp_tuple) in state_map_guard.iter() {
-                    let bit_a = (basis_state & control_mask) != 0;
-                    let bit_b = (basis_state & target_mask) != 0;
-
-                    if bit_a != bit_b {
-
-                        let swapped_idx = basis_state ^ control_mask ^ target_mask;
-                        new_state_map.insert(swapped_idx, amp_tuple);
-                    } else {
+        }
+        Unitary::from_rows(matrix)
+    }
 
-                        new_state_map.insert(basis_state, amp_tuple);
-                    }
+    fn eval_matrix_entry(node: &ASTNode) -> Result<C64, String> {
+        match node {
+            ASTNode::IntLiteral(n) => Ok(C64::new(*n as f64, 0.0)),
+            ASTNode::FloatLiteral(f) => Ok(C64::new(*f, 0.0)),
+            ASTNode::ImaginaryLiteral(f) => Ok(C64::new(0.0, *f)),
+            ASTNode::ArrayLiteral(parts) if parts.len() == 2 => {
+                let re = Self::eval_matrix_entry(&parts[0])?;
+                let im = Self::eval_matrix_entry(&parts[1])?;
+                if re.im != 0.0 || im.im != 0.0 {
+                    return Err("the parts of a [re, im] entry must be real".to_string());
                 }
-                *state_map_guard = new_state_map;
-            }
-            "cnot" | "cx" => {
-                let mut new_state_map = HashMap::new();
-                for (&basis_state, &amp_tuple) in state_map_guard.iter() {
-
-                    if (basis_state & control_mask) != 0 {
-                        let swapped_idx = basis_state ^ target_mask;
-                        new_state_map.insert(swapped_idx, amp_tuple);
-                    } else {
-
-                        new_state_map.insert(basis_state, amp_tuple);
-                    }
+                Ok(C64::new(re.re, im.re))
+            }
+            ASTNode::Unary { operator, operand } => {
+                let value = Self::eval_matrix_entry(operand)?;
+                match operator {
+                    UnaryOperator::Minus => Ok(-value),
+                    UnaryOperator::Plus => Ok(value),
+                    UnaryOperator::Not => Err("'not' is not allowed in a gate matrix".to_string()),
                 }
-                *state_map_guard = new_state_map;
             }
-            "cz" => {
-
-                for (&basis_state, amp_tuple) in state_map_guard.iter_mut() {
-
-                    if (basis_state & control_mask) != 0 && (basis_state & target_mask) != 0 {
-                        *amp_tuple = (-amp_tuple.0, -amp_tuple.1);
-                    }
+            ASTNode::Binary { operator, left, right, .. } => {
+                let (l, r) = (Self::eval_matrix_entry(left)?, Self::eval_matrix_entry(right)?);
+                match operator {
+                    BinaryOperator::Add => Ok(l + r),
+                    BinaryOperator::Sub => Ok(l - r),
+                    BinaryOperator::Mul => Ok(l * r),
+                    BinaryOperator::Div if r.norm() == 0.0 => Err("division by zero in a gate matrix".to_string()),
+                    BinaryOperator::Div => Ok(l / r),
+                    _ => Err(format!("operator {:?} is not allowed in a gate matrix", operator)),
                 }
             }
-            _ => {
-                return Err(format!("Runtime Error: Native 2-qubit gate '{}' not implemented.", gate.name));
-            }
+            _ => Err("matrix entries must be constant numbers or [re, im] pairs".to_string()),
         }
-
-        Ok(RuntimeValue::None)
     }
 
-
-    fn get_gate_matrix(
+    pub fn get_gate_matrix(
         name: &str,
         params: &[f64],
         is_dagger: bool
@@ -641,11 +803,11 @@ impl Evaluator {
 
         match name {
 
-            "x" | "not" | "cnot" | "ccx" | "toffoli" => {
+            "x" | "not" | "cnot" | "cx" | "ccx" | "toffoli" => {
                 Ok([ [C64::new(0.0, 0.0), C64::new(1.0, 0.0)],
                      [C64::new(1.0, 0.0), C64::new(0.0, 0.0)] ])
             }
-            "y" => Ok([ [C64::new(0.0, 0.0), -i], [i, C64::new(0.0, 0.0)] ]),
+            "y" | "cy" => Ok([ [C64::new(0.0, 0.0), -i], [i, C64::new(0.0, 0.0)] ]),
             "z" | "cz" => {
                 Ok([ [C64::new(1.0, 0.0), C64::new(0.0, 0.0)],
                      [C64::new(0.0, 0.0), C64::new(-1.0, 0.0)] ])
@@ -707,7 +869,7 @@ impl Evaluator {
             }
 
 
-            _ => Err(format!("Runtime Error: Unknown gate name '{}' for matrix generation.", name))
+            _ => Err(format!("Unknown gate name '{}' for matrix generation.", name))
         }
     }
 
@@ -715,42 +877,81 @@ impl Evaluator {
     fn eval_measure(
         target_expr: &Box<ASTNode>,
         env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
 
+        if CIRCUIT_CONTROLS.with(|controls| !controls.borrow().is_empty()) {
+            return Err("'measure' cannot be used inside a controlled circuit.".to_string().into());
+        }
         let target_val = Self::evaluate(target_expr, env)?;
-        let (state_rc, target_index, reg_size) = match target_val {
+        Self::measure_value(target_val)
+    }
 
-            RuntimeValue::Qubit { state, index, size } => (state, index, size),
-            _ => return Err(format!("Runtime Error: 'measure' can only be used on a single Qubit, got {}.", target_val.type_name())),
-        };
+    /// A qubit measures to an Int; a register measures to an array of bits, qubit 0 first.
+    fn measure_value(target_val: RuntimeValue) -> Result<RuntimeValue, RuntimeError> {
+        match target_val {
+            RuntimeValue::Qubit { state, index } => {
+                let (state, shift) = state.resolve();
+                let total = state.borrow().num_qubits();
+                Self::perform_measurement(&state, index + shift, total)
+            }
+            RuntimeValue::QuantumRegister { state, size } => {
+                let (state, offset) = state.resolve();
+                let total = state.borrow().num_qubits();
+                let mut bits = Vec::with_capacity(size);
+                for i in 0..size {
+                    let bit = Self::perform_measurement(&state, offset + i, total)?;
+                    bits.push(Rc::new(RefCell::new(bit)));
+                }
+                Ok(RuntimeValue::Register(bits))
+            }
+            _ => Err(format!("'measure' expects a Qubit or a quantum register, got {}.", target_val.type_name()).into()),
+        }
+    }
 
-        Self::perform_measurement(&state_rc, target_index, reg_size)
+    /// Integer view of measured bits: element i is bit i.
+    pub fn bits_to_int(bits: &[Rc<RefCell<RuntimeValue>>]) -> Result<i64, String> {
+        if bits.len() > 63 {
+            return Err(format!("{} bits do not fit in an Int.", bits.len()));
+        }
+        let mut value = 0i64;
+        for (i, bit) in bits.iter().enumerate() {
+            match &*bit.borrow() {
+                RuntimeValue::Int(0) => {}
+                RuntimeValue::Int(1) => value |= 1 << i,
+                other => return Err(format!("Element {} is not a bit (0 or 1), got {}.", i, other)),
+            }
+        }
+        Ok(value)
     }
 
-    fn perform_measurement(
-        state_rc: &Rc<RefCell<HashMap<usize, (f64, f64)>>>,
+    pub fn perform_measurement(
+        state_rc: &Rc<RefCell<QuantumState>>,
         target_index: usize,
         total_size: usize,
-    ) -> Result<RuntimeValue, String> {
-
-        let mut state_map_guard = state_rc.borrow_mut();
-        let old_state_map = &*state_map_guard;
+    ) -> Result<RuntimeValue, RuntimeError> {
 
         if target_index >= total_size {
-            return Err(format!("Runtime Error: Qubit index {} is out of bounds for size {}.", target_index, total_size));
+            return Err(format!("Qubit index {} is out of bounds for size {}.", target_index, total_size).into());
         }
 
-        let target_mask = 1 << target_index;
-        let mut prob0 = 0.0;
+        let mut state = state_rc.borrow_mut();
+        environment::with_rng(|rng| {
+            let measured_result = Self::collapse_qubit(&mut state, target_index, rng);
 
-
-        for (&basis_state, &amp_tuple) in old_state_map.iter() {
-            if (basis_state & target_mask) == 0 {
-                prob0 += amp_tuple.0.powi(2) + amp_tuple.1.powi(2);
+            let (p01, p10) = state.readout_error(target_index);
+            let flip_probability = if measured_result == 0 { p01 } else { p10 };
+            if flip_probability > 0.0 && rng.gen::<f64>() < flip_probability {
+                return Ok(RuntimeValue::Int(1 - measured_result));
             }
-        }
 
-        let mut rng = rand::thread_rng();
+            Ok(RuntimeValue::Int(measured_result))
+        })
+    }
+
+    /// Projective measurement of one qubit in the Z basis, without readout error.
+    fn collapse_qubit(state: &mut QuantumState, target_index: usize, rng: &mut impl Rng) -> i64 {
+        let prob0 = state.probability_zero(target_index);
+
         let rand_val: f64 = rng.gen_range(0.0..1.0);
         let measured_result: i64;
         let probability_of_outcome: f64;
@@ -765,25 +966,27 @@ impl Evaluator {
 
         let norm_factor = if probability_of_outcome.abs() < 1e-9 { 1.0 } else { 1.0 / probability_of_outcome.sqrt() };
 
-        let mut new_state_map = HashMap::new();
-
-
-        for (&basis_state, &amp_tuple) in old_state_map.iter() {
-            let bit_at_index = (basis_state >> target_index) & 1;
-
+        state.collapse(target_index, measured_result as usize, norm_factor);
+        measured_result
+    }
 
-            if bit_at_index as i64 == measured_result {
-                let (real, imag) = amp_tuple;
-                let normalized_amp = Self::complex_scalar_mul((real, imag), norm_factor);
-                new_state_map.insert(basis_state, normalized_amp);
-            }
+    /// Reset a qubit to |0>: measure it, then flip it if the outcome was 1.
+    pub fn reset_qubit(
+        state_rc: &Rc<RefCell<QuantumState>>,
+        target_index: usize,
+        total_size: usize,
+    ) -> Result<(), String> {
 
+        if target_index >= total_size {
+            return Err(format!("Qubit index {} is out of bounds for size {}.", target_index, total_size));
         }
 
-
-        *state_map_guard = new_state_map;
-
-        Ok(RuntimeValue::Int(measured_result))
+        let mut state = state_rc.borrow_mut();
+        if environment::with_rng(|rng| Self::collapse_qubit(&mut state, target_index, rng)) == 1 {
+            let x = Self::get_gate_unitary("x", &[], false)?;
+            state.apply_unitary(&x, &[target_index], &[])?;
+        }
+        Ok(())
     }
 
 
@@ -792,7 +995,7 @@ impl Evaluator {
         index_expr: &Box<ASTNode>,
         loc: &Loc,
         env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
 
         let collection_val = Self::evaluate(collection_expr, env)?;
         let index_val = Self::evaluate(index_expr, env)?;
@@ -801,37 +1004,31 @@ impl Evaluator {
             RuntimeValue::Register(elements) => {
                 let index = match index_val {
                     RuntimeValue::Int(i) => i as usize,
-                    _ => return Err(format!("Runtime Error at {}: Array index must be an integer.", loc)),
+                    _ => return Err(RuntimeError::at(loc, "Array index must be an integer.")),
                 };
-                elements.get(index).map(|e| e.borrow().clone()).ok_or(format!("Runtime Error at {}: Array index {} out of bounds for array of size {}.", loc, index, elements.len()))
+                elements.get(index).map(|e| e.borrow().clone()).ok_or(RuntimeError::at(loc, format!("Array index {} out of bounds for array of size {}.", index, elements.len())))
             }
 
             RuntimeValue::QuantumRegister { size, state } => {
                 let index = match index_val {
                     RuntimeValue::Int(i) => i,
-                    _ => return Err(format!("Runtime Error at {}: Qubit index must be an integer.", loc)),
+                    _ => return Err(RuntimeError::at(loc, "Qubit index must be an integer.")),
                 };
                 if index < 0 || index as usize >= size {
-                    return Err(format!(
-                        "Runtime Error at {}: Qubit index {} out of bounds for register of size {}.",
-                        loc, index, size
-                    ));
+                    return Err(RuntimeError::at(loc, format!(
+                        "Qubit index {} out of bounds for register of size {}.", index, size
+                    )));
                 }
-                Ok(RuntimeValue::Qubit {
-                    state: state.clone(),
-                    index: index as usize,
-                    size,
-                })
+                Ok(RuntimeValue::Qubit { state, index: index as usize })
             }
 
             RuntimeValue::Dict(map) => {
                 let key = Self::value_to_string_key(index_val)?;
-                map.get(&key).map(|v| v.borrow().clone()).ok_or(format!("Runtime Error at {}: Key '{}' not found in dictionary.", loc, key))
+                map.get(&key).map(|v| v.borrow().clone()).ok_or(RuntimeError::at(loc, format!("Key '{}' not found in dictionary.", key)))
             }
-            _ => Err(format!(
-                "Runtime Error at {}: Subscript '[]' is not supported for type {:?}",
-                loc, collection_val.type_name()
-            )),
+            _ => Err(RuntimeError::at(loc, format!(
+                "Subscript '[]' is not supported for type {:?}", collection_val.type_name()
+            ))),
         }
     }
 
@@ -839,7 +1036,7 @@ impl Evaluator {
     object_expr: &ASTNode,
     member: &str,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let object = Self::evaluate(object_expr, env)?;
     match object {
 
@@ -848,57 +1045,83 @@ impl Evaluator {
             match module_env.borrow().get(member) {
                 Some(value_rc) => Ok(value_rc.borrow().clone()),
                 None => Err(format!(
-                    "Runtime Error: Module does not have a member named '{}'",
+                    "Module does not have a member named '{}'",
                     member
-                ))
+                ).into())
             }
         }
 
         RuntimeValue::QuantumRegister { size, .. } => {
             match member {
                 "length" => Ok(RuntimeValue::Int(size as i64)),
-                _ => Err(format!("Runtime Error: QuantumRegister does not have a member named '{}'", member)),
+                _ => Err(format!("QuantumRegister does not have a member named '{}'", member).into()),
             }
         }
         RuntimeValue::Register(rc_register) => {
             match member {
                 "length" => Ok(RuntimeValue::Int(rc_register.len() as i64)),
-                _ => Err(format!("Runtime Error: Array does not have a member named '{}'", member)),
+                "as_int" => Ok(RuntimeValue::Int(Self::bits_to_int(&rc_register)?)),
+                _ => Err(format!("Array does not have a member named '{}'", member).into()),
             }
         }
         RuntimeValue::String(s) => {
             match member {
                 "length" => Ok(RuntimeValue::Int(s.len() as i64)),
-                _ => Err(format!("Runtime Error: String does not have a member named '{}'", member)),
+                _ => Err(format!("String does not have a member named '{}'", member).into()),
             }
         }
         RuntimeValue::Dict(map) => {
-            map.get(member).map(|v| v.borrow().clone()).ok_or(format!("Runtime Error: Value does not have a member named '{}'", member))
+            map.get(member).map(|v| v.borrow().clone()).ok_or(format!("Value does not have a member named '{}'", member).into())
+        }
+        RuntimeValue::Error { kind, message, loc } => {
+            match member {
+                "kind" => Ok(RuntimeValue::String(kind)),
+                "message" => Ok(RuntimeValue::String(message)),
+                "line" => Ok(RuntimeValue::Int(loc.map_or(0, |loc| loc.line as i64))),
+                "column" => Ok(RuntimeValue::Int(loc.map_or(0, |loc| loc.column as i64))),
+                _ => Err(format!("Error does not have a member named '{}'", member).into()),
+            }
+        }
+        RuntimeValue::Struct { ref name, ref fields } => {
+            if let Some((_, value)) = fields.iter().find(|(field, _)| field == member) {
+                return Ok(value.borrow().clone());
+            }
+            let method = Self::lookup_method(name, member, env)
+                .ok_or(format!("Struct '{}' does not have a field or method named '{}'", name, member))?;
+            Ok(RuntimeValue::BoundMethod { receiver: Box::new(object.clone()), method: Box::new(method) })
         }
-        _ => Err(format!("Runtime Error: Member access (.) is not supported for type {:?}", object.type_name())),
+        RuntimeValue::EnumValue { ref enum_name, .. } => {
+            let method = Self::lookup_method(enum_name, member, env)
+                .ok_or(format!("Enum '{}' does not have a method named '{}'", enum_name, member))?;
+            Ok(RuntimeValue::BoundMethod { receiver: Box::new(object.clone()), method: Box::new(method) })
+        }
+        RuntimeValue::EnumType { name, variants } => {
+            match variants.iter().find(|(variant, _)| variant == member) {
+                Some((variant, 0)) => Ok(RuntimeValue::EnumValue { enum_name: name, variant: variant.clone(), payload: vec![] }),
+                Some((variant, arity)) => Ok(RuntimeValue::VariantConstructor { enum_name: name, variant: variant.clone(), arity: *arity }),
+                None => Self::lookup_method(&name, member, env)
+                    .ok_or(format!("Enum '{}' does not have a variant or method named '{}'", name, member).into()),
+            }
+        }
+        RuntimeValue::StructType { name, .. } => {
+            Self::lookup_method(&name, member, env)
+                .ok_or(format!("Struct '{}' does not have a method named '{}'", name, member).into())
+        }
+        _ => Err(format!("Member access (.) is not supported for type {:?}", object.type_name()).into()),
     }
 }
 
 
-    fn insert_if_nonzero(
-    state_map: &mut HashMap<usize, (f64, f64)>,
-    index: usize,
-    amp: C64,
-) {
-
-    if amp.norm_sqr() > 1e-12 {
-        state_map.insert(index, (amp.re, amp.im));
+    fn lookup_method(type_name: &str, method: &str, env: &Rc<RefCell<Environment>>) -> Option<RuntimeValue> {
+        env.borrow().get(&format!("{}.{}", type_name, method)).map(|value| value.borrow().clone())
     }
-}
-
-
 
     fn eval_range_expression(
     start_expr: &Box<ASTNode>,
     end_expr: &Box<ASTNode>,
     inclusive: bool,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let start_val = Self::evaluate(start_expr, env)?;
     let end_val = Self::evaluate(end_expr, env)?;
     match (start_val, end_val) {
@@ -906,7 +1129,7 @@ impl Evaluator {
             let range: Vec<i64> = if inclusive { (start..=end).collect() } else { (start..end).collect() };
             Ok(RuntimeValue::Range(range))
         }
-        _ => Err("Runtime Error: Range boundaries must be integers.".to_string()),
+        _ => Err("Range boundaries must be integers.".to_string().into()),
     }
 }
 
@@ -916,9 +1139,9 @@ impl Evaluator {
     path: &ImportPath,
     spec: &ImportSpec,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let file_path = Self::resolve_import_path(path)?;
-    let source = fs::read_to_string(&file_path).map_err(|e| format!("Runtime Error: Failed to import file '{}': {}", file_path, e))?;
+    let source = fs::read_to_string(&file_path).map_err(|e| format!("Failed to import file '{}': {}", file_path, e))?;
     let mut lexer = Lexer::new(&source);
     let tokens = lexer.tokenize().map_err(|e| format!("Import Lexer Error: {}", e))?;
     let mut parser = Parser::new(tokens);
@@ -931,7 +1154,7 @@ impl Evaluator {
             for name in names {
                 match module_store.get(name) {
                     Some(value_rc) => env.borrow_mut().set(name.clone(), value_rc.borrow().clone()),
-                    None => return Err(format!("Runtime Error: Cannot import name '{}' from file '{}'.", name, file_path)),
+                    None => return Err(format!("Cannot import name '{}' from file '{}'.", name, file_path).into()),
                 }
             }
         }
@@ -946,35 +1169,63 @@ impl Evaluator {
 
     fn eval_try_catch(
     try_block: &ASTNode,
-    error_variable: &Option<String>,
-    catch_block: &ASTNode,
+    handlers: &[CatchClause],
+    finally_block: Option<&ASTNode>,
+    loc: &Loc,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
-    match Self::evaluate(try_block, env) {
-        Ok(value) => Ok(value),
-        Err(error_message) => {
-            let mut catch_env = Environment::new_enclosed(env.clone());
-            if let Some(var_name) = error_variable {
-                catch_env.set(var_name.clone(), RuntimeValue::String(error_message));
+) -> Result<RuntimeValue, RuntimeError> {
+    let outcome = match Self::evaluate(try_block, env) {
+        Err(error) => {
+            let value = error.clone().located(Some(*loc)).value();
+            let handler = match &value {
+                RuntimeValue::Error { kind, .. } => handlers.iter().find(|handler| handler.kinds.is_empty() || handler.kinds.contains(kind)),
+                _ => None,
+            };
+            match handler {
+                Some(handler) => {
+                    let mut catch_env = Environment::new_enclosed(env.clone());
+                    if let Some(var_name) = &handler.variable {
+                        catch_env.set(var_name.clone(), value);
+                    }
+                    Self::evaluate(&handler.body, &Rc::new(RefCell::new(catch_env)))
+                }
+                None => Err(error),
             }
-            let catch_env_rc = Rc::new(RefCell::new(catch_env));
-            Self::evaluate(catch_block, &catch_env_rc)
         }
+        ok => ok,
+    };
+
+    let Some(finally_block) = finally_block else {
+        return outcome;
+    };
+    let finally_result = Self::evaluate(finally_block, env)?;
+    match finally_result {
+        RuntimeValue::ReturnValue(_) | RuntimeValue::Break | RuntimeValue::Continue => Ok(finally_result),
+        _ => outcome,
     }
 }
 
+    fn eval_throw(value: &ASTNode, loc: &Loc, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
+        let error = match Self::evaluate(value, env)? {
+            RuntimeValue::Error { kind, message, loc: origin } => RuntimeValue::Error { kind, message, loc: origin.or(Some(*loc)) },
+            RuntimeValue::String(message) => RuntimeValue::Error { kind: "Error".to_string(), message, loc: Some(*loc) },
+            other @ (RuntimeValue::Struct { .. } | RuntimeValue::EnumValue { .. }) => RuntimeValue::Error {
+                kind: other.type_name().to_string(),
+                message: other.to_string(),
+                loc: Some(*loc),
+            },
+            other => return Err(RuntimeError::at(loc, format!("Cannot throw a value of type {}; throw an Error, a string, a struct or an enum.", other.type_name()))),
+        };
+        Err(RuntimeError::Thrown(error))
+    }
+
     fn eval_for_statement(
     variable_name: &str,
     iterator_expr: &Box<ASTNode>,
     body: &Box<ASTNode>,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
-    let iterator_val = Self::evaluate(iterator_expr, env)?;
-    let iterable: Vec<RuntimeValue> = match iterator_val {
-        RuntimeValue::Range(range_vec) => range_vec.into_iter().map(RuntimeValue::Int).collect(),
-        RuntimeValue::Register(elements) => elements.iter().map(|rc_cell| rc_cell.borrow().clone()).collect(),
-        _ => return Err(format!("Runtime Error: For loop iterator must be a range or an array, not {:?}.", iterator_val.type_name())),
-    };
+) -> Result<RuntimeValue, RuntimeError> {
+    let iterable = Self::eval_for_items(iterator_expr, env)?;
     for item in iterable {
         env.borrow_mut().set(variable_name.to_string(), item);
         let body_result = Self::evaluate(body, env)?;
@@ -988,22 +1239,64 @@ impl Evaluator {
     Ok(RuntimeValue::None)
 }
 
+    fn eval_for_items(iterator_expr: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        let iterator_val = Self::evaluate(iterator_expr, env)?;
+        match iterator_val {
+            RuntimeValue::Range(range_vec) => Ok(range_vec.into_iter().map(RuntimeValue::Int).collect()),
+            RuntimeValue::Register(elements) => Ok(elements.iter().map(|rc_cell| rc_cell.borrow().clone()).collect()),
+            _ => Err(format!("For loop iterator must be a range or an array, not {:?}.", iterator_val.type_name()).into()),
+        }
+    }
+
+    // The inverse of a loop runs the inverse of its body for each item, last item first.
+    fn eval_daggered_for(
+        variable_name: &str,
+        iterator_expr: &ASTNode,
+        body: &ASTNode,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        let statements = match body {
+            ASTNode::Block(stmts) => stmts,
+            _ => return Err("Internal Error: For loop body is not a Block.".to_string().into()),
+        };
+        for item in Self::eval_for_items(iterator_expr, env)?.into_iter().rev() {
+            env.borrow_mut().set(variable_name.to_string(), item);
+            if let RuntimeValue::ReturnValue(val) = Self::eval_daggered_block(statements, env)? {
+                return Ok(RuntimeValue::ReturnValue(val));
+            }
+        }
+        Ok(RuntimeValue::None)
+    }
+
     fn eval_function_call(
         callee_expr: &ASTNode,
         arguments: &[ASTNode],
         loc: &Loc,
         env: &Rc<RefCell<Environment>>,
         is_dagger: bool
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
 
         let evaluated_args = Self::eval_arguments(arguments, env)?;
         let function = Self::evaluate(callee_expr, env)?;
         let name = format!("{:?}", callee_expr);
+        Self::call_value(function, evaluated_args, &name, loc, is_dagger)
+    }
 
+    fn call_value(
+        function: RuntimeValue,
+        mut evaluated_args: Vec<RuntimeValue>,
+        name: &str,
+        loc: &Loc,
+        is_dagger: bool
+    ) -> Result<RuntimeValue, RuntimeError> {
         match function {
+            RuntimeValue::BoundMethod { receiver, method } => {
+                evaluated_args.insert(0, *receiver);
+                Self::call_value(*method, evaluated_args, name, loc, is_dagger)
+            }
             RuntimeValue::BuiltinFunction(func_name) => {
                 if is_dagger {
-                    return Err(format!("Runtime Error at {}: Dagger is not supported for built-in function '{}'.", loc, func_name));
+                    return Err(RuntimeError::at(loc, format!("Dagger is not supported for built-in function '{}'.", func_name)));
                 }
 
                 match func_name.as_str() {
@@ -1018,26 +1311,53 @@ impl Evaluator {
                     "len" => Self::builtin_len(evaluated_args),
                     "debug_state" => Self::builtin_debug_state(evaluated_args),
                     "assert" => Self::builtin_assert(evaluated_args),
-                    _ => Err(format!("Runtime Error at {}: Unknown built-in function '{}'.", loc, func_name)),
+                    "set_seed" => Self::builtin_set_seed(evaluated_args),
+                    "observable" => Self::builtin_observable(evaluated_args),
+                    "expectation" => Self::builtin_observable_stat("expectation", evaluated_args),
+                    "variance" => Self::builtin_observable_stat("variance", evaluated_args),
+                    "amplitude" => Self::builtin_amplitude(evaluated_args),
+                    "probabilities" => Self::builtin_probabilities(evaluated_args),
+                    "fidelity" => Self::builtin_fidelity(evaluated_args),
+                    "reduced_density_matrix" => Self::builtin_reduced_density_matrix(evaluated_args),
+                    "Error" => Self::builtin_error(evaluated_args),
+                    "abs" | "arg" | "conj" | "exp" | "real" | "imag" => Self::builtin_complex_function(&func_name, evaluated_args),
+                    _ => Err(RuntimeError::at(loc, format!("Unknown built-in function '{}'.", func_name))),
                 }
             }
-            RuntimeValue::Function { parameters, body, env: func_env } => {
+            RuntimeValue::Function { parameters, body, env: func_env, where_clause } => {
 
                 let mut function_scope = Environment::new_enclosed(func_env);
                 if parameters.len() != evaluated_args.len() {
-                    return Err(format!(
-                        "Runtime Error at {}: Function '{}' expected {} arguments, but got {}.",
-                        loc, name, parameters.len(), evaluated_args.len()
-                    ));
+                    return Err(RuntimeError::at(loc, format!(
+                        "Function '{}' expected {} arguments, but got {}.", name, parameters.len(), evaluated_args.len()
+                    )));
                 }
+                let mut sizes: HashMap<&str, usize> = HashMap::new();
                 for (param, arg_val) in parameters.iter().zip(evaluated_args) {
-                    function_scope.set(param.name.clone(), arg_val);
+                    // `QuantumRegister[n]` binds the size parameter `n` to the register's size.
+                    if let Type::GenericRegister(size_param) = &param.param_type {
+                        let RuntimeValue::QuantumRegister { size, .. } = &arg_val else {
+                            return Err(RuntimeError::at(loc, format!(
+                                "Argument '{}' of '{}' must be a quantum register, got {}.", param.name, name, arg_val.type_name()
+                            )));
+                        };
+                        match sizes.insert(size_param, *size) {
+                            Some(bound) if bound != *size => return Err(RuntimeError::at(loc, format!(
+                                "'{}' needs registers of the same size '{}', got {} and {} qubits.", name, size_param, bound, size
+                            ))),
+                            _ => function_scope.set(size_param.clone(), RuntimeValue::Int(*size as i64)),
+                        }
+                    }
+                    function_scope.set(param.name.clone(), Self::promote(arg_val, &param.param_type));
                 }
                 let function_scope_rc = Rc::new(RefCell::new(function_scope));
+                if !Self::where_clause_holds(&where_clause, &function_scope_rc)? {
+                    return Err(RuntimeError::at(loc, format!("Call to '{}' does not satisfy its 'where' clause.", name)));
+                }
 
                 let statements = match &*body {
                     ASTNode::Block(stmts) => stmts,
-                    _ => return Err(format!("Internal Error: Function body for '{}' is not a Block.", name)),
+                    _ => return Err(format!("Internal Error: Function body for '{}' is not a Block.", name).into()),
                 };
 
                 let result = if is_dagger {
@@ -1052,14 +1372,37 @@ impl Evaluator {
                     Ok(result)
                 }
             }
-            _ => Err(format!("Runtime Error at {}: '{}' is not a callable function.", loc, name)),
+            RuntimeValue::Gate { base_name, is_dagger: gate_dagger, num_controls, matrix } => {
+                let gate = RuntimeValue::Gate { base_name, is_dagger: gate_dagger != is_dagger, num_controls, matrix };
+                Self::apply_gate_value(gate, &evaluated_args, Vec::new(), loc)
+            }
+            RuntimeValue::StructType { name: struct_name, fields } => {
+                if fields.len() != evaluated_args.len() {
+                    return Err(RuntimeError::at(loc, format!(
+                        "Struct '{}' has {} fields, but got {} values.", struct_name, fields.len(), evaluated_args.len()
+                    )));
+                }
+                let fields = fields.into_iter().zip(evaluated_args)
+                    .map(|(field, value)| (field, Rc::new(RefCell::new(value))))
+                    .collect();
+                Ok(RuntimeValue::Struct { name: struct_name, fields })
+            }
+            RuntimeValue::VariantConstructor { enum_name, variant, arity } => {
+                if arity != evaluated_args.len() {
+                    return Err(RuntimeError::at(loc, format!(
+                        "Variant {}.{} carries {} values, but got {}.", enum_name, variant, arity, evaluated_args.len()
+                    )));
+                }
+                Ok(RuntimeValue::EnumValue { enum_name, variant, payload: evaluated_args })
+            }
+            _ => Err(RuntimeError::at(loc, format!("'{}' is not a callable function.", name))),
         }
     }
 
     fn eval_dict_literal(
     pairs: &Vec<(ASTNode, ASTNode)>,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let mut map = HashMap::new();
     for (key_node, value_node) in pairs {
         let key_str = match key_node {
@@ -1083,7 +1426,7 @@ impl Evaluator {
             let mut pbuf = PathBuf::new();
             for segment in segments { pbuf.push(segment); }
             pbuf.set_extension("qc");
-            pbuf.to_str().map(|s| s.to_string()).ok_or("Runtime Error: Invalid non-UTF8 module path.".to_string())
+            pbuf.to_str().map(|s| s.to_string()).ok_or("Invalid non-UTF8 module path.".to_string())
         }
     }
 }
@@ -1091,11 +1434,18 @@ impl Evaluator {
     fn eval_daggered_block(
     statements: &Vec<ASTNode>,
     env: &Rc<RefCell<Environment>>
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
 
     let mut last_result = RuntimeValue::None;
 
     for stmt in statements.iter().rev() {
+        if let ASTNode::For { variable, iterator, body } = stmt {
+            last_result = Self::eval_daggered_for(variable, iterator, body, env)?;
+            if let RuntimeValue::ReturnValue(_) = last_result {
+                return Ok(last_result);
+            }
+            continue;
+        }
         let flipped_node = match stmt {
             ASTNode::Apply { gate_expr, arguments, loc } => {
                 let daggered_gate_expr = match &**gate_expr {
@@ -1132,7 +1482,7 @@ impl Evaluator {
                                     loc: *loc
                                 }
                             }
-                            _ => return Err("Daggering nested complex gate expressions is not yet supported.".to_string())
+                            other => ASTNode::Dagger { gate_expr: Box::new(other.clone()), loc: *loc },
                         };
                         ASTNode::Controlled {
                             gate_expr: Box::new(inner_gate),
@@ -1150,7 +1500,7 @@ impl Evaluator {
                             loc: *loc
                         }
                     }
-                    _ => return Err("Daggering complex gate expressions is not yet supported.".to_string())
+                    other => ASTNode::Dagger { gate_expr: Box::new(other.clone()), loc: *loc },
                 };
 
                 ASTNode::Apply {
@@ -1185,72 +1535,111 @@ impl Evaluator {
 
 
     pub fn print_quantum_state(
-        state: &Rc<RefCell<HashMap<usize, (f64, f64)>>>,
+        state: &Rc<RefCell<QuantumState>>,
         size: usize,
         max_entries: usize
     ) {
         let num_qubits = size;
-        let amplitudes_map = state.borrow();
-        println!("--- Quantum State ({} qubits, {} non-zero amplitudes) ---", num_qubits, amplitudes_map.len());
-
-        let mut sorted_keys: Vec<usize> = amplitudes_map.keys().cloned().collect();
-        sorted_keys.sort();
+        let state = state.borrow();
+        let amplitudes = match state.nonzero_amplitudes() {
+            Ok(amplitudes) => amplitudes,
+            Err(_) => {
+                if let QuantumState::Mps(mps) = &*state {
+                    println!("--- Quantum State ({} qubits, {}) ---", num_qubits, state.backend_name());
+                    println!("  max bond dimension {}, truncation error {:.3e}", mps.max_bond_dimension(), mps.truncation_error);
+                    println!("-----------------------------------");
+                    return;
+                }
+                if let QuantumState::Stabilizer(tableau) = &*state {
+                    let generators = tableau.generators();
+                    println!("--- Quantum State ({} qubits, {} stabilizer generators, {}) ---", num_qubits, generators.len(), state.backend_name());
+                    for (count, generator) in generators.iter().enumerate() {
+                        if count >= max_entries {
+                            println!("  ... ({} more entries hidden)", generators.len() - count);
+                            break;
+                        }
+                        println!("  {}", generator);
+                    }
+                    println!("-----------------------------------");
+                    return;
+                }
+                let populations = match state.probabilities() {
+                    Ok(populations) => populations,
+                    Err(e) => {
+                        println!("--- Quantum State ({} qubits, {}) ---", num_qubits, state.backend_name());
+                        println!("  {}", e);
+                        println!("-----------------------------------");
+                        return;
+                    }
+                };
+                println!("--- Quantum State ({} qubits, {} populated basis states, {}) ---", num_qubits, populations.len(), state.backend_name());
+                for (count, (i, p)) in populations.iter().enumerate() {
+                    if count >= max_entries {
+                        println!("  ... ({} more entries hidden)", populations.len() - count);
+                        break;
+                    }
+                    println!("  |{:0width$b}> : p = {:.6}", i, p, width = num_qubits);
+                }
+                println!("-----------------------------------");
+                return;
+            }
+        };
+        println!("--- Quantum State ({} qubits, {} non-zero amplitudes, {}) ---", num_qubits, amplitudes.len(), state.backend_name());
+        if let QuantumState::Mps(mps) = &*state {
+            println!("  max bond dimension {}, truncation error {:.3e}", mps.max_bond_dimension(), mps.truncation_error);
+        }
 
-        for (count, i) in sorted_keys.iter().enumerate() {
+        for (count, (i, amp)) in amplitudes.iter().enumerate() {
             if count >= max_entries {
-                println!("  ... ({} more entries hidden)", sorted_keys.len() - count);
+                println!("  ... ({} more entries hidden)", amplitudes.len() - count);
                 break;
             }
-            let (real, imag) = amplitudes_map[i];
             let basis_state = format!("{:0width$b}", i, width = num_qubits);
-            println!("  |{}> : {:.6} + {:.6}i", basis_state, real, imag);
+            println!("  |{}> : {:.6} + {:.6}i", basis_state, amp.re, amp.im);
         }
         println!("-----------------------------------");
     }
-    fn builtin_debug_state(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
+    fn builtin_debug_state(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
         if args.len() != 1 {
-            return Err("Runtime Error: 'debug_state' expects exactly 1 argument (a quantum register).".to_string());
+            return Err("'debug_state' expects exactly 1 argument (a quantum register).".to_string().into());
         }
         match &args[0] {
-            RuntimeValue::QuantumRegister { size, state } => {
-
-                Self::print_quantum_state(state, *size, 10);
+            RuntimeValue::QuantumRegister { state, .. } => {
+                if environment::is_quiet() {
+                    return Ok(RuntimeValue::None);
+                }
+                let (state, _) = state.resolve();
+                let num_qubits = state.borrow().num_qubits();
+                Self::print_quantum_state(&state, num_qubits, 10);
                 Ok(RuntimeValue::None)
             }
-            _ => Err(format!("Runtime Error: 'debug_state' argument must be a quantum register, got {}.", args[0].type_name())),
+            _ => Err(format!("'debug_state' argument must be a quantum register, got {}.", args[0].type_name()).into()),
         }
     }
 
 
-    pub fn builtin_measure(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
+    pub fn builtin_measure(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
         if args.len() != 1 {
-            return Err("Runtime Error: 'measure' expects exactly one qubit argument.".to_string());
+            return Err("'measure' expects exactly one qubit or register argument.".to_string().into());
         }
 
         let target_val = args.into_iter().next().unwrap();
-
-        let (state_rc, target_index, reg_size) = match target_val {
-            RuntimeValue::Qubit { state, index, size } => (state, index, size),
-            _ => return Err(format!("Runtime Error: 'measure' can only be used on a single Qubit, got {}.", target_val.type_name())),
-        };
-
-
-        Self::perform_measurement(&state_rc, target_index, reg_size)
+        Self::measure_value(target_val)
     }
 
-    fn builtin_assert(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
+    fn builtin_assert(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
     if args.len() != 2 {
-        return Err("Runtime Error: 'assert' expects 2 arguments (condition, message).".to_string());
+        return Err("'assert' expects 2 arguments (condition, message).".to_string().into());
     }
 
     let condition = match &args[0] {
         RuntimeValue::Bool(b) => *b,
-        _ => return Err(format!("Runtime Error: 'assert' argument 1 must be a Bool, got {}.", args[0].type_name())),
+        _ => return Err(format!("'assert' argument 1 must be a Bool, got {}.", args[0].type_name()).into()),
     };
 
     let message = match &args[1] {
         RuntimeValue::String(s) => s.clone(),
-        _ => return Err(format!("Runtime Error: 'assert' argument 2 must be a String, got {}.", args[1].type_name())),
+        _ => return Err(format!("'assert' argument 2 must be a String, got {}.", args[1].type_name()).into()),
     };
 
     if condition {
@@ -1258,10 +1647,10 @@ impl Evaluator {
         Ok(RuntimeValue::None)
     } else {
 
-        Err(format!("Assertion Failed: {}", message))
+        Err(format!("Assertion Failed: {}", message).into())
     }
 }
-    fn builtin_print(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
+    fn builtin_print(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
         let output: Vec<String> = args.into_iter()
             .map(|val| {
                 match val {
@@ -1274,98 +1663,346 @@ impl Evaluator {
                 }
             })
             .collect();
-        println!("{}", output.join(" "));
+        if !environment::is_quiet() {
+            println!("{}", output.join(" "));
+        }
         Ok(RuntimeValue::None)
     }
 
-    fn builtin_to_int(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'to_int' expects exactly one argument.".to_string()); }
+    fn builtin_to_int(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'to_int' expects exactly one argument.".to_string().into()); }
         let val = &args[0];
         match val {
             RuntimeValue::Int(i) => Ok(RuntimeValue::Int(*i)),
             RuntimeValue::Float(f) => Ok(RuntimeValue::Int(*f as i64)),
-            RuntimeValue::String(s) => s.parse::<i64>().map(RuntimeValue::Int).map_err(|_| format!("Runtime Error: Could not parse string '{}' as int.", s)),
+            RuntimeValue::String(s) => s.parse::<i64>().map(RuntimeValue::Int).map_err(|_| format!("Could not parse string '{}' as int.", s).into()),
             RuntimeValue::Bool(b) => Ok(RuntimeValue::Int(if *b { 1 } else { 0 })),
-            _ => Err(format!("Runtime Error: Cannot convert type {} to int.", val.type_name())),
+            _ => Err(format!("Cannot convert type {} to int.", val.type_name()).into()),
         }
     }
-    fn builtin_len(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'len' expects exactly one argument.".to_string()); }
+    fn builtin_len(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'len' expects exactly one argument.".to_string().into()); }
         let val = &args[0];
         match val {
             RuntimeValue::String(s) => Ok(RuntimeValue::Int(s.len() as i64)),
             RuntimeValue::Register(arr) => Ok(RuntimeValue::Int(arr.len() as i64)),
             RuntimeValue::Dict(map) => Ok(RuntimeValue::Int(map.len() as i64)),
             RuntimeValue::QuantumRegister { size, .. } => Ok(RuntimeValue::Int(*size as i64)),
-            _ => Err(format!("Runtime Error: len() is not supported for type {}.", val.type_name())),
+            _ => Err(format!("len() is not supported for type {}.", val.type_name()).into()),
         }
     }
-    fn builtin_to_float(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'to_float' expects exactly one argument.".to_string()); }
+    fn builtin_to_float(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'to_float' expects exactly one argument.".to_string().into()); }
         let val = &args[0];
         match val {
             RuntimeValue::Int(i) => Ok(RuntimeValue::Float(*i as f64)),
             RuntimeValue::Float(f) => Ok(RuntimeValue::Float(*f)),
-            RuntimeValue::String(s) => s.parse::<f64>().map(RuntimeValue::Float).map_err(|_| format!("Runtime Error: Could not parse string '{}' as float.", s)),
-            _ => Err(format!("Runtime Error: Cannot convert type {} to float.", val.type_name())),
+            RuntimeValue::String(s) => s.parse::<f64>().map(RuntimeValue::Float).map_err(|_| format!("Could not parse string '{}' as float.", s).into()),
+            _ => Err(format!("Cannot convert type {} to float.", val.type_name()).into()),
         }
     }
-    fn builtin_echo(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'echo' expects exactly one argument.".to_string()); }
+    fn builtin_echo(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'echo' expects exactly one argument.".to_string().into()); }
         let value=args.into_iter().next().unwrap();
-        println!("{}", value);
+        if !environment::is_quiet() {
+            println!("{}", value);
+        }
         Ok(value)
     }
-    fn builtin_type_of(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'type_of' expects exactly one argument.".to_string()); }
+    fn builtin_error(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        match args.as_slice() {
+            [RuntimeValue::String(kind), RuntimeValue::String(message)] => {
+                Ok(RuntimeValue::Error { kind: kind.clone(), message: message.clone(), loc: None })
+            }
+            _ => Err("'Error' expects two strings: (kind, message).".to_string().into()),
+        }
+    }
+    fn builtin_type_of(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'type_of' expects exactly one argument.".to_string().into()); }
         Ok(RuntimeValue::String(args[0].type_name().to_string()))
     }
-    fn builtin_to_string(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'to_string' expects exactly one argument.".to_string()); }
+    fn builtin_to_string(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'to_string' expects exactly one argument.".to_string().into()); }
         Ok(RuntimeValue::String(args[0].to_string()))
     }
-    fn builtin_maybe(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 2 { return Err("Runtime Error: 'maybe' expects two arguments: (value, confidence).".to_string()); }
+    fn builtin_maybe(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 2 { return Err("'maybe' expects two arguments: (value, confidence).".to_string().into()); }
         let value = args.clone().into_iter().next().unwrap();
         let confidence_val = args.into_iter().nth(1).unwrap();
         let confidence = match confidence_val {
             RuntimeValue::Float(p) => {
-                if !(0.0..=1.0).contains(&p) { return Err("Runtime Error: Confidence must be between 0.0 and 1.0.".to_string()); }
+                if !(0.0..=1.0).contains(&p) { return Err("Confidence must be between 0.0 and 1.0.".to_string().into()); }
                 p
             },
-            _ => return Err("Runtime Error: Confidence (arg 2) must be a Float.".to_string()),
+            _ => return Err("Confidence (arg 2) must be a Float.".to_string().into()),
         };
         Ok(RuntimeValue::Probabilistic { value: Box::new(value), confidence })
     }
-    fn builtin_sample(args: Vec<RuntimeValue>) -> Result<RuntimeValue, String> {
-        if args.len() != 1 { return Err("Runtime Error: 'sample' expects exactly one argument.".to_string()); }
+    fn builtin_set_seed(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        match args.as_slice() {
+            [RuntimeValue::Int(seed)] if *seed >= 0 => {
+                environment::set_seed(*seed as u64);
+                Ok(RuntimeValue::None)
+            }
+            _ => Err("'set_seed' expects exactly one non-negative Int.".to_string().into()),
+        }
+    }
+
+    /// `observable("0.5*ZZ - XI")` or `observable({"ZZ": 0.5, "XI": -1.0})`.
+    fn builtin_observable(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        let observable = match args.as_slice() {
+            [RuntimeValue::String(spec)] => Observable::parse(spec),
+            [RuntimeValue::Dict(entries)] => {
+                let mut terms = Vec::new();
+                for (paulis, coefficient) in entries {
+                    let coefficient = match &*coefficient.borrow() {
+                        RuntimeValue::Int(n) => *n as f64,
+                        RuntimeValue::Float(f) => *f,
+                        other => return Err(format!("Coefficient of '{}' must be a number, got {}.", paulis, other.type_name()).into()),
+                    };
+                    terms.push((coefficient, paulis.clone()));
+                }
+                terms.sort_by(|a, b| a.1.cmp(&b.1));
+                Observable::new(terms)
+            }
+            _ => return Err("'observable' expects a Pauli-sum string or a dict of Pauli strings to coefficients.".to_string().into()),
+        };
+        observable.map(RuntimeValue::Observable).map_err(RuntimeError::from)
+    }
+
+    /// `expectation(q, h)` and `variance(q, h)`; neither collapses the register.
+    fn builtin_observable_stat(name: &str, args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        let (state, qubits, observable) = match args.as_slice() {
+            [register @ RuntimeValue::QuantumRegister { .. }, RuntimeValue::Observable(observable)] => {
+                let (state, qubits) = Self::register_qubits(name, register)?;
+                (state, qubits, observable)
+            }
+            _ => return Err(format!("'{}' expects a quantum register and an observable.", name).into()),
+        };
+        let state = state.borrow();
+        let value = if name == "variance" {
+            observable.variance(&state, &qubits)
+        } else {
+            observable.expectation(&state, &qubits)
+        };
+        value.map(RuntimeValue::Float).map_err(RuntimeError::from)
+    }
+
+    // The state behind a register and the state indices of its qubits, in order.
+    fn register_qubits(name: &str, value: &RuntimeValue) -> Result<(Rc<RefCell<QuantumState>>, Vec<usize>), String> {
+        match value {
+            RuntimeValue::QuantumRegister { size, state } => {
+                let (state, offset) = state.resolve();
+                Ok((state, (offset..offset + size).collect()))
+            }
+            other => Err(format!("'{}' expects a quantum register, got {}.", name, other.type_name())),
+        }
+    }
+
+    /// Widen an Int or Float bound to a Complex slot, as the type checker allows.
+    fn promote(value: RuntimeValue, ty: &Type) -> RuntimeValue {
+        match (ty, &value) {
+            (Type::Complex | Type::Complex64 | Type::Complex128, RuntimeValue::Int(_) | RuntimeValue::Float(_)) => {
+                Self::as_complex(&value).map(RuntimeValue::Complex).unwrap_or(value)
+            }
+            _ => value,
+        }
+    }
+
+    fn as_complex(value: &RuntimeValue) -> Option<C64> {
+        match value {
+            RuntimeValue::Int(i) => Some(C64::new(*i as f64, 0.0)),
+            RuntimeValue::Float(f) => Some(C64::new(*f, 0.0)),
+            RuntimeValue::Complex(z) => Some(*z),
+            _ => None,
+        }
+    }
+
+    /// abs, arg, conj, exp, real and imag over ints, floats and complex numbers.
+    fn builtin_complex_function(name: &str, args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        let z = match args.as_slice() {
+            [value] => Self::as_complex(value)
+                .ok_or_else(|| format!("'{}' expects a number, got {}.", name, value.type_name()))?,
+            _ => return Err(format!("'{}' expects exactly one argument.", name).into()),
+        };
+        let is_complex = matches!(args[0], RuntimeValue::Complex(_));
+        Ok(match name {
+            "abs" => RuntimeValue::Float(z.norm()),
+            "arg" => RuntimeValue::Float(z.arg()),
+            "conj" => RuntimeValue::Complex(z.conj()),
+            "exp" if is_complex => RuntimeValue::Complex(z.exp()),
+            "exp" => RuntimeValue::Float(z.re.exp()),
+            "real" => RuntimeValue::Float(z.re),
+            "imag" => RuntimeValue::Float(z.im),
+            _ => return Err(format!("Unknown built-in function '{}'.", name).into()),
+        })
+    }
+
+    // Arithmetic with at least one complex operand; ints and floats are promoted.
+    fn eval_complex_op(operator: &BinaryOperator, left_val: &RuntimeValue, right_val: &RuntimeValue, loc: &Loc) -> Result<RuntimeValue, RuntimeError> {
+        let (l, r) = match (Self::as_complex(left_val), Self::as_complex(right_val)) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return match operator {
+                BinaryOperator::Equal => Ok(RuntimeValue::Bool(false)),
+                BinaryOperator::NotEqual => Ok(RuntimeValue::Bool(true)),
+                _ => Err(RuntimeError::at(loc, format!("Operator {:?} not defined for types {:?} and {:?}", operator, left_val.type_name(), right_val.type_name()))),
+            },
+        };
+        match operator {
+            BinaryOperator::Add => Ok(RuntimeValue::Complex(l + r)),
+            BinaryOperator::Sub => Ok(RuntimeValue::Complex(l - r)),
+            BinaryOperator::Mul => Ok(RuntimeValue::Complex(l * r)),
+            BinaryOperator::Div => Ok(RuntimeValue::Complex(l / r)),
+            BinaryOperator::Power if r.im == 0.0 && r.re.fract() == 0.0 && r.re.abs() <= i32::MAX as f64 => {
+                Ok(RuntimeValue::Complex(l.powi(r.re as i32)))
+            }
+            BinaryOperator::Power => Ok(RuntimeValue::Complex(l.powc(r))),
+            BinaryOperator::Equal => Ok(RuntimeValue::Bool((l - r).norm() < f64::EPSILON)),
+            BinaryOperator::NotEqual => Ok(RuntimeValue::Bool((l - r).norm() >= f64::EPSILON)),
+            _ => Err(RuntimeError::at(loc, format!(
+                "Operator {:?} not defined for types {:?} and {:?}", operator, left_val.type_name(), right_val.type_name()
+            ))),
+        }
+    }
+
+    /// `amplitude(q, 5)` or `amplitude(q, "101")` (qubit 0 first) as a complex number.
+    fn builtin_amplitude(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 2 {
+            return Err("'amplitude' expects a quantum register and a basis state.".to_string().into());
+        }
+        let (state, qubits) = Self::register_qubits("amplitude", &args[0])?;
+        let basis_state = match &args[1] {
+            RuntimeValue::Int(i) if *i >= 0 && (qubits.len() >= 63 || *i < 1 << qubits.len()) => *i as usize,
+            RuntimeValue::String(bits) if bits.len() == qubits.len() && bits.chars().all(|c| c == '0' || c == '1') => {
+                bits.chars().enumerate().fold(0, |index, (i, c)| if c == '1' { index | (1 << i) } else { index })
+            }
+            other => return Err(format!("{} is not a basis state of a {}-qubit register.", other, qubits.len()).into()),
+        };
+        let state = state.borrow();
+        if qubits.len() != state.num_qubits() {
+            return Err("'amplitude' needs a register that holds its whole state; this one is entangled with another register.".to_string().into());
+        }
+        let amplitude = state.nonzero_amplitudes()?.into_iter()
+            .find(|(i, _)| *i == basis_state)
+            .map_or(C64::new(0.0, 0.0), |(_, amp)| amp);
+        Ok(RuntimeValue::Complex(amplitude))
+    }
+
+    /// Non-zero probabilities keyed by bitstring, qubit 0 first.
+    fn builtin_probabilities(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 {
+            return Err("'probabilities' expects exactly one quantum register.".to_string().into());
+        }
+        let (state, qubits) = Self::register_qubits("probabilities", &args[0])?;
+        let probabilities = simulator::marginal_probabilities(&state.borrow(), &qubits)?;
+        let dict = probabilities.into_iter()
+            .map(|(basis_state, p)| {
+                let bits: String = (0..qubits.len()).map(|i| if (basis_state >> i) & 1 == 1 { '1' } else { '0' }).collect();
+                (bits, Rc::new(RefCell::new(RuntimeValue::Float(p))))
+            })
+            .collect();
+        Ok(RuntimeValue::Dict(dict))
+    }
+
+    /// Fidelity between two registers (or a register and a ket) of the same size.
+    fn builtin_fidelity(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 2 {
+            return Err("'fidelity' expects two quantum registers or kets.".to_string().into());
+        }
+        let a = Self::fidelity_operand(&args[0])?;
+        let b = Self::fidelity_operand(&args[1])?;
+        Ok(RuntimeValue::Float(simulator::fidelity(&a, &b)?))
+    }
+
+    fn fidelity_operand(value: &RuntimeValue) -> Result<simulator::LocalState, String> {
+        if let RuntimeValue::Ket(ket) = value {
+            if ket.num_qubits > simulator::MAX_DENSE_QUBITS {
+                return Err(format!("A {}-qubit ket is too large to expand into a state vector.", ket.num_qubits));
+            }
+            let ket = ket.normalized()?;
+            let mut vector = vec![C64::new(0.0, 0.0); 1 << ket.num_qubits];
+            for (index, amp) in ket.amplitudes {
+                vector[index] = amp;
+            }
+            return Ok(simulator::LocalState::Pure(vector));
+        }
+        let (state, qubits) = Self::register_qubits("fidelity", value)?;
+        let local = simulator::local_state(&state.borrow(), &qubits);
+        local
+    }
+
+    /// `reduced_density_matrix(q, [0, 2])`: rows of complex entries, bit i of an
+    /// index being the i-th listed qubit.
+    fn builtin_reduced_density_matrix(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 2 {
+            return Err("'reduced_density_matrix' expects a quantum register and an array of qubit indices.".to_string().into());
+        }
+        let (state, qubits) = Self::register_qubits("reduced_density_matrix", &args[0])?;
+        let kept = match &args[1] {
+            RuntimeValue::Register(indices) => indices.iter()
+                .map(|index| match &*index.borrow() {
+                    RuntimeValue::Int(i) if *i >= 0 && (*i as usize) < qubits.len() => Ok(qubits[*i as usize]),
+                    other => Err(format!("{} is not a qubit index of a {}-qubit register.", other, qubits.len())),
+                })
+                .collect::<Result<Vec<usize>, String>>()?,
+            other => return Err(format!("'reduced_density_matrix' expects an array of qubit indices, got {}.", other.type_name()).into()),
+        };
+        if kept.iter().enumerate().any(|(i, q)| kept[..i].contains(q)) {
+            return Err("'reduced_density_matrix' got the same qubit twice.".to_string().into());
+        }
+        let rho = simulator::reduced_density_matrix(&state.borrow(), &kept)?;
+        let dim = 1 << kept.len();
+        let rows = (0..dim)
+            .map(|row| {
+                let entries = (0..dim).map(|col| Rc::new(RefCell::new(RuntimeValue::Complex(rho[row * dim + col])))).collect();
+                Rc::new(RefCell::new(RuntimeValue::Register(entries)))
+            })
+            .collect();
+        Ok(RuntimeValue::Register(rows))
+    }
+    fn builtin_sample(args: Vec<RuntimeValue>) -> Result<RuntimeValue, RuntimeError> {
+        if args.len() != 1 { return Err("'sample' expects exactly one argument.".to_string().into()); }
         let prob_value = args.into_iter().next().unwrap();
         match prob_value {
             RuntimeValue::Probabilistic { value, confidence } => {
-                if rand::thread_rng().gen::<f64>() < confidence { Ok(*value) } else { Ok(RuntimeValue::None) }
+                if environment::with_rng(|rng| rng.gen::<f64>()) < confidence { Ok(*value) } else { Ok(RuntimeValue::None) }
             }
             other_value => Ok(other_value),
         }
     }
 
 
-    fn eval_let_declaration(name: &str, value_expr: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
-        let value = Self::evaluate(value_expr, env)?;
+    fn eval_let_declaration(name: &str, type_annotation: &Option<Type>, value_expr: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
+        let value = Self::evaluate(value_expr, env)?.deep_copy();
+        let value = match type_annotation {
+            Some(ty) => Self::promote(value, ty),
+            None => value,
+        };
         env.borrow_mut().set(name.to_string(), value);
         Ok(RuntimeValue::None)
     }
 
-    fn eval_assignment(target: &ASTNode, value_expr: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
-        let new_value = Self::evaluate(value_expr, env)?;
+    fn eval_assignment(target: &ASTNode, value_expr: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
+        let new_value = Self::evaluate(value_expr, env)?.deep_copy();
 
         match target {
 
             ASTNode::Identifier { name, .. } => {
                 if let Some(var_rc) = env.borrow().get(name) {
+                    // A struct is overwritten field by field, so that `self = ...` in a
+                    // `mut self` method reaches the receiver's fields.
+                    if let (RuntimeValue::Struct { name: old_name, fields: old }, RuntimeValue::Struct { name: new_name, fields: new }) = (&*var_rc.borrow(), &new_value) {
+                        if old_name == new_name {
+                            for ((_, old_cell), (_, new_cell)) in old.iter().zip(new) {
+                                *old_cell.borrow_mut() = new_cell.borrow().clone();
+                            }
+                            return Ok(RuntimeValue::None);
+                        }
+                    }
                     *std::cell::RefCell::<_>::borrow_mut(&var_rc) = new_value;
                     Ok(RuntimeValue::None)
                 } else {
-                    Err(format!("Runtime Error: Cannot assign to undefined variable '{}'.", name))
+                    Err(format!("Cannot assign to undefined variable '{}'.", name).into())
                 }
             }
 
@@ -1383,29 +2020,41 @@ impl Evaluator {
                     RuntimeValue::Register(elements) => {
                         let index = match index_val {
                             RuntimeValue::Int(i) => i as usize,
-                            _ => return Err(format!("Runtime Error at {}: Array index must be an integer.", loc)),
+                            _ => return Err(RuntimeError::at(loc, "Array index must be an integer.")),
                         };
 
                         if index >= elements.len() {
-                            return Err(format!("Runtime Error at {}: Array index {} out of bounds for array of size {}.", loc, index, elements.len()));
+                            return Err(RuntimeError::at(loc, format!("Array index {} out of bounds for array of size {}.", index, elements.len())));
                         }
 
                         *elements[index].borrow_mut() = new_value;
                         Ok(RuntimeValue::None)
                     }
-                    _ => Err(format!("Runtime Error at {}: Cannot perform subscript assignment on type {:?}", loc, collection_val.type_name()))
+                    _ => Err(RuntimeError::at(loc, format!("Cannot perform subscript assignment on type {:?}", collection_val.type_name())))
                 }
             }
 
-            _ => Err("Runtime Error: Assignment target must be an identifier or subscript expression.".to_string())
+            ASTNode::MemberAccess { object, member } => {
+                match Self::evaluate(object, env)? {
+                    RuntimeValue::Struct { name, fields } => {
+                        let (_, cell) = fields.iter().find(|(field, _)| field == member)
+                            .ok_or(format!("Struct '{}' does not have a field named '{}'", name, member))?;
+                        *cell.borrow_mut() = new_value;
+                        Ok(RuntimeValue::None)
+                    }
+                    other => Err(format!("Cannot assign to field '{}' of type {:?}", member, other.type_name()).into()),
+                }
+            }
+
+            _ => Err("Assignment target must be an identifier, subscript or field expression.".to_string().into())
         }
     }
 
-    fn eval_identifier(name: &str, loc: &Loc, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
+    fn eval_identifier(name: &str, loc: &Loc, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
         if let Some(val_rc) = env.borrow().get(name) {
             Ok(val_rc.borrow().clone())
         } else {
-            Err(format!("Runtime Error at {}: Undefined variable '{}'", loc, name))
+            Err(RuntimeError::at(loc, format!("Undefined variable '{}'", name)))
         }
     }
 
@@ -1416,7 +2065,7 @@ impl Evaluator {
         path: &ImportPath,
         alias: &str,
         env: &Rc<RefCell<Environment>>
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
 
 
         let file_path = match path {
@@ -1437,7 +2086,7 @@ impl Evaluator {
 
 
         let source = fs::read_to_string(&file_path)
-            .map_err(|e| format!("Runtime Error: Failed to read module '{}': {}", file_path, e))?;
+            .map_err(|e| format!("Failed to read module '{}': {}", file_path, e))?;
 
         let mut lexer = Lexer::new(&source);
         let tokens = lexer.tokenize().map_err(|e| format!("Module Lexer Error: {}", e))?;
@@ -1464,7 +2113,7 @@ impl Evaluator {
     elif_blocks: &Vec<(ASTNode, ASTNode)>,
     else_block: &Option<Box<ASTNode>>,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     let cond_val = Self::evaluate(condition, env)?;
     if Self::is_truthy(&cond_val) {
         return Self::evaluate(then_block, env);
@@ -1481,7 +2130,105 @@ impl Evaluator {
     Ok(RuntimeValue::None)
 }
 
-    fn eval_block(statements: &Vec<ASTNode>, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, String> {
+    // The first case whose pattern matches and whose guard holds runs; its bindings
+    // are set in the enclosing scope like a `for` loop variable.
+    fn eval_match_statement(
+        value: &ASTNode,
+        cases: &[MatchCase],
+        loc: &Loc,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        let subject = Self::evaluate(value, env)?;
+        for case in cases {
+            let mut bindings = Vec::new();
+            if !Self::match_pattern(&case.pattern, &subject, loc, env, &mut bindings)? {
+                continue;
+            }
+            // Each case binds into its own scope, so a failed guard leaves nothing behind.
+            let case_env = Rc::new(RefCell::new(Environment::new_enclosed(env.clone())));
+            for (name, bound) in bindings {
+                case_env.borrow_mut().set(name, bound.deep_copy());
+            }
+            if let Some(guard) = &case.guard {
+                if !Self::is_truthy(&Self::evaluate(guard, &case_env)?) {
+                    continue;
+                }
+            }
+            return Self::evaluate(&case.body, &case_env);
+        }
+        Ok(RuntimeValue::None)
+    }
+
+    fn match_pattern(
+        pattern: &Pattern,
+        subject: &RuntimeValue,
+        loc: &Loc,
+        env: &Rc<RefCell<Environment>>,
+        bindings: &mut Vec<(String, RuntimeValue)>,
+    ) -> Result<bool, RuntimeError> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Identifier(name) => {
+                bindings.push((name.clone(), subject.clone()));
+                Ok(true)
+            }
+            Pattern::Literal(expr) => {
+                let expected = Self::evaluate(expr, env)?;
+                let equal = Self::eval_binary_op_runtime(&BinaryOperator::Equal, subject.clone(), expected, loc, env)?;
+                Ok(matches!(equal, RuntimeValue::Bool(true)))
+            }
+            Pattern::Range { start, end, inclusive } => {
+                let as_number = |value: &RuntimeValue| match value {
+                    RuntimeValue::Int(i) => Some(*i as f64),
+                    RuntimeValue::Float(f) => Some(*f),
+                    _ => None,
+                };
+                let (low, high) = (Self::evaluate(start, env)?, Self::evaluate(end, env)?);
+                let (Some(low), Some(high)) = (as_number(&low), as_number(&high)) else {
+                    return Err(RuntimeError::at(loc, format!("Range pattern bounds must be numbers, got {} and {}.", low.type_name(), high.type_name())));
+                };
+                Ok(as_number(subject).is_some_and(|x| low <= x && if *inclusive { x <= high } else { x < high }))
+            }
+            Pattern::Alternatives(options) => {
+                for option in options {
+                    let mut option_bindings = Vec::new();
+                    if Self::match_pattern(option, subject, loc, env, &mut option_bindings)? {
+                        bindings.extend(option_bindings);
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Pattern::Binding { pattern, name } => {
+                if !Self::match_pattern(pattern, subject, loc, env, bindings)? {
+                    return Ok(false);
+                }
+                bindings.push((name.clone(), subject.clone()));
+                Ok(true)
+            }
+            Pattern::Variant { enum_name: expected_enum, variant: expected_variant, fields } => {
+                let RuntimeValue::EnumValue { enum_name, variant, payload } = subject else {
+                    return Ok(false);
+                };
+                if expected_enum.as_ref().is_some_and(|expected| expected != enum_name) || expected_variant != variant {
+                    return Ok(false);
+                }
+                if fields.len() != payload.len() {
+                    return Err(RuntimeError::at(loc, format!(
+                        "Pattern for {}.{} has {} fields, but the variant carries {}.", enum_name, variant, fields.len(), payload.len()
+                    )));
+                }
+                for (field, value) in fields.iter().zip(payload) {
+                    if !Self::match_pattern(field, value, loc, env, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn eval_block(statements: &Vec<ASTNode>, env: &Rc<RefCell<Environment>>) -> Result<RuntimeValue, RuntimeError> {
     let mut last_result = RuntimeValue::None;
     for stmt in statements {
         last_result = Self::evaluate(stmt, env)?;
@@ -1493,10 +2240,11 @@ impl Evaluator {
     Ok(last_result)
 }
 
-    fn eval_arguments(args: &[ASTNode], env: &Rc<RefCell<Environment>>) -> Result<Vec<RuntimeValue>, String> {
+    fn eval_arguments(args: &[ASTNode], env: &Rc<RefCell<Environment>>) -> Result<Vec<RuntimeValue>, RuntimeError> {
         let mut evaluated_args = Vec::new();
+        // Arguments are passed by value; a method's receiver is bound separately.
         for arg_expr in args {
-            let arg_value = Self::evaluate(arg_expr, env)?;
+            let arg_value = Self::evaluate(arg_expr, env)?.deep_copy();
             evaluated_args.push(arg_value);
         }
         Ok(evaluated_args)
@@ -1507,14 +2255,14 @@ impl Evaluator {
         RuntimeValue::String(s) => Ok(s),
         RuntimeValue::Int(i) => Ok(i.to_string()),
         RuntimeValue::Bool(b) => Ok(b.to_string()),
-        _ => Err(format!("Runtime Error: Invalid key type for dictionary. Must be String, Int, or Bool. Found {:?}", value.type_name())),
+        _ => Err(format!("Invalid key type for dictionary. Must be String, Int, or Bool. Found {:?}", value.type_name())),
     }
 }
     fn eval_while_statement(
     condition: &Box<ASTNode>,
     body: &Box<ASTNode>,
     env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeValue, String> {
+) -> Result<RuntimeValue, RuntimeError> {
     loop {
         let cond_val = Self::evaluate(condition, env)?;
         if !Self::is_truthy(&cond_val) { break; }
@@ -1535,7 +2283,7 @@ impl Evaluator {
         right_expr: &Box<ASTNode>,
         loc: &Loc,
         env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
         match operator {
             BinaryOperator::And => {
                 let left_val = Self::evaluate(left_expr, env)?;
@@ -1572,44 +2320,113 @@ impl Evaluator {
         }
     }
 
+    // Ket/bra algebra with int, float or complex coefficients.
+    fn eval_ket_op(
+        operator: &BinaryOperator,
+        left_val: RuntimeValue,
+        right_val: RuntimeValue,
+        loc: &Loc,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        let scalar = Self::as_complex;
+        let ket_error = |e: String| RuntimeError::at(loc, e);
+
+        let result = match (operator, &left_val, &right_val) {
+            (BinaryOperator::Add, RuntimeValue::Ket(a), RuntimeValue::Ket(b)) => RuntimeValue::Ket(a.add(b).map_err(ket_error)?),
+            (BinaryOperator::Sub, RuntimeValue::Ket(a), RuntimeValue::Ket(b)) => {
+                RuntimeValue::Ket(a.add(&b.scale(C64::new(-1.0, 0.0))).map_err(ket_error)?)
+            }
+            (BinaryOperator::Add, RuntimeValue::Bra(Bra(a)), RuntimeValue::Bra(Bra(b))) => RuntimeValue::Bra(Bra(a.add(b).map_err(ket_error)?)),
+            (BinaryOperator::Sub, RuntimeValue::Bra(Bra(a)), RuntimeValue::Bra(Bra(b))) => {
+                RuntimeValue::Bra(Bra(a.add(&b.scale(C64::new(-1.0, 0.0))).map_err(ket_error)?))
+            }
+            (BinaryOperator::TensorProduct, RuntimeValue::Ket(a), RuntimeValue::Ket(b)) => RuntimeValue::Ket(a.tensor(b).map_err(ket_error)?),
+            (BinaryOperator::TensorProduct, RuntimeValue::Bra(Bra(a)), RuntimeValue::Bra(Bra(b))) => {
+                RuntimeValue::Bra(Bra(a.tensor(b).map_err(ket_error)?))
+            }
+            (BinaryOperator::Mul, RuntimeValue::Bra(Bra(a)), RuntimeValue::Ket(b)) => RuntimeValue::Complex(a.inner(b).map_err(ket_error)?),
+            (BinaryOperator::Mul, RuntimeValue::Ket(ket), factor) | (BinaryOperator::Mul, factor, RuntimeValue::Ket(ket)) if scalar(factor).is_some() => {
+                RuntimeValue::Ket(ket.scale(scalar(factor).unwrap_or_default()))
+            }
+            // c{psi| is the dual of conj(c)|psi}.
+            (BinaryOperator::Mul, RuntimeValue::Bra(Bra(ket)), factor) | (BinaryOperator::Mul, factor, RuntimeValue::Bra(Bra(ket))) if scalar(factor).is_some() => {
+                RuntimeValue::Bra(Bra(ket.scale(scalar(factor).unwrap_or_default().conj())))
+            }
+            (BinaryOperator::Div, RuntimeValue::Ket(_) | RuntimeValue::Bra(_), divisor) if scalar(divisor).is_some() => {
+                let divisor = scalar(divisor).unwrap_or_default();
+                if divisor.norm_sqr() == 0.0 {
+                    return Err(RuntimeError::at(loc, "Division by zero"));
+                }
+                return Self::eval_ket_op(&BinaryOperator::Mul, left_val, RuntimeValue::Complex(divisor.inv()), loc);
+            }
+            _ => return Err(RuntimeError::at(loc, format!(
+                "Operator {:?} not defined for types {:?} and {:?}", operator, left_val.type_name(), right_val.type_name()
+            ))),
+        };
+        Ok(result)
+    }
+
+    fn is_user_value(value: &RuntimeValue) -> bool {
+        matches!(value, RuntimeValue::Struct { .. } | RuntimeValue::EnumValue { .. })
+    }
+
+    // Structs and enum values compare field by field.
+    fn user_values_equal(left: &RuntimeValue, right: &RuntimeValue, loc: &Loc, env: &Rc<RefCell<Environment>>) -> Result<bool, RuntimeError> {
+        let pairs: Vec<(RuntimeValue, RuntimeValue)> = match (left, right) {
+            (RuntimeValue::Struct { name: a, fields: fa }, RuntimeValue::Struct { name: b, fields: fb }) if a == b => {
+                fa.iter().zip(fb).map(|((_, x), (_, y))| (x.borrow().clone(), y.borrow().clone())).collect()
+            }
+            (RuntimeValue::EnumValue { enum_name: a, variant: va, payload: pa },
+             RuntimeValue::EnumValue { enum_name: b, variant: vb, payload: pb }) if a == b && va == vb => {
+                pa.iter().cloned().zip(pb.iter().cloned()).collect()
+            }
+            _ => return Ok(false),
+        };
+        for (x, y) in pairs {
+            if !matches!(Self::eval_binary_op_runtime(&BinaryOperator::Equal, x, y, loc, env)?, RuntimeValue::Bool(true)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     fn eval_binary_op_runtime(
         operator: &crate::parser::ast::BinaryOperator,
         left_val: RuntimeValue,
         right_val: RuntimeValue,
         loc:&Loc,
         _env: &Rc<RefCell<Environment>>,
-    ) -> Result<RuntimeValue, String> {
+    ) -> Result<RuntimeValue, RuntimeError> {
         use crate::parser::ast::BinaryOperator::*;
 
+        if matches!(left_val, RuntimeValue::Ket(_) | RuntimeValue::Bra(_)) || matches!(right_val, RuntimeValue::Ket(_) | RuntimeValue::Bra(_)) {
+            return Self::eval_ket_op(operator, left_val, right_val, loc);
+        }
+        if matches!(left_val, RuntimeValue::Complex(_)) || matches!(right_val, RuntimeValue::Complex(_)) {
+            return Self::eval_complex_op(operator, &left_val, &right_val, loc);
+        }
 
         if matches!(operator, TensorProduct) {
             match (left_val.clone(), right_val.clone()) {
-                (RuntimeValue::QuantumRegister { size: size_a, state: state_a_rc }, RuntimeValue::QuantumRegister { size: size_b, state: state_b_rc }) => {
-                    let state_a = state_a_rc.borrow();
-                    let state_b = state_b_rc.borrow();
-                    let new_size = size_a + size_b;
-
-                    let mut new_state = HashMap::new();
-
-
-                    for (&i, &amp_a) in state_a.iter() {
-
-                        for (&j, &amp_b) in state_b.iter() {
-                            let new_index = (i << size_b) | j;
-                            let new_amp_tuple = Self::complex_mul(amp_a, amp_b);
-
-
-                            new_state.insert(new_index, new_amp_tuple);
-                        }
+                (RuntimeValue::QuantumRegister { size: size_a, state: state_a, .. }, RuntimeValue::QuantumRegister { size: size_b, state: state_b, .. }) => {
+                    let ((state_a_rc, _), (state_b_rc, _)) = (state_a.resolve(), state_b.resolve());
+                    if state_a_rc.borrow().num_qubits() != size_a || state_b_rc.borrow().num_qubits() != size_b {
+                        return Err(RuntimeError::at(loc, "Operator '***' needs whole registers, but an operand shares its state with another register."));
                     }
+                    let new_state = state_a_rc.borrow().tensor(&state_b_rc.borrow()).map_err(|e| format!("{} (at {})", e, loc))?;
+                    let new_size = size_a + size_b;
 
-                    return Ok(RuntimeValue::QuantumRegister { size: new_size, state: Rc::new(RefCell::new(new_state)) });
+                    return Ok(RuntimeValue::QuantumRegister { size: new_size, state: StateHandle::new(new_state) });
                 }
-                (l, r) => return Err(format!("Runtime Error at {}: Operator {:?} not defined for types {:?} and {:?}", loc, operator, l.type_name(), r.type_name())),
+                (l, r) => return Err(RuntimeError::at(loc, format!("Operator {:?} not defined for types {:?} and {:?}", operator, l.type_name(), r.type_name()))),
             }
         }
 
 
+        if matches!(operator, Equal | NotEqual) && (Self::is_user_value(&left_val) || Self::is_user_value(&right_val)) {
+            let equal = Self::user_values_equal(&left_val, &right_val, loc, _env)?;
+            return Ok(RuntimeValue::Bool(equal == matches!(operator, Equal)));
+        }
+
         match operator {
             Equal => {
                 let result = match (&left_val, &right_val) {
@@ -1642,7 +2459,7 @@ impl Evaluator {
                     (RuntimeValue::Int(base), RuntimeValue::Int(exp)) if exp >= 0 => {
                         match base.checked_pow(exp as u32) {
                             Some(result) => Ok(RuntimeValue::Int(result)),
-                            None => Err(format!("Runtime Error at {}: Integer overflow in power operation", loc))
+                            None => Err(RuntimeError::at(loc, "Integer overflow in power operation"))
                         }
                     }
                     (RuntimeValue::Int(base), RuntimeValue::Int(exp)) if exp < 0 => {
@@ -1658,10 +2475,9 @@ impl Evaluator {
                         Ok(RuntimeValue::Float(base.powf(exp as f64)))
                     }
                     (l, r) => {
-                        Err(format!(
-                            "Runtime Error at {}: Power operator (^) not defined for types {:?} and {:?}",
-                            loc, l.type_name(), r.type_name()
-                        ))
+                        Err(RuntimeError::at(loc, format!(
+                            "Power operator (^) not defined for types {:?} and {:?}", l.type_name(), r.type_name()
+                        )))
                     }
                 }
             }
@@ -1696,7 +2512,7 @@ impl Evaluator {
                     },
                     (And, RuntimeValue::Bool(l), RuntimeValue::Bool(r)) => Ok(RuntimeValue::Bool(l && r)),
                     (Or, RuntimeValue::Bool(l), RuntimeValue::Bool(r)) => Ok(RuntimeValue::Bool(l || r)),
-                    (op, l, r) => Err(format!("Runtime Error at {}: Operator {:?} not defined for types {:?} and {:?}", loc, op, l.type_name(), r.type_name())),
+                    (op, l, r) => Err(RuntimeError::at(loc, format!("Operator {:?} not defined for types {:?} and {:?}", op, l.type_name(), r.type_name()))),
                 }
             }
         }
@@ -3,6 +3,8 @@
 use crate::quantum_backend::{HardwareCircuit, HardwareGate, QuantumConfig, BackendManager, QuantumResult};
 use crate::parser::ast::ASTNode;
 use crate::environment::{Environment, RuntimeValue};
+use crate::evaluator::Evaluator;
+use crate::simulator::{Observable, Unitary};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -13,6 +15,7 @@ pub struct CircuitRecorder {
     gates: Vec<HardwareGate>,
     measurements: Vec<usize>,
     qubit_mapping: HashMap<String, usize>, // Variable name -> qubit index
+    register_sizes: HashMap<String, usize>,
 }
 
 impl CircuitRecorder {
@@ -22,6 +25,7 @@ impl CircuitRecorder {
             gates: Vec::new(),
             measurements: Vec::new(),
             qubit_mapping: HashMap::new(),
+            register_sizes: HashMap::new(),
         }
     }
     
@@ -29,6 +33,7 @@ impl CircuitRecorder {
     pub fn register_qubits(&mut self, var_name: &str, size: usize) -> usize {
         let start_idx = self.num_qubits;
         self.qubit_mapping.insert(var_name.to_string(), start_idx);
+        self.register_sizes.insert(var_name.to_string(), size);
         self.num_qubits += size;
         start_idx
     }
@@ -40,6 +45,18 @@ impl CircuitRecorder {
             qubits,
             params,
             is_dagger,
+            matrix: None,
+        });
+    }
+
+    /// Record a user-defined gate; controls come first in `qubits`
+    pub fn record_unitary(&mut self, gate_name: &str, matrix: &Unitary, qubits: Vec<usize>, is_dagger: bool) {
+        self.gates.push(HardwareGate {
+            name: gate_name.to_string(),
+            qubits,
+            params: vec![],
+            is_dagger,
+            matrix: Some(matrix.data.iter().map(|entry| (entry.re, entry.im)).collect()),
         });
     }
     
@@ -64,6 +81,8 @@ impl CircuitRecorder {
 pub struct HardwareExecutor {
     recorder: CircuitRecorder,
     config: QuantumConfig,
+    custom_gates: HashMap<String, Unitary>,
+    observables: HashMap<String, Observable>,
 }
 
 impl HardwareExecutor {
@@ -71,6 +90,8 @@ impl HardwareExecutor {
         HardwareExecutor {
             recorder: CircuitRecorder::new(),
             config,
+            custom_gates: HashMap::new(),
+            observables: HashMap::new(),
         }
     }
     
@@ -78,6 +99,8 @@ impl HardwareExecutor {
     pub fn execute_on_hardware(&mut self, program: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<QuantumResult, String> {
         // Record all quantum operations
         self.record_program(program, env)?;
+        // Parse the observables before any hardware time is spent
+        let expectations = self.collect_expectations(program)?;
         
         // Build the circuit
         let circuit = self.recorder.build_circuit();
@@ -91,9 +114,69 @@ impl HardwareExecutor {
         
         // Process results
         self.process_results(&result);
+
+        if !expectations.is_empty() {
+            println!("\n📐 Estimated Expectation Values:");
+            let mut unmeasured = circuit.clone();
+            unmeasured.measurements.clear();
+            for (register, observable) in expectations {
+                let base = self.recorder.qubit_mapping[&register];
+                let qubits: Vec<usize> = (base..base + self.recorder.register_sizes[&register]).collect();
+                let value = backend_manager.estimate_expectation(&unmeasured, &observable, &qubits)?;
+                println!("   ⟨{}⟩ on {} ≈ {:.6}", observable, register, value);
+            }
+        }
         
         Ok(result)
     }
+
+    /// `expectation(reg, h)` calls whose observable is a string literal, either
+    /// inline or bound earlier with `let h = observable("...")`.
+    fn collect_expectations(&mut self, program: &ASTNode) -> Result<Vec<(String, Observable)>, String> {
+        let mut found = Vec::new();
+        for stmt in program.children() {
+            if let ASTNode::LetDeclaration { name, value, .. } = stmt {
+                if let Some(observable) = Self::literal_observable(value)? {
+                    self.observables.insert(name.clone(), observable);
+                }
+            }
+            self.find_expectation_calls(stmt, &mut found)?;
+        }
+        Ok(found)
+    }
+
+    fn find_expectation_calls(&self, node: &ASTNode, found: &mut Vec<(String, Observable)>) -> Result<(), String> {
+        if let ASTNode::FunctionCall { callee, arguments, loc, .. } = node {
+            if matches!(&**callee, ASTNode::Identifier { name, .. } if name == "expectation") {
+                let observable = match arguments.get(1) {
+                    Some(ASTNode::Identifier { name, .. }) => self.observables.get(name).cloned(),
+                    Some(argument) => Self::literal_observable(argument)?,
+                    None => None,
+                };
+                match (arguments.first(), observable) {
+                    (Some(ASTNode::Identifier { name, .. }), Some(observable)) if self.recorder.qubit_mapping.contains_key(name) => {
+                        found.push((name.clone(), observable));
+                    }
+                    _ => eprintln!("⚠️  Skipping expectation() at {}: hardware runs need a declared register and an observable built from a string literal", loc),
+                }
+            }
+        }
+        for child in node.children() {
+            self.find_expectation_calls(child, found)?;
+        }
+        Ok(())
+    }
+
+    fn literal_observable(node: &ASTNode) -> Result<Option<Observable>, String> {
+        if let ASTNode::FunctionCall { callee, arguments, .. } = node {
+            if let (ASTNode::Identifier { name, .. }, [ASTNode::StringLiteral(spec)]) = (&**callee, arguments.as_slice()) {
+                if name == "observable" {
+                    return Observable::parse(spec).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
     
     /// Record quantum operations from AST
     fn record_program(&mut self, node: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<(), String> {
@@ -110,7 +193,14 @@ impl HardwareExecutor {
     
     fn record_statement(&mut self, node: &ASTNode, env: &Rc<RefCell<Environment>>) -> Result<(), String> {
         match node {
-            ASTNode::QuantumDeclaration { name, size, .. } => {
+            ASTNode::QuantumDeclaration { name, size, initial_state } => {
+                let ket = match initial_state {
+                    Some(state_expr) => match Evaluator::evaluate(state_expr, env)? {
+                        RuntimeValue::Ket(ket) => Some(ket),
+                        other => return Err(format!("Initial state of '{}' must be a ket, got {}", name, other.type_name())),
+                    },
+                    None => None,
+                };
                 let qsize = if let Some(size_expr) = size {
                     if let ASTNode::IntLiteral(n) = &**size_expr {
                         *n as usize
@@ -118,10 +208,19 @@ impl HardwareExecutor {
                         return Err("Quantum register size must be a literal".to_string());
                     }
                 } else {
-                    1
+                    ket.as_ref().map_or(1, |ket| ket.num_qubits)
                 };
                 
-                self.recorder.register_qubits(name, qsize);
+                let start = self.recorder.register_qubits(name, qsize);
+                if let Some(ket) = ket {
+                    if ket.num_qubits != qsize {
+                        return Err(format!("Register '{}' has {} qubit(s), but its initial state is a {}-qubit ket", name, qsize, ket.num_qubits));
+                    }
+                    for (qubits, unitary) in ket.preparation_steps()? {
+                        let qubits = qubits.into_iter().map(|q| start + q).collect();
+                        self.recorder.record_unitary("state_prep", &unitary, qubits, false);
+                    }
+                }
                 Ok(())
             }
             
@@ -129,12 +228,40 @@ impl HardwareExecutor {
                 self.record_gate_application(gate_expr, arguments)?;
                 Ok(())
             }
+
+            ASTNode::GateDeclaration { name, matrix, loc } => {
+                let unitary = Evaluator::eval_gate_matrix(matrix)
+                    .map_err(|e| format!("Gate '{}' at {}: {}", name, loc, e))?;
+                self.custom_gates.insert(name.clone(), unitary);
+                Ok(())
+            }
+
+            // `apply MyGate(q[0], q[1])` parses as a call to the user-defined gate
+            ASTNode::FunctionCall { callee, arguments, loc, is_dagger } => {
+                if let ASTNode::Identifier { name, .. } = &**callee {
+                    if self.custom_gates.contains_key(name) {
+                        let gate = ASTNode::Gate { name: name.clone(), loc: *loc };
+                        let gate_expr = if *is_dagger {
+                            ASTNode::Dagger { gate_expr: Box::new(gate), loc: *loc }
+                        } else {
+                            gate
+                        };
+                        self.record_gate_application(&gate_expr, arguments)?;
+                    }
+                }
+                Ok(())
+            }
             
             ASTNode::Measure(qubit_expr) => {
-                let qubit_idx = self.extract_qubit_index(qubit_expr)?;
-                self.recorder.record_measurement(qubit_idx);
+                for qubit_idx in self.extract_measured_qubits(qubit_expr)? {
+                    self.recorder.record_measurement(qubit_idx);
+                }
                 Ok(())
             }
+
+            ASTNode::LetDeclaration { value, .. } if matches!(**value, ASTNode::Measure(_)) => {
+                self.record_statement(value, env)
+            }
             
             ASTNode::Block(statements) => {
                 for stmt in statements {
@@ -155,9 +282,20 @@ impl HardwareExecutor {
         let (gate_name, params, is_dagger) = self.parse_gate_expression(gate_expr)?;
         let qubit_indices = self.extract_qubit_indices(arguments)?;
         
-        self.recorder.record_gate(&gate_name, qubit_indices, params, is_dagger);
+        match self.custom_gate_of(gate_expr).cloned() {
+            Some(matrix) => self.recorder.record_unitary(&gate_name, &matrix, qubit_indices, is_dagger),
+            None => self.recorder.record_gate(&gate_name, qubit_indices, params, is_dagger),
+        }
         Ok(())
     }
+
+    fn custom_gate_of(&self, node: &ASTNode) -> Option<&Unitary> {
+        match node {
+            ASTNode::Gate { name, .. } => self.custom_gates.get(name),
+            ASTNode::Dagger { gate_expr, .. } | ASTNode::Controlled { gate_expr, .. } => self.custom_gate_of(gate_expr),
+            _ => None,
+        }
+    }
     
     fn parse_gate_expression(&self, node: &ASTNode) -> Result<(String, Vec<f64>, bool), String> {
         match node {
@@ -199,6 +337,17 @@ impl HardwareExecutor {
         Ok(indices)
     }
     
+    /// `measure q` on a whole register records one measurement per qubit
+    fn extract_measured_qubits(&self, node: &ASTNode) -> Result<Vec<usize>, String> {
+        if let ASTNode::Identifier { name, .. } = node {
+            let base_idx = self.recorder.qubit_mapping.get(name)
+                .ok_or("Unknown quantum register")?;
+            let size = self.recorder.register_sizes[name];
+            return Ok((*base_idx..base_idx + size).collect());
+        }
+        Ok(vec![self.extract_qubit_index(node)?])
+    }
+
     fn extract_qubit_index(&self, node: &ASTNode) -> Result<usize, String> {
         match node {
             ASTNode::ArrayAccess { array, index, .. } => {
@@ -251,6 +400,7 @@ pub fn parse_hardware_config(args: &[String]) -> Option<QuantumConfig> {
                         "aws" => crate::quantum_backend::QuantumProvider::AWS,
                         "ionq" => crate::quantum_backend::QuantumProvider::IonQ,
                         "google" => crate::quantum_backend::QuantumProvider::GoogleCircuit,
+                        "simulator" => crate::quantum_backend::QuantumProvider::Simulator,
                         _ => return None,
                     };
                     i += 2;
@@ -276,6 +426,20 @@ pub fn parse_hardware_config(args: &[String]) -> Option<QuantumConfig> {
                     return None;
                 }
             }
+            "--noise" => {
+                if i + 1 < args.len() {
+                    match crate::simulator::NoiseModel::from_file(&args[i + 1]) {
+                        Ok(model) => config.noise_model = Some(model),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            return None;
+                        }
+                    }
+                    i += 2;
+                } else {
+                    return None;
+                }
+            }
             "--api-token" => {
                 if i + 1 < args.len() {
                     config.api_token = Some(args[i + 1].clone());
@@ -2,6 +2,8 @@
 pub mod token;
 
 use token::{Token, TokenWithLocation};
+use crate::diagnostics::{Diagnostic, Span, LEXER_ERROR};
+use crate::parser::ast::Loc;
 
 pub struct Lexer {
     input: Vec<char>,
@@ -10,6 +12,8 @@ pub struct Lexer {
     column: usize,
     indent_stack: Vec<usize>,
     start_of_line: bool,
+    // Line breaks inside [...] do not end a statement (multi-line matrices).
+    bracket_depth: usize,
 }
 
 impl Lexer {
@@ -21,17 +25,44 @@ impl Lexer {
             column: 1,
             indent_stack: vec![0],
             start_of_line: true,
+            bracket_depth: 0,
         }
     }
     
+    /// Tokenize the whole input, reporting every lexical error in it rather than the
+    /// first: the text an error is found in is skipped and lexing carries on after it.
+    pub fn tokenize_with_diagnostics(&mut self) -> Result<Vec<TokenWithLocation>, Vec<Diagnostic>> {
+        let (tokens, diagnostics) = self.scan();
+        if diagnostics.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(diagnostics)
+        }
+    }
+
     pub fn tokenize(&mut self) -> Result<Vec<TokenWithLocation>, String> {
+        self.tokenize_with_diagnostics().map_err(|diagnostics| {
+            let first = &diagnostics[0];
+            match &first.primary {
+                Some(label) => format!("{} at line {}, column {}", first.message, label.span.start.line, label.span.start.column),
+                None => first.message.clone(),
+            }
+        })
+    }
+
+    fn scan(&mut self) -> (Vec<TokenWithLocation>, Vec<Diagnostic>) {
         let mut tokens = Vec::new();
+        let mut diagnostics = Vec::new();
         
         while !self.is_at_end() {
             loop {
-                if self.start_of_line {
-                    self.handle_indentation(&mut tokens)?;
+                if self.start_of_line && self.bracket_depth == 0 {
+                    let start = self.here();
+                    if let Err(message) = self.handle_indentation(&mut tokens) {
+                        diagnostics.push(self.error_since(start, message));
+                    }
                 }
+                self.start_of_line = false;
                 self.skip_whitespace_except_newline(); 
     
                 if self.current_char().ok() == Some('/') {
@@ -42,9 +73,12 @@ impl Lexer {
                         continue; 
                     } else if self.peek() == Some('*') {
                         // Multiline comment /* ... */
+                        let start = self.here();
                         self.advance(); 
                         self.advance();
-                        self.skip_multiline_comment()?;
+                        if let Err(message) = self.skip_multiline_comment() {
+                            diagnostics.push(self.error_since(start, message));
+                        }
                         continue;
                     } else {
                         // Division operator
@@ -61,13 +95,15 @@ impl Lexer {
     
             if self.current_char().ok() == Some('\n') {
                 self.advance();
-                tokens.push(self.make_token(Token::Newline, 1));
+                if self.bracket_depth == 0 {
+                    tokens.push(self.make_token(Token::Newline, 1));
+                }
                 continue; 
             }
 
          
-            let _start_col = self.column;
-            let ch = self.current_char()?;
+            let start = self.here();
+            let Ok(ch) = self.current_char() else { break };
 
            
             let token_result = match ch {
@@ -172,8 +208,8 @@ impl Lexer {
                 // Punctuation
                 '(' => { self.advance(); Ok(self.make_token(Token::LeftParen, 1)) }
                 ')' => { self.advance(); Ok(self.make_token(Token::RightParen, 1)) }
-                '[' => { self.advance(); Ok(self.make_token(Token::LeftBracket, 1)) }
-                ']' => { self.advance(); Ok(self.make_token(Token::RightBracket, 1)) }
+                '[' => { self.advance(); self.bracket_depth += 1; Ok(self.make_token(Token::LeftBracket, 1)) }
+                ']' => { self.advance(); self.bracket_depth = self.bracket_depth.saturating_sub(1); Ok(self.make_token(Token::RightBracket, 1)) }
                 '}' => { self.advance(); Ok(self.make_token(Token::RightBrace, 1)) }
                 ',' => { self.advance(); Ok(self.make_token(Token::Comma, 1)) }
                 ';' => { self.advance(); Ok(self.make_token(Token::Semicolon, 1)) }
@@ -202,10 +238,13 @@ impl Lexer {
                     }
                 }
                 
-                _ => Err(format!("Unexpected character '{}' at line {}, column {}", ch, self.line, self.column)),
+                _ => Err(format!("Unexpected character '{}'", ch)),
             };
 
-            tokens.push(token_result?);
+            match token_result {
+                Ok(token) => tokens.push(token),
+                Err(message) => diagnostics.push(self.error_since(start, message)),
+            }
         }
         
    
@@ -215,18 +254,31 @@ impl Lexer {
         }
         
         tokens.push(self.make_token(Token::Eof, 0));
-        Ok(tokens)
+        (tokens, diagnostics)
+    }
+
+    fn here(&self) -> (usize, Loc) {
+        (self.position, Loc { line: self.line, column: self.column, length: 0 })
+    }
+
+    // An error in the text from `start` to the current position, which is moved past
+    // at least one character so that lexing can go on.
+    fn error_since(&mut self, (position, start): (usize, Loc), message: String) -> Diagnostic {
+        if self.position == position {
+            self.advance();
+        }
+        let end = if self.line == start.line {
+            Loc { line: start.line, column: self.column.max(start.column + 1), length: 0 }
+        } else {
+            Loc { line: self.line, column: self.column, length: 0 }
+        };
+        Diagnostic::error(LEXER_ERROR, message).with_primary(Span { start, end }, "")
     }
 
     fn skip_multiline_comment(&mut self) -> Result<(), String> {
-        let start_line = self.line;
-        
         loop {
             if self.is_at_end() {
-                return Err(format!(
-                    "Unterminated multiline comment starting at line {}",
-                    start_line
-                ));
+                return Err("Unterminated multiline comment".to_string());
             }
             
             let ch = self.current_char()?;
@@ -291,7 +343,7 @@ impl Lexer {
         }
         
         if self.indent_stack.last() != Some(&indent_level) {
-            return Err(format!("Indentation error at line {}", self.line));
+            return Err("Indentation does not match any enclosing block".to_string());
         }
     }
     
@@ -583,7 +635,7 @@ impl Lexer {
         }
         
         if self.is_at_end() || self.current_char()? != '}' {
-            return Err(format!("Unclosed quantum ket at line {}", self.line));
+            return Err("Unclosed quantum ket".to_string());
         }
         
         self.advance(); 
@@ -602,7 +654,7 @@ impl Lexer {
         }
         
         if self.is_at_end() || self.current_char()? != '|' {
-            return Err(format!("Unclosed quantum bra at line {}", self.line));
+            return Err("Unclosed quantum bra".to_string());
         }
         
         self.advance(); 
@@ -681,6 +733,7 @@ impl Lexer {
             "quantum" => Token::Quantum,
             "apply" => Token::Apply,
             "measure" => Token::Measure,
+            "gate" => Token::Gate,
             "Swap"=>Token::Swap,
             "Reset"=>Token::Reset,
             "CZ"=>Token::CZ,
@@ -716,6 +769,7 @@ impl Lexer {
             // Data structures
             "struct" => Token::Struct,
             "enum" => Token::Enum,
+            "impl" => Token::Impl,
             
             // Modifiers
             "Const" => Token::Const,
@@ -809,9 +863,18 @@ impl Lexer {
         }
         
         let text: String = self.input[start..self.position].iter().collect();
+
+        // A trailing `i` makes an imaginary literal (`2i`, `0.5i`, `1e-3i`).
+        let is_imaginary = !self.is_at_end() && self.current_char()? == 'i'
+            && !self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if is_imaginary {
+            self.advance();
+        }
         let length = self.column - start_col;
-        
-        let token = if is_float {
+
+        let token = if is_imaginary {
+            Token::ImaginaryLiteral(text.parse().map_err(|_| format!("Invalid imaginary number: {}i", text))?)
+        } else if is_float {
             Token::FloatLiteral(text.parse().map_err(|_| format!("Invalid float: {}", text))?)
         } else {
             Token::IntLiteral(text.parse().map_err(|_| format!("Invalid integer: {}", text))?)
@@ -825,6 +888,8 @@ impl Lexer {
         self.advance(); 
         
         let mut value = String::new();
+        // A bad escape is reported once the string has been read, so lexing resumes after it.
+        let mut invalid_escape = None;
         
         while !self.is_at_end() && self.current_char()? != '"' {
             if self.current_char()? == '\\' {
@@ -839,7 +904,7 @@ impl Lexer {
                     'r' => value.push('\r'),
                     '\\' => value.push('\\'),
                     '"' => value.push('"'),
-                    _ => return Err(format!("Invalid escape sequence: \\{}", self.current_char()?)),
+                    other => invalid_escape = invalid_escape.or(Some(other)),
                 }
             } else {
                 value.push(self.current_char()?);
@@ -852,6 +917,9 @@ impl Lexer {
         }
         
         self.advance(); // 
+        if let Some(escape) = invalid_escape {
+            return Err(format!("Invalid escape sequence: \\{}", escape));
+        }
         let length = self.column - start_col;
         Ok(self.make_token(Token::StringLiteral(value), length))
     }
@@ -1121,6 +1189,17 @@ mod tests {
         assert!(matches!(tokens[3].token, Token::FloatLiteral(_)));
     }
 
+    #[test]
+    fn test_imaginary_numbers() {
+        let tokens = Lexer::new("2i 0.5i 1e-3i 3in").tokenize().unwrap();
+
+        assert_eq!(tokens[0].token, Token::ImaginaryLiteral(2.0));
+        assert_eq!(tokens[1].token, Token::ImaginaryLiteral(0.5));
+        assert_eq!(tokens[2].token, Token::ImaginaryLiteral(1e-3));
+        assert_eq!(tokens[3].token, Token::IntLiteral(3));
+        assert_eq!(tokens[4].token, Token::In);
+    }
+
     #[test]
     fn test_comments_single_line() {
         let input = "let x = 10 // this is a comment\nlet y = 20";
@@ -1161,5 +1240,22 @@ mod tests {
         assert!(tokens.iter().any(|t| matches!(t.token, Token::Indent)));
         assert!(tokens.iter().any(|t| matches!(t.token, Token::Dedent)));
     }
+
+    #[test]
+    fn test_errors_are_all_reported_with_spans() {
+        let input = "let a = 1 $ 2\nlet b = \"bad \\q\"\nlet c = 3 @";
+        let errors = Lexer::new(input).tokenize_with_diagnostics().unwrap_err();
+        let found: Vec<_> = errors.iter()
+            .map(|d| {
+                let span = d.primary.as_ref().unwrap().span;
+                (d.message.as_str(), span.start.line, span.start.column, span.end.column)
+            })
+            .collect();
+        assert_eq!(found, vec![
+            ("Unexpected character '$'", 1, 11, 12),
+            ("Invalid escape sequence: \\q", 2, 9, 17),
+            ("Unexpected character '@'", 3, 11, 12),
+        ]);
+    }
 }
 
@@ -57,6 +57,7 @@ pub enum Token {
     Measure,
     Dagger,
     Controlled,
+    Gate,
 
     //quantum gates
     Hadamard,
@@ -90,6 +91,7 @@ pub enum Token {
     // Data Structures
     Struct,
     Enum,
+    Impl,
     
     // Modifiers
     Const,
@@ -122,6 +124,7 @@ pub enum Token {
     // Literals
     IntLiteral(i64),
     FloatLiteral(f64),
+    ImaginaryLiteral(f64),
     StringLiteral(String),
     True,
     False,
@@ -1,5 +1,6 @@
 // src/lib.rs
 
+pub mod diagnostics;
 pub mod lexer;
 pub mod parser;
 pub mod environment;
@@ -8,13 +9,23 @@ pub mod type_checker;
 pub mod doc_generator;
 pub mod codegen;
 pub mod runtime;
+pub mod simulator;
 pub mod quantum_backend;
 pub mod hardware_integration;
+pub mod shots;
 pub use runtime::{
     quantica_rt_new_state,
     quantica_rt_debug_state,
     quantica_rt_apply_gate,
+    quantica_rt_apply_unitary,
     quantica_rt_measure,
+    quantica_rt_measure_register,
+    quantica_rt_bits_as_int,
+    quantica_rt_bits_get,
+    quantica_rt_length,
+    quantica_rt_reset,
+    quantica_rt_set_seed,
+    quantica_rt_fail,
 };
 
 pub mod linker;
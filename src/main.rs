@@ -1,4 +1,5 @@
 // src/main.rs
+mod diagnostics;
 mod lexer;
 mod parser;
 mod environment;
@@ -8,12 +9,14 @@ mod doc_generator;
 mod codegen;
 mod linker;
 mod runtime;
+mod simulator;
 
 mod quantum_backend;
 mod hardware_integration;
+mod shots;
 
 use hardware_integration::{HardwareExecutor, parse_hardware_config};
-use quantum_backend::QuantumConfig;
+use quantum_backend::{QuantumConfig, QuantumResult};
 
 use std::time::Instant;
 use crate::environment::Environment;
@@ -29,11 +32,13 @@ use parser::ast::ImportSpec;
 use parser::ast::ImportPath;
 use std::io::{self, Write};
 use crate::parser::ast::ASTNode;
+use crate::parser::ast::{GenericParam, Generics};
 use crate::environment::RuntimeValue;
 use crate::doc_generator::DocGenerator;
 use crate::lexer::token::Token;
+use crate::diagnostics::{Diagnostic, MessageFormat};
 use inkwell::context::Context;
-use crate::codegen::Compiler;
+use crate::codegen::{CodegenError, Compiler};
 use crate::linker::Linker;
 use inkwell::OptimizationLevel;
 
@@ -61,6 +66,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut target = CompilationTarget::HostCPU;
     let mut hardware_config: Option<QuantumConfig> = None;
     let mut list_devices = false;
+    let mut shots: Option<u32> = None;
 
 
     let mut i = 1;
@@ -72,17 +78,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 i += 1;
                 while i < args.len() && (args[i] == "ibm" || args[i] == "google" || 
                                         args[i] == "aws" || args[i] == "ionq" ||
+                                        args[i] == "simulator" ||
                                         args[i].starts_with("--device") || 
                                         args[i].starts_with("--shots") ||
+                                        args[i].starts_with("--noise") ||
                                         args[i].starts_with("--api-token")) {
                     i += 1;
                     if i > 0 && (args[i-1].starts_with("--device") || 
                                 args[i-1].starts_with("--shots") || 
+                                args[i-1].starts_with("--noise") ||
                                 args[i-1].starts_with("--api-token")) {
                         i += 1; // skip the value after the flag
                     }
                 }
             }
+            "--noise" if i + 1 < args.len() => {
+                let model = simulator::NoiseModel::from_file(&args[i + 1])?;
+                simulator::set_noise_model(Some(model));
+                if simulator::state_mode() == simulator::StateMode::Auto {
+                    simulator::set_state_mode(simulator::StateMode::Density);
+                }
+                i += 2;
+            }
+            "--seed" if i + 1 < args.len() => {
+                let seed = args[i + 1].parse::<u64>()
+                    .map_err(|_| format!("Invalid seed '{}'", args[i + 1]))?;
+                environment::set_seed(seed);
+                i += 2;
+            }
+            "--shots" if i + 1 < args.len() => {
+                let count = args[i + 1].parse::<u32>().ok().filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid shot count '{}'", args[i + 1]))?;
+                shots = Some(count);
+                i += 2;
+            }
             "--list-devices" => {
                 list_devices = true;
                 i += 1;
@@ -104,12 +133,41 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                 i += 1;
             }
+            _ if args[i].starts_with("--state=") => {
+                let mode = &args[i]["--state=".len()..];
+                simulator::set_state_mode(simulator::StateMode::parse(mode)?);
+                i += 1;
+            }
+            _ if args[i].starts_with("--max-bond=") => {
+                let value = &args[i]["--max-bond=".len()..];
+                let max_bond = value.parse::<usize>().ok().filter(|&b| b > 0)
+                    .ok_or_else(|| format!("Invalid bond dimension '{}'", value))?;
+                let mut config = simulator::mps_config();
+                config.max_bond = max_bond;
+                simulator::set_mps_config(config);
+                i += 1;
+            }
+            _ if args[i].starts_with("--message-format=") => {
+                let format = &args[i]["--message-format=".len()..];
+                diagnostics::set_message_format(MessageFormat::parse(format)?);
+                i += 1;
+            }
+            _ if args[i].starts_with("--truncation=") => {
+                let value = &args[i]["--truncation=".len()..];
+                let threshold = value.parse::<f64>().ok().filter(|t| (0.0..1.0).contains(t))
+                    .ok_or_else(|| format!("Invalid truncation threshold '{}'", value))?;
+                let mut config = simulator::mps_config();
+                config.truncation_threshold = threshold;
+                simulator::set_mps_config(config);
+                i += 1;
+            }
             _ if args[i].starts_with("-O") => {
                 opt_level = parse_opt_level(&args[i])?;
                 i += 1;
             }
             _ if args[i] == "--doc" || args[i] == "--repl" || args[i] == "--test" || 
-                args[i] == "--lex" || args[i] == "--compile" || args[i] == "--run" => {
+                args[i] == "--lex" || args[i] == "--compile" || args[i] == "--run" || args[i] == "--check" ||
+                args[i] == "--version" || args[i] == "--help" || args[i] == "-h" => {
                 command = Some(&args[i]);
                 i += 1;
             }
@@ -138,42 +196,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Handle hardware execution mode
-    if let Some(config) = hardware_config {
+    if let Some(mut config) = hardware_config {
         if let Some(file) = filename {
-            return run_on_hardware(file, config);
+            if let Some(count) = shots {
+                config.shots = count;
+            }
+            run_on_hardware(file, config);
+            diagnostics::print_summary();
+            return Ok(());
         } else {
             eprintln!("Error: No input file specified for hardware execution");
             std::process::exit(1);
         }
     }
 
+    if let Some(count) = shots {
+        if let Some(file) = filename {
+            run_local_shots(file, count);
+            diagnostics::print_summary();
+            return Ok(());
+        } else {
+            eprintln!("Error: No input file specified for --shots");
+            std::process::exit(1);
+        }
+    }
+
 
     if command.is_none() && filename.is_none() {
         println!("Starting REPL mode (type '.quit' to exit, '.clear' to reset).");
         run_repl();
         return Ok(());
     }
-    
-   
-    if args.len() < 2 {
-        println!("Starting REPL mode (type '.quit' to exit, '.clear' to reset).");
-        run_repl(); // Run REPL if no file is given
-        return Ok(());
-    }
 
-    if args[1] == "--version" {
+    if command == Some("--version") {
         println!("Quantica v0.1.0");
         println!("Quantica By Quantica Foundation");
         return Ok(());
     }
 
-    if args[1] == "--help" || args[1] == "-h" {
+    if command == Some("--help") || command == Some("-h") {
         print_help();
         return Ok(());
     }
 
-    if args[1] == "--doc" && args.len() >= 3 {
-        let filename = &args[2];
+    if let (Some("--doc"), Some(filename)) = (command, filename) {
         let output_file = "docs/api.md"; 
         println!("📄 Generating documentation for: {}", filename);
         println!("   Outputting to: {}", output_file);
@@ -190,25 +256,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
     
-    if args[1] == "--repl" {
+    if command == Some("--repl") {
         println!("Starting REPL mode (type '.quit' to exit, '.clear' to reset).");
         run_repl();
         return Ok(());
     }
 
-    if args[1] == "--test" {
+    if command == Some("--test") {
         run_test_suite();
         return Ok(());
     }
     
     
-    if args[1] == "--lex" && args.len() >= 3 {
-        run_lexer_only(&args[2]);
+    if let (Some("--lex"), Some(filename)) = (command, filename) {
+        run_lexer_only(filename);
         return Ok(());
     }
 
-    if args[1] == "--compile" && args.len() >= 3 {
-        let filename = &args[2];
+    if let (Some("--check"), Some(filename)) = (command, filename) {
+        check_file(filename);
+        return Ok(());
+    }
+
+    if let (Some("--compile"), Some(filename)) = (command, filename) {
         let object_file = "output.o";
         
         // Determine output executable name from source file
@@ -240,42 +310,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         println!("   Run your program with: ./{}", output_exe);
                     }
                     Err(e) => {
-                        eprintln!("\n✗ Linking failed: {}", e);
-                        eprintln!("\nℹ️  You can try manual linking:");
-                        eprintln!("   1. Open 'x64 Native Tools Command Prompt for VS'");
-                        eprintln!("   2. Run: link {} target\\debug\\quantica.lib /OUT:{} /SUBSYSTEM:CONSOLE kernel32.lib msvcrt.lib", object_file, output_exe);
+                        let summary = report_error(diagnostics::CODEGEN_ERROR, format!("Linking failed: {}", e), filename);
+                        if diagnostics::message_format() == MessageFormat::Human {
+                            eprintln!("ℹ️  You can try manual linking:");
+                            eprintln!("   1. Open 'x64 Native Tools Command Prompt for VS'");
+                            eprintln!("   2. Run: link {} target\\debug\\quantica.lib /OUT:{} /SUBSYSTEM:CONSOLE kernel32.lib msvcrt.lib", object_file, output_exe);
+                        }
                         fs::remove_file(object_file).unwrap_or_default();
-                        std::process::exit(1);
+                        exit_failed(&format!("\n✗ {}", summary));
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("\n✗ Compilation failed: {}", e);
-                std::process::exit(1);
-            }
+            Err(e) => exit_failed(&format!("\n✗ Compilation failed: {}", e)),
         }
+        diagnostics::print_summary();
         return Ok(());
     }
 
 
-    if args[1] == "--run" && args.len() >= 3 {
-        let filename = &args[2];
+    if let (Some("--run"), Some(filename)) = (command, filename) {
         println!("🚀 Running JIT Compiler: {}", filename);
         match run_jit_file(filename,emit_llvm,opt_level,target) {
             Ok(()) => {
                 println!("\n✓ JIT execution successful!");
             }
-            Err(e) => {
-                eprintln!("\n✗ Execution failed: {}", e);
-                std::process::exit(1);
-            }
+            Err(e) => exit_failed(&format!("\n✗ Execution failed: {}", e)),
         }
+        diagnostics::print_summary();
         return Ok(());
     }
     
     // Full compilation pipeline: Lex + Parse
     if let Some(file) = filename {
         compile_file(file, show_ast, show_tokens, verbose);
+        diagnostics::print_summary();
     } else {
         eprintln!("Error: No input file specified");
         eprintln!("Usage: quantica [options] <file.qc>");
@@ -283,7 +351,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("  -v, --verbose    Show compilation phases");
         eprintln!("  --ast            Show Abstract Syntax Tree");
         eprintln!("  --tokens         Show token stream");
+        eprintln!("  --state=<mode>   Simulator state (auto, sparse, dense, density, stabilizer, mps)");
+        eprintln!("  --message-format=json  Print diagnostics as JSON lines");
         eprintln!("  --emit-llvm  Emit LLVM IR");
+        eprintln!("  --check      Lex, parse and type check only");
         eprintln!("  --compile    Compile to executable");
         eprintln!("  --run        JIT compile and run");
         eprintln!("  --doc        Generate documentation");
@@ -301,12 +372,9 @@ fn compile_file(filename: &str,show_ast: bool, show_tokens: bool,verbose:bool) {
         println!("📄 Compiling: {}\n", filename);
     }
     // Read source
-    let source = match fs::read_to_string(filename) {
+    let source = match read_source(filename) {
         Ok(content) => content,
-        Err(e) => {
-            eprintln!("✗ Failed to read file '{}': {}", filename, e);
-            std::process::exit(1);
-        }
+        Err(summary) => exit_failed(&format!("✗ {}", summary)),
     };
     if verbose {
         println!("Source code:");
@@ -319,17 +387,14 @@ fn compile_file(filename: &str,show_ast: bool, show_tokens: bool,verbose:bool) {
         println!("🔤 Phase 1: Lexical Analysis");
     }
     let mut lexer = Lexer::new(&source);
-    let tokens = match lexer.tokenize() {
+    let tokens = match lexer.tokenize_with_diagnostics() {
         Ok(t) => {
             if verbose {
                 println!("✓ Lexer succeeded! {} tokens\n", t.len());
             }
             t
         }
-        Err(e) => {
-            eprintln!("✗ Lexer error: {}", e);
-            std::process::exit(1);
-        }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
     };
 
     if show_tokens {
@@ -346,32 +411,27 @@ fn compile_file(filename: &str,show_ast: bool, show_tokens: bool,verbose:bool) {
     if verbose{
         println!("🌳 Phase 2: Syntax Analysis");
     }
-    let mut parser = Parser::new(tokens);
-    let ast = match parser.parse() {
+    let mut parser = Parser::new(tokens.clone());
+    let ast = match parser.parse_with_diagnostics() {
         Ok(tree) => {
             if verbose {
                 println!("✓ Parser succeeded!\n");
             }    
             tree
         }
-        Err(e) => {
-            eprintln!("✗ Parser error: {}", e);
-            std::process::exit(1);
-        }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
     };
     if verbose{
         println!("🔬 Phase 2.5: Type Checking");
     }
-    match TypeChecker::check_program(&ast) {
-        Ok(()) => {
+    match TypeChecker::check_program_with_diagnostics(&ast) {
+        Ok(warnings) => {
+            let _ = diagnostics::report(&warnings, &source, filename);
             if verbose{
                 println!("✓ Type check succeeded!\n");
             }
         }
-        Err(e) => {
-            eprintln!("✗ Type error: {}", e);
-            std::process::exit(1);
-        }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
     }
     
     // Display AST (Optional, but good for debugging)
@@ -402,15 +462,69 @@ fn compile_file(filename: &str,show_ast: bool, show_tokens: bool,verbose:bool) {
     
     println!("{:-<60}", "");
     println!("⏱️  Interpreter Time: {:.6} seconds", duration.as_secs_f64());
+    let truncation_error = simulator::total_truncation_error();
+    if truncation_error > 0.0 {
+        let warning = Diagnostic::warning(diagnostics::MPS_TRUNCATION, format!("MPS truncation discarded {:.3e} of the state's weight", truncation_error))
+            .with_suggestion("raise --max-bond=<n> to keep more of the state");
+        let _ = diagnostics::report(&[warning], &source, filename);
+    }
     
     match evaluation_result {
         Ok(_) => {
             println!("✓ Execution successful!");
         }
-        Err(e) => {
-            eprintln!("✗ Runtime Error: {}", e);
-            std::process::exit(1);
+        Err(e) => fail(&[Evaluator::diagnostic(&e)], &source, filename),
+    }
+}
+
+// Print the diagnostics against the source and stop with a failure status.
+fn fail(diagnostics: &[Diagnostic], source: &str, filename: &str) -> ! {
+    let summary = diagnostics::report(diagnostics, source, filename).err().unwrap_or_default();
+    exit_failed(&format!("✗ {}", summary));
+}
+
+// Stop with a failure status, closing the output with `message` or the JSON summary.
+fn exit_failed(message: &str) -> ! {
+    match diagnostics::message_format() {
+        MessageFormat::Human => eprintln!("{}", message),
+        MessageFormat::Json => diagnostics::print_summary(),
+    }
+    std::process::exit(1);
+}
+
+// Report an error that has no place in the source, returning the failure summary.
+fn report_error(code: &'static str, message: String, filename: &str) -> String {
+    diagnostics::report(&[Diagnostic::error(code, message)], "", filename).err().unwrap_or_default()
+}
+
+fn read_source(filename: &str) -> Result<String, String> {
+    fs::read_to_string(filename)
+        .map_err(|e| report_error(diagnostics::FILE_ERROR, format!("Failed to read file '{}': {}", filename, e), filename))
+}
+
+// Lex, parse and type check a file, collecting every diagnostic. A program that
+// checks comes with its warnings.
+fn check_source(source: &str) -> Result<(ASTNode, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let tokens = Lexer::new(source).tokenize_with_diagnostics()?;
+    let ast = Parser::new(tokens).parse_with_diagnostics()?;
+    let warnings = TypeChecker::check_program_with_diagnostics(&ast)?;
+    Ok((ast, warnings))
+}
+
+fn check_file(filename: &str) {
+    let source = match read_source(filename) {
+        Ok(source) => source,
+        Err(summary) => exit_failed(&format!("✗ {}", summary)),
+    };
+    match check_source(&source) {
+        Ok((_, warnings)) => {
+            let _ = diagnostics::report(&warnings, &source, filename);
+            if diagnostics::message_format() == MessageFormat::Human {
+                println!("✓ No errors found in '{}'", filename);
+            }
+            diagnostics::print_summary();
         }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
     }
 }
 
@@ -429,6 +543,8 @@ fn print_help() {
     println!("    --repl               Start interactive REPL");
     println!("    --test               Run test suite");
     println!("    --lex <file>         Tokenize only");
+    println!("    --check <file>       Lex, parse and type check only");
+    println!("    --message-format=<fmt>  Diagnostics as human-readable text or JSON lines (human, json)");
     println!();
     println!("COMPILATION OPTIONS:");
     println!("    --ast                Show Abstract Syntax Tree");
@@ -438,8 +554,16 @@ fn print_help() {
     println!("    -O0, -O1, -O2, -O3   Set optimization level");
     println!("    --target=<target>    Compilation target (host, spirv, xla)");
     println!();
+    println!("SIMULATION OPTIONS:");
+    println!("    --state=<mode>       State representation (auto, sparse, dense, density, stabilizer, mps)");
+    println!("    --max-bond=<n>       MPS bond-dimension cap (default: 64)");
+    println!("    --truncation=<eps>   MPS discarded-weight threshold per split (default: 1e-12)");
+    println!("    --noise <model.json> Noise model for the density-matrix simulator");
+    println!("    --seed <n>           Seed measurement and sampling randomness (printed on test failure)");
+    println!("    --shots <n>          Run <n> shots locally and print the counts histogram");
+    println!();
     println!("QUANTUM HARDWARE OPTIONS:");
-    println!("    --hardware <provider>    Run on quantum hardware (ibm, aws, ionq)");
+    println!("    --hardware <provider>    Run on quantum hardware (ibm, aws, ionq, simulator)");
     println!("    --device <name>          Specify device name");
     println!("    --shots <number>         Number of measurements (default: 1024)");
     println!("    --api-token <token>      API authentication token");
@@ -457,26 +581,17 @@ fn compile_file_llvm(filename: &str, output_file: &str, emit_llvm: bool, opt_lev
     println!("📄 Compiling: {}\n", filename);
 
     // Read source
-    let source = fs::read_to_string(filename)
-        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    let source = read_source(filename)?;
 
-    // Lexical Analysis
-    println!("🔤 Phase 1: Lexical Analysis");
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize()
-        .map_err(|e| format!("Lexer error: {}", e))?;
-    
-    //Syntax Analysis (Parsing)
-    println!("🌳 Phase 2: Syntax Analysis");
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse()
-        .map_err(|e| format!("Parser error: {}", e))?;
-
-    //Type Checking
-    println!("🔬 Phase 3: Type Checking");
-    TypeChecker::check_program(&ast)
-        .map_err(|e| format!("Type error: {}", e))?;
+    // Lexical Analysis, Syntax Analysis (Parsing) and Type Checking
+    println!("🔤 Phases 1-3: Lexing, Parsing and Type Checking");
+    let (ast, warnings) = check_source(&source)
+        .map_err(|diagnostics| diagnostics::report(&diagnostics, &source, filename).err().unwrap_or_default())?;
+    let _ = diagnostics::report(&warnings, &source, filename);
     println!("✓ Type check succeeded!\n");
+    let codegen_failed = |e: CodegenError| {
+        diagnostics::report(&[Compiler::diagnostic(&e)], &source, filename).err().unwrap_or_default()
+    };
 
 
     //Initialize LLVM
@@ -485,11 +600,11 @@ fn compile_file_llvm(filename: &str, output_file: &str, emit_llvm: bool, opt_lev
     let mut compiler = Compiler::new(&context,opt_level);
 
     //Compile Program
-    compiler.compile_program(&ast)?;
+    compiler.compile_program(&ast).map_err(codegen_failed)?;
     println!("   -> Generated LLVM IR");
 
     //Optimize Module
-    compiler.optimize_module(opt_level)?;
+    compiler.optimize_module(opt_level).map_err(codegen_failed)?;
     println!("   -> Optimized module");
 
     compiler.finalize_debug_info();
@@ -498,7 +613,7 @@ fn compile_file_llvm(filename: &str, output_file: &str, emit_llvm: bool, opt_lev
     let module_name = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("quantica_program");
     
     // Simulate exporting the graph for the TPU
-    let hlo_ir = compiler.export_to_hlo_ir(module_name)?;
+    let hlo_ir = compiler.export_to_hlo_ir(module_name).map_err(codegen_failed)?;
     println!("   -> XLA/HLO IR Exported");
     
 
@@ -521,7 +636,7 @@ fn compile_file_llvm(filename: &str, output_file: &str, emit_llvm: bool, opt_lev
         
         // Simulating the final HLO/SPIR-V export by calling the exporter
         let module_name = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or("quantica_program");
-        let hlo_ir = compiler.export_to_hlo_ir(module_name)?;
+        let hlo_ir = compiler.export_to_hlo_ir(module_name).map_err(codegen_failed)?;
 
         if emit_llvm {
             println!("--- XLA/HLO IR (TPU Target) ---");
@@ -532,7 +647,7 @@ fn compile_file_llvm(filename: &str, output_file: &str, emit_llvm: bool, opt_lev
     }
     
     // Save to object file
-    compiler.write_to_object_file(output_file)?;
+    compiler.write_to_object_file(output_file).map_err(codegen_failed)?;
     println!("   -> Emitted object file");
 
     
@@ -545,20 +660,14 @@ fn run_jit_file(filename: &str, emit_llvm: bool, opt_level: OptimizationLevel, t
     println!("🚀 JIT Compiling and Running: {}\n", filename);
 
     // Read, Lex, Parse
-    let source = fs::read_to_string(filename)
-        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
+    let source = read_source(filename)?;
 
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize()
-        .map_err(|e| format!("Lexer error: {}", e))?;
-    
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse()
-        .map_err(|e| format!("Parser error: {}", e))?;
-
-    //  Type Check
-    TypeChecker::check_program(&ast)
-        .map_err(|e| format!("Type error: {}", e))?;
+    let (ast, warnings) = check_source(&source)
+        .map_err(|diagnostics| diagnostics::report(&diagnostics, &source, filename).err().unwrap_or_default())?;
+    let _ = diagnostics::report(&warnings, &source, filename);
+    let codegen_failed = |e: CodegenError| {
+        diagnostics::report(&[Compiler::diagnostic(&e)], &source, filename).err().unwrap_or_default()
+    };
 
     if target == CompilationTarget::SPIRV {
         println!("🚀 Accelerating with SPIR-V/GPU Backend...");
@@ -578,6 +687,7 @@ fn run_jit_file(filename: &str, emit_llvm: bool, opt_level: OptimizationLevel, t
         } else {
             let main_function_node = ASTNode::FunctionDeclaration {
                 name: "main".to_string(),
+                generics: Generics::default(),
                 parameters: Vec::new(),
                 return_type: None,
                 body: Box::new(ASTNode::Block(nodes.clone())),
@@ -585,7 +695,7 @@ fn run_jit_file(filename: &str, emit_llvm: bool, opt_level: OptimizationLevel, t
             ASTNode::Program(vec![main_function_node])
         }
     } else {
-        return Err("Expected ASTNode::Program at root.".to_string());
+        return Err(codegen_failed("Expected ASTNode::Program at root.".into()));
     };
 
     println!("🤖 Phase 4: JIT Compilation & Execution");
@@ -604,8 +714,8 @@ fn run_jit_file(filename: &str, emit_llvm: bool, opt_level: OptimizationLevel, t
         Vec::new()
     };
 
-    compiler.enable_jit_profiling(function_names)?;
-    compiler.compile_jit_program(&jit_program)?;
+    compiler.enable_jit_profiling(function_names).map_err(codegen_failed)?;
+    compiler.compile_jit_program(&jit_program).map_err(codegen_failed)?;
     compiler.finalize_debug_info();
 
     if emit_llvm {
@@ -622,7 +732,7 @@ fn run_jit_file(filename: &str, emit_llvm: bool, opt_level: OptimizationLevel, t
     let _ = std::io::stdout().flush();
     
     let start_time = Instant::now();
-    compiler.run_jit()?;
+    compiler.run_jit().map_err(codegen_failed)?;
     let duration = start_time.elapsed();
     
     // Flush stdout AFTER JIT execution
@@ -640,6 +750,7 @@ fn run_test_suite() {
     let test_dir = "tests/";
     let mut passed_count = 0;
     let mut failed_count = 0;
+    let seed = environment::current_seed();
 
     match fs::read_dir(test_dir) {
         Ok(entries) => {
@@ -658,16 +769,35 @@ fn run_test_suite() {
                         if ext == "qc" {
                             let filename = path.to_str().unwrap_or("unknown file");
                             print!("Running test: {} ... ", filename);
+                            // Every file starts from the same seed, so a failure replays on its own.
+                            environment::set_seed(seed);
                             
                             // Run the test file and capture its result
-                            match run_test_file(filename) {
-                                Ok(()) => {
+                            let result = match fs::read_to_string(filename) {
+                                Ok(source) => match run_test_file(&source) {
+                                    Ok(warnings) => Ok((source, warnings)),
+                                    Err(errors) => Err((source, errors)),
+                                },
+                                Err(e) => Err((String::new(), vec![
+                                    Diagnostic::error(diagnostics::FILE_ERROR, format!("Failed to read file '{}': {}", filename, e)),
+                                ])),
+                            };
+                            match result {
+                                Ok((source, warnings)) => {
                                     println!("PASS ✅");
+                                    let _ = diagnostics::report(&warnings, &source, filename);
                                     passed_count += 1;
                                 }
-                                Err(e) => {
+                                Err((source, errors)) => {
                                     println!("FAIL ❌");
-                                    eprintln!("  Error: {}\n", e);
+                                    let errors: Vec<Diagnostic> = errors.into_iter()
+                                        .map(|error| error.with_note(format!("replay with --seed {}", seed)))
+                                        .collect();
+                                    if let Err(summary) = diagnostics::report(&errors, &source, filename) {
+                                        if diagnostics::message_format() == MessageFormat::Human {
+                                            eprintln!("  Error: {}\n", summary);
+                                        }
+                                    }
                                     failed_count += 1;
                                 }
                             }
@@ -690,40 +820,25 @@ fn run_test_suite() {
     println!("  ❌ Failed: {}", failed_count);
     println!("{:-<60}", "");
 
+    diagnostics::print_summary();
     if failed_count > 0 {
         std::process::exit(1); // Exit with error if any test failed
     }
 }
 
 
-/// Runs the full pipeline on a single file, returning a Result.
-fn run_test_file(filename: &str) -> Result<(), String> {
-    
-    //Read source
-    let source = fs::read_to_string(filename)
-        .map_err(|e| format!("Failed to read file '{}': {}", filename, e))?;
-    
-    // Lexical Analysis
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize()
-        .map_err(|e| format!("Lexer error: {}", e))?;
-    
-    // Syntax Analysis (Parsing)
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse()
-        .map_err(|e| format!("Parser error: {}", e))?;
-    
-    // Type Checking
-    TypeChecker::check_program(&ast)
-        .map_err(|e| format!("Type error: {}", e))?;
+/// Runs the full pipeline on a single file, returning its warnings if it passes.
+fn run_test_file(source: &str) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    // Lexical Analysis, Syntax Analysis (Parsing) and Type Checking
+    let (ast, warnings) = check_source(source)?;
     
     //Interpretation (Evaluation)
     let env = std::rc::Rc::new(std::cell::RefCell::new(Environment::new()));
     Evaluator::evaluate_program(&ast, &env)
-        .map_err(|e| format!("Runtime Error: {}", e))?; // assert() failure will be caught here
+        .map_err(|e| vec![Evaluator::diagnostic(&e)])?; // assert() failure will be caught here
 
     //If all steps passed:
-    Ok(())
+    Ok(warnings)
 }
 
 fn print_ast(node: &ASTNode, indent: usize) { 
@@ -760,24 +875,48 @@ fn print_ast(node: &ASTNode, indent: usize) {
             println!("{}Import {} as {}", prefix, path_str, alias);
         }
         
-        ASTNode::FunctionDeclaration { name, parameters, return_type, body , .. } => {
+        ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } => {
             let ret_str = if let Some(t) = return_type {
                 format!(" -> {:?}", t)
             } else {
                 String::new()
             };
-            println!("{}Function {}({} params){}", prefix, name, parameters.len(), ret_str);
+            println!("{}Function {}{}({} params){}", prefix, name, generic_list(generics), parameters.len(), ret_str);
+            print_where_clause(generics, indent + 1);
             print_ast(body, indent + 1);
         }
-        ASTNode::CircuitDeclaration { name, parameters, return_type, body, .. } => {
+        ASTNode::CircuitDeclaration { name, generics, parameters, return_type, body } => {
             let ret_str = if let Some(t) = return_type {
                 format!(" -> {:?}", t)
             } else {
                 String::new()
             };
-            println!("{}Circuit {}({} params){}", prefix, name, parameters.len(), ret_str);
+            println!("{}Circuit {}{}({} params){}", prefix, name, generic_list(generics), parameters.len(), ret_str);
+            print_where_clause(generics, indent + 1);
             print_ast(body, indent + 1);
         }
+        ASTNode::GateDeclaration { name, matrix, .. } => {
+            println!("{}Gate {}:", prefix, name);
+            print_ast(matrix, indent + 1);
+        }
+        ASTNode::StructDeclaration { name, fields, .. } => {
+            println!("{}Struct {}:", prefix, name);
+            for field in fields {
+                println!("{}  {}: {:?}", prefix, field.name, field.param_type);
+            }
+        }
+        ASTNode::EnumDeclaration { name, variants, .. } => {
+            println!("{}Enum {}:", prefix, name);
+            for variant in variants {
+                println!("{}  {}{:?}", prefix, variant.name, variant.fields);
+            }
+        }
+        ASTNode::ImplBlock { type_name, methods, .. } => {
+            println!("{}Impl {}:", prefix, type_name);
+            for method in methods {
+                print_ast(method, indent + 1);
+            }
+        }
         ASTNode::Return(value) => {
             println!("{}Return:", prefix);
             if let Some(v) = value {
@@ -832,6 +971,15 @@ fn print_ast(node: &ASTNode, indent: usize) {
                 print_ast(arg, indent + 2);
             }
         }
+        ASTNode::Lambda { parameters, return_type, body, .. } => {
+            let ret_str = if let Some(t) = return_type {
+                format!(" -> {:?}", t)
+            } else {
+                String::new()
+            };
+            println!("{}Lambda({} params){}", prefix, parameters.len(), ret_str);
+            print_ast(body, indent + 1);
+        }
         ASTNode::Apply { gate_expr, arguments, .. } => {
             println!("{}Apply ({} args):", prefix, arguments.len());
             println!("{}  Gate:", prefix);
@@ -862,6 +1010,9 @@ fn print_ast(node: &ASTNode, indent: usize) {
         ASTNode::FloatLiteral(f) => {
             println!("{}Float: {}", prefix, f);
         }
+        ASTNode::ImaginaryLiteral(f) => {
+            println!("{}Imaginary: {}i", prefix, f);
+        }
         ASTNode::StringLiteral(s) => {
             println!("{}String: \"{}\"", prefix, s);
         }
@@ -924,12 +1075,16 @@ fn print_ast(node: &ASTNode, indent: usize) {
             println!("{}  Value:", prefix);
             print_ast(value, indent + 2);
         }
-        ASTNode::Match { value, cases } => {
+        ASTNode::Match { value, cases, .. } => {
             println!("{}Match:", prefix);
             print_ast(value, indent + 1);
-            for (pattern, body) in cases {
-                println!("{}  Case {:?}:", prefix, pattern);
-                print_ast(body, indent + 2);
+            for case in cases {
+                println!("{}  Case {:?}:", prefix, case.pattern);
+                if let Some(guard) = &case.guard {
+                    println!("{}  Guard:", prefix);
+                    print_ast(guard, indent + 2);
+                }
+                print_ast(&case.body, indent + 2);
             }
         }
         ASTNode::Break => {
@@ -956,17 +1111,49 @@ fn print_ast(node: &ASTNode, indent: usize) {
         ASTNode::Continue => {
             println!("{}Continue", prefix);
         }
-        ASTNode::TryCatch { try_block, error_variable, catch_block } => {
+        ASTNode::TryCatch { try_block, handlers, finally_block, .. } => {
             println!("{}Try:", prefix);
             print_ast(try_block, indent + 1);
 
-            if let Some(var) = error_variable {
-                println!("{}Catch ({}):", prefix, var);
-            } else {
-                println!("{}Catch:", prefix);
+            for handler in handlers {
+                let kinds = if handler.kinds.is_empty() { String::new() } else { format!(" {}", handler.kinds.join(", ")) };
+                match &handler.variable {
+                    Some(var) => println!("{}Catch{} ({}):", prefix, kinds, var),
+                    None => println!("{}Catch{}:", prefix, kinds),
+                }
+                print_ast(&handler.body, indent + 1);
+            }
+
+            if let Some(block) = finally_block {
+                println!("{}Finally:", prefix);
+                print_ast(block, indent + 1);
             }
+        }
+        ASTNode::Throw { value, .. } => {
+            println!("{}Throw:", prefix);
+            print_ast(value, indent + 1);
+        }
+    }
+}
 
-            print_ast(catch_block, indent + 1);
+fn generic_list(generics: &Generics) -> String {
+    if generics.is_empty() {
+        return String::new();
+    }
+    let names: Vec<String> = generics.params.iter()
+        .map(|param| match param {
+            GenericParam::Type(name) => name.clone(),
+            GenericParam::Size(name) => format!("{}: Int", name),
+        })
+        .collect();
+    format!("<{}>", names.join(", "))
+}
+
+fn print_where_clause(generics: &Generics, indent: usize) {
+    if !generics.where_clause.is_empty() {
+        println!("{}Where:", "  ".repeat(indent));
+        for constraint in &generics.where_clause {
+            print_ast(constraint, indent + 1);
         }
     }
 }
@@ -1102,6 +1289,9 @@ fn run_repl() {
             for stmt in statements {
                 //Type Check the single statement
                 let type_check_result = TypeChecker::check(&stmt, &type_env, None);
+                for warning in TypeChecker::take_warnings() {
+                    println!("Warning: {}", warning.message);
+                }
                 if let Err(e) = type_check_result {
                     println!("Type Error: {}", e);
                     // Don't execute if type check fails
@@ -1167,32 +1357,27 @@ fn parse_opt_level(arg: &str) -> Result<OptimizationLevel, String> {
 }
 
 
-fn run_on_hardware(filename: &str, config: QuantumConfig) -> Result<(), Box<dyn std::error::Error>> {
+fn run_on_hardware(filename: &str, config: QuantumConfig) {
     println!("🚀 Executing on Quantum Hardware: {:?}\n", config.provider);
     println!("📄 File: {}", filename);
     println!("🎯 Device: {}", config.device_name.as_ref().unwrap_or(&"default".to_string()));
     println!("🎲 Shots: {}\n", config.shots);
 
     // Read source
-    let source = fs::read_to_string(filename)
-        .map_err(|e| format!("Failed to read file: {}", e))?;
-
-    // Lex
-    println!("🔤 Phase 1: Lexical Analysis");
-    let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize()
-        .map_err(|e| format!("Lexer error: {}", e))?;
-
-    // Parse
-    println!("🌳 Phase 2: Syntax Analysis");
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse()
-        .map_err(|e| format!("Parser error: {}", e))?;
+    let source = match read_source(filename) {
+        Ok(content) => content,
+        Err(summary) => exit_failed(&format!("✗ {}", summary)),
+    };
 
-    // Type Check
-    println!("🔬 Phase 3: Type Checking");
-    TypeChecker::check_program(&ast)
-        .map_err(|e| format!("Type error: {}", e))?;
+    // Lexical Analysis, Syntax Analysis (Parsing) and Type Checking
+    println!("🔤 Phases 1-3: Lexing, Parsing and Type Checking");
+    let ast = match check_source(&source) {
+        Ok((ast, warnings)) => {
+            let _ = diagnostics::report(&warnings, &source, filename);
+            ast
+        }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
+    };
     println!("✓ Type check succeeded!\n");
 
     // Execute on hardware
@@ -1200,28 +1385,55 @@ fn run_on_hardware(filename: &str, config: QuantumConfig) -> Result<(), Box<dyn
     let env = std::rc::Rc::new(std::cell::RefCell::new(Environment::new()));
     let mut executor = HardwareExecutor::new(config);
     
-    let result = executor.execute_on_hardware(&ast, &env)
-        .map_err(|e| format!("Hardware execution error: {}", e))?;
+    let result = match executor.execute_on_hardware(&ast, &env) {
+        Ok(result) => result,
+        Err(e) => fail(&[Evaluator::diagnostic(&format!("Hardware execution error: {}", e).into())], &source, filename),
+    };
 
     if result.success {
         println!("\n✅ Hardware execution successful!");
-        
-        // Show top results
-        if !result.counts.is_empty() {
-            println!("\n📊 Measurement Results:");
-            let mut sorted: Vec<_> = result.counts.iter().collect();
-            sorted.sort_by(|a, b| b.1.cmp(a.1));
-            
-            for (i, (bitstring, count)) in sorted.iter().take(10).enumerate() {
-                let prob = **count as f64 / result.shots as f64;
-                println!("  {}. |{}⟩: {} ({:.2}%)", i + 1, bitstring, count, prob * 100.0);
-            }
-        }
+        print_quantum_result(&result);
     } else {
-        eprintln!("❌ Hardware execution failed: {}", 
-                  result.error_message.unwrap_or("Unknown error".to_string()));
+        let message = format!("Hardware execution failed: {}", result.error_message.unwrap_or("Unknown error".to_string()));
+        fail(&[Evaluator::diagnostic(&message.into())], &source, filename);
     }
+}
 
-    Ok(())
+fn run_local_shots(filename: &str, shots: u32) {
+    println!("🎲 Running {} shots locally: {}\n", shots, filename);
+
+    let source = match read_source(filename) {
+        Ok(content) => content,
+        Err(summary) => exit_failed(&format!("✗ {}", summary)),
+    };
+    let ast = match check_source(&source) {
+        Ok((ast, warnings)) => {
+            let _ = diagnostics::report(&warnings, &source, filename);
+            ast
+        }
+        Err(diagnostics) => fail(&diagnostics, &source, filename),
+    };
+
+    let result = match shots::run_shots(&ast, shots) {
+        Ok(result) => result,
+        Err(e) => fail(&[Evaluator::diagnostic(&e)], &source, filename),
+    };
+    println!("\n✅ {} shots complete", result.shots);
+    print_quantum_result(&result);
+}
+
+// Top ten outcomes of a run, most frequent first.
+fn print_quantum_result(result: &QuantumResult) {
+    if result.counts.is_empty() {
+        return;
+    }
+    println!("\n📊 Measurement Results:");
+    let mut sorted: Vec<_> = result.counts.iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    for (i, (bitstring, count)) in sorted.iter().take(10).enumerate() {
+        let prob = **count as f64 / result.shots as f64;
+        println!("  {}. |{}⟩: {} ({:.2}%)", i + 1, bitstring, count, prob * 100.0);
+    }
 }
 
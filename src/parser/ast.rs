@@ -1,10 +1,13 @@
 // src/parser/ast.rs
 use std::fmt;
 use std::collections::HashMap;
+/// Where a node is in the source: the start of the token it is anchored at (its name,
+/// keyword or operator) and how many columns that token covers.
 #[derive(Debug, Clone, PartialEq,Copy)]
 pub struct Loc {
     pub line: usize,
     pub column: usize,
+    pub length: usize,
 }
 
 impl fmt::Display for Loc {
@@ -52,8 +55,13 @@ pub enum ASTNode {
 
     TryCatch {
         try_block: Box<ASTNode>,
-        error_variable: Option<String>, // For 'catch err:'
-        catch_block: Box<ASTNode>,
+        handlers: Vec<CatchClause>,
+        finally_block: Option<Box<ASTNode>>,
+        loc: Loc,
+    },
+    Throw {
+        value: Box<ASTNode>,
+        loc: Loc,
     },
     
     QuantumDeclaration {
@@ -64,6 +72,7 @@ pub enum ASTNode {
     FunctionDeclaration {
         
         name: String,
+        generics: Generics,
         parameters: Vec<Parameter>,
         return_type: Option<Type>,
         body: Box<ASTNode>,
@@ -71,10 +80,16 @@ pub enum ASTNode {
     CircuitDeclaration {
         
         name: String,
+        generics: Generics,
         parameters: Vec<Parameter>,
         return_type: Option<Type>,
         body: Box<ASTNode>,
     },
+    GateDeclaration {
+        name: String,
+        matrix: Box<ASTNode>, // e.g., [[1, 0], [0, [0, 1]]] with [re, im] entries
+        loc: Loc,
+    },
     Return(Option<Box<ASTNode>>),
     
     // Control flow
@@ -84,9 +99,26 @@ pub enum ASTNode {
         elif_blocks: Vec<(ASTNode, ASTNode)>,
         else_block: Option<Box<ASTNode>>,
     },
+    StructDeclaration {
+        name: String,
+        fields: Vec<Parameter>,
+        loc: Loc,
+    },
+    EnumDeclaration {
+        name: String,
+        variants: Vec<EnumVariant>,
+        loc: Loc,
+    },
+    /// Methods of a struct or enum; each is a function or circuit declaration.
+    ImplBlock {
+        type_name: String,
+        methods: Vec<ASTNode>,
+        loc: Loc,
+    },
     Match {
         value: Box<ASTNode>,
-        cases: Vec<(Pattern, ASTNode)>,
+        cases: Vec<MatchCase>,
+        loc: Loc,
     },
     For {
         variable: String,
@@ -117,6 +149,13 @@ pub enum ASTNode {
         loc: Loc,
         is_dagger: bool,
     },
+    /// `func(x: Float) -> Float: x * x`; the body is an expression or a block.
+    Lambda {
+        parameters: Vec<Parameter>,
+        return_type: Option<Type>,
+        body: Box<ASTNode>,
+        loc: Loc,
+    },
     Apply {
         gate_expr: Box<ASTNode>,
         arguments: Vec<ASTNode>,
@@ -146,6 +185,7 @@ pub enum ASTNode {
     // Literals
     IntLiteral(i64),
     FloatLiteral(f64),
+    ImaginaryLiteral(f64),
     StringLiteral(String),
     BoolLiteral(bool),
     NoneLiteral,
@@ -186,13 +226,88 @@ pub enum ASTNode {
     },
 }
 
+impl ASTNode {
+    /// Direct sub-nodes, in source order.
+    pub fn children(&self) -> Vec<&ASTNode> {
+        match self {
+            ASTNode::Program(nodes) | ASTNode::Block(nodes) | ASTNode::ArrayLiteral(nodes) |
+            ASTNode::ImplBlock { methods: nodes, .. } => nodes.iter().collect(),
+            ASTNode::LetDeclaration { value, .. } => vec![value],
+            ASTNode::TryCatch { try_block, handlers, finally_block, .. } => std::iter::once(&**try_block)
+                .chain(handlers.iter().map(|handler| &handler.body))
+                .chain(finally_block.iter().map(|n| &**n))
+                .collect(),
+            ASTNode::Throw { value, .. } => vec![value],
+            ASTNode::QuantumDeclaration { size, initial_state, .. } => size.iter().chain(initial_state.iter()).map(|n| &**n).collect(),
+            ASTNode::FunctionDeclaration { body, .. } | ASTNode::CircuitDeclaration { body, .. } => vec![body],
+            ASTNode::GateDeclaration { matrix, .. } => vec![matrix],
+            ASTNode::Return(value) => value.iter().map(|n| &**n).collect(),
+            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+                let mut nodes: Vec<&ASTNode> = vec![condition, then_block];
+                for (elif_condition, elif_block) in elif_blocks {
+                    nodes.push(elif_condition);
+                    nodes.push(elif_block);
+                }
+                nodes.extend(else_block.iter().map(|n| &**n));
+                nodes
+            }
+            ASTNode::Match { value, cases, .. } => {
+                let mut nodes: Vec<&ASTNode> = vec![value];
+                for case in cases {
+                    case.pattern.collect_nodes(&mut nodes);
+                    nodes.extend(case.guard.iter());
+                    nodes.push(&case.body);
+                }
+                nodes
+            }
+            ASTNode::For { iterator, body, .. } => vec![iterator, body],
+            ASTNode::While { condition, body } => vec![condition, body],
+            ASTNode::Binary { left, right, .. } => vec![left, right],
+            ASTNode::Unary { operand, .. } => vec![operand],
+            ASTNode::FunctionCall { callee, arguments, .. } => std::iter::once(&**callee).chain(arguments.iter()).collect(),
+            ASTNode::Lambda { body, .. } => vec![body],
+            ASTNode::Apply { gate_expr, arguments, .. } => std::iter::once(&**gate_expr).chain(arguments.iter()).collect(),
+            ASTNode::ParameterizedGate { parameters, .. } => parameters.iter().collect(),
+            ASTNode::Dagger { gate_expr, .. } | ASTNode::Controlled { gate_expr, .. } => vec![gate_expr],
+            ASTNode::Measure(target) => vec![target],
+            ASTNode::ArrayAccess { array, index, .. } => vec![array, index],
+            ASTNode::MemberAccess { object, .. } => vec![object],
+            ASTNode::DictLiteral(entries) => entries.iter().flat_map(|(key, value)| [key, value]).collect(),
+            ASTNode::Range { start, end, .. } => vec![start, end],
+            ASTNode::Assignment { target, value } => vec![target, value],
+            ASTNode::Import { .. } | ASTNode::FromImport { .. } | ASTNode::Break | ASTNode::Continue |
+            ASTNode::StructDeclaration { .. } | ASTNode::EnumDeclaration { .. } |
+            ASTNode::Gate { .. } | ASTNode::IntLiteral(_) | ASTNode::FloatLiteral(_) | ASTNode::ImaginaryLiteral(_) | ASTNode::StringLiteral(_) |
+            ASTNode::BoolLiteral(_) | ASTNode::NoneLiteral | ASTNode::QuantumKet(_) | ASTNode::QuantumBra(_) |
+            ASTNode::Identifier { .. } => vec![],
+        }
+    }
+
+    /// Location of this node, or of the first node below it that records one.
+    pub fn loc(&self) -> Option<Loc> {
+        match self {
+            ASTNode::TryCatch { loc, .. } | ASTNode::Throw { loc, .. } | ASTNode::GateDeclaration { loc, .. } |
+            ASTNode::StructDeclaration { loc, .. } | ASTNode::EnumDeclaration { loc, .. } | ASTNode::ImplBlock { loc, .. } |
+            ASTNode::Match { loc, .. } | ASTNode::Binary { loc, .. } | ASTNode::FunctionCall { loc, .. } |
+            ASTNode::Lambda { loc, .. } | ASTNode::Apply { loc, .. } | ASTNode::Gate { loc, .. } |
+            ASTNode::ParameterizedGate { loc, .. } | ASTNode::Dagger { loc, .. } | ASTNode::Controlled { loc, .. } |
+            ASTNode::Identifier { loc, .. } | ASTNode::ArrayAccess { loc, .. } => Some(*loc),
+            _ => self.children().into_iter().find_map(ASTNode::loc),
+        }
+    }
+
+    /// True if `pred` holds for this node or any node below it.
+    pub fn any_node(&self, pred: &dyn Fn(&ASTNode) -> bool) -> bool {
+        pred(self) || self.children().into_iter().any(|child| child.any_node(pred))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOperator {
     Add, Sub, Mul, Div, Mod,
     Equal, NotEqual, Less, Greater, LessEqual, GreaterEqual,
     And, Or,
     TensorProduct,  // ***
-    Pipeline, 
     Power,      
 }
 
@@ -207,6 +322,8 @@ pub enum UnaryOperator {
 pub struct Parameter {
     pub name: String,
     pub param_type: Type,
+    // Only a method receiver can be mutable, written `mut self`.
+    pub is_mutable: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -226,15 +343,128 @@ pub enum Type {
     Dict,
     Module(HashMap<String, Type>),
     Function(Vec<Type>, Box<Type>),
+    Observable,
+    Ket(Option<usize>),
+    Bra(Option<usize>),
     Custom(String),
+    Error,
+    /// A generic type parameter `T`, opaque inside the declaration that binds it.
+    Generic(String),
+    /// `QuantumRegister[n]` where `n` is a generic size parameter.
+    GenericRegister(String),
     Any,
     None,
 }
 
+/// Generic parameters of a function or circuit: `<T, n: Int>` followed by an
+/// optional `where n >= 2, ...` before the colon. Both are empty for ordinary
+/// declarations.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Generics {
+    pub params: Vec<GenericParam>,
+    pub where_clause: Vec<ASTNode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenericParam {
+    /// A type parameter, inferred from the argument types at each call.
+    Type(String),
+    /// An `Int` parameter, inferred from the sizes of `QuantumRegister[n]` arguments.
+    Size(String),
+}
+
+impl Generics {
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    pub fn size_params(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().filter_map(|param| match param {
+            GenericParam::Size(name) => Some(name.as_str()),
+            GenericParam::Type(_) => None,
+        })
+    }
+}
+
+/// A variant of an `enum` declaration with its positional payload types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    pub name: String,
+    pub fields: Vec<Type>,
+}
+
+/// A handler of a `Try`: `Except Timeout, QueueFull as e:` handles the listed
+/// error kinds, while `Catch e:` (no kinds) handles every error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatchClause {
+    pub kinds: Vec<String>,
+    pub variable: Option<String>,
+    pub body: ASTNode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchCase {
+    pub pattern: Pattern,
+    pub guard: Option<ASTNode>,
+    pub body: ASTNode,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Pattern {
     Literal(ASTNode),
     Identifier(String),
     Wildcard,
+    Range {
+        start: ASTNode,
+        end: ASTNode,
+        inclusive: bool,
+    },
+    Alternatives(Vec<Pattern>), // 1 | 2 | 3
+    Binding {
+        pattern: Box<Pattern>,
+        name: String, // 1..10 as n
+    },
+    Variant {
+        enum_name: Option<String>,
+        variant: String,
+        fields: Vec<Pattern>, // Shape.Rect(w, _)
+    },
+}
+
+impl Pattern {
+    /// Whether the pattern matches every value, ignoring guards.
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Identifier(_) | Pattern::Wildcard => true,
+            Pattern::Alternatives(options) => options.iter().any(Pattern::is_irrefutable),
+            Pattern::Binding { pattern, .. } => pattern.is_irrefutable(),
+            Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Variant { .. } => false,
+        }
+    }
 
+    /// Names bound when the pattern matches.
+    pub fn bindings(&self) -> Vec<&str> {
+        match self {
+            Pattern::Identifier(name) => vec![name.as_str()],
+            Pattern::Alternatives(options) => options.first().map_or_else(Vec::new, Pattern::bindings),
+            Pattern::Binding { pattern, name } => {
+                let mut names = pattern.bindings();
+                names.push(name.as_str());
+                names
+            }
+            Pattern::Variant { fields, .. } => fields.iter().flat_map(Pattern::bindings).collect(),
+            Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Wildcard => vec![],
+        }
+    }
+
+    fn collect_nodes<'a>(&'a self, nodes: &mut Vec<&'a ASTNode>) {
+        match self {
+            Pattern::Literal(literal) => nodes.push(literal),
+            Pattern::Range { start, end, .. } => nodes.extend([start, end]),
+            Pattern::Alternatives(options) => options.iter().for_each(|option| option.collect_nodes(nodes)),
+            Pattern::Binding { pattern, .. } => pattern.collect_nodes(nodes),
+            Pattern::Variant { fields, .. } => fields.iter().for_each(|field| field.collect_nodes(nodes)),
+            Pattern::Identifier(_) | Pattern::Wildcard => {}
+        }
+    }
 }
@@ -2,20 +2,86 @@
 pub mod ast;
 
 use crate::lexer::token::{Token, TokenWithLocation};
+use crate::diagnostics::{Diagnostic, Span, SYNTAX_ERROR};
 use ast::*;
 use crate::parser::ast::UnaryOperator;
+use std::fmt;
+
+/// A syntax error, with the source it is about when the parser knows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl SyntaxError {
+    fn at(loc: Loc, message: impl Into<String>) -> Self {
+        SyntaxError { message: message.into(), span: Some(Span::from(loc)) }
+    }
+}
+
+impl From<String> for SyntaxError {
+    fn from(message: String) -> Self {
+        SyntaxError { message, span: None }
+    }
+}
+
+impl From<&str> for SyntaxError {
+    fn from(message: &str) -> Self {
+        SyntaxError::from(message.to_string())
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "Syntax Error at {}: {}", span.start, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
 
 pub struct Parser {
     tokens: Vec<TokenWithLocation>,
     position: usize,
+    // Generic parameters of the declarations being parsed, innermost last.
+    generic_params: Vec<GenericParam>,
+    // Errors of the statements skipped so far, with the token each statement began at.
+    errors: Vec<(SyntaxError, usize)>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<TokenWithLocation>) -> Self {
-        Self { tokens, position: 0 }
+        Self { tokens, position: 0, generic_params: Vec::new(), errors: Vec::new() }
     }
     
     pub fn parse(&mut self) -> Result<ASTNode, String> {
+        let program = self.parse_program();
+        match self.errors.first() {
+            Some((error, _)) => Err(error.to_string()),
+            None => Ok(program),
+        }
+    }
+
+    /// Parse the whole file, reporting every syntax error in it rather than the first.
+    pub fn parse_with_diagnostics(&mut self) -> Result<ASTNode, Vec<Diagnostic>> {
+        let program = self.parse_program();
+        if self.errors.is_empty() {
+            return Ok(program);
+        }
+        Err(self.errors.iter().map(|(error, start)| {
+            let mut diagnostic = Diagnostic::error(SYNTAX_ERROR, error.message.clone());
+            if let Some(span) = error.span {
+                diagnostic = diagnostic.with_primary(span, "");
+            }
+            if let Some(first) = self.tokens.get(*start).filter(|first| error.span.is_some_and(|span| span.start.line != first.line)) {
+                diagnostic = diagnostic.with_secondary(Span::of_token(first), "in this statement");
+            }
+            diagnostic.with_note("the rest of this statement was skipped")
+        }).collect())
+    }
+
+    fn parse_program(&mut self) -> ASTNode {
         let mut statements = Vec::new();
         
         while !self.is_at_end() {
@@ -24,12 +90,72 @@ impl Parser {
                 continue;
             }
             
-            statements.push(self.parse_statement()?);
+            statements.extend(self.parse_recovering());
         }
         
-        Ok(ASTNode::Program(statements))
+        ASTNode::Program(statements)
+    }
+
+    // A statement, or `None` after recording its error and skipping past it.
+    fn parse_recovering(&mut self) -> Option<ASTNode> {
+        let start = self.position;
+        match self.parse_statement() {
+            Ok(statement) => Some(statement),
+            Err(mut error) => {
+                // Without a better place, or at a token with no text such as a dedent or
+                // a line break, the error is at the last token the parser reached.
+                let textless = |t: &TokenWithLocation| t.length == 0 || t.token == Token::Newline;
+                let at_textless = self.tokens.get(self.position).is_some_and(|t| {
+                    textless(t) && error.span.is_some_and(|span| (span.start.line, span.start.column) == (t.line, t.column))
+                });
+                if error.span.is_none() || at_textless {
+                    let stopped = self.tokens.iter().take(self.position + 1).rev().find(|t| !textless(t));
+                    error.span = stopped.map(Span::of_token);
+                }
+                self.errors.push((error, start));
+                self.synchronize(start);
+                None
+            }
+        }
+    }
+
+    // Skip to the end of the statement that began at `start`, including any block it
+    // opened, but not past the end of the block it is in.
+    fn synchronize(&mut self, start: usize) {
+        let consumed = &self.tokens[start..self.position.min(self.tokens.len())];
+        let mut depth = consumed.iter().fold(0i64, |depth, t| match t.token {
+            Token::Indent => depth + 1,
+            Token::Dedent => depth - 1,
+            _ => depth,
+        });
+        while !self.is_at_end() {
+            match self.tokens[self.position].token {
+                Token::Newline if depth <= 0 => {
+                    self.advance();
+                    // A block opened by the broken statement goes with it.
+                    if !self.check(&Token::Indent) {
+                        break;
+                    }
+                    continue;
+                }
+                Token::Dedent if depth <= 0 => break,
+                Token::Indent => depth += 1,
+                Token::Dedent => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+        if self.position == start {
+            self.advance();
+        }
     }
-    fn parse_array_literal(&mut self) -> Result<ASTNode, String> {
+    fn parse_array_literal(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut elements = Vec::new();
         
 
@@ -40,9 +166,9 @@ impl Parser {
         
     
         loop {
-    
+            self.skip_newlines();
             elements.push(self.parse_expression()?);
-            
+            self.skip_newlines();
    
             if self.check(&Token::RightBracket) {
                 break;
@@ -57,12 +183,12 @@ impl Parser {
         Ok(ASTNode::ArrayLiteral(elements))
     }
     
-    fn parse_statement(&mut self) -> Result<ASTNode, String> {
+    fn parse_statement(&mut self) -> Result<ASTNode, SyntaxError> {
     
         
         
         if self.is_at_end() {
-            return Err("Unexpected end of file".to_string());
+            return Err("Unexpected end of file".into());
         }
         
         
@@ -75,7 +201,12 @@ impl Parser {
             Token::From => self.parse_from_import(),
             Token::Func => self.parse_function_declaration(),
             Token::Circuit => self.parse_circuit_declaration(),
+            Token::Gate => self.parse_gate_declaration(),
+            Token::Struct => self.parse_struct_declaration(),
+            Token::Enum => self.parse_enum_declaration(),
+            Token::Impl => self.parse_impl_block(),
             Token::Return => self.parse_return(),
+            Token::Throw => self.parse_throw(),
             Token::If => self.parse_if(),
             Token::Match => self.parse_match(),
             Token::For => self.parse_for(),
@@ -112,7 +243,7 @@ impl Parser {
         }
     }
 
-    fn parse_gate_expression(&mut self) -> Result<ASTNode, String> {
+    fn parse_gate_expression(&mut self) -> Result<ASTNode, SyntaxError> {
         let loc = self.get_loc(self.current()?);
         
         if self.match_token(&Token::Controlled) {
@@ -133,7 +264,17 @@ impl Parser {
  
             let gate_token = self.expect_identifier()?;
             let gate_name = self.extract_identifier_name(&gate_token)?;
-            
+
+            // A circuit method such as `controlled(ansatz.layer)`.
+            if self.check(&Token::Dot) {
+                let mut expr = ASTNode::Identifier { name: gate_name, loc: self.get_loc(&gate_token) };
+                while self.match_token(&Token::Dot) {
+                    let member_token = self.expect_identifier()?;
+                    let member = self.extract_identifier_name(&member_token)?;
+                    expr = ASTNode::MemberAccess { object: Box::new(expr), member };
+                }
+                return Ok(expr);
+            }
    
             if self.check(&Token::LeftParen) {
                 self.advance();
@@ -162,7 +303,7 @@ impl Parser {
         }
     }
 
-    fn parse_apply_statement(&mut self) -> Result<ASTNode, String> {
+    fn parse_apply_statement(&mut self) -> Result<ASTNode, SyntaxError> {
     let loc = self.get_loc(self.current()?);
     self.expect(&Token::Apply)?; 
 
@@ -274,7 +415,7 @@ impl Parser {
     Ok(apply_node)
 }
 
-    fn parse_gate_application(&mut self, loc: Loc) -> Result<ASTNode, String> {
+    fn parse_gate_application(&mut self, loc: Loc) -> Result<ASTNode, SyntaxError> {
         let is_simple_gate = matches!(
             self.current()?.token,
             Token::X | Token::Y | Token::Z | Token::Hadamard | Token::S | Token::T |
@@ -351,8 +492,8 @@ impl Parser {
 
         if !self.check(&Token::LeftParen) {
             let current_token = self.current()?;
-            return Err(format!(
-                "Expected '(' for qubit arguments after gate '{}' at line {}, column {}, but found {:?}",
+            return Err(SyntaxError::at(self.get_loc(current_token), format!(
+                "Expected '(' for qubit arguments after gate '{}', but found {:?}",
                 match &gate_expr {
                     ASTNode::Gate { name, .. } => name.as_str(),
                     ASTNode::ParameterizedGate { name, .. } => name.as_str(),
@@ -360,8 +501,8 @@ impl Parser {
                     ASTNode::Dagger { .. } => "dagger",
                     _ => "gate"
                 },
-                current_token.line, current_token.column, current_token.token
-            ));
+                current_token.token
+            )));
         }
         self.advance();
         let arguments = self.parse_arguments()?;
@@ -374,7 +515,7 @@ impl Parser {
     }
     
     
-    fn parse_let_declaration(&mut self,) -> Result<ASTNode, String> {
+    fn parse_let_declaration(&mut self,) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Let)?;
         
         let name_loc = self.expect_identifier()?; 
@@ -404,7 +545,7 @@ impl Parser {
         })
     }
     
-    fn parse_mut_declaration(&mut self,) -> Result<ASTNode, String> {
+    fn parse_mut_declaration(&mut self,) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Mut)?;
         
         let name_loc = self.expect_identifier()?;
@@ -435,36 +576,103 @@ impl Parser {
         })
     }
 
-    fn parse_try_catch(&mut self) -> Result<ASTNode, String> {
+    fn parse_try_catch(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
         self.expect(&Token::Try)?;
         self.expect(&Token::Colon)?;
         self.skip_newlines();
 
         let try_block = self.parse_block()?;
 
-        self.expect(&Token::Catch)?;
+        let mut handlers: Vec<CatchClause> = Vec::new();
+        while self.check(&Token::Except) || self.check(&Token::Catch) {
+            let clause_loc = self.get_loc(self.current()?);
+            let mut kinds = Vec::new();
+            let variable = if self.match_token(&Token::Except) {
+                loop {
+                    let kind_loc = self.expect_identifier()?;
+                    kinds.push(self.extract_identifier_name(&kind_loc)?);
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                if self.match_token(&Token::As) {
+                    let name_loc = self.expect_identifier()?;
+                    Some(self.extract_identifier_name(&name_loc)?)
+                } else {
+                    None
+                }
+            } else {
+                self.expect(&Token::Catch)?;
+                if self.check(&Token::Colon) {
+                    None
+                } else {
+                    let name_loc = self.expect_identifier()?;
+                    Some(self.extract_identifier_name(&name_loc)?)
+                }
+            };
 
-        let error_variable = if self.check(&Token::Colon) {
-            None
+            if handlers.last().is_some_and(|handler| handler.kinds.is_empty()) {
+                return Err(SyntaxError::at(clause_loc, "Unreachable handler; 'Catch' already handles every error and must come last."));
+            }
+            self.expect(&Token::Colon)?;
+            self.skip_newlines();
+            let body = self.parse_block()?;
+            handlers.push(CatchClause { kinds, variable, body });
+        }
+
+        let finally_block = if self.match_token(&Token::Finally) {
+            self.expect(&Token::Colon)?;
+            self.skip_newlines();
+            Some(Box::new(self.parse_block()?))
         } else {
-            let name_loc = self.expect_identifier()?;
-            let name = self.extract_identifier_name(&name_loc)?;
-            Some(name)
+            None
         };
 
-        self.expect(&Token::Colon)?;
-        self.skip_newlines();
-
-        let catch_block = self.parse_block()?;
+        if handlers.is_empty() && finally_block.is_none() {
+            return Err(SyntaxError::at(loc, "'Try' needs an 'Except', 'Catch' or 'Finally' clause."));
+        }
 
         Ok(ASTNode::TryCatch {
             try_block: Box::new(try_block),
-            error_variable,
-            catch_block: Box::new(catch_block),
+            handlers,
+            finally_block,
+            loc,
         })
     }
 
-    fn parse_import_statement(&mut self) -> Result<ASTNode, String> {
+    fn parse_lambda(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Func)?;
+        self.expect(&Token::LeftParen)?;
+        let parameters = self.parse_parameters()?;
+        self.expect(&Token::RightParen)?;
+
+        let return_type = if self.match_token(&Token::Arrow) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.expect(&Token::Colon)?;
+
+        let body = if self.check(&Token::Newline) {
+            self.skip_newlines();
+            self.parse_block()?
+        } else {
+            self.parse_expression()?
+        };
+        Ok(ASTNode::Lambda { parameters, return_type, body: Box::new(body), loc })
+    }
+
+    fn parse_throw(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Throw)?;
+        let value = self.parse_expression()?;
+        self.skip_newlines();
+        Ok(ASTNode::Throw { value: Box::new(value), loc })
+    }
+
+    fn parse_import_statement(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Import)?;
         
         let path;
@@ -495,7 +703,7 @@ impl Parser {
         })
     }
 
-    fn parse_module_path(&mut self) -> Result<Vec<String>, String> {
+    fn parse_module_path(&mut self) -> Result<Vec<String>, SyntaxError> {
         let mut path_segments = Vec::new();
 
         let name_loc = self.expect_identifier()?;
@@ -512,7 +720,7 @@ impl Parser {
     }
     
     
-    fn parse_quantum_declaration(&mut self) -> Result<ASTNode, String> {
+    fn parse_quantum_declaration(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Quantum)?;
         
         let name_loc = self.expect_identifier()?; 
@@ -532,15 +740,6 @@ impl Parser {
             None
         };
         
-       
-        if size.is_some() && initial_state.is_some() {
-            return Err(format!(
-                "Syntax Error at {}: Cannot define a register size `[N]` and an initial state `=` at the same time.",
-                self.get_loc(&name_loc)
-            ));
-        }
-       
-
         self.skip_newlines();
         
         Ok(ASTNode::QuantumDeclaration {
@@ -550,7 +749,7 @@ impl Parser {
         })
     }
 
-    fn parse_from_import(&mut self) -> Result<ASTNode, String> {
+    fn parse_from_import(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::From)?;
         
         let path;
@@ -595,11 +794,14 @@ impl Parser {
         })
     }
     
-    fn parse_function_declaration(&mut self,) -> Result<ASTNode, String> {
+    fn parse_function_declaration(&mut self,) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Func)?;
         
         let name_loc = self.expect_identifier()?; 
         let name = self.extract_identifier_name(&name_loc)?;
+        let enclosing_generics = self.generic_params.len();
+        let params = self.parse_generic_params()?;
+        self.generic_params.extend(params.iter().cloned());
         
         self.expect(&Token::LeftParen)?;
         let parameters = self.parse_parameters()?;
@@ -610,26 +812,158 @@ impl Parser {
         } else {
             None
         };
+        let where_clause = self.parse_where_clause()?;
         
         self.expect(&Token::Colon)?;
         self.skip_newlines();
         
         let body = self.parse_block()?;
+        self.generic_params.truncate(enclosing_generics);
         
         Ok(ASTNode::FunctionDeclaration {
             
             name,
+            generics: Generics { params, where_clause },
             parameters,
             return_type,
             body: Box::new(body),
         })
     }
     
-    fn parse_circuit_declaration(&mut self,) -> Result<ASTNode, String> {
+    fn parse_gate_declaration(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Gate)?;
+
+        let name_token = self.current()?.clone();
+        let name = match &name_token.token {
+            Token::Identifier(name) => name.clone(),
+            other => return Err(SyntaxError::at(self.get_loc(&name_token), format!(
+                "Expected a name for the gate declaration, found {:?} (built-in gates cannot be redefined)", other
+            ))),
+        };
+        self.advance();
+
+        self.expect(&Token::Equal)?;
+        let matrix = self.parse_expression()?;
+        self.skip_newlines();
+
+        Ok(ASTNode::GateDeclaration { name, matrix: Box::new(matrix), loc })
+    }
+
+    // struct Point:
+    //     x: Float
+    //     y: Float
+    fn parse_struct_declaration(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Struct)?;
+        let name_loc = self.expect_identifier()?;
+        let name = self.extract_identifier_name(&name_loc)?;
+        self.expect(&Token::Colon)?;
+        self.skip_newlines();
+        self.expect(&Token::Indent)?;
+
+        let mut fields: Vec<Parameter> = Vec::new();
+        while !self.check(&Token::Dedent) && !self.is_at_end() {
+            let field_loc = self.expect_identifier()?;
+            let field_name = self.extract_identifier_name(&field_loc)?;
+            if fields.iter().any(|field| field.name == field_name) {
+                return Err(SyntaxError::at(self.get_loc(&field_loc), format!("Field '{}' of struct '{}' is declared twice", field_name, name)));
+            }
+            self.expect(&Token::Colon)?;
+            let param_type = self.parse_type()?;
+            fields.push(Parameter { name: field_name, param_type, is_mutable: false });
+            self.skip_newlines();
+        }
+        self.expect(&Token::Dedent)?;
+        Ok(ASTNode::StructDeclaration { name, fields, loc })
+    }
+
+    // enum Shape:
+    //     Circle(Float)
+    //     Empty
+    fn parse_enum_declaration(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Enum)?;
+        let name_loc = self.expect_identifier()?;
+        let name = self.extract_identifier_name(&name_loc)?;
+        self.expect(&Token::Colon)?;
+        self.skip_newlines();
+        self.expect(&Token::Indent)?;
+
+        let mut variants: Vec<EnumVariant> = Vec::new();
+        while !self.check(&Token::Dedent) && !self.is_at_end() {
+            let variant_loc = self.expect_identifier()?;
+            let variant_name = self.extract_identifier_name(&variant_loc)?;
+            if variants.iter().any(|variant| variant.name == variant_name) {
+                return Err(SyntaxError::at(self.get_loc(&variant_loc), format!("Variant '{}' of enum '{}' is declared twice", variant_name, name)));
+            }
+            let mut fields = Vec::new();
+            if self.match_token(&Token::LeftParen) {
+                while !self.check(&Token::RightParen) {
+                    fields.push(self.parse_type()?);
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&Token::RightParen)?;
+            }
+            variants.push(EnumVariant { name: variant_name, fields });
+            self.skip_newlines();
+        }
+        self.expect(&Token::Dedent)?;
+        Ok(ASTNode::EnumDeclaration { name, variants, loc })
+    }
+
+    // impl Point:
+    //     func norm(self) -> Float:
+    //         ...
+    // A bare `self` parameter and the type `Self` both stand for the implemented type.
+    fn parse_impl_block(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
+        self.expect(&Token::Impl)?;
+        let name_loc = self.expect_identifier()?;
+        let type_name = self.extract_identifier_name(&name_loc)?;
+        self.expect(&Token::Colon)?;
+        self.skip_newlines();
+        self.expect(&Token::Indent)?;
+
+        let resolve = |ty: &mut Type| {
+            if *ty == Type::Custom("Self".to_string()) {
+                *ty = Type::Custom(type_name.clone());
+            }
+        };
+        let mut methods = Vec::new();
+        while !self.check(&Token::Dedent) && !self.is_at_end() {
+            let method_token = self.current()?.clone();
+            let mut method = match &method_token.token {
+                Token::Func => self.parse_function_declaration()?,
+                Token::Circuit => self.parse_circuit_declaration()?,
+                other => return Err(SyntaxError::at(self.get_loc(&method_token), format!(
+                    "Expected 'func' or 'circuit' in impl block for '{}', found {:?}", type_name, other
+                ))),
+            };
+            if let ASTNode::FunctionDeclaration { parameters, return_type, .. } |
+                   ASTNode::CircuitDeclaration { parameters, return_type, .. } = &mut method {
+                parameters.iter_mut().for_each(|param| resolve(&mut param.param_type));
+                if let Some(ty) = return_type {
+                    resolve(ty);
+                }
+            }
+            methods.push(method);
+            self.skip_newlines();
+        }
+        self.expect(&Token::Dedent)?;
+        Ok(ASTNode::ImplBlock { type_name, methods, loc })
+    }
+
+    fn parse_circuit_declaration(&mut self,) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Circuit)?;
         
         let name_loc = self.expect_identifier()?; 
         let name = self.extract_identifier_name(&name_loc)?;
+        let enclosing_generics = self.generic_params.len();
+        let params = self.parse_generic_params()?;
+        self.generic_params.extend(params.iter().cloned());
         
         self.expect(&Token::LeftParen)?;
         let parameters = self.parse_parameters()?;
@@ -640,22 +974,90 @@ impl Parser {
         } else {
             None
         };
+        let where_clause = self.parse_where_clause()?;
         
         self.expect(&Token::Colon)?;
         self.skip_newlines();
         
         let body = self.parse_block()?;
+        self.generic_params.truncate(enclosing_generics);
         
         Ok(ASTNode::CircuitDeclaration {
             
             name,
+            generics: Generics { params, where_clause },
             parameters,
             return_type,
             body: Box::new(body),
         })
     }
     
-    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, String> {
+    // `<T, n: Int>` after a function or circuit name: bare names are type parameters,
+    // `Int` ones are register sizes.
+    fn parse_generic_params(&mut self) -> Result<Vec<GenericParam>, SyntaxError> {
+        let mut params = Vec::new();
+        if !self.match_token(&Token::Less) {
+            return Ok(params);
+        }
+        loop {
+            let name_token = self.current()?.clone();
+            let name = Self::type_param_name(&name_token.token).ok_or_else(|| format!(
+                "Syntax Error at {}: Expected a generic parameter name, found {:?}", self.get_loc(&name_token), name_token.token
+            ))?;
+            self.advance();
+            let declared = params.iter().any(|param| matches!(param, GenericParam::Type(n) | GenericParam::Size(n) if *n == name));
+            if declared {
+                return Err(SyntaxError::at(self.get_loc(&name_token), format!("Generic parameter '{}' is declared twice", name)));
+            }
+            if self.match_token(&Token::Colon) {
+                let kind = self.parse_type()?;
+                if kind != Type::Int {
+                    return Err(SyntaxError::at(self.get_loc(&name_token), format!(
+                        "Generic parameter '{}' must be a type or an Int, found {:?}", name, kind
+                    )));
+                }
+                params.push(GenericParam::Size(name));
+            } else {
+                params.push(GenericParam::Type(name));
+            }
+            if !self.match_token(&Token::Comma) {
+                break;
+            }
+        }
+        self.expect(&Token::Greater)?;
+        Ok(params)
+    }
+
+    // Single letters such as `T` and `U` lex as gate names, but are the usual names
+    // for type parameters.
+    fn type_param_name(token: &Token) -> Option<String> {
+        let name = match token {
+            Token::Identifier(name) => return Some(name.clone()),
+            Token::X => "X",
+            Token::Y => "Y",
+            Token::Z => "Z",
+            Token::S => "S",
+            Token::T => "T",
+            Token::U => "U",
+            _ => return None,
+        };
+        Some(name.to_string())
+    }
+
+    fn parse_where_clause(&mut self) -> Result<Vec<ASTNode>, SyntaxError> {
+        let mut constraints = Vec::new();
+        if self.match_token(&Token::Where) {
+            loop {
+                constraints.push(self.parse_expression()?);
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        Ok(constraints)
+    }
+
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, SyntaxError> {
         let mut params = Vec::new();
         
         if self.check(&Token::RightParen) {
@@ -663,8 +1065,21 @@ impl Parser {
         }
         
         loop {
+            let is_mutable = params.is_empty() && self.match_token(&Token::Mut);
             let name_loc = self.expect_identifier()?; 
             let name = self.extract_identifier_name(&name_loc)?;
+            if is_mutable && (name != "self" || self.check(&Token::Colon)) {
+                return Err(SyntaxError::at(self.get_loc(&name_loc), "Only a method receiver can be declared mutable, as 'mut self'"));
+            }
+
+            // The receiver of a method; its type is filled in by the impl block.
+            if name == "self" && params.is_empty() && !self.check(&Token::Colon) {
+                params.push(Parameter { name, param_type: Type::Custom("Self".to_string()), is_mutable });
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+                continue;
+            }
 
             self.expect(&Token::Colon)?;
             let param_type = self.parse_type()?;
@@ -672,6 +1087,7 @@ impl Parser {
             params.push(Parameter {
                 name,
                 param_type,
+                is_mutable: false,
             });
             
             if !self.match_token(&Token::Comma) {
@@ -683,7 +1099,7 @@ impl Parser {
     }
 
     
-    fn parse_arguments(&mut self) -> Result<Vec<ASTNode>, String> {
+    fn parse_arguments(&mut self) -> Result<Vec<ASTNode>, SyntaxError> {
         let mut arguments = Vec::new();
         if !self.check(&Token::RightParen) {
             loop {
@@ -697,7 +1113,7 @@ impl Parser {
         Ok(arguments)
     }
     
-    fn parse_type(&mut self) -> Result<Type, String> {
+    fn parse_type(&mut self) -> Result<Type, SyntaxError> {
         let type_token = self.current()?.token.clone();
         
         let base_type = match type_token {
@@ -711,6 +1127,9 @@ impl Parser {
             Token::Float => { self.advance(); Type::Float },
             Token::Float32 => { self.advance(); Type::Float32 },
             Token::Float64 => { self.advance(); Type::Float64 },
+            Token::Complex => { self.advance(); Type::Complex },
+            Token::Complex64 => { self.advance(); Type::Complex64 },
+            Token::Complex128 => { self.advance(); Type::Complex128 },
             Token::Bool => { self.advance(); Type::Bool },
             Token::Bit => { self.advance(); Type::Bit },
             Token::String => { self.advance(); Type::String },
@@ -719,17 +1138,44 @@ impl Parser {
             
       
             Token::Quantum => { self.advance(); Type::QuantumRegister(None) },
+
+            // `func(Float, Int) -> Float`, the type of functions and lambdas.
+            Token::Func => {
+                self.advance();
+                self.expect(&Token::LeftParen)?;
+                let mut param_types = Vec::new();
+                while !self.check(&Token::RightParen) && !self.is_at_end() {
+                    param_types.push(self.parse_type()?);
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&Token::RightParen)?;
+                let return_type = if self.match_token(&Token::Arrow) { self.parse_type()? } else { Type::None };
+                Type::Function(param_types, Box::new(return_type))
+            }
             
             Token::Identifier(name) => {
                 self.advance();
                 match name.as_str() {
                     "Qubit" => Type::Qubit,
                     "QuantumRegister" => Type::QuantumRegister(None),
+                    "Observable" => Type::Observable,
+                    "Ket" => Type::Ket(None),
+                    "Bra" => Type::Bra(None),
+                    "Error" => Type::Error,
+                    _ if self.generic_params.contains(&GenericParam::Type(name.clone())) => Type::Generic(name),
                     _ => Type::Custom(name),
                 }
             }
             
-            _ => return Err(format!("Expected a type, but found {:?}", type_token)),
+            _ => match Self::type_param_name(&type_token) {
+                Some(name) if self.generic_params.contains(&GenericParam::Type(name.clone())) => {
+                    self.advance();
+                    Type::Generic(name)
+                }
+                _ => return Err(SyntaxError::at(self.get_loc(self.current()?), format!("Expected a type, but found {:?}", type_token))),
+            },
         };
         
         if self.match_token(&Token::LeftBracket) {
@@ -741,6 +1187,15 @@ impl Parser {
             let size = self.parse_expression()?;
             self.expect(&Token::RightBracket)?;
             
+            if base_type == Type::QuantumRegister(None) {
+                match size {
+                    ASTNode::IntLiteral(n) => return Ok(Type::QuantumRegister(Some(n as usize))),
+                    ASTNode::Identifier { name, .. } if self.generic_params.contains(&GenericParam::Size(name.clone())) => {
+                        return Ok(Type::GenericRegister(name));
+                    }
+                    _ => {}
+                }
+            }
             if let ASTNode::IntLiteral(n) = size {
                 return Ok(Type::QuantumArray(Box::new(base_type), Some(n as usize)));
             }
@@ -751,7 +1206,7 @@ impl Parser {
         Ok(base_type)
     }
     
-    fn parse_return(&mut self) -> Result<ASTNode, String> {
+    fn parse_return(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Return)?;
         
         if self.check(&Token::Newline) || self.is_at_end() {
@@ -765,7 +1220,7 @@ impl Parser {
         Ok(ASTNode::Return(Some(Box::new(value))))
     }
     
-    fn parse_if(&mut self) -> Result<ASTNode, String> {
+    fn parse_if(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::If)?;
         
         let condition = self.parse_expression()?;
@@ -822,7 +1277,8 @@ impl Parser {
         })
     }
     
-    fn parse_match(&mut self) -> Result<ASTNode, String> {
+    fn parse_match(&mut self) -> Result<ASTNode, SyntaxError> {
+        let loc = self.get_loc(self.current()?);
         self.expect(&Token::Match)?;
         
         let value = self.parse_expression()?;
@@ -843,12 +1299,23 @@ impl Parser {
             self.expect(&Token::Case)?;
             
             let pattern = self.parse_pattern()?;
+            let guard = if self.match_token(&Token::If) {
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
             self.expect(&Token::Arrow)?;
             
-            let body = self.parse_expression()?;
-            self.skip_newlines();
+            let body = if self.check(&Token::Newline) {
+                self.skip_newlines();
+                self.parse_block()?
+            } else {
+                let stmt = self.parse_statement()?;
+                self.skip_newlines();
+                stmt
+            };
             
-            cases.push((pattern, body));
+            cases.push(MatchCase { pattern, guard, body });
         }
         
         self.expect(&Token::Dedent)?;
@@ -856,22 +1323,62 @@ impl Parser {
         Ok(ASTNode::Match {
             value: Box::new(value),
             cases,
+            loc,
         })
     }
     
-    fn parse_pattern(&mut self) -> Result<Pattern, String> {
-        if self.match_token(&Token::Identifier("_".to_string())) {
-            return Ok(Pattern::Wildcard);
+    // `pattern (| pattern)* (as name)?`
+    fn parse_pattern(&mut self) -> Result<Pattern, SyntaxError> {
+        let mut options = vec![self.parse_single_pattern()?];
+        while self.match_token(&Token::Pipe) {
+            options.push(self.parse_single_pattern()?);
+        }
+        let pattern = if options.len() == 1 { options.remove(0) } else { Pattern::Alternatives(options) };
+        
+        if self.match_token(&Token::As) {
+            let name = match &self.current()?.token {
+                Token::Identifier(name) => name.clone(),
+                other => return Err(SyntaxError::at(self.get_loc(self.current()?), format!("Expected a name after 'as' in a pattern, found {:?}", other))),
+            };
+            self.advance();
+            return Ok(Pattern::Binding { pattern: Box::new(pattern), name });
         }
-        
+        Ok(pattern)
+    }
+    
+    fn parse_single_pattern(&mut self) -> Result<Pattern, SyntaxError> {
+        // `check` compares token kinds only, so `_` is told apart from other names here.
         if let Token::Identifier(name) = &self.current()?.token {
             let id = name.clone();
             self.advance();
-            return Ok(Pattern::Identifier(id));
+            // `Enum.Variant`, `Enum.Variant(...)` or `Variant(...)`
+            let (enum_name, variant) = if self.match_token(&Token::Dot) {
+                let variant_loc = self.expect_identifier()?;
+                (Some(id), self.extract_identifier_name(&variant_loc)?)
+            } else if self.check(&Token::LeftParen) {
+                (None, id)
+            } else {
+                return Ok(if id == "_" { Pattern::Wildcard } else { Pattern::Identifier(id) });
+            };
+            let mut fields = Vec::new();
+            if self.match_token(&Token::LeftParen) {
+                while !self.check(&Token::RightParen) {
+                    fields.push(self.parse_pattern()?);
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&Token::RightParen)?;
+            }
+            return Ok(Pattern::Variant { enum_name, variant, fields });
         }
         
-        let expr = self.parse_primary()?;
-        Ok(Pattern::Literal(expr))
+        let start = self.parse_unary()?;
+        if let Some(op) = self.match_tokens(&[Token::Range, Token::RangeInclusive]) {
+            let end = self.parse_unary()?;
+            return Ok(Pattern::Range { start, end, inclusive: op == Token::RangeInclusive });
+        }
+        Ok(Pattern::Literal(start))
     }
 
     fn match_tokens_loc(&mut self, tokens: &[Token]) -> Option<TokenWithLocation> {
@@ -885,7 +1392,7 @@ impl Parser {
         None
     }
     
-    fn parse_for(&mut self) -> Result<ASTNode, String> {
+    fn parse_for(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::For)?;
         
         let variable_loc = self.expect_identifier()?;
@@ -905,7 +1412,7 @@ impl Parser {
         })
     }
     
-    fn parse_while(&mut self) -> Result<ASTNode, String> {
+    fn parse_while(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::While)?;
         
         let condition = self.parse_expression()?;
@@ -920,7 +1427,7 @@ impl Parser {
         })
     }
     
-    fn parse_block(&mut self) -> Result<ASTNode, String> {
+    fn parse_block(&mut self) -> Result<ASTNode, SyntaxError> {
         self.expect(&Token::Indent)?;
         self.skip_newlines();
         
@@ -930,7 +1437,7 @@ impl Parser {
         while !self.check(&Token::Dedent) && !self.is_at_end() {
             
 
-            statements.push(self.parse_statement()?); 
+            statements.extend(self.parse_recovering()); 
             
 
             self.skip_newlines();
@@ -941,23 +1448,28 @@ impl Parser {
         Ok(ASTNode::Block(statements))
     }
     
-    fn parse_expression(&mut self) -> Result<ASTNode, String> {
+    fn parse_expression(&mut self) -> Result<ASTNode, SyntaxError> {
         self.parse_pipeline()
         
     }
     
-    fn parse_pipeline(&mut self) -> Result<ASTNode, String> {
+    // `value |> f` is `f(value)` and `value |> f(a, b)` is `f(value, a, b)`.
+    fn parse_pipeline(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_logical_or()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::PipeRight]) {
-            let operator = BinaryOperator::Pipeline;
             let loc = self.get_loc(&op_token);
-            let right = self.parse_logical_or()?;
-            expr = ASTNode::Binary { operator, left: Box::new(expr), right: Box::new(right), loc };
+            expr = match self.parse_logical_or()? {
+                ASTNode::FunctionCall { callee, mut arguments, loc, is_dagger } => {
+                    arguments.insert(0, expr);
+                    ASTNode::FunctionCall { callee, arguments, loc, is_dagger }
+                }
+                function => ASTNode::FunctionCall { callee: Box::new(function), arguments: vec![expr], loc, is_dagger: false },
+            };
         }
         Ok(expr)
     }
     
-    fn parse_logical_or(&mut self) -> Result<ASTNode, String> {
+    fn parse_logical_or(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_logical_and()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::Or]) {
             let operator = BinaryOperator::Or;
@@ -968,7 +1480,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_logical_and(&mut self) -> Result<ASTNode, String> {
+    fn parse_logical_and(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_equality()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::And]) {
             let operator = BinaryOperator::And;
@@ -979,7 +1491,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_equality(&mut self) -> Result<ASTNode, String> {
+    fn parse_equality(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_comparison()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::EqualEqual, Token::NotEqual]) {
             let operator = match op_token.token {
@@ -994,7 +1506,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_comparison(&mut self) -> Result<ASTNode, String> {
+    fn parse_comparison(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_range()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::Less, Token::Greater, Token::LessEqual, Token::GreaterEqual]) {
             let operator = match op_token.token {
@@ -1011,7 +1523,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_range(&mut self) -> Result<ASTNode, String> {
+    fn parse_range(&mut self) -> Result<ASTNode, SyntaxError> {
         let expr = self.parse_term()?;
         if let Some(op) = self.match_tokens(&[Token::Range, Token::RangeInclusive]) {
             let inclusive = matches!(op, Token::RangeInclusive);
@@ -1025,7 +1537,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_term(&mut self) -> Result<ASTNode, String> {
+    fn parse_term(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_factor()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::Plus, Token::Minus]) {
             let operator = match op_token.token {
@@ -1040,7 +1552,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_factor(&mut self) -> Result<ASTNode, String> {
+    fn parse_factor(&mut self) -> Result<ASTNode, SyntaxError> {
     let mut expr = self.parse_power()?;
     while let Some(op_token) = self.match_tokens_loc(&[Token::Star, Token::Slash, Token::Percent]) {
         let operator = match op_token.token {
@@ -1056,7 +1568,7 @@ impl Parser {
     Ok(expr)
 }
     
-    fn parse_tensor_product(&mut self) -> Result<ASTNode, String> {
+    fn parse_tensor_product(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_unary()?;
         while let Some(op_token) = self.match_tokens_loc(&[Token::TensorProduct]) {
             let operator = BinaryOperator::TensorProduct;
@@ -1067,7 +1579,7 @@ impl Parser {
         Ok(expr)
     }
     
-    fn parse_unary(&mut self) -> Result<ASTNode, String> {
+    fn parse_unary(&mut self) -> Result<ASTNode, SyntaxError> {
         if let Some(op) = self.match_tokens(&[Token::Not,Token::Bang, Token::Minus, Token::Plus]) {
             let operator = match op {
                 Token::Not => UnaryOperator::Not,
@@ -1087,7 +1599,7 @@ impl Parser {
         self.parse_postfix()
     }
 
-    fn parse_power(&mut self) -> Result<ASTNode, String> {
+    fn parse_power(&mut self) -> Result<ASTNode, SyntaxError> {
     let mut expr = self.parse_tensor_product()?;
     
 
@@ -1105,7 +1617,7 @@ impl Parser {
     
     
 
-    fn parse_postfix(&mut self) -> Result<ASTNode, String> {
+    fn parse_postfix(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut expr = self.parse_primary()?;
 
         loop {
@@ -1155,10 +1667,10 @@ impl Parser {
     }
 
     fn get_loc(&self, token_loc: &TokenWithLocation) -> Loc {
-        Loc { line: token_loc.line, column: token_loc.column }
+        Loc { line: token_loc.line, column: token_loc.column, length: token_loc.length }
     }
     
-    fn parse_primary(&mut self) -> Result<ASTNode, String> {
+    fn parse_primary(&mut self) -> Result<ASTNode, SyntaxError> {
 
         while self.check(&Token::Newline) {
             self.advance();
@@ -1167,11 +1679,10 @@ impl Parser {
 
         if self.check(&Token::Indent) || self.check(&Token::Dedent) {
             let current_loc = self.current()?.clone();
-            return Err(format!(
-                "Unexpected indentation token at {}: {:?}. This usually means a statement is incomplete or malformed.",
-                self.get_loc(&current_loc),
+            return Err(SyntaxError::at(self.get_loc(&current_loc), format!(
+                "Unexpected indentation token {:?}. This usually means a statement is incomplete or malformed.",
                 current_loc.token
-            ));
+            )));
         }
         let current_loc = self.current()?.clone();
         let token = &current_loc.token;
@@ -1180,6 +1691,7 @@ impl Parser {
 
         match token {
             Token::If => self.parse_if(),
+            Token::Func => self.parse_lambda(),
             Token::Print => {
                 self.advance();
                 Ok(ASTNode::Identifier { name: "print".to_string(), loc })
@@ -1206,7 +1718,7 @@ impl Parser {
                 
                 let call_loc = self.get_loc(self.current()?);
                 if !self.match_token(&Token::LeftParen) {
-                    return Err(format!("Error at {}: Expected '(' to call the daggered circuit.", call_loc));
+                    return Err(SyntaxError::at(call_loc, "Expected '(' to call the daggered circuit."));
                 }
                 
                 let arguments = self.parse_arguments()?;
@@ -1221,9 +1733,14 @@ impl Parser {
 
             Token::Measure => {
                 self.advance();
-                self.expect(&Token::LeftParen)?;
-                let qubit = self.parse_expression()?;
-                self.expect(&Token::RightParen)?;
+                let qubit = if self.match_token(&Token::LeftParen) {
+                    let qubit = self.parse_expression()?;
+                    self.expect(&Token::RightParen)?;
+                    qubit
+                } else {
+                    // `measure q` / `measure q[0]` without parentheses
+                    self.parse_postfix()?
+                };
                 Ok(ASTNode::Measure(Box::new(qubit)))
             }
 
@@ -1236,6 +1753,10 @@ impl Parser {
                 self.advance();
                 Ok(ASTNode::FloatLiteral(*f))
             }
+            Token::ImaginaryLiteral(f) => {
+                self.advance();
+                Ok(ASTNode::ImaginaryLiteral(*f))
+            }
             Token::StringLiteral(s) => {
                 self.advance();
                 Ok(ASTNode::StringLiteral(s.clone()))
@@ -1257,8 +1778,20 @@ impl Parser {
                 Ok(ASTNode::QuantumKet(state.clone()))
             }
             Token::BraState(state) => {
+                let loc = self.get_loc(self.current()?);
                 self.advance();
-                Ok(ASTNode::QuantumBra(state.clone()))
+                let bra = ASTNode::QuantumBra(state.clone());
+                // `{a||b}` is the bra-ket product of the two literals.
+                let next_ket = match self.current() {
+                    Ok(TokenWithLocation { token: Token::KetState(ket), .. }) => Some(ket.clone()),
+                    _ => None,
+                };
+                if let Some(ket) = next_ket {
+                    let ket = ASTNode::QuantumKet(ket);
+                    self.advance();
+                    return Ok(ASTNode::Binary { operator: BinaryOperator::Mul, left: Box::new(bra), right: Box::new(ket), loc });
+                }
+                Ok(bra)
             }
             
 
@@ -1286,7 +1819,7 @@ impl Parser {
                 Ok(ASTNode::Identifier { name, loc })
             }
 
-            _ => Err(format!("Unexpected token in expression at {}: {:?}", loc, token)),
+            _ => Err(SyntaxError::at(loc, format!("Unexpected token in expression: {:?}", token))),
         }
     }
 
@@ -1298,7 +1831,7 @@ impl Parser {
         }
     }
 
-    fn parse_dict_literal(&mut self) -> Result<ASTNode, String> {
+    fn parse_dict_literal(&mut self) -> Result<ASTNode, SyntaxError> {
         let mut pairs = Vec::new();
 
         self.skip_layout_tokens();
@@ -1334,9 +1867,9 @@ impl Parser {
     }
     
 
-    fn current(&self) -> Result<&TokenWithLocation, String> {
+    fn current(&self) -> Result<&TokenWithLocation, SyntaxError> {
         if self.is_at_end() {
-            Err("Unexpected end of file".to_string())
+            Err("Unexpected end of file".into())
         } else {
             Ok(&self.tokens[self.position])
         }
@@ -1380,24 +1913,18 @@ impl Parser {
         None
     }
     
-    fn expect(&mut self, token: &Token) -> Result<(), String> {
+    fn expect(&mut self, token: &Token) -> Result<(), SyntaxError> {
         let current_loc = self.current()?.clone();
-        let token = &current_loc.token;
         let loc = self.get_loc(&current_loc);
         if self.check(token) {
             self.advance();
             Ok(())
         } else {
-            let current = if self.is_at_end() {
-                "EOF".to_string()
-            } else {
-                format!("{:?}", self.tokens[self.position].token)
-            };
-            Err(format!("Expected {:?}, found {} at {}", token, current,loc))
+            Err(SyntaxError::at(loc, format!("Expected {:?}, found {:?}", token, current_loc.token)))
         }
     }
 
-    fn extract_identifier_name(&self, token_loc: &TokenWithLocation) -> Result<String, String> {
+    fn extract_identifier_name(&self, token_loc: &TokenWithLocation) -> Result<String, SyntaxError> {
     match &token_loc.token {
         Token::Identifier(name) => Ok(name.clone()),
         Token::Hadamard => Ok("hadamard".to_string()),
@@ -1419,11 +1946,11 @@ impl Parser {
         Token::RZ => Ok("rz".to_string()),       
         Token::CCX => Ok("ccx".to_string()),
         Token::Toffoli => Ok("toffoli".to_string()),
-        _ => Err(format!("Expected Identifier token, found {:?}", token_loc.token))
+        _ => Err(SyntaxError::at(self.get_loc(token_loc), format!("Expected Identifier token, found {:?}", token_loc.token)))
     }
 }
     
-    fn expect_identifier(&mut self) -> Result<TokenWithLocation, String> {
+    fn expect_identifier(&mut self) -> Result<TokenWithLocation, SyntaxError> {
     let current_loc = self.current()?;
     
     match &current_loc.token {
@@ -1438,7 +1965,7 @@ impl Parser {
             self.advance(); 
             Ok(owned_token_loc) 
         }
-        _ => Err(format!("Syntax Error: Expected Identifier or Gate name, found {:?}", current_loc.token))
+        _ => Err(SyntaxError::at(self.get_loc(current_loc), format!("Expected Identifier or Gate name, found {:?}", current_loc.token)))
     }
 }
 
@@ -1494,6 +2021,26 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_sized_ket_declaration_and_bra_ket() {
+        let ast = parse_source("quantum q[2] = |01}\nlet overlap = {0||+}").unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            assert!(matches!(&statements[0], ASTNode::QuantumDeclaration { size: Some(_), initial_state: Some(_), .. }));
+            if let ASTNode::LetDeclaration { value, .. } = &statements[1] {
+                match &**value {
+                    ASTNode::Binary { operator: BinaryOperator::Mul, left, right, .. } => {
+                        assert!(matches!(**left, ASTNode::QuantumBra(ref s) if s == "0"));
+                        assert!(matches!(**right, ASTNode::QuantumKet(ref s) if s == "+"));
+                    }
+                    other => panic!("Expected a bra-ket product, got {:?}", other),
+                }
+            } else {
+                panic!("Expected LetDeclaration");
+            }
+        }
+    }
     
     #[test]
     fn test_tensor_product() {
@@ -1557,6 +2104,71 @@ mod tests {
         }
     }
     
+    #[test]
+    fn test_match_patterns() {
+        let source = "match n:\n    case 0 -> print(\"zero\")\n    case 1 | 2 -> print(\"small\")\n    case 3..=9 as k if k > 4 ->\n        print(k)\n    case _ -> print(\"large\")";
+        let ast = parse_source(source).unwrap();
+        
+        if let ASTNode::Program(statements) = ast {
+            if let ASTNode::Match { cases, .. } = &statements[0] {
+                assert_eq!(cases.len(), 4);
+                assert!(matches!(cases[1].pattern, Pattern::Alternatives(ref options) if options.len() == 2));
+                assert!(matches!(&cases[2].pattern, Pattern::Binding { pattern, name } if name == "k" && matches!(**pattern, Pattern::Range { inclusive: true, .. })));
+                assert!(cases[2].guard.is_some());
+                assert!(matches!(cases[2].body, ASTNode::Block(_)));
+                assert_eq!(cases[3].pattern, Pattern::Wildcard);
+            } else {
+                panic!("Expected Match statement");
+            }
+        }
+    }
+    
+    #[test]
+    fn test_struct_and_enum_declarations() {
+        let source = "struct Point:\n    px: Float\n    py: Float\nenum Shape:\n    Circle(Float)\n    Rect(Float, Float)\n    Empty\nmatch s:\n    case Shape.Rect(w, _) -> print(w)\n    case Circle(r) -> print(r)";
+        let ast = parse_source(source).unwrap();
+        
+        if let ASTNode::Program(statements) = ast {
+            assert!(matches!(&statements[0], ASTNode::StructDeclaration { name, fields, .. } if name == "Point" && fields.len() == 2));
+            if let ASTNode::EnumDeclaration { variants, .. } = &statements[1] {
+                assert_eq!(variants.len(), 3);
+                assert_eq!(variants[1].fields, vec![Type::Float, Type::Float]);
+                assert!(variants[2].fields.is_empty());
+            } else {
+                panic!("Expected EnumDeclaration");
+            }
+            if let ASTNode::Match { cases, .. } = &statements[2] {
+                assert!(matches!(&cases[0].pattern, Pattern::Variant { enum_name: Some(e), variant, fields } if e == "Shape" && variant == "Rect" && fields.len() == 2));
+                assert_eq!(cases[0].pattern.bindings(), vec!["w"]);
+                assert!(matches!(&cases[1].pattern, Pattern::Variant { enum_name: None, fields, .. } if fields.len() == 1));
+            } else {
+                panic!("Expected Match statement");
+            }
+        }
+    }
+
+    #[test]
+    fn test_impl_block() {
+        let source = "impl Point:\n    func norm(self) -> Float:\n        return self.px\n    func origin() -> Self:\n        return Point(0.0, 0.0)\n    func reset(mut self):\n        self.px = 0.0";
+        let ast = parse_source(source).unwrap();
+        
+        if let ASTNode::Program(statements) = ast {
+            if let ASTNode::ImplBlock { type_name, methods, .. } = &statements[0] {
+                assert_eq!(type_name, "Point");
+                assert_eq!(methods.len(), 3);
+                if let ASTNode::FunctionDeclaration { parameters, .. } = &methods[0] {
+                    assert_eq!(parameters[0].name, "self");
+                    assert_eq!(parameters[0].param_type, Type::Custom("Point".to_string()));
+                    assert!(!parameters[0].is_mutable);
+                }
+                assert!(matches!(&methods[2], ASTNode::FunctionDeclaration { parameters, .. } if parameters[0].is_mutable));
+                assert!(matches!(&methods[1], ASTNode::FunctionDeclaration { return_type: Some(Type::Custom(t)), .. } if t == "Point"));
+            } else {
+                panic!("Expected ImplBlock");
+            }
+        }
+    }
+
     #[test]
     fn test_dict_literal() {
         let source = "let d = { 1: \"a\", \"b\": 2 }";
@@ -1673,6 +2285,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_try_except_finally_and_throw() {
+        let source = "Try:\n    Throw Error(\"Timeout\", \"no reply\")\nExcept Timeout, QueueFull as e:\n    print(e)\nCatch:\n    print(0)\nFinally:\n    print(1)";
+        let ast = parse_source(source).unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            if let ASTNode::TryCatch { try_block, handlers, finally_block, .. } = &statements[0] {
+                assert!(matches!(&**try_block, ASTNode::Block(body) if matches!(body[0], ASTNode::Throw { .. })));
+                assert_eq!(handlers.len(), 2);
+                assert_eq!(handlers[0].kinds, vec!["Timeout".to_string(), "QueueFull".to_string()]);
+                assert_eq!(handlers[0].variable.as_deref(), Some("e"));
+                assert!(handlers[1].kinds.is_empty() && handlers[1].variable.is_none());
+                assert!(finally_block.is_some());
+            } else {
+                panic!("Expected TryCatch");
+            }
+        }
+
+        assert!(parse_source("Try:\n    print(1)\nCatch:\n    print(2)\nExcept Timeout:\n    print(3)").is_err());
+        assert!(parse_source("Try:\n    print(1)\nlet a = 2").is_err());
+    }
+
+    #[test]
+    fn test_lambda_and_pipeline() {
+        let source = "let f = func(v: Float) -> Float: v * 2.0\nlet y = 3.0 |> f |> scale(2.0)";
+        let ast = parse_source(source).unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            match &statements[0] {
+                ASTNode::LetDeclaration { value, .. } => match &**value {
+                    ASTNode::Lambda { parameters, return_type, body, .. } => {
+                        assert_eq!(parameters.len(), 1);
+                        assert_eq!(return_type, &Some(Type::Float));
+                        assert!(matches!(&**body, ASTNode::Binary { .. }));
+                    }
+                    other => panic!("Expected Lambda, got {:?}", other),
+                },
+                other => panic!("Expected LetDeclaration, got {:?}", other),
+            }
+            // `3.0 |> f |> scale(2.0)` is `scale(f(3.0), 2.0)`.
+            match &statements[1] {
+                ASTNode::LetDeclaration { value, .. } => match &**value {
+                    ASTNode::FunctionCall { callee, arguments, .. } => {
+                        assert!(matches!(&**callee, ASTNode::Identifier { name, .. } if name == "scale"));
+                        assert_eq!(arguments.len(), 2);
+                        assert!(matches!(&arguments[0], ASTNode::FunctionCall { .. }));
+                    }
+                    other => panic!("Expected FunctionCall, got {:?}", other),
+                },
+                other => panic!("Expected LetDeclaration, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_generic_declarations() {
+        let source = "func first<T>(a: T, b: T[]) -> T:\n    return a\ncircuit GHZ<n: Int>(qreg: QuantumRegister[n]) where n >= 2:\n    apply H(qreg[0])\n";
+        let ast = parse_source(source).unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            match &statements[0] {
+                ASTNode::FunctionDeclaration { generics, parameters, return_type, .. } => {
+                    assert_eq!(generics.params, vec![GenericParam::Type("T".to_string())]);
+                    assert_eq!(parameters[0].param_type, Type::Generic("T".to_string()));
+                    assert_eq!(parameters[1].param_type, Type::Array(Box::new(Type::Generic("T".to_string()))));
+                    assert_eq!(return_type, &Some(Type::Generic("T".to_string())));
+                }
+                other => panic!("Expected FunctionDeclaration, got {:?}", other),
+            }
+            match &statements[1] {
+                ASTNode::CircuitDeclaration { generics, parameters, .. } => {
+                    assert_eq!(generics.params, vec![GenericParam::Size("n".to_string())]);
+                    assert_eq!(generics.where_clause.len(), 1);
+                    assert_eq!(parameters[0].param_type, Type::GenericRegister("n".to_string()));
+                }
+                other => panic!("Expected CircuitDeclaration, got {:?}", other),
+            }
+        }
+    }
+
     #[test]
     fn test_array_literal() {
         let source = "let arr = [1, 2, 3]";
@@ -1700,5 +2392,38 @@ mod tests {
             }
         }
     }
-}
 
+    #[test]
+    fn test_gate_declaration() {
+        let source = "gate SX = [\n    [[0.5, 0.5], [0.5, -0.5]],\n    [[0.5, -0.5], [0.5, 0.5]]\n]\nlet y = 1";
+        let ast = parse_source(source).unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            assert_eq!(statements.len(), 2);
+            if let ASTNode::GateDeclaration { name, matrix, .. } = &statements[0] {
+                assert_eq!(name, "SX");
+                assert!(matches!(&**matrix, ASTNode::ArrayLiteral(rows) if rows.len() == 2));
+            } else {
+                panic!("Expected GateDeclaration");
+            }
+        }
+
+        assert!(parse_source("gate X = [[0, 1], [1, 0]]").is_err());
+    }
+
+    #[test]
+    fn test_measure_without_parentheses() {
+        let source = "let bits = measure q\nlet b = measure q[1]";
+        let ast = parse_source(source).unwrap();
+
+        if let ASTNode::Program(statements) = ast {
+            assert_eq!(statements.len(), 2);
+            if let ASTNode::LetDeclaration { value, .. } = &statements[0] {
+                assert!(matches!(&**value, ASTNode::Measure(target) if matches!(**target, ASTNode::Identifier { .. })));
+            }
+            if let ASTNode::LetDeclaration { value, .. } = &statements[1] {
+                assert!(matches!(&**value, ASTNode::Measure(target) if matches!(**target, ASTNode::ArrayAccess { .. })));
+            }
+        }
+    }
+}
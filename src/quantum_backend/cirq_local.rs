@@ -1,6 +1,6 @@
 // src/quantum_backend/cirq_local.rs
 
-use super::{HardwareCircuit, QuantumConfig, QuantumResult, QuantumBackend};
+use super::{HardwareCircuit, QuantumConfig, QuantumResult, QuantumBackend, python_matrix};
 use std::collections::HashMap;
 use std::process::Command;
 
@@ -21,12 +21,24 @@ impl CirqLocalBackend {
     }
     
     fn generate_cirq_script(&self, circuit: &HardwareCircuit, shots: u32) -> String {
-        let mut script = String::from("import cirq\nimport json\n\n");
+        let mut script = String::from("import cirq\nimport json\nimport numpy as np\n\n");
         
         script.push_str(&format!("qubits = [cirq.LineQubit(i) for i in range({})]\n", circuit.num_qubits));
         script.push_str("circuit = cirq.Circuit()\n\n");
         
         for gate in &circuit.gates {
+            if let (Some(unitary), Some(num_controls)) = (gate.unitary(), gate.num_controls()) {
+                let mut gate_expr = format!("cirq.MatrixGate({}, name='{}')", python_matrix(&unitary), gate.name);
+                if gate.is_dagger {
+                    gate_expr = format!("cirq.inverse({})", gate_expr);
+                }
+                if num_controls > 0 {
+                    gate_expr.push_str(&format!(".controlled(num_controls={})", num_controls));
+                }
+                let qubits: Vec<String> = gate.qubits.iter().map(|q| format!("qubits[{}]", q)).collect();
+                script.push_str(&format!("circuit.append({}.on({}))\n", gate_expr, qubits.join(", ")));
+                continue;
+            }
             let gate_code = match gate.name.as_str() {
                 "hadamard" | "h" => format!("circuit.append(cirq.H(qubits[{}]))", gate.qubits[0]),
                 "x" => format!("circuit.append(cirq.X(qubits[{}]))", gate.qubits[0]),
@@ -40,6 +52,7 @@ impl CirqLocalBackend {
                                gate.params[0], gate.qubits[0]),
                 "rz" => format!("circuit.append(cirq.rz({}).on(qubits[{}]))", 
                                gate.params[0], gate.qubits[0]),
+                "reset" => format!("circuit.append(cirq.reset(qubits[{}]))", gate.qubits[0]),
                 _ => continue,
             };
             script.push_str(&format!("{}\n", gate_code));
@@ -0,0 +1,132 @@
+// src/quantum_backend/decompose.rs
+
+use crate::simulator::{Unitary, C64};
+
+const EPS: f64 = 1e-10;
+
+/// A 2x2 unitary acting on two basis states of a gate that differ in one bit.
+/// `matrix` is written in the (bit = 0, bit = 1) order of that bit.
+#[derive(Debug, Clone)]
+pub struct TwoLevel {
+    pub low: usize,
+    pub high: usize,
+    pub matrix: [[C64; 2]; 2],
+}
+
+/// Factor `u` into two-level unitaries, listed in the order they are applied.
+/// Rows are eliminated in Gray-code order, so every factor flips exactly one
+/// bit and becomes a single-qubit gate controlled on the remaining bits.
+pub fn two_level_factors(u: &Unitary) -> Vec<TwoLevel> {
+    let dim = u.dim();
+    let gray: Vec<usize> = (0..dim).map(|i| i ^ (i >> 1)).collect();
+    let mut v: Vec<Vec<C64>> = (0..dim)
+        .map(|i| (0..dim).map(|j| u.get(gray[i], gray[j])).collect())
+        .collect();
+
+    // Each entry is (upper gray position, 2x2 G) with G applied to rows (i - 1, i).
+    let mut eliminations: Vec<(usize, [[C64; 2]; 2])> = Vec::new();
+    let mut rotate = |v: &mut Vec<Vec<C64>>, i: usize, g: [[C64; 2]; 2]| {
+        let (upper, lower) = v.split_at_mut(i);
+        for (x, y) in upper[i - 1].iter_mut().zip(lower[0].iter_mut()) {
+            let (a, b) = (*x, *y);
+            *x = g[0][0] * a + g[0][1] * b;
+            *y = g[1][0] * a + g[1][1] * b;
+        }
+        eliminations.push((i, g));
+    };
+
+    for j in 0..dim - 1 {
+        for i in (j + 1..dim).rev() {
+            let (a, b) = (v[i - 1][j], v[i][j]);
+            let last = i == j + 1;
+            if b.norm() < EPS && (!last || (a - C64::new(1.0, 0.0)).norm() < EPS) {
+                continue;
+            }
+            let n = (a.norm_sqr() + b.norm_sqr()).sqrt();
+            let g = [[a.conj() / n, b.conj() / n], [-b / n, a / n]];
+            rotate(&mut v, i, g);
+        }
+    }
+    let phase = v[dim - 1][dim - 1];
+    if (phase - C64::new(1.0, 0.0)).norm() > EPS {
+        let one = C64::new(1.0, 0.0);
+        let zero = C64::new(0.0, 0.0);
+        rotate(&mut v, dim - 1, [[one, zero], [zero, phase.conj()]]);
+    }
+
+    // G_m ... G_1 U = I, so U = G_1† ... G_m† and G_m† runs first.
+    eliminations.into_iter().rev().map(|(i, g)| {
+        let adjoint = [[g[0][0].conj(), g[1][0].conj()], [g[0][1].conj(), g[1][1].conj()]];
+        let (first, second) = (gray[i - 1], gray[i]);
+        if first & (first ^ second) == 0 {
+            TwoLevel { low: first, high: second, matrix: adjoint }
+        } else {
+            let m = adjoint;
+            TwoLevel { low: second, high: first, matrix: [[m[1][1], m[1][0]], [m[0][1], m[0][0]]] }
+        }
+    }).collect()
+}
+
+/// Angles (theta, phi, lambda, gamma) with m = e^{i gamma} U3(theta, phi, lambda).
+pub fn u3_angles(m: &[[C64; 2]; 2]) -> (f64, f64, f64, f64) {
+    let (c, s) = (m[0][0].norm(), m[1][0].norm());
+    let theta = 2.0 * s.atan2(c);
+    if c > EPS {
+        let gamma = m[0][0].arg();
+        if s > EPS {
+            (theta, m[1][0].arg() - gamma, (-m[0][1]).arg() - gamma, gamma)
+        } else {
+            (theta, 0.0, m[1][1].arg() - gamma, gamma)
+        }
+    } else {
+        let gamma = m[1][0].arg();
+        (theta, 0.0, (-m[0][1]).arg() - gamma, gamma)
+    }
+}
+
+/// OpenQASM 2.0 has no arbitrary-unitary instruction, so a user-defined gate is
+/// lowered to u3 (one qubit) or to cu3/u1/x (two qubits, controls included).
+/// `qubits` lists the controls first, then the gate's own targets.
+pub fn to_qasm(unitary: &Unitary, is_dagger: bool, qubits: &[usize]) -> Result<String, String> {
+    if qubits.len() < unitary.num_qubits {
+        return Err(format!("{}-qubit gate applied to {} qubit(s)", unitary.num_qubits, qubits.len()));
+    }
+    let mut full = if is_dagger { unitary.dagger() } else { unitary.clone() };
+    while full.num_qubits < qubits.len() {
+        full = full.controlled();
+    }
+
+    match full.num_qubits {
+        1 => {
+            let matrix = full.as_single().expect("one-qubit unitary");
+            let (theta, phi, lambda, _) = u3_angles(&matrix);
+            Ok(format!("u3({},{},{}) q[{}];\n", theta, phi, lambda, qubits[0]))
+        }
+        2 => {
+            let mut qasm = String::new();
+            for factor in two_level_factors(&full) {
+                // Gate index bit 1 is qubits[0], bit 0 is qubits[1].
+                let (target, control) = if factor.low ^ factor.high == 1 {
+                    (qubits[1], qubits[0])
+                } else {
+                    (qubits[0], qubits[1])
+                };
+                let control_on_zero = (factor.low & !(factor.low ^ factor.high)) == 0;
+                let (theta, phi, lambda, gamma) = u3_angles(&factor.matrix);
+
+                if control_on_zero {
+                    qasm.push_str(&format!("x q[{}];\n", control));
+                }
+                if gamma.abs() > EPS {
+                    qasm.push_str(&format!("u1({}) q[{}];\n", gamma, control));
+                }
+                qasm.push_str(&format!("cu3({},{},{}) q[{}],q[{}];\n", theta, phi, lambda, control, target));
+                if control_on_zero {
+                    qasm.push_str(&format!("x q[{}];\n", control));
+                }
+            }
+            Ok(qasm)
+        }
+        n => Err(format!("no OpenQASM 2.0 decomposition for a {}-qubit unitary; use the Qiskit or Cirq backend", n)),
+    }
+}
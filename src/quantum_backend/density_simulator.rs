@@ -0,0 +1,148 @@
+// src/quantum_backend/density_simulator.rs
+
+use super::{HardwareCircuit, HardwareGate, QuantumConfig, QuantumResult, QuantumBackend};
+use crate::environment;
+use crate::evaluator::Evaluator;
+use crate::simulator::{DensityMatrix, NoiseModel, Unitary, MAX_DENSITY_QUBITS, C64, noise_model};
+use std::collections::HashMap;
+use rand::Rng;
+
+/// Local density-matrix simulator with optional noise. Counts are sampled from
+/// the final mixed state, so no re-execution per shot is needed.
+pub struct DensityMatrixBackend;
+
+impl DensityMatrixBackend {
+    pub fn new() -> Self {
+        DensityMatrixBackend
+    }
+
+    fn apply_gate(rho: &mut DensityMatrix, gate: &HardwareGate, noise: Option<&NoiseModel>) -> Result<(), String> {
+        if gate.qubits.is_empty() {
+            return Err(format!("Gate '{}' has no qubits", gate.name));
+        }
+        if gate.name == "reset" && gate.matrix.is_none() {
+            let zero = C64::new(0.0, 0.0);
+            let one = C64::new(1.0, 0.0);
+            let operators = [[[one, zero], [zero, zero]], [[zero, one], [zero, zero]]];
+            for &qubit in gate.qubits.iter() {
+                rho.apply_kraus(&operators, qubit);
+            }
+            return Ok(());
+        }
+
+        let (unitary, num_controls) = Self::resolve_gate(gate)?;
+        let (controls, targets) = gate.qubits.split_at(num_controls);
+        let control_mask = controls.iter().fold(0, |mask, &q| mask | (1 << q));
+        rho.apply_unitary(&unitary, targets, control_mask);
+
+        if let Some(model) = noise {
+            for &qubit in gate.qubits.iter() {
+                for channel in model.gate_channels(&gate.name, qubit) {
+                    if let Some(operators) = channel.kraus_operators() {
+                        rho.apply_kraus(&operators, qubit);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Recorded names carry one leading 'c' per `controlled(...)`, e.g. "ccx" or "crz".
+    // Strip as few as needed until the remaining name is a known gate of the right size.
+    fn resolve_gate(gate: &HardwareGate) -> Result<(Unitary, usize), String> {
+        if let (Some(unitary), Some(num_controls)) = (gate.unitary(), gate.num_controls()) {
+            if unitary.num_qubits > gate.qubits.len() {
+                return Err(format!("Gate '{}' acts on {} qubit(s), but {} were given", gate.name, unitary.num_qubits, gate.qubits.len()));
+            }
+            let unitary = if gate.is_dagger { unitary.dagger() } else { unitary };
+            return Ok((unitary, num_controls));
+        }
+        let mut name = gate.name.as_str();
+        for num_controls in 0..gate.qubits.len() {
+            if let Ok(unitary) = Evaluator::get_gate_unitary(name, &gate.params, gate.is_dagger) {
+                if unitary.num_qubits + num_controls == gate.qubits.len() {
+                    return Ok((unitary, num_controls));
+                }
+            }
+            match name.strip_prefix('c') {
+                Some(rest) => name = rest,
+                None => break,
+            }
+        }
+        Err(format!("Unknown gate '{}' for the density-matrix simulator", gate.name))
+    }
+}
+
+impl QuantumBackend for DensityMatrixBackend {
+    fn execute(&self, circuit: &HardwareCircuit, config: &QuantumConfig) -> Result<QuantumResult, String> {
+        if circuit.num_qubits == 0 {
+            return Err("Circuit has no qubits".to_string());
+        }
+        if circuit.num_qubits > MAX_DENSITY_QUBITS {
+            return Err(format!("Density-matrix simulation is limited to {} qubits (circuit has {})", MAX_DENSITY_QUBITS, circuit.num_qubits));
+        }
+
+        let active = noise_model();
+        let noise = config.noise_model.as_ref().or(active.as_deref());
+        let mut rho = DensityMatrix::from_amplitudes(circuit.num_qubits, vec![(0, C64::new(1.0, 0.0))]);
+        for gate in &circuit.gates {
+            Self::apply_gate(&mut rho, gate, noise)?;
+        }
+
+        let measured: Vec<usize> = if circuit.measurements.is_empty() {
+            (0..circuit.num_qubits).collect()
+        } else {
+            circuit.measurements.clone()
+        };
+
+        let populations = rho.populations();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+
+        environment::with_rng(|rng| {
+            for _ in 0..config.shots {
+                let r: f64 = rng.gen();
+                let mut cumulative = 0.0;
+                let mut outcome = populations.len() - 1;
+                for (basis_state, p) in populations.iter().enumerate() {
+                    cumulative += p;
+                    if r < cumulative {
+                        outcome = basis_state;
+                        break;
+                    }
+                }
+
+                let bits: String = measured.iter().map(|&qubit| {
+                    let mut bit = (outcome >> qubit) & 1;
+                    if let Some(model) = noise {
+                        let (p01, p10) = model.readout_error(qubit);
+                        let flip = if bit == 0 { p01 } else { p10 };
+                        if flip > 0.0 && rng.gen::<f64>() < flip {
+                            bit ^= 1;
+                        }
+                    }
+                    if bit == 1 { '1' } else { '0' }
+                }).collect();
+                *counts.entry(bits).or_insert(0) += 1;
+            }
+        });
+
+        Ok(QuantumResult {
+            counts,
+            shots: config.shots,
+            success: true,
+            error_message: None,
+        })
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn available_devices(&self) -> Vec<String> {
+        vec!["density_matrix".to_string()]
+    }
+
+    fn optimize_circuit(&self, circuit: &HardwareCircuit) -> HardwareCircuit {
+        circuit.clone()
+    }
+}
@@ -1,6 +1,6 @@
 // src/quantum_backend/ibm_qiskit.rs
 
-use super::{HardwareCircuit, QuantumConfig, QuantumResult, QuantumBackend};
+use super::{HardwareCircuit, QuantumConfig, QuantumResult, QuantumBackend, python_matrix};
 use std::collections::HashMap;
 use std::process::Command;
 
@@ -21,7 +21,9 @@ impl IBMQiskitBackend {
     
     fn generate_qiskit_script(&self, circuit: &HardwareCircuit, shots: u32, device: Option<&str>) -> String {
         let mut script = String::from("from qiskit import QuantumCircuit, transpile\n");
+        script.push_str("from qiskit.circuit.library import UnitaryGate\n");
         script.push_str("from qiskit_aer import AerSimulator\n");
+        script.push_str("import numpy as np\n");
         script.push_str("import json\n\n");
         
         script.push_str(&format!("qc = QuantumCircuit({}, {})\n\n", 
@@ -29,6 +31,20 @@ impl IBMQiskitBackend {
         
         // gates
         for gate in &circuit.gates {
+            if let (Some(unitary), Some(num_controls)) = (gate.unitary(), gate.num_controls()) {
+                // Qiskit orders a gate's qubits little-endian, so the targets go in reversed.
+                let (controls, targets) = gate.qubits.split_at(num_controls);
+                let qargs: Vec<String> = controls.iter().chain(targets.iter().rev()).map(|q| q.to_string()).collect();
+                let mut gate_expr = format!("UnitaryGate({}, label='{}')", python_matrix(&unitary), gate.name);
+                if gate.is_dagger {
+                    gate_expr.push_str(".adjoint()");
+                }
+                if num_controls > 0 {
+                    gate_expr.push_str(&format!(".control({})", num_controls));
+                }
+                script.push_str(&format!("qc.append({}, [{}])\n", gate_expr, qargs.join(", ")));
+                continue;
+            }
             let gate_code = match gate.name.as_str() {
                 "hadamard" | "h" => format!("qc.h({})", gate.qubits[0]),
                 "x" => format!("qc.x({})", gate.qubits[0]),
@@ -42,6 +58,7 @@ impl IBMQiskitBackend {
                 "rx" => format!("qc.rx({}, {})", gate.params[0], gate.qubits[0]),
                 "ry" => format!("qc.ry({}, {})", gate.params[0], gate.qubits[0]),
                 "rz" => format!("qc.rz({}, {})", gate.params[0], gate.qubits[0]),
+                "reset" => format!("qc.reset({})", gate.qubits[0]),
                 _ => continue,
             };
             script.push_str(&format!("{}\n", gate_code));
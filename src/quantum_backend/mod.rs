@@ -6,6 +6,10 @@ mod ibm_qiskit;
 use ibm_qiskit::IBMQiskitBackend;
 mod cirq_local;
 use cirq_local::CirqLocalBackend;
+mod density_simulator;
+use density_simulator::DensityMatrixBackend;
+mod decompose;
+use crate::simulator::{basis_rotation, NoiseModel, Observable, Unitary, C64};
 
 /// Supported quantum hardware providers
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -28,6 +32,7 @@ pub struct QuantumConfig {
     pub device_name: Option<String>,
     pub shots: u32, // Number of measurements
     pub optimize: bool,
+    pub noise_model: Option<NoiseModel>, // Only used by the local simulator
 }
 
 impl Default for QuantumConfig {
@@ -38,6 +43,7 @@ impl Default for QuantumConfig {
             device_name: None,
             shots: 1024,
             optimize: true,
+            noise_model: None,
         }
     }
 }
@@ -49,6 +55,37 @@ pub struct HardwareGate {
     pub qubits: Vec<usize>,
     pub params: Vec<f64>,
     pub is_dagger: bool,
+    /// Row-major (re, im) entries of a user-defined gate, before dagger and controls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<Vec<(f64, f64)>>,
+}
+
+impl HardwareGate {
+    pub fn unitary(&self) -> Option<Unitary> {
+        let data: Vec<C64> = self.matrix.as_ref()?.iter().map(|&(re, im)| C64::new(re, im)).collect();
+        let num_qubits = data.len().trailing_zeros() as usize / 2;
+        Some(Unitary { num_qubits, data })
+    }
+
+    /// For user-defined gates: how many leading qubits are controls.
+    pub fn num_controls(&self) -> Option<usize> {
+        self.unitary().map(|u| self.qubits.len().saturating_sub(u.num_qubits))
+    }
+}
+
+/// Python literal for a gate matrix, for the Qiskit and Cirq script generators.
+pub(crate) fn python_matrix(unitary: &Unitary) -> String {
+    let dim = unitary.dim();
+    let rows: Vec<String> = (0..dim).map(|row| {
+        let entries: Vec<String> = (0..dim)
+            .map(|col| {
+                let entry = unitary.get(row, col);
+                format!("complex({}, {})", entry.re, entry.im)
+            })
+            .collect();
+        format!("[{}]", entries.join(", "))
+    }).collect();
+    format!("np.array([{}])", rows.join(", "))
 }
 
 
@@ -104,6 +141,13 @@ impl IBMBackend {
         qasm.push_str(&format!("creg c[{}];\n", circuit.measurements.len()));
         
         for gate in &circuit.gates {
+            if let Some(unitary) = gate.unitary() {
+                match decompose::to_qasm(&unitary, gate.is_dagger, &gate.qubits) {
+                    Ok(lines) => qasm.push_str(&lines),
+                    Err(e) => qasm.push_str(&format!("// Unsupported gate: {} ({})\n", gate.name, e)),
+                }
+                continue;
+            }
             let gate_str = match gate.name.as_str() {
                 "x" => format!("x q[{}];\n", gate.qubits[0]),
                 "y" => format!("y q[{}];\n", gate.qubits[0]),
@@ -118,6 +162,7 @@ impl IBMBackend {
                 "ry" => format!("ry({}) q[{}];\n", gate.params[0], gate.qubits[0]),
                 "rz" => format!("rz({}) q[{}];\n", gate.params[0], gate.qubits[0]),
                 "cphase" => format!("cp({}) q[{}],q[{}];\n", gate.params[0], gate.qubits[0], gate.qubits[1]),
+                "reset" => format!("reset q[{}];\n", gate.qubits[0]),
                 _ => return format!("// Unsupported gate: {}\n", gate.name),
             };
             
@@ -224,6 +269,7 @@ impl BackendManager {
         backends.insert(QuantumProvider::IBM, Box::new(IBMQiskitBackend::new()));
         backends.insert(QuantumProvider::AWS, Box::new(AWSBraketBackend::new("us-east-1".to_string())));
         backends.insert(QuantumProvider::GoogleCircuit, Box::new(CirqLocalBackend::new()));
+        backends.insert(QuantumProvider::Simulator, Box::new(DensityMatrixBackend::new()));
         
         BackendManager { backends, config }
     }
@@ -245,6 +291,51 @@ impl BackendManager {
         
         backend.execute(&optimized_circuit, &self.config)
     }
+
+    /// Shot estimate of ⟨H⟩ after `circuit`, where letter `i` of each Pauli string
+    /// acts on circuit qubit `qubits[i]`. Each term runs as its own job: rotate its
+    /// qubits into the Z basis, measure them, and average the parity.
+    pub fn estimate_expectation(&self, circuit: &HardwareCircuit, observable: &Observable, qubits: &[usize]) -> Result<f64, String> {
+        if qubits.len() != observable.num_qubits() {
+            return Err(format!("Observable acts on {} qubit(s), but {} were given", observable.num_qubits(), qubits.len()));
+        }
+        let mut total = 0.0;
+        for (coefficient, paulis) in &observable.terms {
+            let support: Vec<(usize, char)> = paulis.chars().zip(qubits)
+                .filter(|(pauli, _)| *pauli != 'I')
+                .map(|(pauli, &qubit)| (qubit, pauli))
+                .collect();
+            if support.is_empty() {
+                total += coefficient;
+                continue;
+            }
+
+            let mut term_circuit = circuit.clone();
+            for &(qubit, pauli) in &support {
+                if let Some((name, params)) = basis_rotation(pauli) {
+                    term_circuit.gates.push(HardwareGate { name: name.to_string(), qubits: vec![qubit], params, is_dagger: false, matrix: None });
+                }
+            }
+            term_circuit.measurements = support.iter().map(|&(qubit, _)| qubit).collect();
+
+            let result = self.execute_circuit(&term_circuit)?;
+            if !result.success {
+                return Err(result.error_message.unwrap_or_else(|| "Execution failed".to_string()));
+            }
+            let shots: u32 = result.counts.values().sum();
+            if shots == 0 {
+                return Err(format!("No shots returned for Pauli term '{}'", paulis));
+            }
+            let parity_sum: i64 = result.counts.iter()
+                .map(|(bits, &count)| {
+                    let odd = bits.chars().filter(|&b| b == '1').count() % 2 == 1;
+                    if odd { -(count as i64) } else { count as i64 }
+                })
+                .sum();
+            total += coefficient * parity_sum as f64 / shots as f64;
+        }
+        Ok(total)
+    }
     
     pub fn list_devices(&self) -> Vec<String> {
         if let Some(backend) = self.backends.get(&self.config.provider) {
@@ -270,12 +361,14 @@ mod tests {
                     qubits: vec![0],
                     params: vec![],
                     is_dagger: false,
+                    matrix: None,
                 },
                 HardwareGate {
                     name: "cnot".to_string(),
                     qubits: vec![0, 1],
                     params: vec![],
                     is_dagger: false,
+                    matrix: None,
                 },
             ],
             measurements: vec![0, 1],
@@ -287,4 +380,118 @@ mod tests {
         assert!(qasm.contains("cx q[0],q[1]"));
     }
 
+    #[test]
+    fn test_density_simulator_backend() {
+        let circuit = HardwareCircuit {
+            num_qubits: 2,
+            gates: vec![
+                HardwareGate { name: "hadamard".to_string(), qubits: vec![0], params: vec![], is_dagger: false, matrix: None },
+                HardwareGate { name: "cnot".to_string(), qubits: vec![0, 1], params: vec![], is_dagger: false, matrix: None },
+            ],
+            measurements: vec![0, 1],
+        };
+        let config = QuantumConfig { shots: 200, ..QuantumConfig::default() };
+
+        let manager = BackendManager::new(config);
+        let result = manager.execute_circuit(&circuit).unwrap();
+        assert_eq!(result.counts.values().sum::<u32>(), 200);
+        assert!(result.counts.keys().all(|bits| bits == "00" || bits == "11"));
+    }
+
+    #[test]
+    fn test_reset_export_and_simulation() {
+        let gate = |name: &str, qubits: Vec<usize>| HardwareGate { name: name.to_string(), qubits, params: vec![], is_dagger: false, matrix: None };
+        let circuit = HardwareCircuit {
+            num_qubits: 2,
+            gates: vec![gate("hadamard", vec![0]), gate("cnot", vec![0, 1]), gate("reset", vec![0])],
+            measurements: vec![0, 1],
+        };
+        assert!(IBMBackend::new().to_qasm(&circuit).contains("reset q[0];"));
+
+        let config = QuantumConfig { shots: 200, ..QuantumConfig::default() };
+        let result = BackendManager::new(config).execute_circuit(&circuit).unwrap();
+        assert!(result.counts.keys().all(|bits| bits.starts_with('0')));
+        assert_eq!(result.counts.len(), 2);
+    }
+
+    #[test]
+    fn test_shot_estimated_expectation() {
+        let gate = |name: &str, qubits: Vec<usize>| HardwareGate { name: name.to_string(), qubits, params: vec![], is_dagger: false, matrix: None };
+        let circuit = HardwareCircuit {
+            num_qubits: 3,
+            gates: vec![gate("x", vec![0]), gate("hadamard", vec![1]), gate("cnot", vec![1, 2])],
+            measurements: vec![],
+        };
+        let manager = BackendManager::new(QuantumConfig { shots: 100, ..QuantumConfig::default() });
+        // The Bell pair on qubits 1 and 2 is a +1 eigenstate of XX and ZZ and a -1 eigenstate of YY.
+        let bell = Observable::parse("ZZ + 0.5*XX - YY + 2*II").unwrap();
+        assert_eq!(manager.estimate_expectation(&circuit, &bell, &[1, 2]).unwrap(), 4.5);
+        let z = Observable::parse("Z").unwrap();
+        assert_eq!(manager.estimate_expectation(&circuit, &z, &[0]).unwrap(), -1.0);
+        assert!(manager.estimate_expectation(&circuit, &z, &[0, 1]).is_err());
+    }
+
+    fn iswap() -> Unitary {
+        let (one, i, zero) = (C64::new(1.0, 0.0), C64::new(0.0, 1.0), C64::new(0.0, 0.0));
+        Unitary::from_rows(vec![
+            vec![one, zero, zero, zero],
+            vec![zero, zero, i, zero],
+            vec![zero, i, zero, zero],
+            vec![zero, zero, zero, one],
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_two_level_factors_rebuild_the_gate() {
+        let h = C64::new(1.0 / 2f64.sqrt(), 0.0);
+        let gates = [
+            iswap(),
+            Unitary::single([[h, h], [h, -h]]).controlled(),
+            iswap().dagger(),
+        ];
+        for gate in gates {
+            let dim = gate.dim();
+            let mut product: Vec<C64> = (0..dim * dim)
+                .map(|k| if k % (dim + 1) == 0 { C64::new(1.0, 0.0) } else { C64::new(0.0, 0.0) })
+                .collect();
+            for factor in decompose::two_level_factors(&gate) {
+                assert_eq!((factor.low ^ factor.high).count_ones(), 1);
+                for col in 0..dim {
+                    let (a, b) = (product[factor.low * dim + col], product[factor.high * dim + col]);
+                    product[factor.low * dim + col] = factor.matrix[0][0] * a + factor.matrix[0][1] * b;
+                    product[factor.high * dim + col] = factor.matrix[1][0] * a + factor.matrix[1][1] * b;
+                }
+            }
+            for (rebuilt, original) in product.iter().zip(gate.data.iter()) {
+                assert!((rebuilt - original).norm() < 1e-9);
+            }
+        }
+        assert!(Unitary::from_rows(vec![vec![h, h], vec![h, h]]).is_err());
+    }
+
+    #[test]
+    fn test_custom_gate_export_and_simulation() {
+        let matrix: Vec<(f64, f64)> = iswap().data.iter().map(|c| (c.re, c.im)).collect();
+        let circuit = HardwareCircuit {
+            num_qubits: 3,
+            gates: vec![
+                HardwareGate { name: "x".to_string(), qubits: vec![0], params: vec![], is_dagger: false, matrix: None },
+                HardwareGate { name: "x".to_string(), qubits: vec![1], params: vec![], is_dagger: false, matrix: None },
+                HardwareGate { name: "ciswap".to_string(), qubits: vec![0, 1, 2], params: vec![], is_dagger: false, matrix: Some(matrix.clone()) },
+                HardwareGate { name: "iswap".to_string(), qubits: vec![1, 2], params: vec![], is_dagger: true, matrix: Some(matrix) },
+            ],
+            measurements: vec![0, 1, 2],
+        };
+
+        // Two-qubit custom gates lower to cu3; three-qubit ones have no QASM 2.0 form.
+        let qasm = IBMBackend::new().to_qasm(&circuit);
+        assert!(qasm.contains("cu3("));
+        assert!(qasm.contains("// Unsupported gate: ciswap"));
+
+        let config = QuantumConfig { shots: 50, ..QuantumConfig::default() };
+        let result = BackendManager::new(config).execute_circuit(&circuit).unwrap();
+        assert_eq!(result.counts.len(), 1);
+        assert_eq!(result.counts.values().sum::<u32>(), 50);
+    }
+
 }
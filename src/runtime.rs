@@ -1,13 +1,12 @@
 // src/runtime.rs
 
-use crate::environment::{Environment, RuntimeValue};
+use crate::environment::{Environment, RuntimeValue, StateHandle};
 use crate::evaluator::Evaluator;
-use std::collections::HashMap;
+use crate::simulator::{QuantumState, Unitary, C64};
 use std::ffi::{c_char, c_void, CStr};
 use std::os::raw::c_int;
 use libc;
 use std::rc::Rc;
-use std::cell::RefCell;
 use crate::environment::GateDefinition;
 use std::slice;
 use std::io::Write;
@@ -19,12 +18,17 @@ type StatePtr = *mut c_void;
 #[no_mangle]
 pub extern "C" fn quantica_rt_new_state(num_qubits: c_int) -> StatePtr {
 
-    let mut state_map: HashMap<usize, (f64, f64)> = HashMap::new();
-    state_map.insert(0, (1.0, 0.0));
+    let state = match QuantumState::new(num_qubits as usize) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("(Runtime Error) {}", e);
+            return std::ptr::null_mut();
+        }
+    };
 
 
     let register = RuntimeValue::QuantumRegister {
-        state: Rc::new(RefCell::new(state_map)),
+        state: StateHandle::new(state),
         size: num_qubits as usize,
     };
 
@@ -43,12 +47,11 @@ pub extern "C" fn quantica_rt_measure(state_ptr: StatePtr, qubit_index: c_int) -
     let register = unsafe { &*(state_ptr as *mut RuntimeValue) };
 
     match register {
-        RuntimeValue::QuantumRegister { state, size } => {
+        RuntimeValue::QuantumRegister { state, .. } => {
 
             let qubit_handle = RuntimeValue::Qubit {
                 state: state.clone(),
                 index: qubit_index as usize,
-                size: *size,
             };
 
 
@@ -71,6 +74,144 @@ pub extern "C" fn quantica_rt_measure(state_ptr: StatePtr, qubit_index: c_int) -
     }
 }
 
+/// Reseed the RNG shared with the interpreter.
+#[no_mangle]
+pub extern "C" fn quantica_rt_set_seed(seed: i64) {
+    crate::environment::set_seed(seed as u64);
+}
+
+/// Stop the program on an error the compiled code cannot recover from.
+///
+/// # Safety
+/// `message` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn quantica_rt_fail(message: *const c_char) -> ! {
+    let message = if message.is_null() {
+        "(null)".into()
+    } else {
+        unsafe { CStr::from_ptr(message) }.to_string_lossy()
+    };
+    eprintln!("(Runtime Error) {}", message);
+    let _ = std::io::stdout().flush();
+    std::process::exit(1);
+}
+
+/// Measure every qubit of a register and return the bits as a runtime array
+/// (element i is qubit i), the same value the interpreter produces, or null on error.
+#[no_mangle]
+pub extern "C" fn quantica_rt_measure_register(state_ptr: StatePtr) -> StatePtr {
+    if state_ptr.is_null() {
+        eprintln!("(Runtime Error) quantica_rt_measure_register called with null pointer.");
+        return std::ptr::null_mut();
+    }
+
+    let register = unsafe { &*(state_ptr as *mut RuntimeValue) };
+
+    match Evaluator::builtin_measure(vec![register.clone()]) {
+        Ok(bits) => Box::into_raw(Box::new(bits)) as StatePtr,
+        Err(e) => {
+            eprintln!("(Runtime Error) Measurement failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// The integer view of a measured bit array (bit i is element i), or -1 on error.
+#[no_mangle]
+pub extern "C" fn quantica_rt_bits_as_int(bits_ptr: StatePtr) -> i64 {
+    if bits_ptr.is_null() {
+        eprintln!("(Runtime Error) quantica_rt_bits_as_int called with null pointer.");
+        return -1;
+    }
+
+    let result = match unsafe { &*(bits_ptr as *mut RuntimeValue) } {
+        RuntimeValue::Register(bits) => Evaluator::bits_to_int(bits),
+        other => Err(format!("Cannot get .as_int of {}.", other.type_name())),
+    };
+
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("(Runtime Error) {}", e);
+            -1
+        }
+    }
+}
+
+/// Element `index` of a measured bit array, or -1 on error.
+#[no_mangle]
+pub extern "C" fn quantica_rt_bits_get(bits_ptr: StatePtr, index: i64) -> i64 {
+    if bits_ptr.is_null() {
+        eprintln!("(Runtime Error) quantica_rt_bits_get called with null pointer.");
+        return -1;
+    }
+
+    match unsafe { &*(bits_ptr as *mut RuntimeValue) } {
+        RuntimeValue::Register(bits) => match usize::try_from(index).ok().and_then(|i| bits.get(i)) {
+            Some(bit) => match &*bit.borrow() {
+                RuntimeValue::Int(value) => *value,
+                other => {
+                    eprintln!("(Runtime Error) Element {} is not a bit, got {}.", index, other);
+                    -1
+                }
+            },
+            None => {
+                eprintln!("(Runtime Error) Index {} out of bounds for array of length {}.", index, bits.len());
+                -1
+            }
+        },
+        other => {
+            eprintln!("(Runtime Error) Cannot index {}.", other.type_name());
+            -1
+        }
+    }
+}
+
+/// The `.length` of a runtime array or quantum register, or -1 on error.
+#[no_mangle]
+pub extern "C" fn quantica_rt_length(value_ptr: StatePtr) -> i64 {
+    if value_ptr.is_null() {
+        eprintln!("(Runtime Error) quantica_rt_length called with null pointer.");
+        return -1;
+    }
+
+    match unsafe { &*(value_ptr as *mut RuntimeValue) } {
+        RuntimeValue::Register(items) => items.len() as i64,
+        RuntimeValue::QuantumRegister { size, .. } => *size as i64,
+        other => {
+            eprintln!("(Runtime Error) Cannot get .length of {}.", other.type_name());
+            -1
+        }
+    }
+}
+
+/// Reset a qubit to |0> by measuring it and flipping it on a 1 outcome.
+#[no_mangle]
+pub extern "C" fn quantica_rt_reset(state_ptr: StatePtr, qubit_index: c_int) -> c_int {
+    if state_ptr.is_null() {
+        eprintln!("(Runtime Error) quantica_rt_reset called with null pointer.");
+        return 1;
+    }
+
+    let register = unsafe { &*(state_ptr as *mut RuntimeValue) };
+
+    match register {
+        RuntimeValue::QuantumRegister { state, size, .. } => {
+            match Evaluator::reset_qubit(&state.resolve().0, qubit_index as usize, *size) {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("(Quantum Runtime Error) {}", e);
+                    1
+                }
+            }
+        }
+        _ => {
+            eprintln!("(Runtime Error) Invalid state pointer passed to reset.");
+            1
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quantica_rt_device_alloc(size_bytes: usize) -> *mut c_void {
     
@@ -149,10 +290,10 @@ pub extern "C" fn quantica_rt_debug_state(state_ptr: StatePtr) {
     };
     
     // 2. Check the type
-    if let RuntimeValue::QuantumRegister { state, size } = register {
+    if let RuntimeValue::QuantumRegister { state, size, .. } = register {
 
         println!("(Quantum Runtime) Debugging state ({} qubits):", size);
-        Evaluator::print_quantum_state(state, *size, 10);
+        Evaluator::print_quantum_state(&state.resolve().0, *size, 10);
     } else {
         println!("(Runtime Error) Invalid state pointer passed to debug_state.");
     }
@@ -205,7 +346,7 @@ unsafe fn apply_gate_unsafe(
     }
     let register = &*(state_ptr as *mut RuntimeValue);
     let (state_rc, reg_size) = match register {
-        RuntimeValue::QuantumRegister { state, size } => (state.clone(), *size),
+        RuntimeValue::QuantumRegister { state, size } => (state.resolve().0, *size),
         _ => return Err("Invalid state pointer passed to apply_gate.".to_string()),
     };
 
@@ -243,9 +384,97 @@ unsafe fn apply_gate_unsafe(
         targets: targets.to_vec(),
         register_size: reg_size,
         state_rc: state_rc,
+        matrix: None,
+    };
+
+    Evaluator::apply_multi_controlled_gate(gate_def, is_dagger).map_err(String::from)
+}
+
+/// Apply a user-defined gate. `matrix_ptr` holds the 2^k x 2^k matrix row by
+/// row as interleaved (re, im) pairs, where k = `num_gate_qubits`.
+///
+/// # Safety
+/// `matrix_ptr` must point to 2 * 4^k doubles and `qubit_indices_ptr` to
+/// `num_qubits` ints.
+#[no_mangle]
+pub unsafe extern "C" fn quantica_rt_apply_unitary(
+    state_ptr: StatePtr,
+    gate_name_ptr: *const c_char,
+    matrix_ptr: *const f64,
+    num_gate_qubits: c_int,
+    is_dagger_int: c_int,
+    qubit_indices_ptr: *const c_int,
+    num_qubits: c_int,
+    num_controls: c_int,
+) -> c_int {
+
+    match unsafe { apply_unitary_unsafe(
+        state_ptr,
+        gate_name_ptr,
+        matrix_ptr,
+        num_gate_qubits,
+        is_dagger_int,
+        qubit_indices_ptr,
+        num_qubits,
+        num_controls
+    ) } {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("(Quantum Runtime Error) {}", e);
+            1
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn apply_unitary_unsafe(
+    state_ptr: StatePtr,
+    gate_name_ptr: *const c_char,
+    matrix_ptr: *const f64,
+    num_gate_qubits: c_int,
+    is_dagger_int: c_int,
+    qubit_indices_ptr: *const c_int,
+    num_qubits: c_int,
+    num_controls: c_int,
+) -> Result<RuntimeValue, String> {
+
+    if state_ptr.is_null() || matrix_ptr.is_null() || qubit_indices_ptr.is_null() {
+        return Err("Null pointer passed to apply_unitary.".to_string());
+    }
+    let register = &*(state_ptr as *mut RuntimeValue);
+    let (state_rc, reg_size) = match register {
+        RuntimeValue::QuantumRegister { state, size } => (state.resolve().0, *size),
+        _ => return Err("Invalid state pointer passed to apply_unitary.".to_string()),
+    };
+
+    let gate_name = CStr::from_ptr(gate_name_ptr).to_str().unwrap_or("").to_string();
+
+    let dim = 1usize << num_gate_qubits;
+    let rows: Vec<Vec<C64>> = slice::from_raw_parts(matrix_ptr, 2 * dim * dim)
+        .chunks(2 * dim)
+        .map(|row| row.chunks(2).map(|pair| C64::new(pair[0], pair[1])).collect())
+        .collect();
+    let matrix = Unitary::from_rows(rows)
+        .map_err(|e| format!("Gate '{}': {}.", gate_name, e))?;
+
+    let qubit_indices: Vec<usize> = slice::from_raw_parts(qubit_indices_ptr, num_qubits as usize)
+        .iter().map(|&x| x as usize).collect();
+    if (num_controls as usize) > qubit_indices.len() {
+        return Err("More controls specified than total qubits.".to_string());
+    }
+    let (controls, targets) = qubit_indices.split_at(num_controls as usize);
+
+    let gate_def = GateDefinition {
+        name: gate_name,
+        params: Vec::new(),
+        controls: controls.to_vec(),
+        targets: targets.to_vec(),
+        register_size: reg_size,
+        state_rc,
+        matrix: Some(Rc::new(matrix)),
     };
 
-    Evaluator::apply_multi_controlled_gate(gate_def, is_dagger)
+    Evaluator::apply_multi_controlled_gate(gate_def, is_dagger_int != 0).map_err(String::from)
 }
 #[no_mangle]
 pub extern "C" fn quantica_rt_print_int(n: i64) {
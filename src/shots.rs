@@ -0,0 +1,266 @@
+// src/shots.rs
+
+use crate::environment::{self, Environment, RuntimeValue};
+use crate::evaluator::{Evaluator, RuntimeError};
+use crate::parser::ast::ASTNode;
+use crate::quantum_backend::QuantumResult;
+use crate::simulator::QuantumState;
+use rand::Rng;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type StateRef = Rc<RefCell<QuantumState>>;
+type Layout = Vec<(String, Vec<(StateRef, usize)>)>;
+
+/// Run `program` for `shots` shots on the local simulator and histogram the measured
+/// bits the same way the `--hardware` path does: measured qubits left to right, in the
+/// order the program first measures them (every qubit if it measures none).
+///
+/// Programs whose quantum part never measures or resets are executed once and sampled
+/// from the final state; anything else is re-run from scratch for every shot.
+pub fn run_shots(program: &ASTNode, shots: u32) -> Result<QuantumResult, RuntimeError> {
+    let statements = match program {
+        ASTNode::Program(statements) => statements,
+        _ => return Err("Expected ASTNode::Program at root.".to_string().into()),
+    };
+    if shots == 0 {
+        return Err("Shot count must be positive".to_string().into());
+    }
+
+    let registers: Vec<&str> = statements.iter()
+        .filter_map(|stmt| match stmt {
+            ASTNode::QuantumDeclaration { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    if registers.is_empty() {
+        return Err("Program declares no top-level quantum registers".to_string().into());
+    }
+
+    let builtins = Environment::new();
+    let split = statements.iter().rposition(|stmt| !is_readout(stmt, &builtins)).map_or(0, |i| i + 1);
+    let (circuit, readout) = statements.split_at(split);
+    let samplable = !circuit.iter().any(|stmt| stmt.any_node(&|node| {
+        matches!(node, ASTNode::Measure(_) | ASTNode::Import { .. } | ASTNode::FromImport { .. })
+            || matches!(node, ASTNode::Gate { name, .. } if name == "reset")
+    }));
+
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    if samplable {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        for stmt in circuit {
+            Evaluator::evaluate(stmt, &env)?;
+        }
+        let measured = measured_qubits(program, &resolve_layout(&registers, &env)?);
+        for bits in sample_final_states(&measured, shots)? {
+            *counts.entry(bits).or_insert(0) += 1;
+        }
+        for stmt in readout {
+            Evaluator::evaluate(stmt, &env)?;
+        }
+    } else {
+        // Program output is only shown for the first shot.
+        let quiet = environment::is_quiet();
+        let result = (0..shots).try_for_each(|shot| {
+            environment::set_quiet(quiet || shot > 0);
+            let env = Rc::new(RefCell::new(Environment::new()));
+            Evaluator::evaluate_program(program, &env)?;
+            let measured = measured_qubits(program, &resolve_layout(&registers, &env)?);
+            let bits = measured.iter()
+                .map(|(state, index)| {
+                    let total = state.borrow().num_qubits();
+                    match Evaluator::perform_measurement(state, *index, total)? {
+                        RuntimeValue::Int(1) => Ok('1'),
+                        _ => Ok('0'),
+                    }
+                })
+                .collect::<Result<String, String>>()?;
+            *counts.entry(bits).or_insert(0) += 1;
+            Ok::<(), RuntimeError>(())
+        });
+        environment::set_quiet(quiet);
+        result?;
+    }
+
+    Ok(QuantumResult {
+        counts,
+        shots,
+        success: true,
+        error_message: None,
+    })
+}
+
+// Trailing statements that only read results out: they may measure or print, but they
+// apply no gates, so they can run once after sampling instead of once per shot.
+fn is_readout(stmt: &ASTNode, builtins: &Environment) -> bool {
+    !stmt.any_node(&|node| match node {
+        ASTNode::Apply { .. } | ASTNode::QuantumDeclaration { .. } | ASTNode::FunctionDeclaration { .. } |
+        ASTNode::CircuitDeclaration { .. } | ASTNode::GateDeclaration { .. } | ASTNode::Import { .. } |
+        ASTNode::FromImport { .. } => true,
+        ASTNode::FunctionCall { callee, .. } => match &**callee {
+            ASTNode::Identifier { name, .. } => !matches!(
+                builtins.get(name).map(|value| value.borrow().clone()),
+                Some(RuntimeValue::BuiltinFunction(_))
+            ),
+            _ => true,
+        },
+        _ => false,
+    })
+}
+
+// Every qubit of every top-level register, in declaration order.
+fn resolve_layout(registers: &[&str], env: &Rc<RefCell<Environment>>) -> Result<Layout, String> {
+    let mut layout = Vec::new();
+    for name in registers {
+        let value = env.borrow().get(name)
+            .ok_or_else(|| format!("Quantum register '{}' is not defined", name))?;
+        let qubits = match &*value.borrow() {
+            RuntimeValue::QuantumRegister { size, state } => {
+                let (state, offset) = state.resolve();
+                (0..*size).map(|i| (state.clone(), offset + i)).collect()
+            }
+            other => return Err(format!("'{}' is no longer a quantum register (found {})", name, other.type_name())),
+        };
+        layout.push((name.to_string(), qubits));
+    }
+    Ok(layout)
+}
+
+fn measured_qubits(program: &ASTNode, layout: &Layout) -> Vec<(StateRef, usize)> {
+    let all_qubits = || layout.iter().flat_map(|(_, qubits)| qubits.iter().cloned()).collect::<Vec<_>>();
+    let register = |name: &str| layout.iter().find(|(declared, _)| declared == name).map(|(_, qubits)| qubits);
+
+    let mut targets = Vec::new();
+    collect_measure_targets(program, &mut targets);
+    if targets.is_empty() {
+        return all_qubits();
+    }
+
+    let mut measured: Vec<(StateRef, usize)> = Vec::new();
+    for target in targets {
+        let qubits = match target {
+            ASTNode::Identifier { name, .. } if register(name).is_some() => register(name).cloned().unwrap_or_default(),
+            ASTNode::ArrayAccess { array, index, .. } => match (&**array, &**index) {
+                (ASTNode::Identifier { name, .. }, ASTNode::IntLiteral(i)) if register(name).is_some() => {
+                    register(name).and_then(|qubits| qubits.get(*i as usize)).cloned().into_iter().collect()
+                }
+                _ => return all_qubits(),
+            },
+            _ => return all_qubits(),
+        };
+        for (state, index) in qubits {
+            if !measured.iter().any(|(s, i)| Rc::ptr_eq(s, &state) && *i == index) {
+                measured.push((state, index));
+            }
+        }
+    }
+    measured
+}
+
+fn collect_measure_targets<'a>(node: &'a ASTNode, targets: &mut Vec<&'a ASTNode>) {
+    if let ASTNode::Measure(target) = node {
+        targets.push(target);
+    }
+    for child in node.children() {
+        collect_measure_targets(child, targets);
+    }
+}
+
+// Draw `shots` bitstrings from the final states without disturbing them. Qubits that
+// share a state are sampled jointly so their correlations are kept.
+fn sample_final_states(measured: &[(StateRef, usize)], shots: u32) -> Result<Vec<String>, String> {
+    let mut states: Vec<StateRef> = Vec::new();
+    for (state, _) in measured {
+        if !states.iter().any(|s| Rc::ptr_eq(s, state)) {
+            states.push(state.clone());
+        }
+    }
+    let distributions = states.iter()
+        .map(|s| {
+            let state = s.borrow();
+            if state.is_expandable() { state.probabilities().map(Some) } else { Ok(None) }
+        })
+        .collect::<Result<Vec<Option<Vec<(usize, f64)>>>, String>>()?;
+
+    let mut results = Vec::with_capacity(shots as usize);
+    for _ in 0..shots {
+        let outcomes: Vec<Vec<usize>> = states.iter().zip(&distributions)
+            .map(|(state, distribution)| sample_outcome(state, distribution.as_deref()))
+            .collect();
+        let bits: String = measured.iter()
+            .map(|(state, index)| {
+                let slot = states.iter().position(|s| Rc::ptr_eq(s, state)).unwrap_or(0);
+                let mut bit = outcomes[slot][*index];
+                let (p01, p10) = state.borrow().readout_error(*index);
+                let flip = if bit == 0 { p01 } else { p10 };
+                if flip > 0.0 && environment::with_rng(|rng| rng.gen::<f64>()) < flip {
+                    bit ^= 1;
+                }
+                if bit == 1 { '1' } else { '0' }
+            })
+            .collect();
+        results.push(bits);
+    }
+    Ok(results)
+}
+
+// One bit per qubit of a basis state drawn from `distribution`. States too large to
+// expand have no distribution and are measured one qubit at a time on a copy instead.
+fn sample_outcome(state: &StateRef, distribution: Option<&[(usize, f64)]>) -> Vec<usize> {
+    let total = state.borrow().num_qubits();
+    if let Some(distribution @ &[.., (last, _)]) = distribution {
+        let r: f64 = environment::with_rng(|rng| rng.gen());
+        let mut cumulative = 0.0;
+        let basis_state = distribution.iter()
+            .find(|(_, p)| {
+                cumulative += p;
+                r < cumulative
+            })
+            .map_or(last, |(basis_state, _)| *basis_state);
+        return (0..total).map(|qubit| (basis_state >> qubit) & 1).collect();
+    }
+
+    let mut copy = state.borrow().clone();
+    environment::with_rng(|rng| {
+        (0..total).map(|qubit| {
+            let p0 = copy.probability_zero(qubit);
+            let bit = if rng.gen::<f64>() < p0 { 0 } else { 1 };
+            let p = if bit == 0 { p0 } else { 1.0 - p0 };
+            let norm = if p.abs() < 1e-9 { 1.0 } else { 1.0 / p.sqrt() };
+            copy.collapse(qubit, bit, norm);
+            bit
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> ASTNode {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_sampled_shots_match_bell_correlations() {
+        let program = parse("quantum q[2]\napply Hadamard(q[0])\napply CNOT(q[0], q[1])\nlet a = measure(q[0])\nlet b = measure(q[1])\n");
+        environment::set_seed(11);
+        let result = run_shots(&program, 200).unwrap();
+        assert_eq!(result.counts.values().sum::<u32>(), 200);
+        assert!(result.counts.keys().all(|bits| bits == "00" || bits == "11"));
+        assert_eq!(result.counts.len(), 2);
+    }
+
+    #[test]
+    fn test_feedback_reruns_every_shot() {
+        let program = parse("quantum q[2]\napply Hadamard(q[0])\nlet a = measure(q[0])\nif a == 1:\n    apply X(q[1])\nlet b = measure(q[1])\n");
+        environment::set_seed(5);
+        let result = run_shots(&program, 100).unwrap();
+        assert_eq!(result.counts.values().sum::<u32>(), 100);
+        assert!(result.counts.keys().all(|bits| bits == "00" || bits == "11"));
+    }
+}
@@ -0,0 +1,90 @@
+// src/simulator/dense.rs
+use super::{C64, AMPLITUDE_EPSILON};
+use super::unitary::{Unitary, target_offsets};
+
+/// Full 2^n amplitude vector, indexed by basis state.
+#[derive(Debug, Clone)]
+pub struct DenseState {
+    pub num_qubits: usize,
+    pub amplitudes: Vec<C64>,
+}
+
+impl DenseState {
+    pub fn from_amplitudes(num_qubits: usize, amps: Vec<(usize, C64)>) -> Self {
+        let mut amplitudes = vec![C64::new(0.0, 0.0); 1 << num_qubits];
+        for (index, amp) in amps {
+            amplitudes[index] = amp;
+        }
+        DenseState { num_qubits, amplitudes }
+    }
+
+    pub fn nonzero_count(&self) -> usize {
+        self.amplitudes.iter().filter(|amp| amp.norm_sqr() > AMPLITUDE_EPSILON).count()
+    }
+
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, control_mask: usize) {
+        let target_mask = 1 << target;
+
+        for idx0 in 0..self.amplitudes.len() {
+            if (idx0 & target_mask) != 0 || (idx0 & control_mask) != control_mask {
+                continue;
+            }
+            let idx1 = idx0 | target_mask;
+            let amp0 = self.amplitudes[idx0];
+            let amp1 = self.amplitudes[idx1];
+            self.amplitudes[idx0] = matrix[0][0] * amp0 + matrix[0][1] * amp1;
+            self.amplitudes[idx1] = matrix[1][0] * amp0 + matrix[1][1] * amp1;
+        }
+    }
+
+    /// Apply a k-qubit gate to `targets` wherever all bits in `control_mask` are set.
+    pub fn apply_unitary(&mut self, gate: &Unitary, targets: &[usize], control_mask: usize) {
+        let offsets = target_offsets(targets);
+        let target_mask = offsets[offsets.len() - 1];
+        let dim = gate.dim();
+        let mut block = vec![C64::new(0.0, 0.0); dim];
+
+        for base in 0..self.amplitudes.len() {
+            if (base & target_mask) != 0 || (base & control_mask) != control_mask {
+                continue;
+            }
+            for (slot, &offset) in block.iter_mut().zip(offsets.iter()) {
+                *slot = self.amplitudes[base | offset];
+            }
+            for (row, &offset) in offsets.iter().enumerate() {
+                self.amplitudes[base | offset] = (0..dim).map(|col| gate.get(row, col) * block[col]).sum();
+            }
+        }
+    }
+
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        let mask_a = 1 << qubit_a;
+        let mask_b = 1 << qubit_b;
+
+        for basis_state in 0..self.amplitudes.len() {
+            // Visit each swapped pair once, from the |..1..0..> side.
+            if (basis_state & mask_a) != 0 && (basis_state & mask_b) == 0 {
+                self.amplitudes.swap(basis_state, basis_state ^ mask_a ^ mask_b);
+            }
+        }
+    }
+
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        let target_mask = 1 << target;
+        self.amplitudes.iter()
+            .enumerate()
+            .filter(|(basis_state, _)| (basis_state & target_mask) == 0)
+            .map(|(_, amp)| amp.norm_sqr())
+            .sum()
+    }
+
+    pub fn collapse(&mut self, target: usize, outcome: usize, norm_factor: f64) {
+        for (basis_state, amp) in self.amplitudes.iter_mut().enumerate() {
+            if ((basis_state >> target) & 1) == outcome {
+                *amp *= norm_factor;
+            } else {
+                *amp = C64::new(0.0, 0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+// src/simulator/density.rs
+use super::C64;
+use super::unitary::{Unitary, target_offsets};
+
+/// Row-major 2^n x 2^n density matrix.
+#[derive(Debug, Clone)]
+pub struct DensityMatrix {
+    pub num_qubits: usize,
+    pub rho: Vec<C64>,
+}
+
+impl DensityMatrix {
+    /// |psi><psi| for the given (sparse) pure state.
+    pub fn from_amplitudes(num_qubits: usize, amps: Vec<(usize, C64)>) -> Self {
+        let dim = 1 << num_qubits;
+        let mut rho = vec![C64::new(0.0, 0.0); dim * dim];
+        for &(i, amp_i) in amps.iter() {
+            for &(j, amp_j) in amps.iter() {
+                rho[i * dim + j] = amp_i * amp_j.conj();
+            }
+        }
+        DensityMatrix { num_qubits, rho }
+    }
+
+    pub fn dim(&self) -> usize {
+        1 << self.num_qubits
+    }
+
+    /// Diagonal entries, i.e. the probability of each basis state.
+    pub fn populations(&self) -> Vec<f64> {
+        let dim = self.dim();
+        (0..dim).map(|i| self.rho[i * dim + i].re).collect()
+    }
+
+    /// rho -> U rho U^dagger for a (controlled) single-qubit U.
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, control_mask: usize) {
+        self.apply_left(matrix, target, control_mask);
+        let conj = [[matrix[0][0].conj(), matrix[0][1].conj()],
+                    [matrix[1][0].conj(), matrix[1][1].conj()]];
+        self.apply_right(&conj, target, control_mask);
+    }
+
+    /// rho -> U rho U^dagger for a (controlled) k-qubit U.
+    pub fn apply_unitary(&mut self, gate: &Unitary, targets: &[usize], control_mask: usize) {
+        let dim = self.dim();
+        let offsets = target_offsets(targets);
+        let target_mask = offsets[offsets.len() - 1];
+        let k_dim = gate.dim();
+        let mut block = vec![C64::new(0.0, 0.0); k_dim];
+
+        for base in 0..dim {
+            if (base & target_mask) != 0 || (base & control_mask) != control_mask {
+                continue;
+            }
+            // U acting on the row index, column by column.
+            for col in 0..dim {
+                for (slot, &offset) in block.iter_mut().zip(offsets.iter()) {
+                    *slot = self.rho[(base | offset) * dim + col];
+                }
+                for (r, &offset) in offsets.iter().enumerate() {
+                    self.rho[(base | offset) * dim + col] = (0..k_dim).map(|c| gate.get(r, c) * block[c]).sum();
+                }
+            }
+            // conj(U) acting on the column index, row by row.
+            for row in 0..dim {
+                for (slot, &offset) in block.iter_mut().zip(offsets.iter()) {
+                    *slot = self.rho[row * dim + (base | offset)];
+                }
+                for (r, &offset) in offsets.iter().enumerate() {
+                    self.rho[row * dim + (base | offset)] = (0..k_dim).map(|c| gate.get(r, c).conj() * block[c]).sum();
+                }
+            }
+        }
+    }
+
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        let dim = self.dim();
+        let mask_a = 1 << qubit_a;
+        let mask_b = 1 << qubit_b;
+        let permute = |i: usize| {
+            if ((i & mask_a) != 0) != ((i & mask_b) != 0) { i ^ mask_a ^ mask_b } else { i }
+        };
+        let mut new_rho = vec![C64::new(0.0, 0.0); dim * dim];
+        for i in 0..dim {
+            for j in 0..dim {
+                new_rho[permute(i) * dim + permute(j)] = self.rho[i * dim + j];
+            }
+        }
+        self.rho = new_rho;
+    }
+
+    /// rho -> sum_k K rho K^dagger for single-qubit Kraus operators acting on `target`.
+    pub fn apply_kraus(&mut self, operators: &[[[C64; 2]; 2]], target: usize) {
+        let mut result = vec![C64::new(0.0, 0.0); self.rho.len()];
+        for op in operators {
+            let mut term = self.clone();
+            term.apply_single_qubit(op, target, 0);
+            for (acc, value) in result.iter_mut().zip(term.rho.iter()) {
+                *acc += value;
+            }
+        }
+        self.rho = result;
+    }
+
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        let target_mask = 1 << target;
+        self.populations().iter()
+            .enumerate()
+            .filter(|(basis_state, _)| (basis_state & target_mask) == 0)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// Project onto `outcome` for `target`; `norm_factor` is the amplitude
+    /// normalisation 1/sqrt(p), so the matrix is scaled by its square.
+    pub fn collapse(&mut self, target: usize, outcome: usize, norm_factor: f64) {
+        let dim = self.dim();
+        let scale = norm_factor * norm_factor;
+        for i in 0..dim {
+            for j in 0..dim {
+                let keep = ((i >> target) & 1) == outcome && ((j >> target) & 1) == outcome;
+                let entry = &mut self.rho[i * dim + j];
+                *entry = if keep { *entry * scale } else { C64::new(0.0, 0.0) };
+            }
+        }
+    }
+
+    /// Kronecker product `self ⊗ other`, `self` on the high-order qubits.
+    pub fn tensor(&self, other: &DensityMatrix) -> DensityMatrix {
+        let dim_a = self.dim();
+        let dim_b = other.dim();
+        let dim = dim_a * dim_b;
+        let mut rho = vec![C64::new(0.0, 0.0); dim * dim];
+        for i_a in 0..dim_a {
+            for j_a in 0..dim_a {
+                let a = self.rho[i_a * dim_a + j_a];
+                if a.norm_sqr() == 0.0 {
+                    continue;
+                }
+                for i_b in 0..dim_b {
+                    for j_b in 0..dim_b {
+                        let row = i_a * dim_b + i_b;
+                        let col = j_a * dim_b + j_b;
+                        rho[row * dim + col] = a * other.rho[i_b * dim_b + j_b];
+                    }
+                }
+            }
+        }
+        DensityMatrix { num_qubits: self.num_qubits + other.num_qubits, rho }
+    }
+
+    // U acting on the row index: every column is transformed like a state vector.
+    fn apply_left(&mut self, matrix: &[[C64; 2]; 2], target: usize, control_mask: usize) {
+        let dim = self.dim();
+        let target_mask = 1 << target;
+        for i0 in 0..dim {
+            if (i0 & target_mask) != 0 || (i0 & control_mask) != control_mask {
+                continue;
+            }
+            let i1 = i0 | target_mask;
+            for col in 0..dim {
+                let a0 = self.rho[i0 * dim + col];
+                let a1 = self.rho[i1 * dim + col];
+                self.rho[i0 * dim + col] = matrix[0][0] * a0 + matrix[0][1] * a1;
+                self.rho[i1 * dim + col] = matrix[1][0] * a0 + matrix[1][1] * a1;
+            }
+        }
+    }
+
+    // conj(U) acting on the column index, which is rho * U^dagger.
+    fn apply_right(&mut self, conj: &[[C64; 2]; 2], target: usize, control_mask: usize) {
+        let dim = self.dim();
+        let target_mask = 1 << target;
+        for j0 in 0..dim {
+            if (j0 & target_mask) != 0 || (j0 & control_mask) != control_mask {
+                continue;
+            }
+            let j1 = j0 | target_mask;
+            for row in 0..dim {
+                let a0 = self.rho[row * dim + j0];
+                let a1 = self.rho[row * dim + j1];
+                self.rho[row * dim + j0] = conj[0][0] * a0 + conj[0][1] * a1;
+                self.rho[row * dim + j1] = conj[1][0] * a0 + conj[1][1] * a1;
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// src/simulator/inspect.rs
+use std::collections::HashMap;
+use super::{QuantumState, C64, MAX_DENSE_QUBITS, MAX_DENSITY_QUBITS};
+
+/// Largest subsystem whose fidelity is computed from two mixed density matrices.
+pub const MAX_MIXED_FIDELITY_QUBITS: usize = 6;
+
+/// The state of a group of qubits: a vector when they are in a pure state,
+/// otherwise their reduced density matrix (row-major). Bit `i` of an index
+/// is the `i`-th qubit of the group.
+#[derive(Debug, Clone)]
+pub enum LocalState {
+    Pure(Vec<C64>),
+    Mixed(Vec<C64>),
+}
+
+/// Basis-state probabilities of `qubits`, marginalised over every other qubit.
+pub fn marginal_probabilities(state: &QuantumState, qubits: &[usize]) -> Result<Vec<(usize, f64)>, String> {
+    let full = state.probabilities()?;
+    let mut marginal: HashMap<usize, f64> = HashMap::new();
+    for (basis_state, p) in full {
+        *marginal.entry(gather_bits(basis_state, qubits)).or_insert(0.0) += p;
+    }
+    let mut marginal: Vec<(usize, f64)> = marginal.into_iter().collect();
+    marginal.sort_by_key(|(i, _)| *i);
+    Ok(marginal)
+}
+
+/// Reduced density matrix of `qubits` (trace over all other qubits).
+pub fn reduced_density_matrix(state: &QuantumState, qubits: &[usize]) -> Result<Vec<C64>, String> {
+    if qubits.len() > MAX_DENSITY_QUBITS {
+        return Err(format!("Reduced density matrices are limited to {} qubits (asked for {}).", MAX_DENSITY_QUBITS, qubits.len()));
+    }
+    let dim = 1 << qubits.len();
+    let mut reduced = vec![C64::new(0.0, 0.0); dim * dim];
+    let kept_mask = qubits.iter().fold(0, |mask, &q| mask | (1 << q));
+
+    if let QuantumState::Density(rho) = state {
+        let full_dim = rho.dim();
+        for row in 0..full_dim {
+            let env = row & !kept_mask;
+            let local_row = gather_bits(row, qubits);
+            // Columns sharing the row's environment bits are the kept-bit patterns over `env`.
+            for local_col in 0..dim {
+                let col = qubits.iter().enumerate()
+                    .fold(env, |index, (bit, &q)| index | (((local_col >> bit) & 1) << q));
+                reduced[local_row * dim + local_col] += rho.rho[row * full_dim + col];
+            }
+        }
+        return Ok(reduced);
+    }
+
+    let mut by_environment: HashMap<usize, Vec<(usize, C64)>> = HashMap::new();
+    for (basis_state, amp) in state.nonzero_amplitudes()? {
+        by_environment.entry(basis_state & !kept_mask).or_default().push((gather_bits(basis_state, qubits), amp));
+    }
+    for group in by_environment.values() {
+        for &(i, a) in group {
+            for &(j, b) in group {
+                reduced[i * dim + j] += a * b.conj();
+            }
+        }
+    }
+    Ok(reduced)
+}
+
+/// State of `qubits`, kept as a vector whenever the group is in a pure state.
+pub fn local_state(state: &QuantumState, qubits: &[usize]) -> Result<LocalState, String> {
+    let whole = qubits.len() == state.num_qubits() && qubits.iter().enumerate().all(|(i, &q)| i == q);
+    if whole && !matches!(state, QuantumState::Density(_)) {
+        if qubits.len() > MAX_DENSE_QUBITS {
+            return Err(format!("A {}-qubit register is too large to expand into a state vector.", qubits.len()));
+        }
+        let mut vector = vec![C64::new(0.0, 0.0); 1 << qubits.len()];
+        for (basis_state, amp) in state.nonzero_amplitudes()? {
+            vector[basis_state] = amp;
+        }
+        return Ok(LocalState::Pure(vector));
+    }
+
+    let rho = reduced_density_matrix(state, qubits)?;
+    let dim = 1 << qubits.len();
+    let purity: f64 = rho.iter().map(|entry| entry.norm_sqr()).sum();
+    if (purity - 1.0).abs() > 1e-9 {
+        return Ok(LocalState::Mixed(rho));
+    }
+    // rho = |psi><psi|: any column with a non-zero diagonal is psi up to a global phase.
+    let pivot = (0..dim).max_by(|&a, &b| rho[a * dim + a].re.total_cmp(&rho[b * dim + b].re)).unwrap_or(0);
+    let scale = rho[pivot * dim + pivot].re.sqrt();
+    Ok(LocalState::Pure((0..dim).map(|i| rho[i * dim + pivot] / scale).collect()))
+}
+
+/// Uhlmann fidelity F(a, b) = (Tr sqrt(sqrt(a) b sqrt(a)))², which is |<a|b>|² for pure states.
+pub fn fidelity(a: &LocalState, b: &LocalState) -> Result<f64, String> {
+    let dimension = |s: &LocalState| match s {
+        LocalState::Pure(v) => v.len(),
+        LocalState::Mixed(m) => (m.len() as f64).sqrt() as usize,
+    };
+    let dim = dimension(a);
+    if dimension(b) != dim {
+        return Err(format!(
+            "Cannot compare a {}-qubit state with a {}-qubit state.",
+            dim.trailing_zeros(), dimension(b).trailing_zeros()
+        ));
+    }
+
+    match (a, b) {
+        (LocalState::Pure(psi), LocalState::Pure(phi)) => {
+            let overlap: C64 = psi.iter().zip(phi).map(|(x, y)| x.conj() * y).sum();
+            Ok(overlap.norm_sqr())
+        }
+        (LocalState::Pure(psi), LocalState::Mixed(rho)) | (LocalState::Mixed(rho), LocalState::Pure(psi)) => {
+            let mut value = C64::new(0.0, 0.0);
+            for i in 0..dim {
+                for j in 0..dim {
+                    value += psi[i].conj() * rho[i * dim + j] * psi[j];
+                }
+            }
+            Ok(value.re)
+        }
+        (LocalState::Mixed(rho), LocalState::Mixed(sigma)) => {
+            if dim > 1 << MAX_MIXED_FIDELITY_QUBITS {
+                return Err(format!("Fidelity between two mixed states is limited to {} qubits.", MAX_MIXED_FIDELITY_QUBITS));
+            }
+            // Work with the real symmetric embedding [[Re, -Im], [Im, Re]], which maps
+            // products and square roots of Hermitian matrices onto their own and doubles
+            // every eigenvalue's multiplicity.
+            let sqrt_rho = symmetric_sqrt(&embed(rho, dim), 2 * dim);
+            let product = multiply(&multiply(&sqrt_rho, &embed(sigma, dim), 2 * dim), &sqrt_rho, 2 * dim);
+            let (eigenvalues, _) = jacobi_eigen(&product, 2 * dim);
+            let trace: f64 = eigenvalues.iter().map(|&l| l.max(0.0).sqrt()).sum::<f64>() / 2.0;
+            Ok(trace * trace)
+        }
+    }
+}
+
+// Bit `i` of the result is bit `qubits[i]` of `basis_state`.
+fn gather_bits(basis_state: usize, qubits: &[usize]) -> usize {
+    qubits.iter().enumerate().fold(0, |index, (bit, &q)| index | (((basis_state >> q) & 1) << bit))
+}
+
+fn embed(matrix: &[C64], dim: usize) -> Vec<f64> {
+    let n = 2 * dim;
+    let mut real = vec![0.0; n * n];
+    for i in 0..dim {
+        for j in 0..dim {
+            let entry = matrix[i * dim + j];
+            real[i * n + j] = entry.re;
+            real[(i + dim) * n + j + dim] = entry.re;
+            real[i * n + j + dim] = -entry.im;
+            real[(i + dim) * n + j] = entry.im;
+        }
+    }
+    real
+}
+
+fn multiply(a: &[f64], b: &[f64], n: usize) -> Vec<f64> {
+    let mut product = vec![0.0; n * n];
+    for i in 0..n {
+        for k in 0..n {
+            let a_ik = a[i * n + k];
+            if a_ik != 0.0 {
+                for j in 0..n {
+                    product[i * n + j] += a_ik * b[k * n + j];
+                }
+            }
+        }
+    }
+    product
+}
+
+fn symmetric_sqrt(matrix: &[f64], n: usize) -> Vec<f64> {
+    let (eigenvalues, vectors) = jacobi_eigen(matrix, n);
+    let roots: Vec<f64> = eigenvalues.iter().map(|&l| l.max(0.0).sqrt()).collect();
+    let mut result = vec![0.0; n * n];
+    for i in 0..n {
+        for j in 0..n {
+            result[i * n + j] = (0..n).map(|k| vectors[i * n + k] * roots[k] * vectors[j * n + k]).sum();
+        }
+    }
+    result
+}
+
+// Cyclic Jacobi rotations for a real symmetric matrix. Returns the eigenvalues and
+// the eigenvectors as the columns of a row-major matrix.
+fn jacobi_eigen(matrix: &[f64], n: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut a = matrix.to_vec();
+    let mut v = vec![0.0; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n).flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i * n + j] * a[i * n + j])
+            .sum();
+        if off_diagonal < 1e-22 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let a_pq = a[p * n + q];
+                if a_pq.abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2.0 * a_pq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (a_kp, a_kq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * a_kp - s * a_kq;
+                    a[k * n + q] = s * a_kp + c * a_kq;
+                }
+                for k in 0..n {
+                    let (a_pk, a_qk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * a_pk - s * a_qk;
+                    a[q * n + k] = s * a_pk + c * a_qk;
+                }
+                for k in 0..n {
+                    let (v_kp, v_kq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * v_kp - s * v_kq;
+                    v[k * n + q] = s * v_kp + c * v_kq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i * n + i]).collect(), v)
+}
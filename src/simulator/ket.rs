@@ -0,0 +1,229 @@
+// src/simulator/ket.rs
+use std::collections::BTreeMap;
+use std::f64::consts::FRAC_1_SQRT_2;
+use super::{Unitary, C64, AMPLITUDE_EPSILON, MAX_DENSE_QUBITS};
+
+/// Largest ket that is turned into a dense state-preparation unitary.
+pub const MAX_PREPARATION_QUBITS: usize = 10;
+
+/// A state vector written in ket notation, e.g. `|0+1}` or `0.6*|00} + 0.8*|11}`.
+/// Bit `i` of an index is qubit `i`, so the leftmost label character is bit 0.
+/// Kets are not normalized until they are used to initialize a register.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ket {
+    pub num_qubits: usize,
+    /// Non-zero amplitudes, sorted by basis state.
+    pub amplitudes: Vec<(usize, C64)>,
+}
+
+impl Ket {
+    /// Product state from a label of `0`, `1`, `+` and `-`, one character per qubit.
+    pub fn parse(label: &str) -> Result<Self, String> {
+        if label.is_empty() {
+            return Err("Ket Error: A ket needs at least one qubit.".to_string());
+        }
+        let superposed = label.chars().filter(|c| matches!(c, '+' | '-')).count();
+        if superposed > MAX_DENSE_QUBITS {
+            return Err(format!("Ket Error: |{}}} superposes {} qubits; at most {} can be expanded.", label, superposed, MAX_DENSE_QUBITS));
+        }
+        let h = C64::new(FRAC_1_SQRT_2, 0.0);
+        let mut amplitudes = vec![(0usize, C64::new(1.0, 0.0))];
+        for (qubit, c) in label.chars().enumerate() {
+            let (zero, one) = match c {
+                '0' => (C64::new(1.0, 0.0), C64::new(0.0, 0.0)),
+                '1' => (C64::new(0.0, 0.0), C64::new(1.0, 0.0)),
+                '+' => (h, h),
+                '-' => (h, -h),
+                _ => return Err(format!("Ket Error: Invalid ket label '{}' (expected only 0, 1, + and -).", label)),
+            };
+            amplitudes = amplitudes.into_iter()
+                .flat_map(|(index, amp)| [(index, amp * zero), (index | (1 << qubit), amp * one)])
+                .filter(|(_, amp)| amp.norm_sqr() > 0.0)
+                .collect();
+        }
+        amplitudes.sort_by_key(|(index, _)| *index);
+        Ok(Ket { num_qubits: label.chars().count(), amplitudes })
+    }
+
+    pub fn scale(&self, factor: C64) -> Ket {
+        Ket::from_terms(self.num_qubits, self.amplitudes.iter().map(|&(i, amp)| (i, amp * factor)))
+    }
+
+    pub fn add(&self, other: &Ket) -> Result<Ket, String> {
+        self.check_width(other, "add")?;
+        Ok(Ket::from_terms(self.num_qubits, self.amplitudes.iter().chain(&other.amplitudes).copied()))
+    }
+
+    /// `self ⊗ other`: the qubits of `self` come first.
+    pub fn tensor(&self, other: &Ket) -> Result<Ket, String> {
+        let num_qubits = self.num_qubits + other.num_qubits;
+        if num_qubits > usize::BITS as usize {
+            return Err(format!("Ket Error: A {}-qubit ket cannot be indexed.", num_qubits));
+        }
+        let amplitudes = self.amplitudes.iter()
+            .flat_map(|&(i, a)| other.amplitudes.iter().map(move |&(j, b)| (i | (j << self.num_qubits), a * b)));
+        Ok(Ket::from_terms(num_qubits, amplitudes))
+    }
+
+    /// ⟨self|other⟩, conjugating the amplitudes of `self`.
+    pub fn inner(&self, other: &Ket) -> Result<C64, String> {
+        self.check_width(other, "take the inner product of")?;
+        let theirs: BTreeMap<usize, C64> = other.amplitudes.iter().copied().collect();
+        Ok(self.amplitudes.iter()
+            .filter_map(|(i, a)| theirs.get(i).map(|b| a.conj() * b))
+            .sum())
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.amplitudes.iter().map(|(_, amp)| amp.norm_sqr()).sum::<f64>().sqrt()
+    }
+
+    pub fn normalized(&self) -> Result<Ket, String> {
+        let norm = self.norm();
+        if norm * norm < AMPLITUDE_EPSILON {
+            return Err("Ket Error: Cannot normalize a zero vector.".to_string());
+        }
+        Ok(self.scale(C64::new(1.0 / norm, 0.0)))
+    }
+
+    /// A unitary taking |0...0> to the normalized ket, with qubit 0 as the first target.
+    /// It is a Householder reflection times a global phase.
+    pub fn preparation(&self) -> Result<Unitary, String> {
+        if self.num_qubits > MAX_PREPARATION_QUBITS {
+            return Err(format!("Ket Error: State preparation is limited to {} qubits (ket has {}).", MAX_PREPARATION_QUBITS, self.num_qubits));
+        }
+        let n = self.num_qubits;
+        let dim = 1 << n;
+        // Unitary indices put qubit 0 in the most significant bit.
+        let reverse = |index: usize| (0..n).fold(0, |r, q| r | (((index >> q) & 1) << (n - 1 - q)));
+        let mut psi = vec![C64::new(0.0, 0.0); dim];
+        for (index, amp) in self.normalized()?.amplitudes {
+            psi[reverse(index)] = amp;
+        }
+
+        let phase = if psi[0].norm_sqr() > AMPLITUDE_EPSILON { psi[0] / psi[0].norm() } else { C64::new(1.0, 0.0) };
+        let mut w: Vec<C64> = psi.iter().map(|amp| -amp / phase).collect();
+        w[0] += 1.0;
+        let w_norm: f64 = w.iter().map(|x| x.norm_sqr()).sum();
+
+        let mut data = vec![C64::new(0.0, 0.0); dim * dim];
+        for row in 0..dim {
+            data[row * dim + row] = phase;
+            if w_norm > AMPLITUDE_EPSILON {
+                for col in 0..dim {
+                    data[row * dim + col] -= phase * 2.0 * w[row] * w[col].conj() / w_norm;
+                }
+            }
+        }
+        Ok(Unitary { num_qubits: n, data })
+    }
+
+    /// Gates preparing the normalized ket from |0...0>, as (register qubits, unitary)
+    /// pairs: one single-qubit rotation per qubit of a product state, otherwise a
+    /// single unitary over the whole register.
+    pub fn preparation_steps(&self) -> Result<Vec<(Vec<usize>, Unitary)>, String> {
+        let ket = self.normalized()?;
+        let Some(factors) = ket.product_factors() else {
+            return Ok(vec![((0..ket.num_qubits).collect(), ket.preparation()?)]);
+        };
+        Ok(factors.into_iter().enumerate()
+            .filter(|(_, [_, one])| one.norm_sqr() > AMPLITUDE_EPSILON)
+            .map(|(q, [zero, one])| (vec![q], Unitary::single([[zero, -one.conj()], [one, zero.conj()]])))
+            .collect())
+    }
+
+    /// Split the ket into single-qubit states `[a0, a1]` if it is a product state.
+    pub fn product_factors(&self) -> Option<Vec<[C64; 2]>> {
+        let &(first, first_amp) = self.amplitudes.first()?;
+        let lookup: BTreeMap<usize, C64> = self.amplitudes.iter().copied().collect();
+        let amp = |index: usize| lookup.get(&index).copied().unwrap_or(C64::new(0.0, 0.0));
+        // `first` is the smallest index, so clearing one of its bits gives a zero amplitude.
+        let factors: Vec<[C64; 2]> = (0..self.num_qubits)
+            .map(|q| [amp(first & !(1 << q)), amp(first | (1 << q))])
+            .collect();
+
+        let support: usize = factors.iter()
+            .map(|[zero, one]| (zero.norm_sqr() > 0.0) as usize + (one.norm_sqr() > 0.0) as usize)
+            .product();
+        if support != self.amplitudes.len() {
+            return None;
+        }
+        let scale = first_amp.norm();
+        let tolerance = AMPLITUDE_EPSILON.sqrt() * scale;
+        let is_product = self.amplitudes.iter().all(|&(index, value)| {
+            let predicted = factors.iter().enumerate()
+                .fold(first_amp, |acc, (q, f)| acc * f[(index >> q) & 1] / f[(first >> q) & 1]);
+            (predicted - value).norm() <= tolerance
+        });
+        if !is_product {
+            return None;
+        }
+        Some(factors.into_iter()
+            .map(|[zero, one]| {
+                let norm = (zero.norm_sqr() + one.norm_sqr()).sqrt();
+                [zero / norm, one / norm]
+            })
+            .collect())
+    }
+
+    fn check_width(&self, other: &Ket, action: &str) -> Result<(), String> {
+        if self.num_qubits != other.num_qubits {
+            return Err(format!("Ket Error: Cannot {} a {}-qubit ket and a {}-qubit ket.", action, self.num_qubits, other.num_qubits));
+        }
+        Ok(())
+    }
+
+    fn from_terms(num_qubits: usize, amplitudes: impl Iterator<Item = (usize, C64)>) -> Ket {
+        let mut summed: BTreeMap<usize, C64> = BTreeMap::new();
+        for (index, amp) in amplitudes {
+            *summed.entry(index).or_insert(C64::new(0.0, 0.0)) += amp;
+        }
+        let amplitudes = summed.into_iter().filter(|(_, amp)| amp.norm_sqr() > AMPLITUDE_EPSILON).collect();
+        Ket { num_qubits, amplitudes }
+    }
+}
+
+/// The dual of a ket, written `{0|` or `0.6*{00| + 0.8*{11|`. The wrapped ket is
+/// the one it conjugates, so `Bra(k).0.inner(&other)` is the bra-ket product.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bra(pub Ket);
+
+impl Ket {
+    fn write_terms(&self, f: &mut std::fmt::Formatter<'_>, as_bra: bool) -> std::fmt::Result {
+        if self.amplitudes.is_empty() {
+            return write!(f, "0");
+        }
+        for (i, (index, amp)) in self.amplitudes.iter().enumerate() {
+            let amp = if as_bra { amp.conj() } else { *amp };
+            let label: String = (0..self.num_qubits).map(|q| if (index >> q) & 1 == 1 { '1' } else { '0' }).collect();
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            if (amp - 1.0).norm() > 1e-12 {
+                if amp.im.abs() < 1e-12 {
+                    write!(f, "{:.4}", amp.re)?;
+                } else {
+                    write!(f, "({:.4}{:+.4}i)", amp.re, amp.im)?;
+                }
+            }
+            if as_bra {
+                write!(f, "{{{}|", label)?;
+            } else {
+                write!(f, "|{}}}", label)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Ket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_terms(f, false)
+    }
+}
+
+impl std::fmt::Display for Bra {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.write_terms(f, true)
+    }
+}
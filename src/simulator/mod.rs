@@ -0,0 +1,889 @@
+// src/simulator/mod.rs
+mod sparse;
+mod dense;
+mod density;
+mod stabilizer;
+pub mod mps;
+mod unitary;
+pub mod noise;
+mod observable;
+mod inspect;
+mod ket;
+
+pub use sparse::SparseState;
+pub use dense::DenseState;
+pub use density::DensityMatrix;
+pub use stabilizer::StabilizerState;
+pub use unitary::Unitary;
+pub use mps::{MpsState, MAX_MPS_EXPAND_QUBITS, set_mps_config, mps_config, total_truncation_error};
+pub use noise::{NoiseModel, set_noise_model, noise_model};
+pub use observable::{Observable, basis_rotation};
+pub use inspect::{LocalState, fidelity, local_state, marginal_probabilities, reduced_density_matrix};
+pub use ket::{Bra, Ket};
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use num_complex::Complex;
+
+pub type C64 = Complex<f64>;
+
+/// Amplitudes with a squared norm below this are treated as zero.
+pub const AMPLITUDE_EPSILON: f64 = 1e-12;
+
+/// Largest register the dense backend will allocate (2^26 amplitudes = 1 GiB).
+pub const MAX_DENSE_QUBITS: usize = 26;
+pub const MAX_SPARSE_QUBITS: usize = 32;
+/// A density matrix squares the dense footprint (4^12 entries = 256 MiB).
+pub const MAX_DENSITY_QUBITS: usize = 12;
+/// The tableau needs 2n rows of 2n bits (4096 qubits is about 8 MiB).
+pub const MAX_STABILIZER_QUBITS: usize = 4096;
+/// Memory per MPS site grows with the bond dimension, not the register size.
+pub const MAX_MPS_QUBITS: usize = 1024;
+
+/// In auto mode, registers up to this size always start dense; larger ones
+/// start as stabilizer tableaux and fall back on the first non-Clifford gate.
+const AUTO_DENSE_QUBITS: usize = 10;
+/// In auto mode, a sparse state is promoted once this fraction of amplitudes is non-zero...
+const DENSE_FILL_RATIO: f64 = 0.125;
+/// ...and a dense state is demoted again when it drops below this.
+const SPARSE_FILL_RATIO: f64 = 0.015625;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateMode {
+    Auto,
+    Sparse,
+    Dense,
+    Density,
+    Stabilizer,
+    Mps,
+}
+
+impl StateMode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "auto" => Ok(StateMode::Auto),
+            "sparse" => Ok(StateMode::Sparse),
+            "dense" => Ok(StateMode::Dense),
+            "density" => Ok(StateMode::Density),
+            "stabilizer" => Ok(StateMode::Stabilizer),
+            "mps" => Ok(StateMode::Mps),
+            _ => Err(format!("Unknown state representation '{}' (expected auto, sparse, dense, density, stabilizer or mps)", name)),
+        }
+    }
+}
+
+static STATE_MODE: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_state_mode(mode: StateMode) {
+    let raw = match mode {
+        StateMode::Auto => 0,
+        StateMode::Sparse => 1,
+        StateMode::Dense => 2,
+        StateMode::Density => 3,
+        StateMode::Stabilizer => 4,
+        StateMode::Mps => 5,
+    };
+    STATE_MODE.store(raw, Ordering::Relaxed);
+}
+
+pub fn state_mode() -> StateMode {
+    match STATE_MODE.load(Ordering::Relaxed) {
+        1 => StateMode::Sparse,
+        2 => StateMode::Dense,
+        3 => StateMode::Density,
+        4 => StateMode::Stabilizer,
+        5 => StateMode::Mps,
+        _ => StateMode::Auto,
+    }
+}
+
+/// Largest register that can be stored as explicit amplitudes in the current mode.
+pub fn max_qubits() -> usize {
+    match state_mode() {
+        StateMode::Dense => MAX_DENSE_QUBITS,
+        StateMode::Density => MAX_DENSITY_QUBITS,
+        _ => MAX_SPARSE_QUBITS,
+    }
+}
+
+
+/// The state of one simulated register. All gate kernels go through here so the
+/// evaluator and the runtime never need to know which representation is in use.
+#[derive(Debug, Clone)]
+pub enum QuantumState {
+    Sparse(SparseState),
+    Dense(DenseState),
+    Density(DensityMatrix),
+    Stabilizer(StabilizerState),
+    Mps(MpsState),
+}
+
+impl QuantumState {
+    pub fn new(num_qubits: usize) -> Result<Self, String> {
+        if state_mode() == StateMode::Mps && num_qubits > 0 {
+            if num_qubits > MAX_MPS_QUBITS {
+                return Err(format!("Register size {} is too large to simulate.", num_qubits));
+            }
+            return Ok(QuantumState::Mps(MpsState::new(num_qubits)));
+        }
+        let use_tableau = match state_mode() {
+            StateMode::Stabilizer => true,
+            StateMode::Auto => num_qubits > AUTO_DENSE_QUBITS,
+            _ => false,
+        };
+        if !use_tableau || num_qubits == 0 {
+            return Self::from_amplitudes(num_qubits, vec![(0, C64::new(1.0, 0.0))]);
+        }
+        if num_qubits > MAX_STABILIZER_QUBITS {
+            return Err(format!("Register size {} is too large to simulate.", num_qubits));
+        }
+        Ok(Self::tableau(StabilizerState::new(num_qubits)))
+    }
+
+    pub fn from_amplitudes(num_qubits: usize, amps: Vec<(usize, C64)>) -> Result<Self, String> {
+        if num_qubits == 0 {
+            return Err("Quantum register size must be > 0".to_string());
+        }
+        if state_mode() == StateMode::Stabilizer {
+            return Self::stabilizer_state(num_qubits, &amps);
+        }
+        if state_mode() == StateMode::Mps {
+            if num_qubits > MAX_MPS_QUBITS {
+                return Err(format!("Register size {} is too large to simulate.", num_qubits));
+            }
+            return Ok(QuantumState::Mps(MpsState::from_amplitudes(num_qubits, amps)?));
+        }
+        if num_qubits > max_qubits() {
+            return Err(format!("Register size {} is too large to simulate.", num_qubits));
+        }
+
+        let mut state = match state_mode() {
+            StateMode::Dense => QuantumState::Dense(DenseState::from_amplitudes(num_qubits, amps)),
+            StateMode::Density => QuantumState::Density(DensityMatrix::from_amplitudes(num_qubits, amps)),
+            StateMode::Auto if num_qubits <= AUTO_DENSE_QUBITS => {
+                QuantumState::Dense(DenseState::from_amplitudes(num_qubits, amps))
+            }
+            _ => QuantumState::Sparse(SparseState::from_amplitudes(num_qubits, amps)),
+        };
+        state.rebalance();
+        Ok(state)
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        match self {
+            QuantumState::Sparse(s) => s.num_qubits,
+            QuantumState::Dense(d) => d.num_qubits,
+            QuantumState::Density(m) => m.num_qubits,
+            QuantumState::Stabilizer(t) => t.num_qubits,
+            QuantumState::Mps(m) => m.num_qubits,
+        }
+    }
+
+    pub fn backend_name(&self) -> &str {
+        match self {
+            QuantumState::Sparse(_) => "sparse",
+            QuantumState::Dense(_) => "dense",
+            QuantumState::Density(_) => "density",
+            QuantumState::Stabilizer(_) => "stabilizer",
+            QuantumState::Mps(_) => "mps",
+        }
+    }
+
+    /// Non-zero amplitudes sorted by basis state. Only defined for pure states.
+    pub fn nonzero_amplitudes(&self) -> Result<Vec<(usize, C64)>, String> {
+        let mut amps: Vec<(usize, C64)> = match self {
+            QuantumState::Sparse(s) => s.amplitudes.iter().map(|(&i, &a)| (i, a)).collect(),
+            QuantumState::Dense(d) => d.amplitudes.iter()
+                .enumerate()
+                .filter(|(_, a)| a.norm_sqr() > AMPLITUDE_EPSILON)
+                .map(|(i, &a)| (i, a))
+                .collect(),
+            QuantumState::Density(_) => {
+                return Err("Amplitudes are not defined for a density-matrix (mixed) state.".to_string());
+            }
+            QuantumState::Stabilizer(t) => {
+                if t.num_qubits > MAX_SPARSE_QUBITS {
+                    return Err(format!("A {}-qubit stabilizer state is too large to expand into amplitudes.", t.num_qubits));
+                }
+                if t.history().is_none() {
+                    return Ok(t.amplitudes());
+                }
+                return Self::replay(t)?.nonzero_amplitudes();
+            }
+            QuantumState::Mps(m) => {
+                if m.num_qubits > MAX_MPS_EXPAND_QUBITS {
+                    return Err(format!("A {}-qubit MPS is too large to expand into amplitudes.", m.num_qubits));
+                }
+                m.to_amplitudes()
+            }
+        };
+        amps.sort_by_key(|(i, _)| *i);
+        Ok(amps)
+    }
+
+    /// Non-zero basis-state probabilities sorted by basis state.
+    pub fn probabilities(&self) -> Result<Vec<(usize, f64)>, String> {
+        match self {
+            QuantumState::Density(m) => Ok(m.populations().into_iter()
+                .enumerate()
+                .filter(|(_, p)| *p > AMPLITUDE_EPSILON)
+                .collect()),
+            _ => Ok(self.nonzero_amplitudes()?.into_iter()
+                .map(|(i, a)| (i, a.norm_sqr()))
+                .collect()),
+        }
+    }
+
+    /// Whether the state is small enough to list its amplitudes or probabilities.
+    pub fn is_expandable(&self) -> bool {
+        match self {
+            QuantumState::Stabilizer(t) => t.num_qubits <= MAX_SPARSE_QUBITS,
+            QuantumState::Mps(m) => m.num_qubits <= MAX_MPS_EXPAND_QUBITS,
+            _ => true,
+        }
+    }
+
+    /// Apply a 2x2 unitary to `target` on every basis state where all `controls` are set.
+    /// Fails only for a non-Clifford gate on a stabilizer register that cannot fall back.
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, controls: &[usize]) -> Result<(), String> {
+        if let QuantumState::Stabilizer(t) = self {
+            match t.apply_single_qubit(matrix, target, controls) {
+                Ok(()) => return Ok(()),
+                Err(reason) => self.leave_stabilizer(reason)?,
+            }
+        }
+        if let QuantumState::Mps(m) = self {
+            m.apply_single_qubit(matrix, target, controls);
+            return Ok(());
+        }
+
+        let control_mask = controls.iter().fold(0, |mask, &q| mask | (1 << q));
+        match self {
+            QuantumState::Sparse(s) => s.apply_single_qubit(matrix, target, control_mask),
+            QuantumState::Dense(d) => d.apply_single_qubit(matrix, target, control_mask),
+            QuantumState::Density(m) => m.apply_single_qubit(matrix, target, control_mask),
+            QuantumState::Stabilizer(_) | QuantumState::Mps(_) => unreachable!(),
+        }
+        // Counting a dense vector costs as much as the gate itself, so dense
+        // states are only re-examined after a measurement.
+        if let QuantumState::Sparse(_) = self {
+            self.rebalance();
+        }
+        Ok(())
+    }
+
+    /// Apply a k-qubit gate to `targets` (first target = most significant bit of
+    /// the gate index) on every basis state where all `controls` are set.
+    /// Targets the gate only uses as controls are peeled off first, so CNOT,
+    /// Toffoli and friends still run through the single-qubit kernels.
+    pub fn apply_unitary(&mut self, gate: &Unitary, targets: &[usize], controls: &[usize]) -> Result<(), String> {
+        let (reduced, peeled) = gate.reduce_controls();
+        let mut all_controls = controls.to_vec();
+        all_controls.extend(peeled.iter().map(|&p| targets[p]));
+        let remaining: Vec<usize> = targets.iter().enumerate()
+            .filter(|(p, _)| !peeled.contains(p))
+            .map(|(_, &q)| q)
+            .collect();
+
+        if let Some(matrix) = reduced.as_single() {
+            return self.apply_single_qubit(&matrix, remaining[0], &all_controls);
+        }
+        if reduced.is_swap() && all_controls.is_empty() {
+            self.apply_swap(remaining[0], remaining[1]);
+            return Ok(());
+        }
+        if let QuantumState::Stabilizer(_) = self {
+            self.leave_stabilizer("multi-qubit gate is not a recognised Clifford operation".to_string())?;
+        }
+
+        let control_mask = all_controls.iter().fold(0, |mask, &q| mask | (1 << q));
+        match self {
+            QuantumState::Sparse(s) => s.apply_unitary(&reduced, &remaining, control_mask),
+            QuantumState::Dense(d) => d.apply_unitary(&reduced, &remaining, control_mask),
+            QuantumState::Density(m) => m.apply_unitary(&reduced, &remaining, control_mask),
+            QuantumState::Mps(m) => m.apply_unitary(&reduced, &remaining, &all_controls),
+            QuantumState::Stabilizer(_) => unreachable!(),
+        }
+        if let QuantumState::Sparse(_) = self {
+            self.rebalance();
+        }
+        Ok(())
+    }
+
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        match self {
+            QuantumState::Sparse(s) => s.apply_swap(qubit_a, qubit_b),
+            QuantumState::Dense(d) => d.apply_swap(qubit_a, qubit_b),
+            QuantumState::Density(m) => m.apply_swap(qubit_a, qubit_b),
+            QuantumState::Stabilizer(t) => t.apply_swap(qubit_a, qubit_b),
+            QuantumState::Mps(m) => m.apply_swap(qubit_a, qubit_b),
+        }
+    }
+
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        match self {
+            QuantumState::Sparse(s) => s.probability_zero(target),
+            QuantumState::Dense(d) => d.probability_zero(target),
+            QuantumState::Density(m) => m.probability_zero(target),
+            QuantumState::Stabilizer(t) => t.probability_zero(target),
+            QuantumState::Mps(m) => m.probability_zero(target),
+        }
+    }
+
+    /// Project `target` onto `outcome` and rescale the remaining amplitudes by `norm_factor`.
+    pub fn collapse(&mut self, target: usize, outcome: usize, norm_factor: f64) {
+        match self {
+            QuantumState::Sparse(s) => s.collapse(target, outcome, norm_factor),
+            QuantumState::Dense(d) => d.collapse(target, outcome, norm_factor),
+            QuantumState::Density(m) => m.collapse(target, outcome, norm_factor),
+            QuantumState::Stabilizer(t) => t.collapse(target, outcome),
+            QuantumState::Mps(m) => m.collapse(target, outcome),
+        }
+        self.rebalance();
+    }
+
+    /// `self ⊗ other`, with `self` occupying the high-order qubits.
+    pub fn tensor(&self, other: &QuantumState) -> Result<QuantumState, String> {
+        if let (QuantumState::Stabilizer(a), QuantumState::Stabilizer(b)) = (self, other) {
+            let combined = a.num_qubits + b.num_qubits;
+            if combined > MAX_STABILIZER_QUBITS {
+                return Err(format!("Register size {} is too large to simulate.", combined));
+            }
+            return Ok(Self::tableau(a.tensor(b)));
+        }
+        if matches!(self, QuantumState::Mps(_)) || matches!(other, QuantumState::Mps(_)) {
+            let combined = self.num_qubits() + other.num_qubits();
+            if combined > MAX_MPS_QUBITS {
+                return Err(format!("Register size {} is too large to simulate.", combined));
+            }
+            return Ok(QuantumState::Mps(self.to_mps()?.tensor(&other.to_mps()?)));
+        }
+        if matches!(self, QuantumState::Density(_)) || matches!(other, QuantumState::Density(_)) {
+            let combined = self.num_qubits() + other.num_qubits();
+            if combined > MAX_DENSITY_QUBITS {
+                return Err(format!("Register size {} is too large to simulate.", combined));
+            }
+            return Ok(QuantumState::Density(self.to_density()?.tensor(&other.to_density()?)));
+        }
+
+        let size_b = other.num_qubits();
+        let right = other.nonzero_amplitudes()?;
+        let mut amps = Vec::new();
+        for (i, amp_a) in self.nonzero_amplitudes()? {
+            for &(j, amp_b) in right.iter() {
+                amps.push(((i << size_b) | j, amp_a * amp_b));
+            }
+        }
+        QuantumState::from_amplitudes(self.num_qubits() + size_b, amps)
+    }
+
+    /// Apply the active noise model's channels after `gate_name` acted on `qubits`.
+    /// Pure-state representations cannot hold mixed states, so they are left untouched.
+    pub fn apply_gate_noise(&mut self, gate_name: &str, qubits: &[usize]) {
+        let rho = match self {
+            QuantumState::Density(m) => m,
+            _ => return,
+        };
+        if let Some(model) = noise_model() {
+            for &qubit in qubits {
+                for channel in model.gate_channels(gate_name, qubit) {
+                    if let Some(operators) = channel.kraus_operators() {
+                        rho.apply_kraus(&operators, qubit);
+                    }
+                }
+            }
+        }
+    }
+
+    /// (P(read 1 | 0), P(read 0 | 1)) for measuring `qubit` under the active noise model.
+    pub fn readout_error(&self, qubit: usize) -> (f64, f64) {
+        match (self, noise_model()) {
+            (QuantumState::Density(_), Some(model)) => model.readout_error(qubit),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    fn to_mps(&self) -> Result<MpsState, String> {
+        match self {
+            QuantumState::Mps(m) => Ok(m.clone()),
+            _ => MpsState::from_amplitudes(self.num_qubits(), self.nonzero_amplitudes()?),
+        }
+    }
+
+    fn to_density(&self) -> Result<DensityMatrix, String> {
+        match self {
+            QuantumState::Density(m) => Ok(m.clone()),
+            _ => Ok(DensityMatrix::from_amplitudes(self.num_qubits(), self.nonzero_amplitudes()?)),
+        }
+    }
+
+    /// A gate the tableau cannot represent: switch to a state vector in auto
+    /// mode if the register is small enough, otherwise report why not.
+    fn leave_stabilizer(&mut self, reason: String) -> Result<(), String> {
+        let num_qubits = self.num_qubits();
+        if state_mode() != StateMode::Auto {
+            return Err(format!("The stabilizer simulator only supports Clifford gates (H, S, CNOT, CZ, X, Y, Z); {}. Use --state=auto to fall back to a state vector.", reason));
+        }
+        if num_qubits > MAX_SPARSE_QUBITS {
+            return Err(format!("Non-Clifford gate on a {}-qubit stabilizer register ({}); only registers up to {} qubits can fall back to a state vector.", num_qubits, reason, MAX_SPARSE_QUBITS));
+        }
+        if let QuantumState::Stabilizer(t) = self {
+            *self = Self::replay(t)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild a stabilizer register as amplitudes by replaying its gate and
+    /// measurement history from |0...0>.
+    fn replay(tableau: &StabilizerState) -> Result<QuantumState, String> {
+        let num_qubits = tableau.num_qubits;
+        let start = vec![(0, C64::new(1.0, 0.0))];
+        let mut state = if num_qubits <= AUTO_DENSE_QUBITS {
+            QuantumState::Dense(DenseState::from_amplitudes(num_qubits, start))
+        } else {
+            QuantumState::Sparse(SparseState::from_amplitudes(num_qubits, start))
+        };
+        let history = tableau.history().ok_or("The stabilizer register kept no gate history to replay.")?;
+        for op in history {
+            match *op {
+                stabilizer::StabilizerOp::Gate(matrix, target, ref controls) => {
+                    state.apply_single_qubit(&matrix, target, controls)?;
+                    state.rebalance_amplitudes();
+                }
+                stabilizer::StabilizerOp::Collapse(target, outcome) => {
+                    let p_zero = state.probability_zero(target);
+                    let p = if outcome == 0 { p_zero } else { 1.0 - p_zero };
+                    if p > AMPLITUDE_EPSILON {
+                        state.collapse(target, outcome, 1.0 / p.sqrt());
+                    }
+                }
+            }
+        }
+        state.rebalance_amplitudes();
+        Ok(state)
+    }
+
+    /// Stabilizer mode can only prepare computational basis states directly.
+    fn stabilizer_state(num_qubits: usize, amps: &[(usize, C64)]) -> Result<QuantumState, String> {
+        if num_qubits > MAX_STABILIZER_QUBITS {
+            return Err(format!("Register size {} is too large to simulate.", num_qubits));
+        }
+        Ok(Self::tableau(StabilizerState::from_amplitudes(num_qubits, amps)?))
+    }
+
+    /// Wrap a tableau, dropping its gate history if it can never be replayed.
+    fn tableau(mut tableau: StabilizerState) -> QuantumState {
+        if state_mode() != StateMode::Auto || tableau.num_qubits > MAX_SPARSE_QUBITS {
+            tableau.forget_history();
+        }
+        QuantumState::Stabilizer(tableau)
+    }
+
+    /// Switch representation when running in auto mode and the fill ratio crosses a threshold.
+    fn rebalance(&mut self) {
+        if state_mode() == StateMode::Auto {
+            self.rebalance_amplitudes();
+        }
+    }
+
+    fn rebalance_amplitudes(&mut self) {
+        if matches!(self, QuantumState::Stabilizer(_) | QuantumState::Mps(_)) {
+            return;
+        }
+        let num_qubits = self.num_qubits();
+        if num_qubits <= AUTO_DENSE_QUBITS {
+            if let QuantumState::Sparse(s) = self {
+                let amps = s.amplitudes.drain().collect();
+                *self = QuantumState::Dense(DenseState::from_amplitudes(num_qubits, amps));
+            }
+            return;
+        }
+
+        let capacity = (1usize << num_qubits) as f64;
+        match self {
+            QuantumState::Sparse(s)
+                if num_qubits <= MAX_DENSE_QUBITS && s.nonzero_count() as f64 / capacity > DENSE_FILL_RATIO =>
+            {
+                let amps = s.amplitudes.drain().collect();
+                *self = QuantumState::Dense(DenseState::from_amplitudes(num_qubits, amps));
+            }
+            QuantumState::Dense(d) if (d.nonzero_count() as f64 / capacity) < SPARSE_FILL_RATIO => {
+                let amps = self.nonzero_amplitudes().unwrap_or_default();
+                *self = QuantumState::Sparse(SparseState::from_amplitudes(num_qubits, amps));
+            }
+            _ => {}
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::noise::NoiseChannel;
+    use super::mps::MpsConfig;
+
+    fn hadamard() -> [[C64; 2]; 2] {
+        let v = 1.0 / std::f64::consts::SQRT_2;
+        [[C64::new(v, 0.0), C64::new(v, 0.0)], [C64::new(v, 0.0), C64::new(-v, 0.0)]]
+    }
+
+    fn pauli_x() -> [[C64; 2]; 2] {
+        [[C64::new(0.0, 0.0), C64::new(1.0, 0.0)], [C64::new(1.0, 0.0), C64::new(0.0, 0.0)]]
+    }
+
+    fn run_bell(mut state: QuantumState) -> Vec<(usize, C64)> {
+        state.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        state.apply_single_qubit(&pauli_x(), 1, &[0]).unwrap();
+        state.nonzero_amplitudes().unwrap()
+    }
+
+    #[test]
+    fn test_sparse_and_dense_agree() {
+        let sparse = run_bell(QuantumState::Sparse(SparseState::from_amplitudes(3, vec![(0, C64::new(1.0, 0.0))])));
+        let dense = run_bell(QuantumState::Dense(DenseState::from_amplitudes(3, vec![(0, C64::new(1.0, 0.0))])));
+
+        assert_eq!(sparse.len(), 2);
+        assert_eq!(sparse.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0b00, 0b11]);
+        for ((i, a), (j, b)) in sparse.iter().zip(dense.iter()) {
+            assert_eq!(i, j);
+            assert!((a - b).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_dense_swap_and_collapse() {
+        let mut state = QuantumState::Dense(DenseState::from_amplitudes(2, vec![(0, C64::new(1.0, 0.0))]));
+        state.apply_single_qubit(&pauli_x(), 0, &[]).unwrap();
+        state.apply_swap(0, 1);
+        assert_eq!(state.nonzero_amplitudes().unwrap()[0].0, 0b10);
+
+        state.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        let p0 = state.probability_zero(0);
+        assert!((p0 - 0.5).abs() < 1e-12);
+        state.collapse(0, 1, 1.0 / (1.0 - p0).sqrt());
+        let amps = state.nonzero_amplitudes().unwrap();
+        assert_eq!(amps.len(), 1);
+        assert_eq!(amps[0].0, 0b11);
+        assert!((amps[0].1.norm() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_density_matrix_noise_channels() {
+        let mut rho = DensityMatrix::from_amplitudes(1, vec![(1, C64::new(1.0, 0.0))]);
+        let damping = NoiseChannel::AmplitudeDamping { gamma: 1.0 }.kraus_operators().unwrap();
+        rho.apply_kraus(&damping, 0);
+        assert!((rho.populations()[0] - 1.0).abs() < 1e-12);
+
+        let mut rho = DensityMatrix::from_amplitudes(2, vec![(0, C64::new(1.0, 0.0))]);
+        rho.apply_single_qubit(&hadamard(), 0, 0);
+        rho.apply_single_qubit(&pauli_x(), 1, 1 << 0);
+        let depolarizing = NoiseChannel::Depolarizing { p: 0.3 }.kraus_operators().unwrap();
+        rho.apply_kraus(&depolarizing, 1);
+        let populations = rho.populations();
+        assert!((populations.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!((populations[0b00] - 0.4).abs() < 1e-12);
+        assert!((populations[0b01] - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stabilizer_matches_dense_marginals() {
+        let s = [[C64::new(1.0, 0.0), C64::new(0.0, 0.0)], [C64::new(0.0, 0.0), C64::new(0.0, 1.0)]];
+        let y = [[C64::new(0.0, 0.0), C64::new(0.0, -1.0)], [C64::new(0.0, 1.0), C64::new(0.0, 0.0)]];
+        let z = [[C64::new(1.0, 0.0), C64::new(0.0, 0.0)], [C64::new(0.0, 0.0), C64::new(-1.0, 0.0)]];
+        let circuit: Vec<([[C64; 2]; 2], usize, Vec<usize>)> = vec![
+            (hadamard(), 0, vec![]), (s, 0, vec![]), (pauli_x(), 1, vec![0]), (hadamard(), 2, vec![]),
+            (z, 1, vec![2]), (y, 2, vec![1]), (hadamard(), 1, vec![]), (s, 1, vec![]), (hadamard(), 1, vec![]),
+        ];
+
+        let mut tableau = QuantumState::Stabilizer(StabilizerState::new(3));
+        let mut dense = QuantumState::Dense(DenseState::from_amplitudes(3, vec![(0, C64::new(1.0, 0.0))]));
+        for (matrix, target, controls) in circuit.iter() {
+            tableau.apply_single_qubit(matrix, *target, controls).unwrap();
+            dense.apply_single_qubit(matrix, *target, controls).unwrap();
+        }
+        assert_eq!(tableau.backend_name(), "stabilizer");
+
+        for q in 0..3 {
+            let p = dense.probability_zero(q);
+            assert!((tableau.probability_zero(q) - p).abs() < 1e-9);
+            let outcome = if p > 0.5 { 0 } else { 1 };
+            let p_outcome = if outcome == 0 { p } else { 1.0 - p };
+            tableau.collapse(q, outcome, 1.0);
+            dense.collapse(q, outcome, 1.0 / p_outcome.sqrt());
+        }
+        assert_eq!(tableau.nonzero_amplitudes().unwrap()[0].0, dense.nonzero_amplitudes().unwrap()[0].0);
+    }
+
+    #[test]
+    fn test_stabilizer_large_ghz_and_non_clifford() {
+        let n = 300;
+        let mut state = QuantumState::Stabilizer(StabilizerState::new(n));
+        state.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        for q in 1..n {
+            state.apply_single_qubit(&pauli_x(), q, &[q - 1]).unwrap();
+        }
+        assert_eq!(state.probability_zero(n - 1), 0.5);
+        state.collapse(0, 1, 1.0);
+        assert!((1..n).all(|q| state.probability_zero(q) == 0.0));
+
+        let t = [[C64::new(1.0, 0.0), C64::new(0.0, 0.0)], [C64::new(0.0, 0.0), C64::from_polar(1.0, std::f64::consts::FRAC_PI_4)]];
+        assert!(state.apply_single_qubit(&t, 0, &[]).is_err());
+
+        // Small registers fall back to a state vector with the same history.
+        let mut small = QuantumState::Stabilizer(StabilizerState::new(2));
+        small.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        small.apply_single_qubit(&pauli_x(), 1, &[0]).unwrap();
+        small.apply_single_qubit(&t, 1, &[]).unwrap();
+        assert_ne!(small.backend_name(), "stabilizer");
+        let amps = small.nonzero_amplitudes().unwrap();
+        assert_eq!(amps.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0b00, 0b11]);
+        assert!((amps[1].1.arg() - std::f64::consts::FRAC_PI_4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stabilizer_history_only_while_replayable() {
+        let QuantumState::Stabilizer(large) = QuantumState::new(MAX_SPARSE_QUBITS + 1).unwrap() else {
+            panic!("large registers start as a tableau");
+        };
+        assert!(large.history().is_none());
+
+        // Without a history the amplitudes come from the generators, up to a global phase.
+        let s = [[C64::new(1.0, 0.0), C64::new(0.0, 0.0)], [C64::new(0.0, 0.0), C64::new(0.0, 1.0)]];
+        let mut replayed = StabilizerState::new(3);
+        for (matrix, target, controls) in [(hadamard(), 0, vec![]), (s, 0, vec![]), (pauli_x(), 2, vec![0]), (hadamard(), 1, vec![])] {
+            replayed.apply_single_qubit(&matrix, target, &controls).unwrap();
+        }
+        replayed.collapse(1, 1);
+        let mut forgotten = replayed.clone();
+        forgotten.forget_history();
+        let expected = QuantumState::Stabilizer(replayed).nonzero_amplitudes().unwrap();
+        let actual = QuantumState::Stabilizer(forgotten).nonzero_amplitudes().unwrap();
+        assert_eq!(actual.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0b010, 0b111]);
+        let phase = expected[0].1 / actual[0].1;
+        for ((i, a), (j, b)) in expected.iter().zip(&actual) {
+            assert_eq!(i, j);
+            assert!((a - b * phase).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_mps_matches_dense_with_routing() {
+        let rz = |theta: f64| [[C64::from_polar(1.0, -theta / 2.0), C64::new(0.0, 0.0)],
+                               [C64::new(0.0, 0.0), C64::from_polar(1.0, theta / 2.0)]];
+        let circuit: Vec<([[C64; 2]; 2], usize, Vec<usize>)> = vec![
+            (hadamard(), 0, vec![]), (hadamard(), 3, vec![]), (pauli_x(), 4, vec![0]),
+            (rz(0.7), 2, vec![4]), (hadamard(), 2, vec![]), (rz(1.3), 1, vec![3, 0]),
+            (pauli_x(), 0, vec![2, 4]), (hadamard(), 4, vec![]), (rz(0.4), 3, vec![1]),
+        ];
+
+        let mut mps = QuantumState::Mps(MpsState::new(5));
+        let mut dense = QuantumState::Dense(DenseState::from_amplitudes(5, vec![(0, C64::new(1.0, 0.0))]));
+        for (i, (matrix, target, controls)) in circuit.iter().enumerate() {
+            mps.apply_single_qubit(matrix, *target, controls).unwrap();
+            dense.apply_single_qubit(matrix, *target, controls).unwrap();
+            if i == 4 {
+                mps.apply_swap(0, 3);
+                dense.apply_swap(0, 3);
+            }
+        }
+
+        let (a, b) = (mps.nonzero_amplitudes().unwrap(), dense.nonzero_amplitudes().unwrap());
+        assert_eq!(a.len(), b.len());
+        for ((i, x), (j, y)) in a.iter().zip(b.iter()) {
+            assert_eq!(i, j);
+            assert!((x - y).norm() < 1e-9);
+        }
+
+        let p0 = dense.probability_zero(2);
+        assert!((mps.probability_zero(2) - p0).abs() < 1e-9);
+        mps.collapse(2, 1, 1.0 / (1.0 - p0).sqrt());
+        dense.collapse(2, 1, 1.0 / (1.0 - p0).sqrt());
+        for q in 0..5 {
+            assert!((mps.probability_zero(q) - dense.probability_zero(q)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_mps_truncation_error() {
+        let config = MpsConfig { max_bond: 1, truncation_threshold: 0.0 };
+        let mut mps = MpsState::with_config(2, config);
+        mps.apply_single_qubit(&hadamard(), 0, &[]);
+        mps.apply_single_qubit(&pauli_x(), 1, &[0]);
+        assert_eq!(mps.max_bond_dimension(), 1);
+        assert!((mps.truncation_error - 0.5).abs() < 1e-9);
+
+        // A product-state circuit on a long chain never needs to truncate.
+        let mut chain = MpsState::with_config(200, MpsConfig::default());
+        for q in 0..200 {
+            chain.apply_single_qubit(&hadamard(), q, &[]);
+        }
+        chain.apply_single_qubit(&pauli_x(), 150, &[10]);
+        assert_eq!(chain.truncation_error, 0.0);
+        assert!((chain.probability_zero(150) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_multi_qubit_unitary_on_every_backend() {
+        // controlled(swap) with its control passed as a target of a 3-qubit gate.
+        let fredkin = Unitary::swap().controlled();
+        let (reduced, peeled) = fredkin.reduce_controls();
+        assert!(reduced.is_swap());
+        assert_eq!(peeled, vec![0]);
+
+        let phase = Unitary::single([[C64::new(1.0, 0.0), C64::new(0.0, 0.0)],
+                                     [C64::new(0.0, 0.0), C64::new(0.0, 1.0)]]);
+        let mut mixer = Unitary::swap();
+        mixer.data[6] = C64::new(0.0, 1.0);
+        mixer.data[9] = C64::new(0.0, 1.0);
+
+        let start = vec![(0b0101, C64::new(1.0, 0.0))];
+        let states = vec![
+            QuantumState::Sparse(SparseState::from_amplitudes(4, start.clone())),
+            QuantumState::Dense(DenseState::from_amplitudes(4, start.clone())),
+            QuantumState::Mps(MpsState::from_amplitudes(4, start.clone()).unwrap()),
+        ];
+        let mut results = Vec::new();
+        for mut state in states {
+            state.apply_single_qubit(&hadamard(), 1, &[]).unwrap();
+            state.apply_unitary(&fredkin, &[0, 1, 3], &[]).unwrap();
+            state.apply_unitary(&phase.controlled(), &[3, 2], &[]).unwrap();
+            state.apply_unitary(&mixer, &[3, 0], &[2]).unwrap();
+            state.apply_unitary(&mixer.dagger(), &[3, 0], &[2]).unwrap();
+            results.push(state.nonzero_amplitudes().unwrap());
+        }
+        assert_eq!(results[0].iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0b0101, 0b1101]);
+        assert!((results[0][1].1 - C64::new(0.0, 1.0 / std::f64::consts::SQRT_2)).norm() < 1e-9);
+        for other in &results[1..] {
+            for ((i, a), (j, b)) in results[0].iter().zip(other.iter()) {
+                assert_eq!(i, j);
+                assert!((a - b).norm() < 1e-9);
+            }
+        }
+
+        let mut rho = DensityMatrix::from_amplitudes(2, vec![(0b01, C64::new(1.0, 0.0))]);
+        rho.apply_unitary(&mixer, &[1, 0], 0);
+        assert!((rho.populations()[0b10] - 1.0).abs() < 1e-12);
+        rho.apply_unitary(&mixer.dagger(), &[1, 0], 0);
+        assert!((rho.populations()[0b01] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pauli_observables_on_every_backend() {
+        let h = Observable::parse("0.5*ZZ + XX - 0.25 * YY + IZ").unwrap();
+        assert_eq!(h.terms, vec![(0.5, "ZZ".to_string()), (1.0, "XX".to_string()), (-0.25, "YY".to_string()), (1.0, "IZ".to_string())]);
+        assert_eq!(Observable::parse("-1e-3*XZ").unwrap().terms, vec![(-1e-3, "XZ".to_string())]);
+        assert!(Observable::parse("ZZ + XYZ").is_err());
+        assert!(Observable::parse("2*AB").is_err());
+
+        let start = vec![(0, C64::new(1.0, 0.0))];
+        let states = vec![
+            QuantumState::Dense(DenseState::from_amplitudes(2, start.clone())),
+            QuantumState::Density(DensityMatrix::from_amplitudes(2, start.clone())),
+            QuantumState::Stabilizer(StabilizerState::new(2)),
+            QuantumState::Mps(MpsState::from_amplitudes(2, start.clone()).unwrap()),
+        ];
+        for mut state in states {
+            state.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+            state.apply_single_qubit(&pauli_x(), 1, &[0]).unwrap();
+            let before = state.probability_zero(0);
+            assert!((h.expectation(&state, &[0, 1]).unwrap() - 1.75).abs() < 1e-9);
+            assert!((h.variance(&state, &[0, 1]).unwrap() - 1.0).abs() < 1e-9);
+            assert_eq!(state.probability_zero(0), before);
+        }
+
+        // Qubit order follows the slice, so the same string can read other qubits.
+        let mut ghz = QuantumState::Stabilizer(StabilizerState::new(300));
+        ghz.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        for q in 1..300 {
+            ghz.apply_single_qubit(&pauli_x(), q, &[q - 1]).unwrap();
+        }
+        let zz = Observable::parse("ZZ").unwrap();
+        assert!((zz.expectation(&ghz, &[0, 299]).unwrap() - 1.0).abs() < 1e-9);
+        assert!(zz.expectation(&ghz, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_ket_algebra_and_state_preparation() {
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let product = Ket::parse("++-").unwrap();
+        assert_eq!(product.amplitudes.len(), 8);
+        assert!((product.amplitudes[4].1 - C64::new(-h * h * h, 0.0)).norm() < 1e-12);
+        assert!((Ket::parse("0").unwrap().inner(&Ket::parse("+").unwrap()).unwrap() - h).norm() < 1e-12);
+        assert!(Ket::parse("01").unwrap().add(&Ket::parse("0").unwrap()).is_err());
+        assert!(Ket::parse("0x").is_err());
+
+        // (|000> + i|111>) / sqrt(2): entangled, but still a stabilizer state.
+        let i = C64::new(0.0, 1.0);
+        let ghz = Ket::parse("000").unwrap().add(&Ket::parse("111").unwrap().scale(i)).unwrap().normalized().unwrap();
+        assert!(ghz.product_factors().is_none());
+        assert_eq!(Ket::parse("+1").unwrap().product_factors().unwrap().len(), 2);
+        assert_eq!(Ket::parse("+1").unwrap().preparation_steps().unwrap().len(), 2);
+
+        let start = vec![(0, C64::new(1.0, 0.0))];
+        let mut prepared = QuantumState::Dense(DenseState::from_amplitudes(3, start.clone()));
+        for (qubits, unitary) in ghz.preparation_steps().unwrap() {
+            prepared.apply_unitary(&unitary, &qubits, &[]).unwrap();
+        }
+        let stabilizer = QuantumState::Stabilizer(StabilizerState::from_amplitudes(3, &ghz.amplitudes).unwrap());
+        let expected = LocalState::Pure((0..8).map(|index| {
+            ghz.amplitudes.iter().find(|(i, _)| *i == index).map_or(C64::new(0.0, 0.0), |(_, a)| *a)
+        }).collect());
+        for state in [&prepared, &stabilizer] {
+            let local = local_state(state, &[0, 1, 2]).unwrap();
+            assert!((fidelity(&local, &expected).unwrap() - 1.0).abs() < 1e-9);
+        }
+        // The relative phase of the prepared state is exact, not just its populations.
+        let amplitudes = prepared.nonzero_amplitudes().unwrap();
+        assert!((amplitudes[1].1 / amplitudes[0].1 - i).norm() < 1e-9);
+
+        let skewed = [(0, C64::new(0.6, 0.0)), (3, C64::new(0.8, 0.0))];
+        assert!(StabilizerState::from_amplitudes(2, &skewed).is_err());
+    }
+
+    #[test]
+    fn test_reduced_states_and_fidelity() {
+        let (zero, one) = (C64::new(0.0, 0.0), C64::new(1.0, 0.0));
+        let mut rho = QuantumState::Density(DensityMatrix::from_amplitudes(3, vec![(0, one)]));
+        rho.apply_single_qubit(&hadamard(), 0, &[]).unwrap();
+        rho.apply_single_qubit(&pauli_x(), 2, &[0]).unwrap();
+        rho.apply_single_qubit(&pauli_x(), 1, &[]).unwrap();
+        let half = C64::new(0.5, 0.0);
+        let close = |a: Vec<C64>, b: Vec<C64>| a.iter().zip(&b).all(|(x, y)| (x - y).norm() < 1e-9);
+        assert!(close(reduced_density_matrix(&rho, &[2]).unwrap(), vec![half, zero, zero, half]));
+        assert!(close(reduced_density_matrix(&rho, &[1]).unwrap(), vec![zero, zero, zero, one]));
+        let marginal = marginal_probabilities(&rho, &[2, 1]).unwrap();
+        assert_eq!(marginal.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0b10, 0b11]);
+        assert!(marginal.iter().all(|(_, p)| (p - 0.5).abs() < 1e-9));
+
+        // Qubit 1 is in |1> on its own, so it comes back as a pure vector.
+        assert!(matches!(local_state(&rho, &[1]).unwrap(), LocalState::Pure(v) if (v[1].norm() - 1.0).abs() < 1e-12));
+        let maximally_mixed = local_state(&rho, &[0]).unwrap();
+        assert!(matches!(maximally_mixed, LocalState::Mixed(_)));
+        assert!((fidelity(&maximally_mixed, &maximally_mixed).unwrap() - 1.0).abs() < 1e-9);
+        assert!((fidelity(&maximally_mixed, &LocalState::Pure(vec![one, zero])).unwrap() - 0.5).abs() < 1e-9);
+
+        let s = std::f64::consts::FRAC_1_SQRT_2;
+        let as_mixed = |psi: [C64; 2]| LocalState::Mixed((0..4).map(|k| psi[k / 2] * psi[k % 2].conj()).collect());
+        let plus_i = as_mixed([C64::new(s, 0.0), C64::new(0.0, s)]);
+        let plus = as_mixed([C64::new(s, 0.0), C64::new(s, 0.0)]);
+        assert!((fidelity(&plus_i, &plus).unwrap() - 0.5).abs() < 1e-9);
+        let biased = LocalState::Mixed(vec![C64::new(0.75, 0.0), zero, zero, C64::new(0.25, 0.0)]);
+        let flipped = LocalState::Mixed(vec![C64::new(0.25, 0.0), zero, zero, C64::new(0.75, 0.0)]);
+        assert!((fidelity(&biased, &flipped).unwrap() - 0.75).abs() < 1e-9);
+        assert!(fidelity(&biased, &LocalState::Pure(vec![one, zero, zero, zero])).is_err());
+    }
+
+    #[test]
+    fn test_noise_model_from_json() {
+        let model = NoiseModel::from_json(r#"{
+            "gates": { "h": [{ "channel": "bit_flip", "p": 0.1 }] },
+            "qubits": { "2": [{ "channel": "readout_error", "p01": 0.01, "p10": 0.02 }] }
+        }"#).unwrap();
+        assert_eq!(model.gate_channels("hadamard", 0), vec![&NoiseChannel::BitFlip { p: 0.1 }]);
+        assert_eq!(model.readout_error(2), (0.01, 0.02));
+        assert_eq!(model.readout_error(0), (0.0, 0.0));
+
+        assert!(NoiseModel::from_json(r#"{ "gates": { "x": [{ "channel": "bit_flip", "p": 1.5 }] } }"#).is_err());
+    }
+}
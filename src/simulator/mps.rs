@@ -0,0 +1,624 @@
+// src/simulator/mps.rs
+use std::sync::RwLock;
+use super::{C64, AMPLITUDE_EPSILON};
+use super::unitary::Unitary;
+
+/// Largest register that can be built from (or expanded into) explicit amplitudes.
+pub const MAX_MPS_EXPAND_QUBITS: usize = 16;
+
+/// Bond-dimension cap and truncation threshold for new MPS registers. Singular
+/// values are discarded from the smallest up while the discarded weight stays
+/// below `truncation_threshold`; beyond that, `max_bond` forces further cuts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpsConfig {
+    pub max_bond: usize,
+    pub truncation_threshold: f64,
+}
+
+impl Default for MpsConfig {
+    fn default() -> Self {
+        DEFAULT_CONFIG
+    }
+}
+
+const DEFAULT_CONFIG: MpsConfig = MpsConfig { max_bond: 64, truncation_threshold: 1e-12 };
+
+static MPS_CONFIG: RwLock<MpsConfig> = RwLock::new(DEFAULT_CONFIG);
+static TRUNCATION_TOTAL: RwLock<f64> = RwLock::new(0.0);
+
+pub fn set_mps_config(config: MpsConfig) {
+    if let Ok(mut guard) = MPS_CONFIG.write() {
+        *guard = config;
+    }
+}
+
+pub fn mps_config() -> MpsConfig {
+    MPS_CONFIG.read().map(|guard| *guard).unwrap_or_default()
+}
+
+/// Discarded weight summed over every MPS register in this process.
+pub fn total_truncation_error() -> f64 {
+    TRUNCATION_TOTAL.read().map(|guard| *guard).unwrap_or(0.0)
+}
+
+fn record_truncation(weight: f64) {
+    if let Ok(mut guard) = TRUNCATION_TOTAL.write() {
+        *guard += weight;
+    }
+}
+
+
+/// Matrix-product state over a chain of sites. Site `i` holds a
+/// (bonds[i], 2, bonds[i + 1]) tensor stored row-major. Logical qubits are
+/// mapped onto sites so swaps are free relabelings and routed gates leave
+/// qubits where they ended up. Sites left of `center` are left-canonical,
+/// sites right of it right-canonical.
+#[derive(Debug, Clone)]
+pub struct MpsState {
+    pub num_qubits: usize,
+    tensors: Vec<Vec<C64>>,
+    bonds: Vec<usize>,
+    site_of: Vec<usize>,
+    qubit_at: Vec<usize>,
+    center: usize,
+    config: MpsConfig,
+    pub truncation_error: f64,
+}
+
+impl MpsState {
+    pub fn new(num_qubits: usize) -> Self {
+        Self::with_config(num_qubits, mps_config())
+    }
+
+    pub fn with_config(num_qubits: usize, config: MpsConfig) -> Self {
+        let zero = vec![C64::new(1.0, 0.0), C64::new(0.0, 0.0)];
+        MpsState {
+            num_qubits,
+            tensors: vec![zero; num_qubits],
+            bonds: vec![1; num_qubits + 1],
+            site_of: (0..num_qubits).collect(),
+            qubit_at: (0..num_qubits).collect(),
+            center: 0,
+            config,
+            truncation_error: 0.0,
+        }
+    }
+
+    pub fn from_amplitudes(num_qubits: usize, amps: Vec<(usize, C64)>) -> Result<Self, String> {
+        let mut state = MpsState::new(num_qubits);
+        let nonzero: Vec<(usize, C64)> = amps.into_iter().filter(|(_, a)| a.norm_sqr() > AMPLITUDE_EPSILON).collect();
+        if let [(index, amp)] = nonzero[..] {
+            for q in 0..num_qubits.min(usize::BITS as usize) {
+                if (index >> q) & 1 == 1 {
+                    state.tensors[q] = vec![C64::new(0.0, 0.0), C64::new(1.0, 0.0)];
+                }
+            }
+            for value in state.tensors[0].iter_mut() {
+                *value *= amp;
+            }
+            return Ok(state);
+        }
+        if num_qubits > MAX_MPS_EXPAND_QUBITS {
+            return Err(format!("Cannot build a {}-qubit MPS from a superposition of basis states (limit {} qubits).", num_qubits, MAX_MPS_EXPAND_QUBITS));
+        }
+
+        // Site 0 is the most significant physical index of the block.
+        let dim = 1usize << num_qubits;
+        let mut theta = vec![C64::new(0.0, 0.0); dim];
+        for (index, amp) in nonzero {
+            let mut block_index = 0;
+            for q in 0..num_qubits {
+                block_index |= ((index >> q) & 1) << (num_qubits - 1 - q);
+            }
+            theta[block_index] = amp;
+        }
+        state.split_block(0, num_qubits, theta);
+        Ok(state)
+    }
+
+    /// Largest bond dimension currently in the chain.
+    pub fn max_bond_dimension(&self) -> usize {
+        self.bonds.iter().cloned().max().unwrap_or(1)
+    }
+
+    /// Apply a (controlled) 2x2 unitary.
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, controls: &[usize]) {
+        if controls.is_empty() {
+            self.apply_local(self.site_of[target], matrix);
+        } else {
+            self.apply_unitary(&Unitary::single(*matrix), &[target], controls);
+        }
+    }
+
+    /// Apply a (controlled) k-qubit gate. Control and target qubits are routed
+    /// onto neighbouring sites with swaps before the gate is contracted in.
+    pub fn apply_unitary(&mut self, gate: &Unitary, targets: &[usize], controls: &[usize]) {
+        let mut qubits: Vec<usize> = controls.to_vec();
+        qubits.extend_from_slice(targets);
+        let start = self.route(&qubits);
+        let m = qubits.len();
+
+        // Full 2^m matrix in block order (the qubit on site `start` is the most significant bit).
+        let dim = 1usize << m;
+        let bit_of = |q: usize| 1usize << (m - 1 - (self.site_of[q] - start));
+        let control_bits: usize = controls.iter().fold(0, |mask, &q| mask | bit_of(q));
+        let target_bits: Vec<usize> = targets.iter().map(|&q| bit_of(q)).collect();
+        let target_mask: usize = target_bits.iter().sum();
+        let k = targets.len();
+        let to_block = |sub: usize| (0..k).filter(|j| (sub >> (k - 1 - j)) & 1 == 1).map(|j| target_bits[j]).sum::<usize>();
+        let from_block = |index: usize| (0..k).fold(0, |sub, j| (sub << 1) | ((index & target_bits[j] != 0) as usize));
+
+        let mut block = vec![C64::new(0.0, 0.0); dim * dim];
+        for col in 0..dim {
+            if col & control_bits != control_bits {
+                block[col * dim + col] = C64::new(1.0, 0.0);
+                continue;
+            }
+            let col_sub = from_block(col);
+            for row_sub in 0..gate.dim() {
+                let row = (col & !target_mask) | to_block(row_sub);
+                block[row * dim + col] = gate.get(row_sub, col_sub);
+            }
+        }
+        self.apply_block(start, m, &block);
+    }
+
+    /// Swapping two qubits only relabels which site holds each of them.
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        let (site_a, site_b) = (self.site_of[qubit_a], self.site_of[qubit_b]);
+        self.site_of.swap(qubit_a, qubit_b);
+        self.qubit_at.swap(site_a, site_b);
+    }
+
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        let site = self.site_of[target];
+        let left = self.left_environment(site);
+        let right = self.right_environment(site);
+        let (l_dim, r_dim) = (self.bonds[site], self.bonds[site + 1]);
+        let tensor = &self.tensors[site];
+
+        let mut weights = [0.0; 2];
+        for (p, weight) in weights.iter_mut().enumerate() {
+            let mut total = C64::new(0.0, 0.0);
+            for l in 0..l_dim {
+                for l2 in 0..l_dim {
+                    let env_l = left[l * l_dim + l2];
+                    if env_l.norm_sqr() == 0.0 {
+                        continue;
+                    }
+                    for r in 0..r_dim {
+                        let a = tensor[(l * 2 + p) * r_dim + r];
+                        for r2 in 0..r_dim {
+                            total += env_l * a * tensor[(l2 * 2 + p) * r_dim + r2].conj() * right[r * r_dim + r2];
+                        }
+                    }
+                }
+            }
+            *weight = total.re;
+        }
+        let norm = weights[0] + weights[1];
+        if norm > 0.0 { weights[0] / norm } else { 0.0 }
+    }
+
+    /// Project `target` onto `outcome` and renormalise.
+    pub fn collapse(&mut self, target: usize, outcome: usize) {
+        let site = self.site_of[target];
+        self.move_center(site);
+        let r_dim = self.bonds[site + 1];
+        let tensor = &mut self.tensors[site];
+        for (index, value) in tensor.iter_mut().enumerate() {
+            if (index / r_dim) % 2 != outcome {
+                *value = C64::new(0.0, 0.0);
+            }
+        }
+        let norm = tensor.iter().map(|v| v.norm_sqr()).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            for value in tensor.iter_mut() {
+                *value /= norm;
+            }
+        }
+    }
+
+    /// `self ⊗ other`, with `self` on the high-order qubits.
+    pub fn tensor(&self, other: &MpsState) -> MpsState {
+        let nb = other.num_qubits;
+        let mut result = self.clone();
+        result.num_qubits += nb;
+        result.tensors.extend(other.tensors.iter().cloned());
+        result.bonds.pop();
+        result.bonds.extend(other.bonds.iter().cloned());
+        result.site_of = other.site_of.iter().map(|&s| s + self.num_qubits)
+            .chain(self.site_of.iter().cloned())
+            .collect();
+        result.qubit_at = self.qubit_at.iter().map(|&q| q + nb)
+            .chain(other.qubit_at.iter().cloned())
+            .collect();
+        result.truncation_error += other.truncation_error;
+
+        // A left-to-right sweep restores canonical form regardless of the inputs.
+        result.center = 0;
+        result.move_center(result.num_qubits - 1);
+        result
+    }
+
+    /// Contract the chain into non-zero amplitudes keyed by logical basis index.
+    pub fn to_amplitudes(&self) -> Vec<(usize, C64)> {
+        let mut psi = vec![C64::new(1.0, 0.0)];
+        for site in 0..self.num_qubits {
+            let (l_dim, r_dim) = (self.bonds[site], self.bonds[site + 1]);
+            let prefixes = psi.len() / l_dim;
+            let mut next = vec![C64::new(0.0, 0.0); prefixes * 2 * r_dim];
+            for prefix in 0..prefixes {
+                for l in 0..l_dim {
+                    let amp = psi[prefix * l_dim + l];
+                    if amp.norm_sqr() == 0.0 {
+                        continue;
+                    }
+                    for p in 0..2 {
+                        for r in 0..r_dim {
+                            next[(prefix * 2 + p) * r_dim + r] += amp * self.tensors[site][(l * 2 + p) * r_dim + r];
+                        }
+                    }
+                }
+            }
+            psi = next;
+        }
+
+        let n = self.num_qubits;
+        psi.into_iter().enumerate()
+            .filter(|(_, a)| a.norm_sqr() > AMPLITUDE_EPSILON)
+            .map(|(block_index, a)| {
+                let mut index = 0;
+                for site in 0..n {
+                    index |= ((block_index >> (n - 1 - site)) & 1) << self.qubit_at[site];
+                }
+                (index, a)
+            })
+            .collect()
+    }
+
+    fn apply_local(&mut self, site: usize, matrix: &[[C64; 2]; 2]) {
+        let (l_dim, r_dim) = (self.bonds[site], self.bonds[site + 1]);
+        let tensor = &mut self.tensors[site];
+        for l in 0..l_dim {
+            for r in 0..r_dim {
+                let a0 = tensor[(l * 2) * r_dim + r];
+                let a1 = tensor[(l * 2 + 1) * r_dim + r];
+                tensor[(l * 2) * r_dim + r] = matrix[0][0] * a0 + matrix[0][1] * a1;
+                tensor[(l * 2 + 1) * r_dim + r] = matrix[1][0] * a0 + matrix[1][1] * a1;
+            }
+        }
+    }
+
+    // Bring `qubits` onto consecutive sites with adjacent swaps, moving each one
+    // towards the leftmost. Returns the first site of the block.
+    fn route(&mut self, qubits: &[usize]) -> usize {
+        let mut order: Vec<usize> = qubits.to_vec();
+        order.sort_by_key(|&q| self.site_of[q]);
+        let start = self.site_of[order[0]];
+        for (offset, &q) in order.iter().enumerate().skip(1) {
+            while self.site_of[q] > start + offset {
+                self.swap_adjacent(self.site_of[q] - 1);
+            }
+        }
+        start
+    }
+
+    fn swap_adjacent(&mut self, site: usize) {
+        self.apply_block(site, 2, &Unitary::swap().data);
+        let (a, b) = (self.qubit_at[site], self.qubit_at[site + 1]);
+        self.qubit_at.swap(site, site + 1);
+        self.site_of[a] = site + 1;
+        self.site_of[b] = site;
+    }
+
+    // Contract sites start..start+m, apply `gate` to the joint physical index and split again.
+    fn apply_block(&mut self, start: usize, m: usize, gate: &[C64]) {
+        self.move_center(start);
+        let mut theta = self.tensors[start].clone();
+        for site in start + 1..start + m {
+            let inner = self.bonds[site];
+            let r_dim = self.bonds[site + 1];
+            let rows = theta.len() / inner;
+            let mut next = vec![C64::new(0.0, 0.0); rows * 2 * r_dim];
+            for row in 0..rows {
+                for k in 0..inner {
+                    let a = theta[row * inner + k];
+                    if a.norm_sqr() == 0.0 {
+                        continue;
+                    }
+                    for col in 0..2 * r_dim {
+                        next[row * 2 * r_dim + col] += a * self.tensors[site][k * 2 * r_dim + col];
+                    }
+                }
+            }
+            theta = next;
+        }
+
+        let (l_dim, r_dim) = (self.bonds[start], self.bonds[start + m]);
+        let dim = 1usize << m;
+        let mut result = vec![C64::new(0.0, 0.0); theta.len()];
+        for l in 0..l_dim {
+            for out in 0..dim {
+                for inp in 0..dim {
+                    let g = gate[out * dim + inp];
+                    if g.norm_sqr() == 0.0 {
+                        continue;
+                    }
+                    for r in 0..r_dim {
+                        result[(l * dim + out) * r_dim + r] += g * theta[(l * dim + inp) * r_dim + r];
+                    }
+                }
+            }
+        }
+        self.split_block(start, m, result);
+    }
+
+    // Split a (bonds[start], 2^m, bonds[start + m]) block back into m site tensors
+    // with truncated SVDs. Leaves the orthogonality center on the last site.
+    fn split_block(&mut self, start: usize, m: usize, theta: Vec<C64>) {
+        let mut current = theta;
+        for site in start..start + m - 1 {
+            let rows = self.bonds[site] * 2;
+            let cols = current.len() / rows;
+            let (u, mut s, vh) = svd(&current, rows, cols);
+            let keep = self.truncate(&mut s);
+            let mut left = vec![C64::new(0.0, 0.0); rows * keep];
+            for row in 0..rows {
+                for k in 0..keep {
+                    left[row * keep + k] = u[row * s.len() + k];
+                }
+            }
+            let mut rest = vec![C64::new(0.0, 0.0); keep * cols];
+            for k in 0..keep {
+                for col in 0..cols {
+                    rest[k * cols + col] = s[k] * vh[k * cols + col];
+                }
+            }
+            self.tensors[site] = left;
+            self.bonds[site + 1] = keep;
+            current = rest;
+        }
+        self.tensors[start + m - 1] = current;
+        self.center = start + m - 1;
+    }
+
+    // Decide how many singular values to keep and renormalise them.
+    fn truncate(&mut self, s: &mut [f64]) -> usize {
+        let total: f64 = s.iter().map(|x| x * x).sum();
+        if total == 0.0 {
+            return 1;
+        }
+        let mut keep = s.iter().take_while(|&&x| x * x > total * 1e-28).count().max(1);
+        let mut discarded = 0.0;
+        while keep > 1 {
+            let weight = s[keep - 1] * s[keep - 1] / total;
+            if discarded + weight > self.config.truncation_threshold && keep <= self.config.max_bond {
+                break;
+            }
+            discarded += weight;
+            keep -= 1;
+        }
+        if discarded > 0.0 {
+            self.truncation_error += discarded;
+            record_truncation(discarded);
+        }
+        let kept: f64 = s[..keep].iter().map(|x| x * x).sum();
+        let scale = (total / kept).sqrt();
+        for value in s[..keep].iter_mut() {
+            *value *= scale;
+        }
+        keep
+    }
+
+    // Shift the orthogonality center with exact (untruncated) SVDs.
+    fn move_center(&mut self, to: usize) {
+        while self.center < to {
+            let site = self.center;
+            let (rows, cols) = (self.bonds[site] * 2, self.bonds[site + 1]);
+            let (u, s, vh) = svd(&self.tensors[site], rows, cols);
+            let k = s.len();
+            let next_cols = 2 * self.bonds[site + 2];
+            let mut next = vec![C64::new(0.0, 0.0); k * next_cols];
+            for i in 0..k {
+                for j in 0..cols {
+                    let factor = s[i] * vh[i * cols + j];
+                    for col in 0..next_cols {
+                        next[i * next_cols + col] += factor * self.tensors[site + 1][j * next_cols + col];
+                    }
+                }
+            }
+            self.tensors[site] = u;
+            self.tensors[site + 1] = next;
+            self.bonds[site + 1] = k;
+            self.center += 1;
+        }
+        while self.center > to {
+            let site = self.center;
+            let (rows, cols) = (self.bonds[site], 2 * self.bonds[site + 1]);
+            let (u, s, vh) = svd(&self.tensors[site], rows, cols);
+            let k = s.len();
+            let prev_rows = self.bonds[site - 1] * 2;
+            let mut prev = vec![C64::new(0.0, 0.0); prev_rows * k];
+            for row in 0..prev_rows {
+                for i in 0..rows {
+                    let a = self.tensors[site - 1][row * rows + i];
+                    for j in 0..k {
+                        prev[row * k + j] += a * u[i * k + j] * s[j];
+                    }
+                }
+            }
+            self.tensors[site] = vh;
+            self.tensors[site - 1] = prev;
+            self.bonds[site] = k;
+            self.center -= 1;
+        }
+    }
+
+    // Environment to the left of `site`; identity up to the orthogonality center.
+    fn left_environment(&self, site: usize) -> Vec<C64> {
+        let from = self.center.min(site);
+        let mut env = identity(self.bonds[from]);
+        for s in from..site {
+            env = self.extend_left(&env, s);
+        }
+        env
+    }
+
+    fn right_environment(&self, site: usize) -> Vec<C64> {
+        let from = self.center.max(site);
+        let mut env = identity(self.bonds[from + 1]);
+        for s in (site + 1..=from).rev() {
+            env = self.extend_right(&env, s);
+        }
+        env
+    }
+
+    fn extend_left(&self, env: &[C64], site: usize) -> Vec<C64> {
+        let (l_dim, r_dim) = (self.bonds[site], self.bonds[site + 1]);
+        let a = &self.tensors[site];
+        // t[l2][p][r] = sum_l env[l][l2] * a[l][p][r]
+        let mut t = vec![C64::new(0.0, 0.0); l_dim * 2 * r_dim];
+        for l in 0..l_dim {
+            for l2 in 0..l_dim {
+                let e = env[l * l_dim + l2];
+                if e.norm_sqr() == 0.0 {
+                    continue;
+                }
+                for pr in 0..2 * r_dim {
+                    t[l2 * 2 * r_dim + pr] += e * a[l * 2 * r_dim + pr];
+                }
+            }
+        }
+        let mut out = vec![C64::new(0.0, 0.0); r_dim * r_dim];
+        for l2 in 0..l_dim {
+            for p in 0..2 {
+                for r in 0..r_dim {
+                    let tv = t[(l2 * 2 + p) * r_dim + r];
+                    for r2 in 0..r_dim {
+                        out[r * r_dim + r2] += tv * a[(l2 * 2 + p) * r_dim + r2].conj();
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn extend_right(&self, env: &[C64], site: usize) -> Vec<C64> {
+        let (l_dim, r_dim) = (self.bonds[site], self.bonds[site + 1]);
+        let a = &self.tensors[site];
+        // t[l][p][r2] = sum_r a[l][p][r] * env[r][r2]
+        let mut t = vec![C64::new(0.0, 0.0); l_dim * 2 * r_dim];
+        for lp in 0..l_dim * 2 {
+            for r in 0..r_dim {
+                let av = a[lp * r_dim + r];
+                if av.norm_sqr() == 0.0 {
+                    continue;
+                }
+                for r2 in 0..r_dim {
+                    t[lp * r_dim + r2] += av * env[r * r_dim + r2];
+                }
+            }
+        }
+        let mut out = vec![C64::new(0.0, 0.0); l_dim * l_dim];
+        for l in 0..l_dim {
+            for l2 in 0..l_dim {
+                let mut total = C64::new(0.0, 0.0);
+                for pr in 0..2 * r_dim {
+                    total += t[l * 2 * r_dim + pr] * a[l2 * 2 * r_dim + pr].conj();
+                }
+                out[l * l_dim + l2] = total;
+            }
+        }
+        out
+    }
+}
+
+
+fn identity(dim: usize) -> Vec<C64> {
+    let mut m = vec![C64::new(0.0, 0.0); dim * dim];
+    for i in 0..dim {
+        m[i * dim + i] = C64::new(1.0, 0.0);
+    }
+    m
+}
+
+fn adjoint(a: &[C64], rows: usize, cols: usize) -> Vec<C64> {
+    let mut out = vec![C64::new(0.0, 0.0); rows * cols];
+    for i in 0..rows {
+        for j in 0..cols {
+            out[j * rows + i] = a[i * cols + j].conj();
+        }
+    }
+    out
+}
+
+/// Thin SVD `a = u * diag(s) * vh` of a row-major rows x cols matrix, using
+/// one-sided Jacobi rotations. Singular values come back in descending order.
+fn svd(a: &[C64], rows: usize, cols: usize) -> (Vec<C64>, Vec<f64>, Vec<C64>) {
+    if rows < cols {
+        let (u, s, vh) = svd(&adjoint(a, rows, cols), cols, rows);
+        let k = s.len();
+        return (adjoint(&vh, k, rows), s, adjoint(&u, cols, k));
+    }
+
+    let zero = C64::new(0.0, 0.0);
+    let mut w: Vec<Vec<C64>> = (0..cols).map(|j| (0..rows).map(|i| a[i * cols + j]).collect()).collect();
+    let mut v: Vec<Vec<C64>> = (0..cols).map(|j| {
+        let mut column = vec![zero; cols];
+        column[j] = C64::new(1.0, 0.0);
+        column
+    }).collect();
+
+    for _sweep in 0..60 {
+        let mut rotated = false;
+        for p in 0..cols {
+            for q in p + 1..cols {
+                let alpha: f64 = w[p].iter().map(|x| x.norm_sqr()).sum();
+                let beta: f64 = w[q].iter().map(|x| x.norm_sqr()).sum();
+                let gamma: C64 = w[p].iter().zip(w[q].iter()).map(|(x, y)| x.conj() * y).sum();
+                let g = gamma.norm();
+                if g <= 1e-15 * (alpha * beta).sqrt() || g < 1e-300 {
+                    continue;
+                }
+                rotated = true;
+                let phase = gamma / g;
+                let zeta = (beta - alpha) / (2.0 * g);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                for column in [&mut w, &mut v] {
+                    for i in 0..column[p].len() {
+                        let (xp, xq) = (column[p][i], column[q][i]);
+                        column[p][i] = xp * c - phase.conj() * xq * s;
+                        column[q][i] = phase * xp * s + xq * c;
+                    }
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+
+    let norms: Vec<f64> = w.iter().map(|column| column.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt()).collect();
+    let mut order: Vec<usize> = (0..cols).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+
+    let k = cols;
+    let mut u = vec![zero; rows * k];
+    let mut s = vec![0.0; k];
+    let mut vh = vec![zero; k * cols];
+    for (rank, &j) in order.iter().enumerate() {
+        s[rank] = norms[j];
+        if norms[j] > 0.0 {
+            for i in 0..rows {
+                u[i * k + rank] = w[j][i] / norms[j];
+            }
+        }
+        for i in 0..cols {
+            vh[rank * cols + i] = v[j][i].conj();
+        }
+    }
+    (u, s, vh)
+}
@@ -0,0 +1,150 @@
+// src/simulator/noise.rs
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use super::C64;
+
+/// A single noise channel. Kraus channels act after a gate; `readout_error`
+/// flips the classical result of a measurement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum NoiseChannel {
+    Depolarizing { p: f64 },
+    AmplitudeDamping { gamma: f64 },
+    PhaseDamping { lambda: f64 },
+    BitFlip { p: f64 },
+    ReadoutError { p01: f64, p10: f64 },
+}
+
+/// Noise model description, usually loaded from JSON:
+///
+/// ```json
+/// {
+///   "gates":  { "hadamard": [{ "channel": "depolarizing", "p": 0.01 }] },
+///   "qubits": { "0": [{ "channel": "amplitude_damping", "gamma": 0.02 },
+///                     { "channel": "readout_error", "p01": 0.02, "p10": 0.05 }] }
+/// }
+/// ```
+///
+/// Gate channels apply to every qubit the named gate touches; qubit channels
+/// apply after any gate on that qubit. A `readout_error` under `qubits` (or
+/// under the gate name `measure`) applies when that qubit is measured.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NoiseModel {
+    #[serde(default)]
+    pub gates: HashMap<String, Vec<NoiseChannel>>,
+    #[serde(default)]
+    pub qubits: HashMap<usize, Vec<NoiseChannel>>,
+}
+
+impl NoiseChannel {
+    fn validate(&self) -> Result<(), String> {
+        let probabilities = match self {
+            NoiseChannel::Depolarizing { p } | NoiseChannel::BitFlip { p } => vec![*p],
+            NoiseChannel::AmplitudeDamping { gamma } => vec![*gamma],
+            NoiseChannel::PhaseDamping { lambda } => vec![*lambda],
+            NoiseChannel::ReadoutError { p01, p10 } => vec![*p01, *p10],
+        };
+        if probabilities.iter().all(|p| (0.0..=1.0).contains(p)) {
+            Ok(())
+        } else {
+            Err(format!("Noise Model Error: Channel {:?} has a probability outside [0, 1].", self))
+        }
+    }
+
+    /// Kraus operators for the channel, or None for classical readout error.
+    pub fn kraus_operators(&self) -> Option<Vec<[[C64; 2]; 2]>> {
+        let zero = C64::new(0.0, 0.0);
+        let real = |x: f64| C64::new(x, 0.0);
+        match self {
+            NoiseChannel::Depolarizing { p } => {
+                let a = (1.0 - p).sqrt();
+                let b = (p / 3.0).sqrt();
+                let i = C64::new(0.0, 1.0);
+                Some(vec![
+                    [[real(a), zero], [zero, real(a)]],
+                    [[zero, real(b)], [real(b), zero]],
+                    [[zero, -i * b], [i * b, zero]],
+                    [[real(b), zero], [zero, real(-b)]],
+                ])
+            }
+            NoiseChannel::AmplitudeDamping { gamma } => Some(vec![
+                [[real(1.0), zero], [zero, real((1.0 - gamma).sqrt())]],
+                [[zero, real(gamma.sqrt())], [zero, zero]],
+            ]),
+            NoiseChannel::PhaseDamping { lambda } => Some(vec![
+                [[real(1.0), zero], [zero, real((1.0 - lambda).sqrt())]],
+                [[zero, zero], [zero, real(lambda.sqrt())]],
+            ]),
+            NoiseChannel::BitFlip { p } => Some(vec![
+                [[real((1.0 - p).sqrt()), zero], [zero, real((1.0 - p).sqrt())]],
+                [[zero, real(p.sqrt())], [real(p.sqrt()), zero]],
+            ]),
+            NoiseChannel::ReadoutError { .. } => None,
+        }
+    }
+}
+
+impl NoiseModel {
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let model: NoiseModel = serde_json::from_str(source)
+            .map_err(|e| format!("Noise Model Error: {}", e))?;
+        for channel in model.gates.values().chain(model.qubits.values()).flatten() {
+            channel.validate()?;
+        }
+        Ok(model)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Noise Model Error: Failed to read '{}': {}", path, e))?;
+        Self::from_json(&source)
+    }
+
+    /// Channels to apply to `qubit` after `gate_name` acted on it.
+    pub fn gate_channels(&self, gate_name: &str, qubit: usize) -> Vec<&NoiseChannel> {
+        let name = gate_name.to_lowercase();
+        let alias = match name.as_str() {
+            "h" => "hadamard",
+            "hadamard" => "h",
+            "cx" => "cnot",
+            "cnot" => "cx",
+            "ccx" => "toffoli",
+            "toffoli" => "ccx",
+            other => other,
+        };
+        let by_gate = self.gates.get(&name).or_else(|| self.gates.get(alias));
+        by_gate.into_iter().flatten()
+            .chain(self.qubits.get(&qubit).into_iter().flatten())
+            .filter(|channel| !matches!(channel, NoiseChannel::ReadoutError { .. }))
+            .collect()
+    }
+
+    /// Flip probabilities (0 read as 1, 1 read as 0) when measuring `qubit`.
+    pub fn readout_error(&self, qubit: usize) -> (f64, f64) {
+        let channels = self.gates.get("measure").into_iter().flatten()
+            .chain(self.qubits.get(&qubit).into_iter().flatten());
+        let mut flips = (0.0, 0.0);
+        for channel in channels {
+            if let NoiseChannel::ReadoutError { p01, p10 } = channel {
+                flips = (*p01, *p10);
+            }
+        }
+        flips
+    }
+}
+
+
+static NOISE_MODEL: RwLock<Option<Arc<NoiseModel>>> = RwLock::new(None);
+
+pub fn set_noise_model(model: Option<NoiseModel>) {
+    if let Ok(mut guard) = NOISE_MODEL.write() {
+        *guard = model.map(Arc::new);
+    }
+}
+
+/// The active model, shared rather than copied since it is consulted on every gate.
+pub fn noise_model() -> Option<Arc<NoiseModel>> {
+    NOISE_MODEL.read().ok().and_then(|guard| guard.clone())
+}
@@ -0,0 +1,192 @@
+// src/simulator/observable.rs
+use std::collections::HashMap;
+use std::f64::consts::FRAC_1_SQRT_2;
+use super::{QuantumState, C64};
+
+/// A Hermitian observable written as a real-weighted sum of Pauli strings,
+/// e.g. `0.5*ZZ - 1.2*XI + IZ`. Letter `i` of each string acts on qubit `i`
+/// of the register it is measured on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observable {
+    pub terms: Vec<(f64, String)>,
+}
+
+impl Observable {
+    pub fn new(terms: Vec<(f64, String)>) -> Result<Self, String> {
+        let Some((_, first)) = terms.first() else {
+            return Err("Observable Error: An observable needs at least one Pauli term.".to_string());
+        };
+        let num_qubits = first.len();
+        for (coefficient, paulis) in &terms {
+            if paulis.is_empty() || !paulis.chars().all(|c| matches!(c, 'I' | 'X' | 'Y' | 'Z')) {
+                return Err(format!("Observable Error: '{}' is not a Pauli string (expected only I, X, Y and Z).", paulis));
+            }
+            if paulis.len() != num_qubits {
+                return Err(format!("Observable Error: Pauli strings '{}' and '{}' act on different numbers of qubits.", first, paulis));
+            }
+            if !coefficient.is_finite() {
+                return Err(format!("Observable Error: Coefficient of '{}' is not finite.", paulis));
+            }
+        }
+        Ok(Observable { terms })
+    }
+
+    /// Parse a sum such as `0.5*ZZ - 1.2 * XI + IZ`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let compact: String = spec.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut terms = Vec::new();
+        let mut rest = compact.as_str();
+        while !rest.is_empty() {
+            let (sign, body) = match rest.as_bytes()[0] {
+                b'+' => (1.0, &rest[1..]),
+                b'-' => (-1.0, &rest[1..]),
+                _ if terms.is_empty() => (1.0, rest),
+                _ => return Err(format!("Observable Error: Expected '+' or '-' before '{}'.", rest)),
+            };
+            // A sign right after an exponent marker belongs to the coefficient.
+            let end = body.char_indices()
+                .find(|&(i, c)| (c == '+' || c == '-') && !body[..i].ends_with(['e', 'E']))
+                .map_or(body.len(), |(i, _)| i);
+            let (term, remaining) = body.split_at(end);
+            let (coefficient, paulis) = match term.split_once('*') {
+                Some((number, paulis)) => {
+                    let value = number.parse::<f64>()
+                        .map_err(|_| format!("Observable Error: Invalid coefficient '{}'.", number))?;
+                    (value, paulis)
+                }
+                None => (1.0, term),
+            };
+            terms.push((sign * coefficient, paulis.to_string()));
+            rest = remaining;
+        }
+        Observable::new(terms)
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.terms[0].1.len()
+    }
+
+    /// ⟨ψ|H|ψ⟩ (or Tr(ρH)) over `qubits`, the state indices of the register's
+    /// qubits in order. The state itself is left untouched.
+    pub fn expectation(&self, state: &QuantumState, qubits: &[usize]) -> Result<f64, String> {
+        self.check_width(qubits)?;
+        let mut total = 0.0;
+        for (coefficient, paulis) in &self.terms {
+            total += coefficient * pauli_expectation(state, paulis, qubits)?;
+        }
+        Ok(total)
+    }
+
+    /// ⟨H²⟩ - ⟨H⟩². Anti-commuting pairs cancel in H², so only commuting
+    /// products (which are Pauli strings up to a sign) are evaluated.
+    pub fn variance(&self, state: &QuantumState, qubits: &[usize]) -> Result<f64, String> {
+        self.check_width(qubits)?;
+        let mut cache: HashMap<String, f64> = HashMap::new();
+        let mut square = 0.0;
+        for (a, left) in &self.terms {
+            for (b, right) in &self.terms {
+                let (phase, product) = multiply(left, right);
+                if phase % 2 != 0 {
+                    continue;
+                }
+                let sign = if phase == 0 { 1.0 } else { -1.0 };
+                let value = match cache.get(&product) {
+                    Some(value) => *value,
+                    None => {
+                        let value = pauli_expectation(state, &product, qubits)?;
+                        cache.insert(product, value);
+                        value
+                    }
+                };
+                square += a * b * sign * value;
+            }
+        }
+        let mean = self.expectation(state, qubits)?;
+        Ok((square - mean * mean).max(0.0))
+    }
+
+    fn check_width(&self, qubits: &[usize]) -> Result<(), String> {
+        if qubits.len() != self.num_qubits() {
+            return Err(format!("Observable Error: Observable acts on {} qubit(s), but the register has {}.", self.num_qubits(), qubits.len()));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Observable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (coefficient, paulis)) in self.terms.iter().enumerate() {
+            match (i, *coefficient < 0.0) {
+                (0, false) => write!(f, "{}*{}", coefficient, paulis)?,
+                (0, true) => write!(f, "-{}*{}", -coefficient, paulis)?,
+                (_, false) => write!(f, " + {}*{}", coefficient, paulis)?,
+                (_, true) => write!(f, " - {}*{}", -coefficient, paulis)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Single-qubit gate taking the eigenbasis of `pauli` to the computational basis,
+/// as a hardware gate name and parameters: H for X and RX(π/2) for Y.
+pub fn basis_rotation(pauli: char) -> Option<(&'static str, Vec<f64>)> {
+    match pauli {
+        'X' => Some(("hadamard", vec![])),
+        'Y' => Some(("rx", vec![std::f64::consts::FRAC_PI_2])),
+        _ => None,
+    }
+}
+
+// Rotate every qubit of the string into the Z basis, fold their parity onto the
+// last one with CNOTs and read ⟨Z⟩ = 2·P(0) - 1 there. Only Clifford gates are
+// used, so stabilizer states stay tableaux.
+fn pauli_expectation(state: &QuantumState, paulis: &str, qubits: &[usize]) -> Result<f64, String> {
+    let support: Vec<(usize, char)> = paulis.chars().zip(qubits)
+        .filter(|(pauli, _)| *pauli != 'I')
+        .map(|(pauli, &qubit)| (qubit, pauli))
+        .collect();
+    let Some(&(last, _)) = support.last() else {
+        return Ok(1.0);
+    };
+
+    let zero = C64::new(0.0, 0.0);
+    let h = C64::new(FRAC_1_SQRT_2, 0.0);
+    let hadamard = [[h, h], [h, -h]];
+    let rx_half_pi = [[h, C64::new(0.0, -FRAC_1_SQRT_2)], [C64::new(0.0, -FRAC_1_SQRT_2), h]];
+    let x = [[zero, C64::new(1.0, 0.0)], [C64::new(1.0, 0.0), zero]];
+
+    let mut rotated = state.clone();
+    for &(qubit, pauli) in &support {
+        match pauli {
+            'X' => rotated.apply_single_qubit(&hadamard, qubit, &[])?,
+            'Y' => rotated.apply_single_qubit(&rx_half_pi, qubit, &[])?,
+            _ => {}
+        }
+    }
+    for &(qubit, _) in &support[..support.len() - 1] {
+        rotated.apply_single_qubit(&x, last, &[qubit])?;
+    }
+    Ok(2.0 * rotated.probability_zero(last) - 1.0)
+}
+
+// Product of two Pauli strings as (power of i, string).
+fn multiply(left: &str, right: &str) -> (u8, String) {
+    let mut phase = 0u8;
+    let product = left.chars().zip(right.chars())
+        .map(|(a, b)| {
+            let (power, result) = match (a, b) {
+                ('I', p) | (p, 'I') => (0, p),
+                (p, q) if p == q => (0, 'I'),
+                ('X', 'Y') => (1, 'Z'),
+                ('Y', 'Z') => (1, 'X'),
+                ('Z', 'X') => (1, 'Y'),
+                ('Y', 'X') => (3, 'Z'),
+                ('Z', 'Y') => (3, 'X'),
+                _ => (3, 'Y'),
+            };
+            phase = (phase + power) % 4;
+            result
+        })
+        .collect();
+    (phase, product)
+}
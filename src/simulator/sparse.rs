@@ -0,0 +1,134 @@
+// src/simulator/sparse.rs
+use std::collections::HashMap;
+use std::collections::HashSet;
+use super::{C64, AMPLITUDE_EPSILON};
+use super::unitary::{Unitary, target_offsets};
+
+/// Basis-state -> amplitude map. Only non-zero amplitudes are stored.
+#[derive(Debug, Clone)]
+pub struct SparseState {
+    pub num_qubits: usize,
+    pub amplitudes: HashMap<usize, C64>,
+}
+
+impl SparseState {
+    pub fn from_amplitudes(num_qubits: usize, amps: Vec<(usize, C64)>) -> Self {
+        let mut amplitudes = HashMap::new();
+        for (index, amp) in amps {
+            if amp.norm_sqr() > AMPLITUDE_EPSILON {
+                amplitudes.insert(index, amp);
+            }
+        }
+        SparseState { num_qubits, amplitudes }
+    }
+
+    pub fn nonzero_count(&self) -> usize {
+        self.amplitudes.len()
+    }
+
+    pub fn amplitude(&self, index: usize) -> C64 {
+        self.amplitudes.get(&index).cloned().unwrap_or(C64::new(0.0, 0.0))
+    }
+
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, control_mask: usize) {
+        let target_mask = 1 << target;
+        let mut new_amplitudes = HashMap::new();
+        let mut processed = HashSet::new();
+
+        for (&basis_state, &amp) in self.amplitudes.iter() {
+            if processed.contains(&basis_state) {
+                continue;
+            }
+
+            if (basis_state & control_mask) == control_mask {
+                let partner_state = basis_state ^ target_mask;
+                let amp_partner = self.amplitude(partner_state);
+
+                let (amp0, amp1, idx0, idx1) = if (basis_state & target_mask) == 0 {
+                    (amp, amp_partner, basis_state, partner_state)
+                } else {
+                    (amp_partner, amp, partner_state, basis_state)
+                };
+
+                let new_amp0 = matrix[0][0] * amp0 + matrix[0][1] * amp1;
+                let new_amp1 = matrix[1][0] * amp0 + matrix[1][1] * amp1;
+
+                Self::insert_if_nonzero(&mut new_amplitudes, idx0, new_amp0);
+                Self::insert_if_nonzero(&mut new_amplitudes, idx1, new_amp1);
+
+                processed.insert(idx0);
+                processed.insert(idx1);
+            } else {
+                new_amplitudes.insert(basis_state, amp);
+                processed.insert(basis_state);
+            }
+        }
+
+        self.amplitudes = new_amplitudes;
+    }
+
+    pub fn apply_unitary(&mut self, gate: &Unitary, targets: &[usize], control_mask: usize) {
+        let offsets = target_offsets(targets);
+        let target_mask = offsets[offsets.len() - 1];
+        let dim = gate.dim();
+        let mut new_amplitudes = HashMap::new();
+        let mut bases = HashSet::new();
+
+        for (&basis_state, &amp) in self.amplitudes.iter() {
+            if (basis_state & control_mask) == control_mask {
+                bases.insert(basis_state & !target_mask);
+            } else {
+                new_amplitudes.insert(basis_state, amp);
+            }
+        }
+
+        for base in bases {
+            let block: Vec<C64> = offsets.iter().map(|&offset| self.amplitude(base | offset)).collect();
+            for (row, &offset) in offsets.iter().enumerate() {
+                let amp = (0..dim).map(|col| gate.get(row, col) * block[col]).sum();
+                Self::insert_if_nonzero(&mut new_amplitudes, base | offset, amp);
+            }
+        }
+
+        self.amplitudes = new_amplitudes;
+    }
+
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        let mask_a = 1 << qubit_a;
+        let mask_b = 1 << qubit_b;
+        let mut new_amplitudes = HashMap::with_capacity(self.amplitudes.len());
+
+        for (&basis_state, &amp) in self.amplitudes.iter() {
+            let bit_a = (basis_state & mask_a) != 0;
+            let bit_b = (basis_state & mask_b) != 0;
+            if bit_a != bit_b {
+                new_amplitudes.insert(basis_state ^ mask_a ^ mask_b, amp);
+            } else {
+                new_amplitudes.insert(basis_state, amp);
+            }
+        }
+
+        self.amplitudes = new_amplitudes;
+    }
+
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        let target_mask = 1 << target;
+        self.amplitudes.iter()
+            .filter(|(&basis_state, _)| (basis_state & target_mask) == 0)
+            .map(|(_, amp)| amp.norm_sqr())
+            .sum()
+    }
+
+    pub fn collapse(&mut self, target: usize, outcome: usize, norm_factor: f64) {
+        self.amplitudes.retain(|&basis_state, _| ((basis_state >> target) & 1) == outcome);
+        for amp in self.amplitudes.values_mut() {
+            *amp *= norm_factor;
+        }
+    }
+
+    fn insert_if_nonzero(amplitudes: &mut HashMap<usize, C64>, index: usize, amp: C64) {
+        if amp.norm_sqr() > AMPLITUDE_EPSILON {
+            amplitudes.insert(index, amp);
+        }
+    }
+}
@@ -0,0 +1,540 @@
+// src/simulator/stabilizer.rs
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use super::C64;
+
+const EPS: f64 = 1e-9;
+
+type Matrix2 = [[C64; 2]; 2];
+
+/// Operation log kept so a stabilizer register can be replayed onto a state
+/// vector if a non-Clifford gate shows up later. Registers that can never fall
+/// back do not keep one.
+#[derive(Debug, Clone)]
+pub enum StabilizerOp {
+    Gate([[C64; 2]; 2], usize, Vec<usize>),
+    Collapse(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Primitive {
+    H(usize),
+    S(usize),
+    Cnot(usize, usize),
+}
+
+/// Aaronson-Gottesman tableau: rows 0..n are destabilizers, n..2n stabilizers.
+/// Each row is a Pauli string stored as bit-packed x/z vectors plus a sign bit.
+#[derive(Debug, Clone)]
+pub struct StabilizerState {
+    pub num_qubits: usize,
+    words: usize,
+    x: Vec<u64>,
+    z: Vec<u64>,
+    r: Vec<bool>,
+    history: Option<Vec<StabilizerOp>>,
+}
+
+impl StabilizerState {
+    pub fn new(num_qubits: usize) -> Self {
+        let words = num_qubits.div_ceil(64);
+        let rows = 2 * num_qubits;
+        let mut state = StabilizerState {
+            num_qubits,
+            words,
+            x: vec![0; rows * words],
+            z: vec![0; rows * words],
+            r: vec![false; rows],
+            history: Some(Vec::new()),
+        };
+        for q in 0..num_qubits {
+            state.set_x(q, q, true);
+            state.set_z(num_qubits + q, q, true);
+        }
+        state
+    }
+
+    /// The operations applied so far, unless the history was forgotten.
+    pub fn history(&self) -> Option<&[StabilizerOp]> {
+        self.history.as_deref()
+    }
+
+    /// Stop recording operations, for a register that can no longer be replayed.
+    pub fn forget_history(&mut self) {
+        self.history = None;
+    }
+
+    fn record(&mut self, op: StabilizerOp) {
+        if let Some(history) = &mut self.history {
+            history.push(op);
+        }
+    }
+
+    /// Apply a single-qubit unitary, optionally with one control. Fails if the
+    /// resulting operation is not a Clifford gate.
+    pub fn apply_single_qubit(&mut self, matrix: &[[C64; 2]; 2], target: usize, controls: &[usize]) -> Result<(), String> {
+        let primitives = Self::decompose(matrix, target, controls)?;
+        for primitive in primitives {
+            match primitive {
+                Primitive::H(q) => self.h(q),
+                Primitive::S(q) => self.s(q),
+                Primitive::Cnot(c, t) => self.cnot(c, t),
+            }
+        }
+        self.record(StabilizerOp::Gate(*matrix, target, controls.to_vec()));
+        Ok(())
+    }
+
+    pub fn apply_swap(&mut self, qubit_a: usize, qubit_b: usize) {
+        self.cnot(qubit_a, qubit_b);
+        self.cnot(qubit_b, qubit_a);
+        self.cnot(qubit_a, qubit_b);
+        let x = [[C64::new(0.0, 0.0), C64::new(1.0, 0.0)], [C64::new(1.0, 0.0), C64::new(0.0, 0.0)]];
+        self.record(StabilizerOp::Gate(x, qubit_b, vec![qubit_a]));
+        self.record(StabilizerOp::Gate(x, qubit_a, vec![qubit_b]));
+        self.record(StabilizerOp::Gate(x, qubit_b, vec![qubit_a]));
+    }
+
+    /// 0.5 if the outcome is random, otherwise 0.0 or 1.0.
+    pub fn probability_zero(&self, target: usize) -> f64 {
+        if self.random_pivot(target).is_some() {
+            return 0.5;
+        }
+        if self.deterministic_outcome(target) == 0 { 1.0 } else { 0.0 }
+    }
+
+    /// Force the measurement of `target` to `outcome`.
+    pub fn collapse(&mut self, target: usize, outcome: usize) {
+        let n = self.num_qubits;
+        if let Some(p) = self.random_pivot(target) {
+            for i in 0..2 * n {
+                if i != p && self.get_x(i, target) {
+                    self.rowsum(i, p);
+                }
+            }
+            self.copy_row(p - n, p);
+            for w in 0..self.words {
+                self.x[p * self.words + w] = 0;
+                self.z[p * self.words + w] = 0;
+            }
+            self.set_z(p, target, true);
+            self.r[p] = outcome == 1;
+        }
+        self.record(StabilizerOp::Collapse(target, outcome));
+    }
+
+    /// `self ⊗ other`, with `self` on the high-order qubits.
+    pub fn tensor(&self, other: &StabilizerState) -> StabilizerState {
+        let (na, nb) = (self.num_qubits, other.num_qubits);
+        let mut result = StabilizerState::new(na + nb);
+        let n = na + nb;
+        for row in 0..n {
+            for q in 0..n {
+                result.set_x(row, q, false);
+                result.set_z(n + row, q, false);
+            }
+        }
+
+        // Keep destabilizer i paired with stabilizer i for both halves.
+        for i in 0..na {
+            for (src, dst) in [(i, nb + i), (na + i, n + nb + i)] {
+                for q in 0..na {
+                    result.set_x(dst, q + nb, self.get_x(src, q));
+                    result.set_z(dst, q + nb, self.get_z(src, q));
+                }
+                result.r[dst] = self.r[src];
+            }
+        }
+        for i in 0..nb {
+            for (src, dst) in [(i, i), (nb + i, n + i)] {
+                for q in 0..nb {
+                    result.set_x(dst, q, other.get_x(src, q));
+                    result.set_z(dst, q, other.get_z(src, q));
+                }
+                result.r[dst] = other.r[src];
+            }
+        }
+
+        result.history = match (&self.history, &other.history) {
+            (Some(high), Some(low)) => Some(high.iter()
+                .map(|op| match op {
+                    StabilizerOp::Gate(m, t, controls) => StabilizerOp::Gate(*m, t + nb, controls.iter().map(|c| c + nb).collect()),
+                    StabilizerOp::Collapse(q, o) => StabilizerOp::Collapse(q + nb, *o),
+                })
+                .chain(low.iter().cloned())
+                .collect()),
+            _ => None,
+        };
+        result
+    }
+
+    /// Non-zero amplitudes sorted by basis state, up to a global phase chosen so
+    /// the first one is real and positive. A basis state the register can be
+    /// measured in is projected onto the +1 eigenspace of every stabilizer.
+    pub fn amplitudes(&self) -> Vec<(usize, C64)> {
+        let n = self.num_qubits;
+        let mut measured = self.clone();
+        measured.forget_history();
+        let mut basis_state = 0;
+        for q in 0..n {
+            let outcome = if measured.random_pivot(q).is_some() { 0 } else { measured.deterministic_outcome(q) };
+            measured.collapse(q, outcome);
+            basis_state |= outcome << q;
+        }
+
+        let powers = [C64::new(1.0, 0.0), C64::new(0.0, 1.0), C64::new(-1.0, 0.0), C64::new(0.0, -1.0)];
+        let mut amplitudes: HashMap<usize, C64> = HashMap::from([(basis_state, C64::new(1.0, 0.0))]);
+        for row in n..2 * n {
+            let (x_mask, z_mask) = (0..n).fold((0usize, 0usize), |(x, z), q| {
+                (x | (self.get_x(row, q) as usize) << q, z | (self.get_z(row, q) as usize) << q)
+            });
+            let y_count = (x_mask & z_mask).count_ones() as usize;
+            let mut projected = amplitudes.clone();
+            for (&index, &amp) in &amplitudes {
+                // X^x Z^z with i for every Y, so Z acts before X flips the bits.
+                let minus_signs = 2 * (self.r[row] as usize + (index & z_mask).count_ones() as usize);
+                *projected.entry(index ^ x_mask).or_insert(C64::new(0.0, 0.0)) += amp * powers[(y_count + minus_signs) % 4];
+            }
+            projected.retain(|_, amp| amp.norm_sqr() > EPS * EPS);
+            amplitudes = projected;
+        }
+
+        let mut amplitudes: Vec<(usize, C64)> = amplitudes.into_iter().collect();
+        amplitudes.sort_by_key(|(i, _)| *i);
+        let norm = amplitudes.iter().map(|(_, a)| a.norm_sqr()).sum::<f64>().sqrt();
+        let phase = amplitudes.first().map_or(C64::new(1.0, 0.0), |(_, a)| a / a.norm());
+        for (_, amp) in amplitudes.iter_mut() {
+            *amp /= phase * norm;
+        }
+        amplitudes
+    }
+
+    /// Prepare the state with the given amplitudes, up to a global phase. Stabilizer
+    /// states are exactly those whose support is an affine subspace `c + span(v_j)`
+    /// and whose relative phases are `i^(a.x) (-1)^(x^T B x)` in the coordinates `x`,
+    /// which H, S and CZ on pivot qubits followed by CNOTs and Xs produce.
+    pub fn from_amplitudes(num_qubits: usize, amps: &[(usize, C64)]) -> Result<Self, String> {
+        let not_stabilizer = || "The stabilizer simulator can only initialize stabilizer states.".to_string();
+        let support: Vec<(usize, C64)> = amps.iter().copied().filter(|(_, a)| a.norm_sqr() > EPS * EPS).collect();
+        let &(first, _) = support.first().ok_or_else(not_stabilizer)?;
+        if num_qubits > usize::BITS as usize && support.len() > 1 {
+            return Err(not_stabilizer());
+        }
+
+        // Reduced row echelon basis of the differences: each pivot bit appears in one vector.
+        let mut basis: Vec<(usize, usize)> = Vec::new();
+        for &(index, _) in &support {
+            let reduced = basis.iter().fold(index ^ first, |d, &(pivot, v)| if (d >> pivot) & 1 == 1 { d ^ v } else { d });
+            if reduced != 0 {
+                let pivot = reduced.trailing_zeros() as usize;
+                for (_, v) in basis.iter_mut().filter(|(_, v)| (*v >> pivot) & 1 == 1) {
+                    *v ^= reduced;
+                }
+                basis.push((pivot, reduced));
+            }
+        }
+        if basis.len() >= usize::BITS as usize || support.len() != 1 << basis.len() {
+            return Err(not_stabilizer());
+        }
+        let offset = basis.iter().fold(first, |c, &(pivot, v)| if (c >> pivot) & 1 == 1 { c ^ v } else { c });
+        let amplitude = |index: usize| support.iter().find(|(i, _)| *i == index).map(|(_, a)| *a);
+        let reference = amplitude(offset).ok_or_else(not_stabilizer)?;
+        let relative = |index: usize| amplitude(index).map(|a| a / reference);
+
+        let powers = [C64::new(1.0, 0.0), C64::new(0.0, 1.0), C64::new(-1.0, 0.0), C64::new(0.0, -1.0)];
+        let power_of_i = |value: C64| powers.iter().position(|p| (p - value).norm() < 1e-6);
+        let s_powers: Vec<usize> = basis.iter()
+            .map(|&(_, v)| relative(offset ^ v).and_then(power_of_i))
+            .collect::<Option<_>>()
+            .ok_or_else(not_stabilizer)?;
+        let mut cz_pairs = Vec::new();
+        for j in 0..basis.len() {
+            for k in j + 1..basis.len() {
+                let pair = relative(offset ^ basis[j].1 ^ basis[k].1).ok_or_else(not_stabilizer)?;
+                match power_of_i(pair / (powers[s_powers[j]] * powers[s_powers[k]])) {
+                    Some(0) => {}
+                    Some(2) => cz_pairs.push((j, k)),
+                    _ => return Err(not_stabilizer()),
+                }
+            }
+        }
+        let consistent = support.iter().all(|&(index, amp)| {
+            let bits: Vec<bool> = basis.iter().map(|&(pivot, _)| (index >> pivot) & 1 == 1).collect();
+            let s_total: usize = bits.iter().zip(&s_powers).filter(|(bit, _)| **bit).map(|(_, p)| p).sum();
+            let cz_total = cz_pairs.iter().filter(|&&(j, k)| bits[j] && bits[k]).count();
+            let predicted = powers[(s_total + 2 * cz_total) % 4] * reference;
+            (predicted - amp).norm() < 1e-6 * reference.norm()
+        });
+        if !consistent {
+            return Err(not_stabilizer());
+        }
+
+        let one = C64::new(1.0, 0.0);
+        let zero = C64::new(0.0, 0.0);
+        let h = C64::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+        let hadamard = [[h, h], [h, -h]];
+        let s = [[one, zero], [zero, C64::new(0.0, 1.0)]];
+        let z = [[one, zero], [zero, -one]];
+        let x = [[zero, one], [one, zero]];
+
+        let mut state = StabilizerState::new(num_qubits);
+        for (&(pivot, _), &power) in basis.iter().zip(&s_powers) {
+            state.apply_single_qubit(&hadamard, pivot, &[])?;
+            for _ in 0..power {
+                state.apply_single_qubit(&s, pivot, &[])?;
+            }
+        }
+        for &(j, k) in &cz_pairs {
+            state.apply_single_qubit(&z, basis[k].0, &[basis[j].0])?;
+        }
+        for &(pivot, v) in &basis {
+            for target in (0..usize::BITS as usize).filter(|&t| t != pivot && (v >> t) & 1 == 1) {
+                state.apply_single_qubit(&x, target, &[pivot])?;
+            }
+        }
+        for q in (0..num_qubits.min(usize::BITS as usize)).filter(|q| (offset >> q) & 1 == 1) {
+            state.apply_single_qubit(&x, q, &[])?;
+        }
+        Ok(state)
+    }
+
+    /// Stabilizer generators as signed Pauli strings, highest qubit first.
+    pub fn generators(&self) -> Vec<String> {
+        let n = self.num_qubits;
+        (n..2 * n).map(|row| {
+            let mut s = String::from(if self.r[row] { "-" } else { "+" });
+            for q in (0..n).rev() {
+                s.push(match (self.get_x(row, q), self.get_z(row, q)) {
+                    (false, false) => 'I',
+                    (true, false) => 'X',
+                    (false, true) => 'Z',
+                    (true, true) => 'Y',
+                });
+            }
+            s
+        }).collect()
+    }
+
+    fn decompose(matrix: &[[C64; 2]; 2], target: usize, controls: &[usize]) -> Result<Vec<Primitive>, String> {
+        match controls.len() {
+            0 => {
+                for (clifford, word) in single_qubit_cliffords() {
+                    if phase_between(clifford, matrix).is_some() {
+                        return Ok(word.iter().map(|&is_h| if is_h { Primitive::H(target) } else { Primitive::S(target) }).collect());
+                    }
+                }
+                Err("gate is not a Clifford operation".to_string())
+            }
+            1 => {
+                let control = controls[0];
+                // controlled(c * P) = controlled(P) followed by diag(1, c) = S^k on the control.
+                for (pauli, pauli_matrix) in paulis().iter().enumerate() {
+                    let phase = match phase_between(pauli_matrix, matrix) {
+                        Some(phase) => phase,
+                        None => continue,
+                    };
+                    let k = match [C64::new(1.0, 0.0), C64::new(0.0, 1.0), C64::new(-1.0, 0.0), C64::new(0.0, -1.0)]
+                        .iter().position(|c| (c - phase).norm() < EPS) {
+                        Some(k) => k,
+                        None => break,
+                    };
+                    let mut ops = match pauli {
+                        0 => vec![],
+                        1 => vec![Primitive::Cnot(control, target)],
+                        // CY = S_t CX S_t^dagger
+                        2 => vec![Primitive::S(target), Primitive::S(target), Primitive::S(target),
+                                  Primitive::Cnot(control, target), Primitive::S(target)],
+                        _ => vec![Primitive::H(target), Primitive::Cnot(control, target), Primitive::H(target)],
+                    };
+                    ops.extend(std::iter::repeat_n(Primitive::S(control), k));
+                    return Ok(ops);
+                }
+                Err("controlled gate is not a Clifford operation".to_string())
+            }
+            _ => Err("gates with more than one control are not Clifford operations".to_string()),
+        }
+    }
+
+    fn h(&mut self, q: usize) {
+        for row in 0..2 * self.num_qubits {
+            let (xv, zv) = (self.get_x(row, q), self.get_z(row, q));
+            self.r[row] ^= xv && zv;
+            self.set_x(row, q, zv);
+            self.set_z(row, q, xv);
+        }
+    }
+
+    fn s(&mut self, q: usize) {
+        for row in 0..2 * self.num_qubits {
+            let (xv, zv) = (self.get_x(row, q), self.get_z(row, q));
+            self.r[row] ^= xv && zv;
+            self.set_z(row, q, zv ^ xv);
+        }
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        for row in 0..2 * self.num_qubits {
+            let (xc, zc) = (self.get_x(row, control), self.get_z(row, control));
+            let (xt, zt) = (self.get_x(row, target), self.get_z(row, target));
+            self.r[row] ^= xc && zt && (xt == zc);
+            self.set_x(row, target, xt ^ xc);
+            self.set_z(row, control, zc ^ zt);
+        }
+    }
+
+    fn random_pivot(&self, target: usize) -> Option<usize> {
+        let n = self.num_qubits;
+        (n..2 * n).find(|&row| self.get_x(row, target))
+    }
+
+    fn deterministic_outcome(&self, target: usize) -> usize {
+        let n = self.num_qubits;
+        let mut sx = vec![0u64; self.words];
+        let mut sz = vec![0u64; self.words];
+        let mut sr = false;
+        for i in 0..n {
+            if self.get_x(i, target) {
+                let row = i + n;
+                let range = row * self.words..(row + 1) * self.words;
+                sr = self.row_product(&mut sx, &mut sz, sr, &self.x[range.clone()], &self.z[range], self.r[row]);
+            }
+        }
+        if sr { 1 } else { 0 }
+    }
+
+    // Multiply row `i` into row `h`, tracking the sign.
+    fn rowsum(&mut self, h: usize, i: usize) {
+        let w = self.words;
+        let ix = self.x[i * w..(i + 1) * w].to_vec();
+        let iz = self.z[i * w..(i + 1) * w].to_vec();
+        let mut hx = self.x[h * w..(h + 1) * w].to_vec();
+        let mut hz = self.z[h * w..(h + 1) * w].to_vec();
+        let hr = self.row_product(&mut hx, &mut hz, self.r[h], &ix, &iz, self.r[i]);
+        self.x[h * w..(h + 1) * w].copy_from_slice(&hx);
+        self.z[h * w..(h + 1) * w].copy_from_slice(&hz);
+        self.r[h] = hr;
+    }
+
+    fn row_product(&self, hx: &mut [u64], hz: &mut [u64], hr: bool, ix: &[u64], iz: &[u64], ir: bool) -> bool {
+        let mut sum: i64 = 2 * (hr as i64) + 2 * (ir as i64);
+        for q in 0..self.num_qubits {
+            let (w, b) = (q / 64, q % 64);
+            let x1 = ((ix[w] >> b) & 1) as i64;
+            let z1 = ((iz[w] >> b) & 1) as i64;
+            let x2 = ((hx[w] >> b) & 1) as i64;
+            let z2 = ((hz[w] >> b) & 1) as i64;
+            sum += match (x1, z1) {
+                (0, 0) => 0,
+                (1, 1) => z2 - x2,
+                (1, 0) => z2 * (2 * x2 - 1),
+                _ => x2 * (1 - 2 * z2),
+            };
+        }
+        for w in 0..self.words {
+            hx[w] ^= ix[w];
+            hz[w] ^= iz[w];
+        }
+        sum.rem_euclid(4) == 2
+    }
+
+    fn copy_row(&mut self, dst: usize, src: usize) {
+        let w = self.words;
+        self.x.copy_within(src * w..(src + 1) * w, dst * w);
+        self.z.copy_within(src * w..(src + 1) * w, dst * w);
+        self.r[dst] = self.r[src];
+    }
+
+    fn get_x(&self, row: usize, q: usize) -> bool {
+        (self.x[row * self.words + q / 64] >> (q % 64)) & 1 == 1
+    }
+
+    fn get_z(&self, row: usize, q: usize) -> bool {
+        (self.z[row * self.words + q / 64] >> (q % 64)) & 1 == 1
+    }
+
+    fn set_x(&mut self, row: usize, q: usize, value: bool) {
+        let word = &mut self.x[row * self.words + q / 64];
+        if value { *word |= 1 << (q % 64) } else { *word &= !(1 << (q % 64)) }
+    }
+
+    fn set_z(&mut self, row: usize, q: usize, value: bool) {
+        let word = &mut self.z[row * self.words + q / 64];
+        if value { *word |= 1 << (q % 64) } else { *word &= !(1 << (q % 64)) }
+    }
+}
+
+
+/// `Some(c)` if `b == c * a` for a unit-modulus `c`.
+fn phase_between(a: &[[C64; 2]; 2], b: &[[C64; 2]; 2]) -> Option<C64> {
+    let mut phase = None;
+    for i in 0..2 {
+        for j in 0..2 {
+            if a[i][j].norm() > EPS {
+                phase = Some(b[i][j] / a[i][j]);
+                break;
+            }
+        }
+        if phase.is_some() {
+            break;
+        }
+    }
+    let phase = phase?;
+    if (phase.norm() - 1.0).abs() > EPS {
+        return None;
+    }
+    for i in 0..2 {
+        for j in 0..2 {
+            if (a[i][j] * phase - b[i][j]).norm() > EPS {
+                return None;
+            }
+        }
+    }
+    Some(phase)
+}
+
+fn paulis() -> [[[C64; 2]; 2]; 4] {
+    let (o, l, i) = (C64::new(0.0, 0.0), C64::new(1.0, 0.0), C64::new(0.0, 1.0));
+    [
+        [[l, o], [o, l]],
+        [[o, l], [l, o]],
+        [[o, -i], [i, o]],
+        [[l, o], [o, -l]],
+    ]
+}
+
+fn mat_mul(a: &[[C64; 2]; 2], b: &[[C64; 2]; 2]) -> [[C64; 2]; 2] {
+    let mut out = [[C64::new(0.0, 0.0); 2]; 2];
+    for i in 0..2 {
+        for j in 0..2 {
+            out[i][j] = a[i][0] * b[0][j] + a[i][1] * b[1][j];
+        }
+    }
+    out
+}
+
+/// The 24 single-qubit Cliffords (mod global phase), each with an H/S word
+/// (`true` = H) that produces it when applied left to right.
+fn single_qubit_cliffords() -> &'static Vec<(Matrix2, Vec<bool>)> {
+    static CLIFFORDS: OnceLock<Vec<(Matrix2, Vec<bool>)>> = OnceLock::new();
+    CLIFFORDS.get_or_init(|| {
+        let v = 1.0 / std::f64::consts::SQRT_2;
+        let h = [[C64::new(v, 0.0), C64::new(v, 0.0)], [C64::new(v, 0.0), C64::new(-v, 0.0)]];
+        let s = [[C64::new(1.0, 0.0), C64::new(0.0, 0.0)], [C64::new(0.0, 0.0), C64::new(0.0, 1.0)]];
+
+        let mut found: Vec<(Matrix2, Vec<bool>)> = vec![(paulis()[0], vec![])];
+        let mut frontier = 0;
+        while frontier < found.len() {
+            let (matrix, word) = found[frontier].clone();
+            for is_h in [true, false] {
+                let next = mat_mul(if is_h { &h } else { &s }, &matrix);
+                if found.iter().all(|(m, _)| phase_between(m, &next).is_none()) {
+                    let mut next_word = word.clone();
+                    next_word.push(is_h);
+                    found.push((next, next_word));
+                }
+            }
+            frontier += 1;
+        }
+        found
+    })
+}
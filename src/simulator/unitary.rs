@@ -0,0 +1,183 @@
+// src/simulator/unitary.rs
+use super::C64;
+
+const EPS: f64 = 1e-12;
+// Hand-written matrices only carry a handful of digits.
+const UNITARITY_TOLERANCE: f64 = 1e-6;
+
+/// Row-major 2^k x 2^k gate matrix. The first target qubit is the most
+/// significant bit of the row/column index, so CNOT is diag(I, X).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unitary {
+    pub num_qubits: usize,
+    pub data: Vec<C64>,
+}
+
+impl Unitary {
+    pub fn single(matrix: [[C64; 2]; 2]) -> Self {
+        Unitary {
+            num_qubits: 1,
+            data: vec![matrix[0][0], matrix[0][1], matrix[1][0], matrix[1][1]],
+        }
+    }
+
+    /// Square matrix given row by row. Fails unless it is a 2^k x 2^k unitary.
+    pub fn from_rows(rows: Vec<Vec<C64>>) -> Result<Self, String> {
+        let dim = rows.len();
+        if dim < 2 || !dim.is_power_of_two() {
+            return Err(format!("matrix must be 2^k x 2^k with k >= 1, but it has {} rows", dim));
+        }
+        if let Some((i, row)) = rows.iter().enumerate().find(|(_, row)| row.len() != dim) {
+            return Err(format!("matrix must be square, but row {} has {} entries (expected {})", i, row.len(), dim));
+        }
+
+        let unitary = Unitary {
+            num_qubits: dim.trailing_zeros() as usize,
+            data: rows.into_iter().flatten().collect(),
+        };
+        let deviation = unitary.unitarity_deviation();
+        if deviation > UNITARITY_TOLERANCE {
+            return Err(format!("matrix is not unitary (max |U†U - I| entry is {:.2e})", deviation));
+        }
+        Ok(unitary)
+    }
+
+    pub fn swap() -> Self {
+        let mut data = vec![C64::new(0.0, 0.0); 16];
+        for (row, col) in [(0, 0), (1, 2), (2, 1), (3, 3)] {
+            data[row * 4 + col] = C64::new(1.0, 0.0);
+        }
+        Unitary { num_qubits: 2, data }
+    }
+
+    pub fn dim(&self) -> usize {
+        1 << self.num_qubits
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> C64 {
+        self.data[row * self.dim() + col]
+    }
+
+    /// diag(I, U): a new control qubit in front of the existing targets.
+    pub fn controlled(&self) -> Self {
+        let dim = self.dim();
+        let new_dim = 2 * dim;
+        let mut data = vec![C64::new(0.0, 0.0); new_dim * new_dim];
+        for i in 0..dim {
+            data[i * new_dim + i] = C64::new(1.0, 0.0);
+            for j in 0..dim {
+                data[(dim + i) * new_dim + dim + j] = self.get(i, j);
+            }
+        }
+        Unitary { num_qubits: self.num_qubits + 1, data }
+    }
+
+    /// Conjugate transpose.
+    pub fn dagger(&self) -> Self {
+        let dim = self.dim();
+        let mut data = vec![C64::new(0.0, 0.0); dim * dim];
+        for i in 0..dim {
+            for j in 0..dim {
+                data[j * dim + i] = self.get(i, j).conj();
+            }
+        }
+        Unitary { num_qubits: self.num_qubits, data }
+    }
+
+    pub fn as_single(&self) -> Option<[[C64; 2]; 2]> {
+        if self.num_qubits != 1 {
+            return None;
+        }
+        Some([[self.data[0], self.data[1]], [self.data[2], self.data[3]]])
+    }
+
+    pub fn is_swap(&self) -> bool {
+        self.num_qubits == 2 && self.approx_eq(&Unitary::swap())
+    }
+
+    /// Peel off target positions that only act as controls (the matrix is
+    /// diag(I, V) with respect to them). Returns the remaining operator and
+    /// the peeled positions, indexed into the original target list.
+    pub fn reduce_controls(&self) -> (Unitary, Vec<usize>) {
+        let mut current = self.clone();
+        let mut positions: Vec<usize> = (0..self.num_qubits).collect();
+        let mut peeled = Vec::new();
+
+        let mut j = 0;
+        while current.num_qubits > 1 && j < current.num_qubits {
+            match current.controlled_block(j) {
+                Some(block) => {
+                    peeled.push(positions.remove(j));
+                    current = block;
+                }
+                None => j += 1,
+            }
+        }
+        peeled.sort_unstable();
+        (current, peeled)
+    }
+
+    // If position `j` acts as a control, the operator on the remaining qubits.
+    fn controlled_block(&self, j: usize) -> Option<Unitary> {
+        let dim = self.dim();
+        let bit = 1 << (self.num_qubits - 1 - j);
+        for row in 0..dim {
+            for col in 0..dim {
+                let value = self.get(row, col);
+                let expected_zero = (row & bit) != (col & bit);
+                let in_identity_block = (row & bit) == 0 && (col & bit) == 0;
+                if expected_zero && value.norm() > EPS {
+                    return None;
+                }
+                if in_identity_block {
+                    let identity = if row == col { C64::new(1.0, 0.0) } else { C64::new(0.0, 0.0) };
+                    if (value - identity).norm() > EPS {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        // Drop bit j from the indices of the bit-set block.
+        let low = bit - 1;
+        let squeeze = |index: usize| ((index >> 1) & !low) | (index & low);
+        let new_dim = dim / 2;
+        let mut data = vec![C64::new(0.0, 0.0); new_dim * new_dim];
+        for row in (0..dim).filter(|r| r & bit != 0) {
+            for col in (0..dim).filter(|c| c & bit != 0) {
+                data[squeeze(row) * new_dim + squeeze(col)] = self.get(row, col);
+            }
+        }
+        Some(Unitary { num_qubits: self.num_qubits - 1, data })
+    }
+
+    // Largest entry of U†U - I.
+    fn unitarity_deviation(&self) -> f64 {
+        let dim = self.dim();
+        let mut worst: f64 = 0.0;
+        for i in 0..dim {
+            for j in 0..dim {
+                let dot: C64 = (0..dim).map(|k| self.get(k, i).conj() * self.get(k, j)).sum();
+                let identity = if i == j { C64::new(1.0, 0.0) } else { C64::new(0.0, 0.0) };
+                worst = worst.max((dot - identity).norm());
+            }
+        }
+        worst
+    }
+
+    fn approx_eq(&self, other: &Unitary) -> bool {
+        self.num_qubits == other.num_qubits
+            && self.data.iter().zip(other.data.iter()).all(|(a, b)| (a - b).norm() < EPS)
+    }
+}
+
+/// Basis-index offsets for every column of a gate acting on `targets`
+/// (first target = most significant bit of the gate index).
+pub fn target_offsets(targets: &[usize]) -> Vec<usize> {
+    let k = targets.len();
+    (0..1usize << k).map(|m| {
+        targets.iter().enumerate()
+            .filter(|(j, _)| (m >> (k - 1 - j)) & 1 == 1)
+            .fold(0, |offset, (_, &q)| offset | (1 << q))
+    }).collect()
+}
@@ -1,17 +1,29 @@
 // src/type_checker.rs
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::string::String;
-use crate::parser::ast::{ASTNode, Type, BinaryOperator, UnaryOperator, ImportSpec};
+use crate::parser::ast::{ASTNode, Type, BinaryOperator, UnaryOperator, ImportSpec, MatchCase, Pattern, Parameter, EnumVariant, Generics, GenericParam};
 
 
 use crate::parser::ast::ImportPath;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 use std::fs;
+use std::fmt;
 use crate::parser::ast::Loc;
+use crate::evaluator::Evaluator;
+use crate::environment::{Environment, RuntimeValue};
+use crate::diagnostics::{self, Diagnostic, Span};
+
+thread_local! {
+    // While `check_program` runs, the errors of statements it skipped so that it could
+    // carry on and report the rest of the file, with where each statement is. `None` otherwise.
+    static SKIPPED_ERRORS: RefCell<Option<Vec<TypeError>>> = const { RefCell::new(Option::None) };
+    // Warnings found since the last `take_warnings`.
+    static WARNINGS: RefCell<Vec<Diagnostic>> = const { RefCell::new(Vec::new()) };
+}
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -20,19 +32,71 @@ pub struct TypeInfo {
     pub is_mutable: bool,
 }
 
+/// Layout of a user-defined type, referred to elsewhere as `Type::Custom(name)`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserType {
+    Struct(Vec<Parameter>),
+    Enum(Vec<EnumVariant>),
+}
+
+/// Whether a function or circuit only applies gates, and so can be daggered or
+/// controlled. A non-unitary body records its first irreversible statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unitarity {
+    Unitary,
+    NonUnitary { loc: Option<Loc>, reason: String },
+}
+
 #[derive(Debug, Clone)]
 pub struct TypeEnvironment {
     store: HashMap<String, TypeInfo>,
+    user_types: HashMap<String, UserType>,
+    // Generic parameters and `where` clauses of generic functions, by name.
+    generics: HashMap<String, Generics>,
+    unitarity: HashMap<String, Unitarity>,
     outer: Option<Rc<RefCell<TypeEnvironment>>>,
 }
 
 impl TypeEnvironment {
     pub fn new() -> Self {
-        TypeEnvironment { store: HashMap::new(), outer: Option::None }
+        TypeEnvironment { store: HashMap::new(), user_types: HashMap::new(), generics: HashMap::new(), unitarity: HashMap::new(), outer: Option::None }
     }
 
     pub fn new_enclosed(outer_env: Rc<RefCell<TypeEnvironment>>) -> Self {
-        TypeEnvironment { store: HashMap::new(), outer: Some(outer_env) }
+        TypeEnvironment { store: HashMap::new(), user_types: HashMap::new(), generics: HashMap::new(), unitarity: HashMap::new(), outer: Some(outer_env) }
+    }
+
+    pub fn get_generics(&self, name: &str) -> Option<Generics> {
+        if let Some(g) = self.generics.get(name) {
+            return Some(g.clone());
+        }
+        self.outer.as_ref().and_then(|outer| outer.borrow().get_generics(name))
+    }
+
+    pub fn set_generics(&mut self, name: String, generics: Generics) {
+        self.generics.insert(name, generics);
+    }
+
+    pub fn get_unitarity(&self, name: &str) -> Option<Unitarity> {
+        if let Some(u) = self.unitarity.get(name) {
+            return Some(u.clone());
+        }
+        self.outer.as_ref().and_then(|outer| outer.borrow().get_unitarity(name))
+    }
+
+    pub fn set_unitarity(&mut self, name: String, unitarity: Unitarity) {
+        self.unitarity.insert(name, unitarity);
+    }
+
+    pub fn get_user_type(&self, name: &str) -> Option<UserType> {
+        if let Some(t) = self.user_types.get(name) {
+            return Some(t.clone());
+        }
+        self.outer.as_ref().and_then(|outer| outer.borrow().get_user_type(name))
+    }
+
+    pub fn set_user_type(&mut self, name: String, t: UserType) {
+        self.user_types.insert(name, t);
     }
 
     pub fn get(&self, name: &str) -> Option<TypeInfo> {
@@ -51,6 +115,77 @@ impl TypeEnvironment {
 }
 
 
+// The types a module defines, the generics of its generic functions and whether
+// its functions and circuits are unitary.
+type ModuleTypes = (HashMap<String, Type>, HashMap<String, Generics>, HashMap<String, Unitarity>);
+
+/// A type error, where it is, and which kind of mistake it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub code: &'static str,
+    pub message: String,
+    pub loc: Option<Loc>,
+    // The name an undefined-name error is about, to suggest similar ones.
+    name: Option<String>,
+    // Another place the error involves, and what happens there.
+    related: Option<Box<(Loc, String)>>,
+    note: Option<&'static str>,
+}
+
+impl TypeError {
+    fn at(loc: &Loc, message: impl Into<String>) -> Self {
+        TypeError::from(message.into()).located(Some(*loc))
+    }
+
+    fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    fn undefined(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self.with_code(diagnostics::UNDEFINED_NAME)
+    }
+
+    fn with_related(mut self, loc: Loc, label: impl Into<String>) -> Self {
+        self.related = Some(Box::new((loc, label.into())));
+        self
+    }
+
+    fn with_note(mut self, note: &'static str) -> Self {
+        self.note = Some(note);
+        self
+    }
+
+    // Errors raised below a node without a location of their own are about that node.
+    fn located(mut self, loc: Option<Loc>) -> Self {
+        self.loc = self.loc.or(loc);
+        self
+    }
+}
+
+impl From<String> for TypeError {
+    fn from(message: String) -> Self {
+        TypeError { code: diagnostics::TYPE_ERROR, message, loc: Option::None, name: Option::None, related: Option::None, note: Option::None }
+    }
+}
+
+impl From<&str> for TypeError {
+    fn from(message: &str) -> Self {
+        TypeError::from(message.to_string())
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.code == diagnostics::MUTABILITY_ERROR { "Mutability Error" } else { "Type Error" };
+        match self.loc {
+            Some(loc) => write!(f, "{} at {}: {}", kind, loc, self.message),
+            Option::None => write!(f, "{}: {}", kind, self.message),
+        }
+    }
+}
+
 pub struct TypeChecker;
 
 impl TypeChecker {
@@ -85,6 +220,27 @@ impl TypeChecker {
         env_mut.set("to_string".to_string(), immut(Type::Function(vec![any.clone()], Box::new(Type::String))));
         env_mut.set("to_int".to_string(), immut(Type::Function(vec![any.clone()], Box::new(Type::Int))));
         env_mut.set("to_float".to_string(), immut(Type::Function(vec![any.clone()], Box::new(Type::Float))));
+        env_mut.set("set_seed".to_string(), immut(Type::Function(vec![Type::Int], none_type.clone())));
+        env_mut.set("observable".to_string(), immut(Type::Function(vec![Type::Any], Box::new(Type::Observable))));
+        let observable_stat = Type::Function(vec![Type::QuantumRegister(None), Type::Observable], Box::new(Type::Float));
+        env_mut.set("expectation".to_string(), immut(observable_stat.clone()));
+        env_mut.set("variance".to_string(), immut(observable_stat));
+        let register = Type::QuantumRegister(None);
+        env_mut.set("amplitude".to_string(), immut(Type::Function(vec![register.clone(), any.clone()], Box::new(Type::Complex))));
+        env_mut.set("probabilities".to_string(), immut(Type::Function(vec![register.clone()], Box::new(Type::Dict))));
+        // Registers or kets, checked at runtime.
+        env_mut.set("fidelity".to_string(), immut(Type::Function(vec![any.clone(), any.clone()], Box::new(Type::Float))));
+        env_mut.set("reduced_density_matrix".to_string(), immut(Type::Function(
+            vec![register, Type::Array(Box::new(Type::Int))],
+            Box::new(Type::Array(Box::new(Type::Array(Box::new(Type::Complex)))))
+        )));
+        // Ints, floats or complex numbers; `exp` of a complex number is complex.
+        let real_valued = Type::Function(vec![any.clone()], Box::new(Type::Float));
+        for name in ["abs", "arg", "exp", "real", "imag"] {
+            env_mut.set(name.to_string(), immut(real_valued.clone()));
+        }
+        env_mut.set("conj".to_string(), immut(Type::Function(vec![any.clone()], Box::new(Type::Complex))));
+        env_mut.set("Error".to_string(), immut(Type::Function(vec![Type::String, Type::String], Box::new(Type::Error))));
 
         // --- Single-Qubit Gates ---
         let single_qubit_gate = Type::Function(vec![qubit_type.clone()], none_type.clone());
@@ -135,15 +291,504 @@ impl TypeChecker {
 
 
     pub fn check_program(node: &ASTNode) -> Result<(), String> {
-        if let ASTNode::Program(statements) = node {
-            let env = Rc::new(RefCell::new(TypeEnvironment::new()));
-            Self::prefill_environment(&env);
-            for stmt in statements {
-                Self::check(stmt, &env, Option::None)?;
+        match Self::check_program_errors(node).0.into_iter().next() {
+            Some(error) => Err(error.to_string()),
+            Option::None => Ok(()),
+        }
+    }
+
+    /// Check a whole program and report every error in it rather than the first.
+    /// Either way, the warnings come last.
+    pub fn check_program_with_diagnostics(node: &ASTNode) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+        let (errors, env) = Self::check_program_errors(node);
+        let warnings = Self::take_warnings();
+        if errors.is_empty() {
+            return Ok(warnings);
+        }
+        let env = env.borrow();
+        let names: Vec<&str> = env.store.keys().map(String::as_str).collect();
+        Err(errors.iter().map(|error| Self::diagnostic(error, &names)).chain(warnings).collect())
+    }
+
+    /// The warnings found since this was last called.
+    pub fn take_warnings() -> Vec<Diagnostic> {
+        WARNINGS.with(|warnings| warnings.take())
+    }
+
+    // Errors in source order, and the top-level environment the program left behind.
+    fn check_program_errors(node: &ASTNode) -> (Vec<TypeError>, Rc<RefCell<TypeEnvironment>>) {
+        // Warnings nobody took belong to an earlier program.
+        Self::take_warnings();
+        let env = Rc::new(RefCell::new(TypeEnvironment::new()));
+        Self::prefill_environment(&env);
+        let ASTNode::Program(statements) = node else {
+            return (vec![TypeError::from("Expected Program node")], env);
+        };
+        let outer = SKIPPED_ERRORS.with(|skipped| skipped.replace(Some(Vec::new())));
+        for stmt in statements {
+            if let Err(error) = Self::check(stmt, &env, Option::None) {
+                let _ = Self::skip_statement(stmt, &env, error);
+            }
+        }
+        let mut errors = SKIPPED_ERRORS.with(|skipped| skipped.replace(outer)).unwrap_or_default();
+        if let Err(error) = QubitOwnership::new(&env).check_sequence(statements) {
+            errors.push(error);
+        }
+        (errors, env)
+    }
+
+    // Inside `check_program`, record the error of a statement and declare whatever it
+    // would have declared as `Any`, so later uses do not report the same mistake again.
+    fn skip_statement(stmt: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>, error: TypeError) -> Result<(), TypeError> {
+        let recorded = SKIPPED_ERRORS.with(|skipped| match skipped.borrow_mut().as_mut() {
+            Some(errors) => {
+                errors.push(error.clone().located(stmt.loc()));
+                true
             }
-            Ok(())
+            Option::None => false,
+        });
+        if !recorded {
+            return Err(error);
+        }
+        if let ASTNode::LetDeclaration { name, .. } | ASTNode::QuantumDeclaration { name, .. } = stmt {
+            env.borrow_mut().set(name.clone(), TypeInfo { var_type: Type::Any, is_mutable: true });
+        }
+        Ok(())
+    }
+
+    /// A diagnostic for a type error, with a suggestion for misspelled names among `names`.
+    pub fn diagnostic(error: &TypeError, names: &[&str]) -> Diagnostic {
+        let mut diagnostic = Diagnostic::error(error.code, error.message.clone());
+        if let Some(loc) = error.loc {
+            diagnostic = diagnostic.with_primary(Span::from(loc), "");
+        }
+        if let Some((loc, label)) = error.related.as_deref() {
+            diagnostic = diagnostic.with_secondary(Span::from(*loc), label.clone());
+        }
+        if let Some(note) = error.note {
+            diagnostic = diagnostic.with_note(note);
+        }
+        if error.code == diagnostics::MUTABILITY_ERROR {
+            return diagnostic.with_suggestion("declare it with 'mut' to allow assignment");
+        }
+        match error.name.as_deref().and_then(|name| diagnostics::closest_name(name, names.iter().copied())) {
+            Some(similar) => diagnostic.with_suggestion(format!("a similar name is defined: '{}'", similar)),
+            Option::None => diagnostic,
+        }
+    }
+
+    // A sized register or ket where the annotation leaves the size open, or a
+    // number widened to a complex type.
+    fn is_assignable(actual: &Type, expected: &Type) -> bool {
+        matches!(
+            (actual, expected),
+            (Type::QuantumRegister(_), Type::QuantumRegister(None)) | (Type::Ket(_), Type::Ket(None)) | (Type::Bra(_), Type::Bra(None))
+        ) || (Self::is_complex(expected) && (Self::is_complex(actual) || matches!(actual, Type::Int | Type::Float)))
+            || Self::is_compatible_function(actual, expected)
+    }
+
+    // Function types match position by position, with `Any` (e.g. an undeclared return type) matching anything.
+    fn is_compatible_function(actual: &Type, expected: &Type) -> bool {
+        let (Type::Function(actual_params, actual_return), Type::Function(expected_params, expected_return)) = (actual, expected) else {
+            return false;
+        };
+        let fits = |a: &Type, b: &Type| a == b || *a == Type::Any || *b == Type::Any || Self::is_assignable(a, b);
+        actual_params.len() == expected_params.len()
+            && actual_params.iter().zip(expected_params).all(|(a, b)| fits(b, a))
+            && fits(actual_return, expected_return)
+    }
+
+    fn is_complex(t: &Type) -> bool {
+        matches!(t, Type::Complex | Type::Complex64 | Type::Complex128)
+    }
+
+    // Arithmetic with a complex operand promotes the other one.
+    fn complex_promotion(left: &Type, right: &Type) -> Option<Type> {
+        let is_number = |t: &Type| matches!(t, Type::Int | Type::Float) || Self::is_complex(t);
+        if (Self::is_complex(left) || Self::is_complex(right)) && is_number(left) && is_number(right) {
+            Some(Type::Complex)
         } else {
-            Err("Expected Program node".to_string())
+            Option::None
+        }
+    }
+
+    fn check_pattern(pattern: &Pattern, value_type: &Type, env: &Rc<RefCell<TypeEnvironment>>, loc: &Loc) -> Result<(), TypeError> {
+        let is_number = |t: &Type| matches!(t, Type::Int | Type::Float | Type::Any);
+        let compatible = |t: &Type| t == value_type || *t == Type::Any || *value_type == Type::Any || (is_number(t) && is_number(value_type));
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Identifier(name) => {
+                env.borrow_mut().set(name.clone(), Self::immutable_info(value_type.clone()));
+                Ok(())
+            }
+            Pattern::Literal(expr) => {
+                let literal_type = Self::check(expr, env, Option::None)?;
+                if !compatible(&literal_type) {
+                    return Err(TypeError::at(loc, format!("Pattern of type {:?} can never match a value of type {:?}", literal_type, value_type)));
+                }
+                Ok(())
+            }
+            Pattern::Range { start, end, .. } => {
+                let start_type = Self::check(start, env, Option::None)?;
+                let end_type = Self::check(end, env, Option::None)?;
+                if !is_number(&start_type) || !is_number(&end_type) || !is_number(value_type) {
+                    return Err(TypeError::at(loc, format!(
+                        "Range patterns need numeric bounds and a numeric value, got {:?}..{:?} for {:?}", start_type, end_type, value_type
+                    )));
+                }
+                Ok(())
+            }
+            Pattern::Alternatives(options) => {
+                let names = pattern.bindings();
+                for option in options {
+                    let mut option_names = option.bindings();
+                    option_names.sort();
+                    let mut expected = names.clone();
+                    expected.sort();
+                    if option_names != expected {
+                        return Err(TypeError::at(loc, "Every alternative of a pattern must bind the same names"));
+                    }
+                    Self::check_pattern(option, value_type, env, loc)?;
+                }
+                Ok(())
+            }
+            Pattern::Binding { pattern, name } => {
+                Self::check_pattern(pattern, value_type, env, loc)?;
+                env.borrow_mut().set(name.clone(), Self::immutable_info(value_type.clone()));
+                Ok(())
+            }
+            Pattern::Variant { enum_name, variant, fields } => {
+                let variants = match value_type {
+                    Type::Custom(name) if enum_name.as_ref().is_none_or(|expected| expected == name) => {
+                        match env.borrow().get_user_type(name) {
+                            Some(UserType::Enum(variants)) => variants,
+                            _ => return Err(TypeError::at(loc, format!("Variant pattern '{}' cannot match a value of type {:?}", variant, value_type))),
+                        }
+                    }
+                    Type::Any => {
+                        for field in fields {
+                            Self::check_pattern(field, &Type::Any, env, loc)?;
+                        }
+                        return Ok(());
+                    }
+                    _ => return Err(TypeError::at(loc, format!("Variant pattern '{}' cannot match a value of type {:?}", variant, value_type))),
+                };
+                let Some(declared) = variants.iter().find(|v| v.name == *variant) else {
+                    return Err(TypeError::at(loc, format!("{:?} has no variant named '{}'", value_type, variant)));
+                };
+                if declared.fields.len() != fields.len() {
+                    return Err(TypeError::at(loc, format!(
+                        "Variant '{}' carries {} values, but the pattern has {}", variant, declared.fields.len(), fields.len()
+                    )));
+                }
+                for (field, field_type) in fields.iter().zip(&declared.fields) {
+                    Self::check_pattern(field, field_type, env, loc)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Impl blocks replace `Self`, so any that is left appears outside of one.
+    fn check_receiver(name: &str, parameters: &[Parameter], return_type: &Option<Type>) -> Result<(), TypeError> {
+        let self_type = Type::Custom("Self".to_string());
+        if parameters.iter().any(|p| p.param_type == self_type) || return_type.as_ref() == Some(&self_type) {
+            return Err(TypeError::from(format!("'{}' uses 'self' or 'Self' outside of an impl block", name)));
+        }
+        Ok(())
+    }
+
+    fn mentions_generic(ty: &Type, name: Option<&str>) -> bool {
+        match ty {
+            Type::Generic(param) | Type::GenericRegister(param) => name.is_none_or(|name| name == param),
+            Type::Array(inner) | Type::QuantumArray(inner, _) | Type::Tensor(inner, _) => Self::mentions_generic(inner, name),
+            Type::Function(params, return_type) => {
+                params.iter().any(|p| Self::mentions_generic(p, name)) || Self::mentions_generic(return_type, name)
+            }
+            _ => false,
+        }
+    }
+
+    // Match an argument type against a parameter type that mentions generic parameters,
+    // recording what each parameter stands for. Registers of unknown size bind nothing.
+    fn bind_generics(param: &Type, arg: &Type, types: &mut HashMap<String, Type>, sizes: &mut HashMap<String, usize>) -> bool {
+        match (param, arg) {
+            (Type::Generic(name), _) => match types.get(name) {
+                Some(bound) if *bound != Type::Any => bound == arg || Self::is_assignable(arg, bound),
+                _ => {
+                    types.insert(name.clone(), arg.clone());
+                    true
+                }
+            },
+            (Type::GenericRegister(name), Type::QuantumRegister(size)) => match (sizes.get(name), size) {
+                (Some(bound), Some(size)) => bound == size,
+                (Option::None, Some(size)) => {
+                    sizes.insert(name.clone(), *size);
+                    true
+                }
+                (_, Option::None) => true,
+            },
+            (Type::Array(param), Type::Array(arg)) => Self::bind_generics(param, arg, types, sizes),
+            (Type::Function(params, return_type), Type::Function(args, arg_return)) => {
+                params.len() == args.len()
+                    && params.iter().zip(args).all(|(p, a)| Self::bind_generics(p, a, types, sizes))
+                    && Self::bind_generics(return_type, arg_return, types, sizes)
+            }
+            _ => param == arg || *param == Type::Any || Self::is_assignable(arg, param),
+        }
+    }
+
+    fn substitute_generics(ty: &Type, types: &HashMap<String, Type>, sizes: &HashMap<String, usize>) -> Type {
+        match ty {
+            Type::Generic(name) => types.get(name).cloned().unwrap_or(Type::Any),
+            Type::GenericRegister(name) => Type::QuantumRegister(sizes.get(name).copied()),
+            Type::Array(inner) => Type::Array(Box::new(Self::substitute_generics(inner, types, sizes))),
+            Type::Function(params, return_type) => Type::Function(
+                params.iter().map(|p| Self::substitute_generics(p, types, sizes)).collect(),
+                Box::new(Self::substitute_generics(return_type, types, sizes)),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    // `where` clauses are checked once every size is known from the arguments; calls
+    // passing registers of unknown size are left to the runtime check.
+    fn check_where_clause(name: &str, generics: &Generics, sizes: &HashMap<String, usize>, loc: &Loc) -> Result<(), TypeError> {
+        if generics.size_params().any(|param| !sizes.contains_key(param)) {
+            return Ok(());
+        }
+        let env = Rc::new(RefCell::new(Environment::new()));
+        for (param, size) in sizes {
+            env.borrow_mut().set(param.clone(), RuntimeValue::Int(*size as i64));
+        }
+        if let Ok(false) = Evaluator::where_clause_holds(&generics.where_clause, &env) {
+            let bound: Vec<String> = generics.size_params().map(|param| format!("{} = {}", param, sizes[param])).collect();
+            return Err(TypeError::at(loc, format!(
+                "Call to '{}' does not satisfy its 'where' clause with {}", name, bound.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    // The variable a place such as `a.b[0].c` lives in, if that variable is immutable.
+    fn immutable_root(place: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>) -> Option<String> {
+        let mut root = place;
+        while let ASTNode::MemberAccess { object, .. } | ASTNode::ArrayAccess { array: object, .. } = root {
+            root = object;
+        }
+        match root {
+            ASTNode::Identifier { name, .. } if env.borrow().get(name).is_some_and(|info| !info.is_mutable) => Some(name.clone()),
+            _ => Option::None,
+        }
+    }
+
+    // A method taking `mut self` changes its receiver in place, so the receiver must be
+    // mutable, and it cannot be called on the type with the receiver passed by value.
+    fn check_mut_receiver(callee: &ASTNode, loc: &Loc, env: &Rc<RefCell<TypeEnvironment>>) -> Result<(), TypeError> {
+        let ASTNode::MemberAccess { object, member } = callee else {
+            return Ok(());
+        };
+        if let ASTNode::Identifier { name, .. } = object.as_ref() {
+            let is_value = matches!(env.borrow().get(name), Some(TypeInfo { var_type: Type::Custom(_), .. }));
+            let is_type = !is_value && env.borrow().get_user_type(name).is_some();
+            if is_type && env.borrow().get(&format!("{}.{}", name, member)).is_some_and(|info| info.is_mutable) {
+                return Err(TypeError::at(loc, format!(
+                    "'{}.{}' takes 'mut self' and must be called on a mutable value, as 'value.{}(...)'", name, member, member
+                )));
+            }
+        }
+        let Some(method) = Self::callable_key(callee, env) else {
+            return Ok(());
+        };
+        if !env.borrow().get(&method).is_some_and(|info| info.is_mutable) {
+            return Ok(());
+        }
+        match Self::immutable_root(object, env) {
+            Some(name) => Err(TypeError::at(loc, format!(
+                "'{}' takes 'mut self', but '{}' is immutable; declare it with 'mut'.", method, name
+            )).with_code(diagnostics::MUTABILITY_ERROR)),
+            Option::None => Ok(()),
+        }
+    }
+
+    // The name a callee is classified under: methods are qualified by their type, and
+    // functions of a module imported under an alias by the alias.
+    fn callable_key(callee: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>) -> Option<String> {
+        match callee {
+            ASTNode::Identifier { name, .. } | ASTNode::Gate { name, .. } => Some(name.clone()),
+            ASTNode::MemberAccess { object, member } => match (&**object, Self::check(object, env, Option::None)) {
+                (_, Ok(Type::Custom(type_name))) => Some(format!("{}.{}", type_name, member)),
+                (ASTNode::Identifier { name: alias, .. }, Ok(Type::Module(_))) => Some(format!("{}.{}", alias, member)),
+                _ => Option::None,
+            },
+            _ => Option::None,
+        }
+    }
+
+    // A body is unitary if it only applies gates, calls unitary functions and circuits
+    // and loops over them with `for`; anything else makes it irreversible.
+    fn classify_unitarity(statements: &[ASTNode], env: &Rc<RefCell<TypeEnvironment>>) -> Unitarity {
+        match statements.iter().find_map(|stmt| Self::irreversible_statement(stmt, env)) {
+            Some((loc, reason)) => Unitarity::NonUnitary { loc, reason },
+            Option::None => Unitarity::Unitary,
+        }
+    }
+
+    fn irreversible_statement(stmt: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>) -> Option<(Option<Loc>, String)> {
+        let found = |reason: &str| Some((stmt.loc(), reason.to_string()));
+        match stmt {
+            ASTNode::Block(statements) => return statements.iter().find_map(|stmt| Self::irreversible_statement(stmt, env)),
+            ASTNode::For { iterator, body, .. } if !iterator.any_node(&|node| matches!(node, ASTNode::Measure(_))) => {
+                return Self::irreversible_statement(body, env);
+            }
+            _ => {}
+        }
+        let mut measures = Vec::new();
+        Self::collect_measures(stmt, &mut measures);
+        if let Some(measure) = measures.first() {
+            return Some((measure.loc().or(stmt.loc()), "measures a qubit".to_string()));
+        }
+        let call_reason = |callee: &ASTNode| {
+            let key = Self::callable_key(callee, env);
+            match key.as_ref().map(|key| (key, env.borrow().get_unitarity(key))) {
+                Some((_, Some(Unitarity::Unitary))) => Option::None,
+                Some((key, Some(Unitarity::NonUnitary { .. }))) => Some(format!("calls '{}', which is not unitary", key)),
+                Some((key, Option::None)) => Some(format!("calls '{}'", key)),
+                Option::None => Some("calls a value that is not known to be unitary".to_string()),
+            }
+        };
+        match stmt {
+            ASTNode::Apply { gate_expr, .. } => {
+                let mut base = &**gate_expr;
+                while let ASTNode::Dagger { gate_expr, .. } | ASTNode::Controlled { gate_expr, .. } = base {
+                    base = gate_expr;
+                }
+                match base {
+                    ASTNode::Gate { name, .. } if name == "reset" => found("resets a qubit"),
+                    // Built-in gates are unitary; everything else must have been classified.
+                    ASTNode::Gate { name, .. } if env.borrow().get_unitarity(name).is_none() && Self::is_builtin_gate(name) => Option::None,
+                    ASTNode::ParameterizedGate { .. } => Option::None,
+                    _ => call_reason(base).map(|reason| (stmt.loc(), reason)),
+                }
+            }
+            ASTNode::FunctionCall { callee, .. } => call_reason(callee).map(|reason| (stmt.loc(), reason)),
+            ASTNode::Return(Option::None) => Option::None,
+            ASTNode::Return(Some(_)) => found("returns a value"),
+            ASTNode::LetDeclaration { .. } | ASTNode::Assignment { .. } => found("assigns a classical variable"),
+            ASTNode::If { .. } | ASTNode::Match { .. } => found("branches on a classical value"),
+            ASTNode::While { .. } => found("has a 'while' loop"),
+            ASTNode::Break | ASTNode::Continue => found("leaves a loop early"),
+            ASTNode::TryCatch { .. } | ASTNode::Throw { .. } => found("raises or handles errors"),
+            ASTNode::QuantumDeclaration { .. } => found("allocates a quantum register"),
+            _ => found("evaluates a classical expression"),
+        }
+    }
+
+    fn is_builtin_gate(name: &str) -> bool {
+        Evaluator::get_gate_unitary(&name.to_lowercase(), &[0.0; 3], false).is_ok()
+    }
+
+    fn collect_measures<'a>(node: &'a ASTNode, measures: &mut Vec<&'a ASTNode>) {
+        if let ASTNode::Measure(_) = node {
+            measures.push(node);
+        }
+        for child in node.children() {
+            Self::collect_measures(child, measures);
+        }
+    }
+
+    // `dagger` and `controlled` need a unitary target; the error points at the
+    // statement that makes it irreversible when that is in this file.
+    fn check_unitary_target(operation: &str, target: &ASTNode, loc: &Loc, env: &Rc<RefCell<TypeEnvironment>>) -> Result<(), TypeError> {
+        let not_unitary = |error: TypeError| error.with_code(diagnostics::NOT_UNITARY)
+            .with_note("only bodies that apply gates, call unitary circuits and loop with 'for' can be inverted or controlled");
+        let mut base = target;
+        while let ASTNode::Dagger { gate_expr, .. } | ASTNode::Controlled { gate_expr, .. } = base {
+            base = gate_expr;
+        }
+        if let ASTNode::Gate { name, .. } | ASTNode::Identifier { name, .. } = base {
+            if name.eq_ignore_ascii_case("reset") {
+                let done = if operation == "dagger" { "daggered" } else { "controlled" };
+                return Err(not_unitary(TypeError::at(loc, format!("'reset' is not unitary and cannot be {}.", done))));
+            }
+        }
+        if let ASTNode::ParameterizedGate { .. } = base {
+            return Ok(());
+        }
+        let Some(key) = Self::callable_key(base, env) else {
+            return Err(TypeError::at(loc, format!("'{}' needs a gate or circuit, whose unitarity is known.", operation)));
+        };
+        let builtin_gate = || matches!(base, ASTNode::Gate { .. } | ASTNode::Identifier { .. }) && Self::is_builtin_gate(&key);
+        let unitarity = env.borrow().get_unitarity(&key);
+        match unitarity {
+            Option::None if builtin_gate() => Ok(()),
+            Option::None => Err(not_unitary(TypeError::at(loc, format!(
+                "'{}' needs '{}' to be unitary, but it is not a gate or circuit whose unitarity is known.", operation, key
+            )))),
+            Some(Unitarity::NonUnitary { loc: Some(at), reason }) => Err(not_unitary(TypeError::at(&at, format!(
+                "'{}' at {} needs '{}' to be unitary, but it {} here.", operation, loc, key, reason
+            )).with_related(*loc, format!("'{}' is applied here", operation)))),
+            Some(Unitarity::NonUnitary { loc: Option::None, reason }) => Err(not_unitary(TypeError::at(loc, format!(
+                "'{}' needs '{}' to be unitary, but it {}.", operation, key, reason
+            )))),
+            _ => Ok(()),
+        }
+    }
+
+    // struct and enum declarations may only mention declared types (or themselves).
+    fn check_user_type_fields<'a>(name: &str, field_types: impl Iterator<Item = &'a Type>, env: &Rc<RefCell<TypeEnvironment>>, loc: &Loc) -> Result<(), TypeError> {
+        for field_type in field_types {
+            if let Type::Custom(field_type_name) = field_type {
+                if field_type_name != name && env.borrow().get_user_type(field_type_name).is_none() {
+                    return Err(TypeError::at(loc, format!("Unknown type '{}' in declaration of '{}'", field_type_name, name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Values no unguarded case covers, for the types whose values can be listed.
+    fn missing_match_cases(value_type: &Type, cases: &[MatchCase], env: &Rc<RefCell<TypeEnvironment>>) -> Option<String> {
+        let unguarded: Vec<&Pattern> = cases.iter().filter(|case| case.guard.is_none()).map(|case| &case.pattern).collect();
+        if unguarded.iter().any(|pattern| pattern.is_irrefutable()) {
+            return Option::None;
+        }
+        fn covers_bool(pattern: &Pattern, value: bool) -> bool {
+            match pattern {
+                Pattern::Literal(ASTNode::BoolLiteral(b)) => *b == value,
+                Pattern::Alternatives(options) => options.iter().any(|option| covers_bool(option, value)),
+                Pattern::Binding { pattern, .. } => covers_bool(pattern, value),
+                _ => false,
+            }
+        }
+        match value_type {
+            Type::Bool => {
+                let missing: Vec<&str> = [(true, "true"), (false, "false")].into_iter()
+                    .filter(|(value, _)| !unguarded.iter().any(|pattern| covers_bool(pattern, *value)))
+                    .map(|(_, name)| name)
+                    .collect();
+                if missing.is_empty() { Option::None } else { Some(missing.join(" and ")) }
+            }
+            Type::Custom(name) => {
+                let Some(UserType::Enum(variants)) = env.borrow().get_user_type(name) else {
+                    return Option::None;
+                };
+                // A variant is covered by a pattern that accepts every payload.
+                fn covers_variant(pattern: &Pattern, variant: &str) -> bool {
+                    match pattern {
+                        Pattern::Variant { variant: v, fields, .. } => v == variant && fields.iter().all(Pattern::is_irrefutable),
+                        Pattern::Alternatives(options) => options.iter().any(|option| covers_variant(option, variant)),
+                        Pattern::Binding { pattern, .. } => covers_variant(pattern, variant),
+                        _ => false,
+                    }
+                }
+                let missing: Vec<String> = variants.iter()
+                    .filter(|variant| !unguarded.iter().any(|pattern| covers_variant(pattern, &variant.name)))
+                    .map(|variant| format!("{}.{}", name, variant.name))
+                    .collect();
+                if missing.is_empty() { Option::None } else { Some(missing.join(", ")) }
+            }
+            _ => Option::None,
         }
     }
 
@@ -151,7 +796,7 @@ impl TypeChecker {
         TypeInfo { var_type: t, is_mutable: false }
     }
 
-    fn check_module(path: &ImportPath) -> Result<HashMap<String, Type>, String> {
+    fn check_module(path: &ImportPath) -> Result<ModuleTypes, TypeError> {
         let file_path = match path {
 
             ImportPath::File(f) => {
@@ -181,56 +826,108 @@ impl TypeChecker {
         Self::prefill_environment(&module_env);
 
         if let ASTNode::Program(statements) = ast {
-            for stmt in statements {
-                Self::check(&stmt, &module_env, None)?;
-            }
+            // A module stops at its first error: its locations mean nothing in the importing file.
+            let outer = SKIPPED_ERRORS.with(|skipped| skipped.take());
+            let outer_warnings = Self::take_warnings();
+            let checked = statements.iter().try_for_each(|stmt| Self::check(stmt, &module_env, None).map(|_| ()))
+                .and_then(|()| QubitOwnership::new(&module_env).check_sequence(&statements));
+            SKIPPED_ERRORS.with(|skipped| skipped.replace(outer));
+            let module_warnings = Self::take_warnings().into_iter().map(|warning| {
+                let at = warning.primary.as_ref().map(|label| format!(" at {}", label.span.start)).unwrap_or_default();
+                Diagnostic { message: format!("In module '{}'{}: {}", file_path, at, warning.message), primary: Option::None, secondary: Vec::new(), ..warning }
+            });
+            WARNINGS.with(|warnings| warnings.replace(outer_warnings.into_iter().chain(module_warnings).collect()));
+            checked.map_err(|error| TypeError { message: format!("In module '{}': {}", file_path, error), loc: Option::None, related: Option::None, ..error })?;
         } else {
-            return Err("Module root is not a Program node".to_string());
+            return Err("Module root is not a Program node".to_string().into());
         }
 
         let module_types = module_env.borrow().store.iter()
             .map(|(k, v)| (k.clone(), v.var_type.clone()))
             .collect();
+        let module_generics = module_env.borrow().generics.clone();
+        // Locations inside the module mean nothing in the importing file.
+        let module_unitarity = module_env.borrow().unitarity.iter()
+            .map(|(name, unitarity)| match unitarity {
+                Unitarity::NonUnitary { reason, .. } => (name.clone(), Unitarity::NonUnitary { loc: Option::None, reason: reason.clone() }),
+                Unitarity::Unitary => (name.clone(), Unitarity::Unitary),
+            })
+            .collect();
 
-        Ok(module_types)
+        Ok((module_types, module_generics, module_unitarity))
+    }
+
+    // Ket/bra algebra; `None` when neither operand is a ket or a bra.
+    fn check_ket_op(operator: &BinaryOperator, left: &Type, right: &Type, loc: &Loc) -> Result<Option<Type>, TypeError> {
+        if !matches!(left, Type::Ket(_) | Type::Bra(_)) && !matches!(right, Type::Ket(_) | Type::Bra(_)) {
+            return Ok(Option::None);
+        }
+        let is_scalar = |t: &Type| matches!(t, Type::Int | Type::Float | Type::Any) || Self::is_complex(t);
+        let same_width = |a: &Option<usize>, b: &Option<usize>| match (a, b) {
+            (Some(a), Some(b)) if a != b => Err(TypeError::at(loc, format!(
+                "Operator {:?} needs kets of the same width, got {} and {} qubits", operator, a, b
+            ))),
+            (Some(_), _) => Ok(*a),
+            _ => Ok(*b),
+        };
+        let result = match (operator, left, right) {
+            (BinaryOperator::Add | BinaryOperator::Sub, Type::Ket(a), Type::Ket(b)) => Type::Ket(same_width(a, b)?),
+            (BinaryOperator::Add | BinaryOperator::Sub, Type::Bra(a), Type::Bra(b)) => Type::Bra(same_width(a, b)?),
+            (BinaryOperator::TensorProduct, Type::Ket(a), Type::Ket(b)) => Type::Ket(a.zip(*b).map(|(a, b)| a + b)),
+            (BinaryOperator::TensorProduct, Type::Bra(a), Type::Bra(b)) => Type::Bra(a.zip(*b).map(|(a, b)| a + b)),
+            (BinaryOperator::Mul, Type::Bra(a), Type::Ket(b)) => {
+                same_width(a, b)?;
+                Type::Complex
+            }
+            (BinaryOperator::Mul, Type::Ket(_) | Type::Bra(_), factor) if is_scalar(factor) => left.clone(),
+            (BinaryOperator::Mul, factor, Type::Ket(_) | Type::Bra(_)) if is_scalar(factor) => right.clone(),
+            (BinaryOperator::Div, Type::Ket(_) | Type::Bra(_), divisor) if is_scalar(divisor) => left.clone(),
+            _ => return Err(TypeError::at(loc, format!(
+                "Operator {:?} is not defined for {:?} and {:?}", operator, left, right
+            ))),
+        };
+        Ok(Some(result))
     }
 
 
     fn check_gate_expression(
         node: &ASTNode,
         env: &Rc<RefCell<TypeEnvironment>>
-    ) -> Result<Type, String> {
+    ) -> Result<Type, TypeError> {
         let loc = match node {
             ASTNode::Gate { loc, .. } |ASTNode::ParameterizedGate { loc, .. } | ASTNode::Controlled { loc, .. } | ASTNode::Dagger { loc, .. } => *loc,
-            _ => Loc { line: 0, column: 0 },
+            _ => Loc { line: 0, column: 0, length: 0 },
         };
 
         match node {
             ASTNode::Gate { name, loc } => {
                 let gate_name_lower = name.to_lowercase();
 
-                if let Some(info) = env.borrow().get(&gate_name_lower) {
+                let info = env.borrow().get(name).or_else(|| env.borrow().get(&gate_name_lower));
+                if let Some(info) = info {
                     if let Type::Function(..) = &info.var_type {
                         return Ok(info.var_type.clone());
                     }
                 }
 
 
-                Err(format!("Type Error at {}: Unknown quantum gate '{}'.", loc, name))
+                Err(TypeError::at(loc, format!("Unknown quantum gate '{}'.", name)).undefined(name))
             }
 
             ASTNode::Dagger { gate_expr, .. } => {
+                Self::check_unitary_target("dagger", gate_expr, &loc, env)?;
 
                 let inner_gate_type = Self::check_gate_expression(gate_expr, env)?;
 
                 if let Type::Function(..) = inner_gate_type {
                     Ok(inner_gate_type)
                 } else {
-                    Err(format!("Type Error at {}: 'dagger' can only be applied to a gate or circuit.", loc))
+                    Err(TypeError::at(&loc, "'dagger' can only be applied to a gate or circuit."))
                 }
             }
 
             ASTNode::Controlled { gate_expr, loc } => {
+                Self::check_unitary_target("controlled", gate_expr, loc, env)?;
 
                 let inner_gate_type = Self::check_gate_expression(gate_expr, env)?;
 
@@ -238,7 +935,7 @@ impl TypeChecker {
                     Type::Function(mut params, ret_type) => {
 
                         if params.is_empty() {
-                            return Err(format!("Type Error at {}: 'controlled' target gate must take at least one argument.", loc));
+                            return Err(TypeError::at(loc, "'controlled' target gate must take at least one argument."));
                         }
 
 
@@ -247,7 +944,7 @@ impl TypeChecker {
 
                         Ok(Type::Function(params, ret_type))
                     }
-                    _ => Err(format!("Type Error at {}: 'controlled' can only be applied to a callable gate.", loc))
+                    _ => Err(TypeError::at(loc, "'controlled' can only be applied to a callable gate."))
                 }
             }
 
@@ -256,17 +953,16 @@ impl TypeChecker {
 
 
             let gate_info = env.borrow().get(&gate_name_lower)
-                .ok_or_else(|| format!("Type Error at {}: Unknown parameterized gate '{}'.", loc, name))?;
+                .ok_or_else(|| TypeError::at(loc, format!("Unknown parameterized gate '{}'.", name)))?;
 
             match gate_info.var_type {
                 Type::Function(param_types, return_type) => {
 
 
                     if parameters.len() > param_types.len() {
-                        return Err(format!(
-                            "Type Error at {}: Gate '{}' takes at most {} parameters, but got {}",
-                            loc, name, param_types.len(), parameters.len()
-                        ));
+                        return Err(TypeError::at(loc, format!(
+                            "Gate '{}' takes at most {} parameters, but got {}", name, param_types.len(), parameters.len()
+                        )));
                     }
 
 
@@ -277,10 +973,9 @@ impl TypeChecker {
 
                         if param_type != *expected_type &&
                            !(*expected_type == Type::Float && param_type == Type::Int) {
-                            return Err(format!(
-                                "Type Error at {}: Parameter {} of gate '{}' has wrong type. Expected {:?}, got {:?}",
-                                loc, i + 1, name, expected_type, param_type
-                            ));
+                            return Err(TypeError::at(loc, format!(
+                                "Parameter {} of gate '{}' has wrong type. Expected {:?}, got {:?}", i + 1, name, expected_type, param_type
+                            )));
                         }
                     }
 
@@ -288,28 +983,36 @@ impl TypeChecker {
                     let remaining_params = param_types[parameters.len()..].to_vec();
                     Ok(Type::Function(remaining_params, return_type))
                 }
-                _ => Err(format!("Type Error at {}: '{}' is not a parameterized gate.", loc, name))
+                _ => Err(TypeError::at(loc, format!("'{}' is not a parameterized gate.", name)))
             }
         }
 
-            _ => Err(format!("Type Error: This expression is not a valid gate."))
+            ASTNode::MemberAccess { member, .. } => match Self::check(node, env, Option::None)? {
+                circuit @ Type::Function(..) => Ok(circuit),
+                other => Err(TypeError::from(format!("'{}' is not a circuit method (found {:?}).", member, other))),
+            },
+            _ => Err(TypeError::from("This expression is not a valid gate."))
         }
     }
 
 
 
-    pub fn check(node: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>, expected_return_type: Option<&Type>) -> Result<Type, String> {
+    pub fn check(node: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>, expected_return_type: Option<&Type>) -> Result<Type, TypeError> {
+        Self::check_node(node, env, expected_return_type).map_err(|error| error.located(node.loc()))
+    }
 
+    fn check_node(node: &ASTNode, env: &Rc<RefCell<TypeEnvironment>>, expected_return_type: Option<&Type>) -> Result<Type, TypeError> {
         match node {
             // --- Literals ---
             ASTNode::IntLiteral(_) => Ok(Type::Int),
             ASTNode::FloatLiteral(_) => Ok(Type::Float),
+            ASTNode::ImaginaryLiteral(_) => Ok(Type::Complex),
             ASTNode::StringLiteral(_) => Ok(Type::String),
             ASTNode::BoolLiteral(_) => Ok(Type::Bool),
             ASTNode::NoneLiteral => Ok(Type::None),
             ASTNode::DictLiteral(_) => Ok(Type::Dict),
-            ASTNode::QuantumKet(_) => Ok(Type::QuantumRegister(Some(1))),
-            ASTNode::QuantumBra(_) => Err("Bra notation is not yet supported.".to_string()),
+            ASTNode::QuantumKet(label) => Ok(Type::Ket(Some(label.chars().count()))),
+            ASTNode::QuantumBra(label) => Ok(Type::Bra(Some(label.chars().count()))),
 
             // --- Declarations ---
             ASTNode::LetDeclaration { name, type_annotation, value, is_mutable, ..} => {
@@ -320,13 +1023,13 @@ impl TypeChecker {
                         if value_type != *expected_type &&
                            value_type != Type::None &&
                            *expected_type != Type::Any {
-                            if let (Type::QuantumRegister(_), Type::QuantumRegister(None)) = (&value_type, expected_type) {
+                            if Self::is_assignable(&value_type, expected_type) {
                                 // OK
                             } else {
-                                return Err(format!(
-                                    "Type Error: Variable '{}' is annotated as {:?} but is being assigned a value of type {:?}",
+                                return Err(TypeError::from(format!(
+                                    "Variable '{}' is annotated as {:?} but is being assigned a value of type {:?}",
                                     name, expected_type, value_type
-                                ));
+                                )));
                             }
                         }
                         expected_type.clone()
@@ -347,11 +1050,11 @@ impl TypeChecker {
                     ASTNode::Identifier { name, .. } => {
                         let original_info = match env.borrow().get(name) {
                             Some(info) => info,
-                            Option::None => return Err(format!("Type Error: Cannot assign to undefined variable '{}'", name)),
+                            Option::None => return Err(TypeError::from(format!("Cannot assign to undefined variable '{}'", name))),
                         };
 
                         if !original_info.is_mutable {
-                            return Err(format!("Mutability Error: Cannot assign to immutable variable '{}'.", name));
+                            return Err(TypeError::from(format!("Cannot assign to immutable variable '{}'.", name)).with_code(diagnostics::MUTABILITY_ERROR));
                         }
 
                         if original_info.var_type != new_type &&
@@ -360,10 +1063,10 @@ impl TypeChecker {
                             if let (Type::QuantumRegister(_), Type::QuantumRegister(None)) = (&new_type, &original_info.var_type) {
                                 // OK
                             } else {
-                                return Err(format!(
-                                    "Type Error: Mismatched types in assignment. Cannot assign type {:?} to variable '{}' of type {:?}",
+                                return Err(TypeError::from(format!(
+                                    "Mismatched types in assignment. Cannot assign type {:?} to variable '{}' of type {:?}",
                                     new_type, name, original_info.var_type
-                                ));
+                                )));
                             }
                         }
                         Ok(Type::None)
@@ -380,10 +1083,9 @@ impl TypeChecker {
                                 if index_type != Type::String &&
                                 index_type != Type::Int &&
                                 index_type != Type::Bool {
-                                    return Err(format!(
-                                        "Type Error at {}: Dictionary keys must be String, Int, or Bool, got {:?}",
-                                        loc, index_type
-                                    ));
+                                    return Err(TypeError::at(loc, format!(
+                                        "Dictionary keys must be String, Int, or Bool, got {:?}", index_type
+                                    )));
                                 }
 
                                 Ok(Type::None)
@@ -391,40 +1093,55 @@ impl TypeChecker {
                             Type::Array(inner_type) => {
 
                                 if index_type != Type::Int {
-                                    return Err(format!(
-                                        "Type Error at {}: Array index must be Int, got {:?}",
-                                        loc, index_type
-                                    ));
+                                    return Err(TypeError::at(loc, format!(
+                                        "Array index must be Int, got {:?}", index_type
+                                    )));
                                 }
 
                                 if new_type != *inner_type && *inner_type != Type::Any {
-                                    return Err(format!(
-                                        "Type Error at {}: Cannot assign {:?} to array of {:?}",
-                                        loc, new_type, inner_type
-                                    ));
+                                    return Err(TypeError::at(loc, format!(
+                                        "Cannot assign {:?} to array of {:?}", new_type, inner_type
+                                    )));
                                 }
                                 Ok(Type::None)
                             }
-                            _ => Err(format!(
-                                "Type Error at {}: Cannot perform subscript assignment on type {:?}",
-                                loc, array_type
-                            ))
+                            _ => Err(TypeError::at(loc, format!(
+                                "Cannot perform subscript assignment on type {:?}", array_type
+                            )))
                         }
                     }
 
-                    _ => Err("Type Error: Assignment target must be an identifier or subscript expression.".to_string())
+                    ASTNode::MemberAccess { object, member } => {
+                        if let Some(name) = Self::immutable_root(object, env) {
+                            return Err(TypeError::from(format!("Cannot assign to field '{}' of immutable variable '{}'.", member, name)).with_code(diagnostics::MUTABILITY_ERROR));
+                        }
+                        let field_type = Self::check(target, env, Option::None)?;
+                        if field_type != new_type && field_type != Type::Any && !Self::is_assignable(&new_type, &field_type) {
+                            return Err(TypeError::from(format!(
+                                "Mismatched types in assignment. Cannot assign type {:?} to field '{}' of type {:?}",
+                                new_type, member, field_type
+                            )));
+                        }
+                        Ok(Type::None)
+                    }
+
+                    _ => Err(TypeError::from("Assignment target must be an identifier, subscript or field expression."))
                 }
             }
             ASTNode::Identifier { name, loc } => {
                 match env.borrow().get(name) {
                     Some(info) => Ok(info.var_type.clone()),
-                    Option::None => Err(format!("Type Error at {}: Undefined variable '{}'", loc, name)),
+                    Option::None => Err(TypeError::at(loc, format!("Undefined variable '{}'", name)).undefined(name)),
                 }
             }
 
 
-            ASTNode::FunctionCall { callee, arguments, loc, .. } => {
+            ASTNode::FunctionCall { callee, arguments, loc, is_dagger } => {
                 let callee_type = Self::check(callee, env, Option::None)?;
+                Self::check_mut_receiver(callee, loc, env)?;
+                if *is_dagger {
+                    Self::check_unitary_target("dagger", callee, loc, env)?;
+                }
 
                 let name = format!("{:?}", callee);
                 match callee_type {
@@ -432,33 +1149,49 @@ impl TypeChecker {
                         if arguments.len() > 0 && param_types.len() == 0 {
 
                         } else if arguments.len() != param_types.len() {
-                            return Err(format!(
-                                "Type Error at {}: Function '{}' expected {} arguments, but got {}",
-                                loc, name, param_types.len(), arguments.len()
-                            ));
+                            return Err(TypeError::at(loc, format!(
+                                "Function '{}' expected {} arguments, but got {}", name, param_types.len(), arguments.len()
+                            )));
                         }
+                        let mut complex_argument = false;
+                        let mut bound_types = HashMap::new();
+                        let mut bound_sizes = HashMap::new();
                         for (i, arg_node) in arguments.iter().enumerate() {
                             if i >= param_types.len() { break; }
                             let arg_type = Self::check(arg_node, env, Option::None)?;
+                            complex_argument |= Self::is_complex(&arg_type);
                             let expected_type = &param_types[i];
 
-                            if arg_type != *expected_type && *expected_type != Type::Any {
-                                if let (Type::QuantumRegister(_), Type::QuantumRegister(None)) = (&arg_type, expected_type) {
+                            if Self::mentions_generic(expected_type, Option::None) {
+                                if !Self::bind_generics(expected_type, &arg_type, &mut bound_types, &mut bound_sizes) {
+                                    return Err(TypeError::at(loc, format!(
+                                        "Argument {} of function call is wrong type. Expected {:?}, got {:?}", (i + 1), Self::substitute_generics(expected_type, &bound_types, &bound_sizes), arg_type
+                                    )));
+                                }
+                            } else if arg_type != *expected_type && *expected_type != Type::Any {
+                                if Self::is_assignable(&arg_type, expected_type) {
                                     // OK
                                 } else {
-                                    return Err(format!(
-                                        "Type Error at {}: Argument {} of function call is wrong type. Expected {:?}, got {:?}",
-                                        loc, (i + 1), expected_type, arg_type
-                                    ));
+                                    return Err(TypeError::at(loc, format!(
+                                        "Argument {} of function call is wrong type. Expected {:?}, got {:?}", (i + 1), expected_type, arg_type
+                                    )));
                                 }
                             }
                         }
-                        Ok(*return_type)
+                        if complex_argument && matches!(&**callee, ASTNode::Identifier { name, .. } if name == "exp") && *return_type == Type::Float {
+                            return Ok(Type::Complex);
+                        }
+                        if let Some(callee_name) = Self::callable_key(callee, env) {
+                            let generics = env.borrow().get_generics(&callee_name);
+                            if let Some(generics) = generics {
+                                Self::check_where_clause(&callee_name, &generics, &bound_sizes, loc)?;
+                            }
+                        }
+                        Ok(Self::substitute_generics(&return_type, &bound_types, &bound_sizes))
                     }
-                    _ => Err(format!(
-                        "Type Error at {}: Cannot call a value of type {:?} as a function.",
-                        loc, callee_type
-                    )),
+                    _ => Err(TypeError::at(loc, format!(
+                        "Cannot call a value of type {:?} as a function.", callee_type
+                    ))),
                 }
             }
 
@@ -469,6 +1202,18 @@ impl TypeChecker {
                 let left_type = Self::check(left, env, Option::None)?;
                 let right_type = Self::check(right, env, Option::None)?;
 
+                if let Some(result) = Self::check_ket_op(operator, &left_type, &right_type, loc)? {
+                    return Ok(result);
+                }
+
+                if let Some(result) = Self::complex_promotion(&left_type, &right_type) {
+                    return match operator {
+                        BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Power => Ok(result),
+                        BinaryOperator::Equal | BinaryOperator::NotEqual => Ok(Type::Bool),
+                        _ => Err(TypeError::at(loc, format!("Operator {:?} is not defined for complex numbers", operator))),
+                    };
+                }
+
                 if *operator == BinaryOperator::TensorProduct {
                     match (left_type.clone(), right_type.clone()) {
                         (Type::QuantumRegister(Some(s1)), Type::QuantumRegister(Some(s2))) => {
@@ -477,10 +1222,9 @@ impl TypeChecker {
                         (Type::QuantumRegister(_), Type::QuantumRegister(_)) => {
                             return Ok(Type::QuantumRegister(None));
                         }
-                        _ => return Err(format!(
-                            "Type Error at {}: Tensor product '***' is only defined for quantum registers, got {:?} and {:?}",
-                            loc, left_type, right_type
-                        )),
+                        _ => return Err(TypeError::at(loc, format!(
+                            "Tensor product '***' is only defined for quantum registers, got {:?} and {:?}", left_type, right_type
+                        ))),
                     }
                 }
 
@@ -492,7 +1236,7 @@ impl TypeChecker {
                             (Type::Int, Type::Float) => Ok(Type::Float),
                             (Type::Float, Type::Int) => Ok(Type::Float),
                             (Type::String, Type::String) => Ok(Type::String),
-                            _ => Err(format!("Type Error at {}: Cannot add types {:?} and {:?}", loc, left_type, right_type)),
+                            _ => Err(TypeError::at(loc, format!("Cannot add types {:?} and {:?}", left_type, right_type))),
                         }
                     }
                     BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Mod => {
@@ -501,7 +1245,7 @@ impl TypeChecker {
                             (Type::Float, Type::Float) => Ok(Type::Float),
                             (Type::Int, Type::Float) => Ok(Type::Float),
                             (Type::Float, Type::Int) => Ok(Type::Float),
-                            _ => Err(format!("Type Error at {}: Cannot perform arithmetic on types {:?} and {:?}", loc, left_type, right_type)),
+                            _ => Err(TypeError::at(loc, format!("Cannot perform arithmetic on types {:?} and {:?}", left_type, right_type))),
                         }
                     }
                     BinaryOperator::Power => {
@@ -510,23 +1254,25 @@ impl TypeChecker {
                             (Type::Float, Type::Float) => Ok(Type::Float),
                             (Type::Int, Type::Float) => Ok(Type::Float),
                             (Type::Float, Type::Int) => Ok(Type::Float),
-                            _ => Err(format!("Type Error at {}: Power operator (^) requires numeric types, got {:?} and {:?}", loc, left_type, right_type)),
+                            _ => Err(TypeError::at(loc, format!("Power operator (^) requires numeric types, got {:?} and {:?}", left_type, right_type))),
                         }
                     }
                     BinaryOperator::Equal | BinaryOperator::NotEqual => Ok(Type::Bool),
                     BinaryOperator::Less | BinaryOperator::Greater | BinaryOperator::LessEqual | BinaryOperator::GreaterEqual => {
                         match (&left_type, &right_type) {
                             (Type::Int, Type::Int) | (Type::Float, Type::Float) => Ok(Type::Bool),
-                            _ => Err(format!("Type Error at {}: Cannot perform ordered comparison on types {:?} and {:?}", loc, left_type, right_type)),
+                            // Dict lookups are untyped until runtime.
+                            (Type::Any, Type::Int | Type::Float | Type::Any) | (Type::Int | Type::Float, Type::Any) => Ok(Type::Bool),
+                            _ => Err(TypeError::at(loc, format!("Cannot perform ordered comparison on types {:?} and {:?}", left_type, right_type))),
                         }
                     }
                     BinaryOperator::And | BinaryOperator::Or => {
                         match (&left_type, &right_type) {
                             (Type::Bool, Type::Bool) => Ok(Type::Bool),
-                            _ => Err(format!("Type Error at {}: Logical operators 'and'/'or' require two booleans, got {:?} and {:?}", loc, left_type, right_type)),
+                            _ => Err(TypeError::at(loc, format!("Logical operators 'and'/'or' require two booleans, got {:?} and {:?}", left_type, right_type))),
                         }
                     }
-                    _ => Err(format!("Type checking not implemented for operator {:?}", operator))
+                    _ => Err(format!("Type checking not implemented for operator {:?}", operator).into())
                 }
             }
 
@@ -535,13 +1281,15 @@ impl TypeChecker {
                 match operator {
                     UnaryOperator::Not => {
                         if operand_type != Type::Bool {
-                            return Err(format!("Type Error: Unary operator '!' cannot be applied to type {:?}", operand_type));
+                            return Err(TypeError::from(format!("Unary operator '!' cannot be applied to type {:?}", operand_type)));
                         }
                         Ok(Type::Bool)
                     }
+                    UnaryOperator::Minus if matches!(operand_type, Type::Ket(_) | Type::Bra(_)) => Ok(operand_type),
+                    UnaryOperator::Minus | UnaryOperator::Plus if Self::is_complex(&operand_type) => Ok(operand_type),
                     UnaryOperator::Minus | UnaryOperator::Plus => {
                         if operand_type != Type::Int && operand_type != Type::Float {
-                             return Err(format!("Type Error: Unary operator '-' or '+' cannot be applied to type {:?}", operand_type));
+                             return Err(TypeError::from(format!("Unary operator '-' or '+' cannot be applied to type {:?}", operand_type)));
                         }
                         Ok(operand_type)
                     }
@@ -549,23 +1297,28 @@ impl TypeChecker {
             }
 
             ASTNode::QuantumDeclaration { name, size, initial_state } => {
-                let register_type: Type;
+                let mut register_type = Type::QuantumRegister(Some(1));
+                let mut declared_size = None;
                 if let Some(size_expr) = size {
                     let size_type = Self::check(size_expr, env, Option::None)?;
                     if size_type != Type::Int {
-                        return Err(format!("Type Error: Quantum register size must be an Int, but got {:?}", size_type));
-                    }
-                    let size_val = if let ASTNode::IntLiteral(n) = &**size_expr { Some(*n as usize) } else { None };
-                    register_type = Type::QuantumRegister(size_val);
-                } else if let Some(state_expr) = initial_state {
-                    let init_type = Self::check(state_expr, env, Option::None)?;
-                    if let Type::QuantumRegister(size_opt) = init_type {
-                        register_type = Type::QuantumRegister(size_opt);
-                    } else {
-                        return Err(format!("Type Error: Initial state for a quantum register must be another quantum register, got {:?}", init_type));
+                        return Err(TypeError::from(format!("Quantum register size must be an Int, but got {:?}", size_type)));
                     }
-                } else {
-                    register_type = Type::QuantumRegister(Some(1));
+                    declared_size = if let ASTNode::IntLiteral(n) = &**size_expr { Some(*n as usize) } else { None };
+                    register_type = Type::QuantumRegister(declared_size);
+                }
+                if let Some(state_expr) = initial_state {
+                    let init_size = match Self::check(state_expr, env, Option::None)? {
+                        Type::Ket(size_opt) => size_opt,
+                        Type::QuantumRegister(size_opt) if size.is_none() => size_opt,
+                        init_type => return Err(TypeError::from(format!("Initial state for a quantum register must be a ket or another quantum register, got {:?}", init_type))),
+                    };
+                    if let (Some(declared), Some(init)) = (declared_size, init_size) {
+                        if declared != init {
+                            return Err(TypeError::from(format!("Quantum register '{}' has {} qubit(s), but its initial state has {}", name, declared, init)));
+                        }
+                    }
+                    register_type = Type::QuantumRegister(declared_size.or(init_size));
                 }
                 env.borrow_mut().set(name.clone(), Self::immutable_info(register_type));
                 Ok(Type::None)
@@ -574,14 +1327,15 @@ impl TypeChecker {
             ASTNode::ArrayAccess { array, index, loc } => {
                 let array_type = Self::check(array, env, Option::None)?;
                 let index_type = Self::check(index, env, Option::None)?;
-                if index_type != Type::Int {
-                    return Err(format!("Type Error at {}: Array index must be an Int, but got {:?}", loc, index_type));
+                let string_key = array_type == Type::Dict && index_type == Type::String;
+                if index_type != Type::Int && !string_key {
+                    return Err(TypeError::at(loc, format!("Array index must be an Int, but got {:?}", index_type)));
                 }
                 match array_type {
                     Type::QuantumRegister(size_opt) => {
                         if let (Some(size), ASTNode::IntLiteral(idx)) = (size_opt, &**index) {
                             if *idx < 0 || *idx as usize >= size {
-                                return Err(format!("Type Error at {}: Qubit index {} is out of bounds for register of size {}.", loc, idx, size));
+                                return Err(TypeError::at(loc, format!("Qubit index {} is out of bounds for register of size {}.", idx, size)));
                             }
                         }
                         Ok(Type::Qubit)
@@ -589,17 +1343,67 @@ impl TypeChecker {
                     Type::Array(inner_type) => Ok(*inner_type),
                     Type::String => Ok(Type::String),
                     Type::Dict => Ok(Type::Any),
-                    _ => Err(format!("Type Error at {}: Cannot perform array access '[]' on type {:?}", loc, array_type)),
+                    _ => Err(TypeError::at(loc, format!("Cannot perform array access '[]' on type {:?}", array_type))),
                 }
             }
 
             ASTNode::MemberAccess { object, member } => {
+                // `Point.origin`: a method looked up on the type itself, receiver included.
+                if let ASTNode::Identifier { name, .. } = object.as_ref() {
+                    let is_value = matches!(env.borrow().get(name), Some(TypeInfo { var_type: Type::Custom(_), .. }));
+                    if !is_value && env.borrow().get_user_type(name).is_some() {
+                        if let Some(info) = env.borrow().get(&format!("{}.{}", name, member)) {
+                            return Ok(info.var_type);
+                        }
+                    }
+                }
+
+                // `Shape.Circle`: a variant, or the constructor of one with a payload.
+                if let ASTNode::Identifier { name, loc } = object.as_ref() {
+                    if env.borrow().get(name).is_none() {
+                        if let Some(UserType::Enum(variants)) = env.borrow().get_user_type(name) {
+                            let enum_type = Type::Custom(name.clone());
+                            return match variants.iter().find(|variant| variant.name == *member) {
+                                Some(variant) if variant.fields.is_empty() => Ok(enum_type),
+                                Some(variant) => Ok(Type::Function(variant.fields.clone(), Box::new(enum_type))),
+                                None => Err(TypeError::at(loc, format!("Enum '{}' has no variant named '{}'", name, member))),
+                            };
+                        }
+                    }
+                }
+
                 let object_type = Self::check(object, env, Option::None)?;
+                if let Type::Custom(name) = &object_type {
+                    let user_type = env.borrow().get_user_type(name);
+                    if let Some(UserType::Struct(fields)) = &user_type {
+                        if let Some(field) = fields.iter().find(|field| field.name == *member) {
+                            return Ok(field.param_type.clone());
+                        }
+                    }
+                    if user_type.is_some() {
+                        // A bound method: the receiver is already supplied.
+                        return match env.borrow().get(&format!("{}.{}", name, member)).map(|info| info.var_type) {
+                            Some(Type::Function(params, return_type)) if !params.is_empty() => {
+                                Ok(Type::Function(params[1..].to_vec(), return_type))
+                            }
+                            Some(_) => Err(TypeError::from(format!("'{}.{}' has no 'self' parameter and must be called on the type", name, member))),
+                            Option::None => Err(TypeError::from(format!("Type '{}' has no field or method named '{}'", name, member))),
+                        };
+                    }
+                }
+
+                if let Type::Error = object_type {
+                    return match member.as_str() {
+                        "kind" | "message" => Ok(Type::String),
+                        "line" | "column" => Ok(Type::Int),
+                        _ => Err(TypeError::from(format!("Error has no member named '{}'", member))),
+                    };
+                }
 
                 if let Type::Module(module_types) = object_type {
                     match module_types.get(member) {
                         Some(t) => Ok(t.clone()),
-                        None => Err(format!("Type Error: Module has no member named '{}'", member))
+                        None => Err(TypeError::from(format!("Module has no member named '{}'", member)))
                     }
                 }
 
@@ -608,13 +1412,18 @@ impl TypeChecker {
                         Type::Array(_) | Type::String | Type::Dict => Ok(Type::Int),
                         Type::QuantumRegister(Some(_size)) => Ok(Type::Int),
                         Type::QuantumRegister(None) => Ok(Type::Int),
-                        _ => Err(format!("Type Error: Cannot get .length of type {:?}", object_type)),
+                        _ => Err(TypeError::from(format!("Cannot get .length of type {:?}", object_type))),
+                    }
+                } else if member == "as_int" {
+                    match object_type {
+                        Type::Array(_) => Ok(Type::Int),
+                        _ => Err(TypeError::from(format!("Cannot get .as_int of type {:?}", object_type))),
                     }
                 } else {
                     if let Type::Dict = object_type {
                         Ok(Type::Any)
                     } else {
-                        Err(format!("Type Error: Type {:?} has no member named '{}'", object_type, member))
+                        Err(TypeError::from(format!("Type {:?} has no member named '{}'", object_type, member)))
                     }
                 }
             }
@@ -632,27 +1441,25 @@ impl TypeChecker {
                     Type::Function(param_types, return_type) => {
 
                         if arguments.len() != param_types.len() {
-                            return Err(format!(
-                                "Type Error at {}: Gate requires {} arguments, but got {}",
-                                loc, param_types.len(), arguments.len()
-                            ));
+                            return Err(TypeError::at(loc, format!(
+                                "Gate requires {} arguments, but got {}", param_types.len(), arguments.len()
+                            )));
                         }
 
                         for (i, arg_node) in arguments.iter().enumerate() {
                             let arg_type = Self::check(arg_node, env, Option::None)?;
                             let expected_type = &param_types[i];
 
-                            if arg_type != *expected_type && *expected_type != Type::Any {
-                                return Err(format!(
-                                    "Type Error at {}: Argument {} is wrong type. Expected {:?}, got {:?}",
-                                    loc, (i + 1), expected_type, arg_type
-                                ));
+                            if arg_type != *expected_type && *expected_type != Type::Any && !Self::is_assignable(&arg_type, expected_type) {
+                                return Err(TypeError::at(loc, format!(
+                                    "Argument {} is wrong type. Expected {:?}, got {:?}", (i + 1), expected_type, arg_type
+                                )));
                             }
                         }
 
                         Ok(*return_type)
                     }
-                    _ => Err(format!("Type Error at {}: This expression is not a callable gate.", loc))
+                    _ => Err(TypeError::at(loc, "This expression is not a callable gate."))
                 }
             }
 
@@ -671,31 +1478,50 @@ impl TypeChecker {
                 let target_type = Self::check(target_expr, env, Option::None)?;
                 match target_type {
                     Type::Qubit | Type::Any => Ok(Type::Int),
-                    _ => Err(format!("Type Error: 'measure' can only be used on a single Qubit, got {:?}", target_type)),
+                    Type::QuantumRegister(_) => Ok(Type::Array(Box::new(Type::Int))),
+                    _ => Err(TypeError::from(format!("'measure' expects a Qubit or a quantum register, got {:?}", target_type))),
                 }
             }
 
             ASTNode::Import { path, alias } => {
-                let module_types = Self::check_module(path)?;
+                let (module_types, module_generics, module_unitarity) = Self::check_module(path)?;
                 let info = Self::immutable_info(Type::Module(module_types));
                 env.borrow_mut().set(alias.clone(), info);
+                for (name, generics) in module_generics {
+                    env.borrow_mut().set_generics(format!("{}.{}", alias, name), generics);
+                }
+                for (name, unitarity) in module_unitarity {
+                    env.borrow_mut().set_unitarity(format!("{}.{}", alias, name), unitarity);
+                }
                 Ok(Type::None)
             }
 
             ASTNode::FromImport { path, spec } => {
-                let module_types = Self::check_module(path)?;
+                let (module_types, module_generics, module_unitarity) = Self::check_module(path)?;
                 match spec {
                     ImportSpec::All => {
                         for (name, var_type) in module_types {
                             env.borrow_mut().set(name, Self::immutable_info(var_type));
                         }
+                        for (name, generics) in module_generics {
+                            env.borrow_mut().set_generics(name, generics);
+                        }
+                        for (name, unitarity) in module_unitarity {
+                            env.borrow_mut().set_unitarity(name, unitarity);
+                        }
                     }
                     ImportSpec::List(names) => {
                         for name in names {
                             if let Some(var_type) = module_types.get(name.as_str()) {
                                 env.borrow_mut().set(name.clone(), Self::immutable_info(var_type.clone()));
+                                if let Some(generics) = module_generics.get(name.as_str()) {
+                                    env.borrow_mut().set_generics(name.clone(), generics.clone());
+                                }
+                                if let Some(unitarity) = module_unitarity.get(name.as_str()) {
+                                    env.borrow_mut().set_unitarity(name.clone(), unitarity.clone());
+                                }
                             } else {
-                                return Err(format!("Type Error: Cannot import name '{}'. It does not exist in module.", name));
+                                return Err(TypeError::from(format!("Cannot import name '{}'. It does not exist in module.", name)));
                             }
                         }
                     }
@@ -704,29 +1530,96 @@ impl TypeChecker {
             }
 
 
-            ASTNode::FunctionDeclaration { name, parameters, return_type, body, .. } => {
-                let param_types: Vec<Type> = parameters.iter().map(|p| p.param_type.clone()).collect();
-                let rt = return_type.clone().unwrap_or(Type::Any);
-                let func_type = Type::Function(param_types, Box::new(rt.clone()));
-                env.borrow_mut().set(name.clone(), Self::immutable_info(func_type));
-                let func_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
-                for param in parameters {
-                    func_env.borrow_mut().set(param.name.clone(), Self::immutable_info(param.param_type.clone()));
+            ASTNode::ImplBlock { type_name, methods, loc } => {
+                if env.borrow().get_user_type(type_name).is_none() {
+                    return Err(TypeError::at(loc, format!("Cannot implement methods for unknown type '{}'", type_name)));
+                }
+                // Declare every signature first so that methods can call each other.
+                let mut declared = Vec::new();
+                for method in methods {
+                    if let ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } |
+                           ASTNode::CircuitDeclaration { name, generics, parameters, return_type, body } = method {
+                        let qualified = format!("{}.{}", type_name, name);
+                        if env.borrow().get(&qualified).is_some() {
+                            return Err(TypeError::at(loc, format!("Method '{}' is already defined for '{}'", name, type_name)));
+                        }
+                        let param_types = parameters.iter().map(|p| p.param_type.clone()).collect();
+                        let rt = return_type.clone().unwrap_or(Type::Any);
+                        // A method is recorded as mutable when it takes `mut self`.
+                        let takes_mut_self = parameters.first().is_some_and(|p| p.is_mutable);
+                        env.borrow_mut().set(qualified.clone(), TypeInfo { var_type: Type::Function(param_types, Box::new(rt)), is_mutable: takes_mut_self });
+                        declared.push(ASTNode::FunctionDeclaration {
+                            name: qualified,
+                            generics: generics.clone(),
+                            parameters: parameters.clone(),
+                            return_type: return_type.clone(),
+                            body: body.clone(),
+                        });
+                    }
+                }
+                for method in &declared {
+                    Self::check(method, env, Option::None)?;
                 }
-                Self::check(body, &func_env, Some(&rt))?;
                 Ok(Type::None)
             }
 
-            ASTNode::CircuitDeclaration { name, parameters, return_type, body, .. } => {
+            ASTNode::FunctionDeclaration { name, generics, parameters, return_type, body } |
+            ASTNode::CircuitDeclaration { name, generics, parameters, return_type, body } => {
+                Self::check_receiver(name, parameters, return_type)?;
                 let param_types: Vec<Type> = parameters.iter().map(|p| p.param_type.clone()).collect();
+                for generic in &generics.params {
+                    let (GenericParam::Type(param) | GenericParam::Size(param)) = generic;
+                    if !param_types.iter().any(|t| Self::mentions_generic(t, Some(param))) {
+                        return Err(TypeError::from(format!(
+                            "Generic parameter '{}' of '{}' does not appear in its parameters, so it cannot be inferred",
+                            param, name
+                        )));
+                    }
+                }
                 let rt = return_type.clone().unwrap_or(Type::Any);
                 let func_type = Type::Function(param_types, Box::new(rt.clone()));
-                env.borrow_mut().set(name.clone(), Self::immutable_info(func_type));
+                let takes_mut_self = parameters.first().is_some_and(|p| p.is_mutable);
+                env.borrow_mut().set(name.clone(), TypeInfo { var_type: func_type, is_mutable: takes_mut_self });
+                if !generics.is_empty() {
+                    env.borrow_mut().set_generics(name.clone(), generics.clone());
+                }
                 let func_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
+                for size in generics.size_params() {
+                    func_env.borrow_mut().set(size.to_string(), Self::immutable_info(Type::Int));
+                }
                 for param in parameters {
-                    func_env.borrow_mut().set(param.name.clone(), Self::immutable_info(param.param_type.clone()));
+                    // Inside the body a register sized by a parameter is an ordinary register.
+                    let var_type = match &param.param_type {
+                        Type::GenericRegister(_) => Type::QuantumRegister(Option::None),
+                        other => other.clone(),
+                    };
+                    func_env.borrow_mut().set(param.name.clone(), TypeInfo { var_type, is_mutable: param.is_mutable });
+                }
+                for constraint in &generics.where_clause {
+                    let constraint_type = Self::check(constraint, &func_env, Option::None)?;
+                    if constraint_type != Type::Bool {
+                        return Err(TypeError::from(format!("'where' clause of '{}' must be a Bool, but got {:?}", name, constraint_type)));
+                    }
                 }
                 Self::check(body, &func_env, Some(&rt))?;
+                if let ASTNode::Block(statements) = &**body {
+                    let unitarity = Self::classify_unitarity(statements, &func_env);
+                    env.borrow_mut().set_unitarity(name.clone(), unitarity);
+                }
+                Ok(Type::None)
+            }
+
+            ASTNode::GateDeclaration { name, matrix, loc } => {
+                let name_lower = name.to_lowercase();
+                let is_builtin_gate = name_lower == "reset" || Evaluator::get_gate_unitary(&name_lower, &[0.0; 3], false).is_ok();
+                if is_builtin_gate || env.borrow().get(name).is_some() || env.borrow().get(&name_lower).is_some() {
+                    return Err(TypeError::at(loc, format!("Cannot declare gate '{}': the name is already defined.", name)));
+                }
+                let unitary = Evaluator::eval_gate_matrix(matrix)
+                    .map_err(|e| TypeError::at(loc, format!("Gate '{}': {}.", name, e)))?;
+                let gate_type = Type::Function(vec![Type::Qubit; unitary.num_qubits], Box::new(Type::None));
+                env.borrow_mut().set(name.clone(), Self::immutable_info(gate_type));
+                env.borrow_mut().set_unitarity(name.clone(), Unitarity::Unitary);
                 Ok(Type::None)
             }
 
@@ -737,10 +1630,10 @@ impl TypeChecker {
                     Type::None
                 };
                match expected_return_type {
-                    Option::None => Err("Type Error: 'return' statement found outside of a function.".to_string()),
+                    Option::None => Err(TypeError::from("'return' statement found outside of a function.")),
                     Some(expected) => {
                         if value_type != *expected && *expected != Type::Any {
-                            Err(format!("Type Error: Function expected return type {:?}, but found return with type {:?}", expected, value_type))
+                            Err(TypeError::from(format!("Function expected return type {:?}, but found return with type {:?}", expected, value_type)))
                         } else {
                             Ok(Type::None)
                         }
@@ -751,7 +1644,7 @@ impl TypeChecker {
             ASTNode::While { condition, body } => {
                 let cond_type = Self::check(condition, env, Option::None)?;
                 if cond_type != Type::Bool {
-                    return Err(format!("Type Error: 'while' loop condition must be a Bool, but got {:?}", cond_type));
+                    return Err(TypeError::from(format!("'while' loop condition must be a Bool, but got {:?}", cond_type)));
                 }
                 Self::check(body, env, expected_return_type)?;
                 Ok(Type::None)
@@ -765,7 +1658,7 @@ impl TypeChecker {
                     for (i, element) in elements.iter().skip(1).enumerate() {
                         let element_type = Self::check(element, env, Option::None)?;
                         if element_type != first_type {
-                            return Err(format!("Type Error: Array literal has mismatched types. Element 0 has type {:?}, but element {} has type {:?}", first_type, (i+1), element_type));
+                            return Err(TypeError::from(format!("Array literal has mismatched types. Element 0 has type {:?}, but element {} has type {:?}", first_type, (i+1), element_type)));
                         }
                     }
                     Ok(Type::Array(Box::new(first_type)))
@@ -777,10 +1670,10 @@ impl TypeChecker {
                 let end_type = Self::check(end, env, Option::None)?;
 
                 if start_type != Type::Int {
-                    return Err(format!("Type Error: Range start must be an Int, got {:?}", start_type));
+                    return Err(TypeError::from(format!("Range start must be an Int, got {:?}", start_type)));
                 }
                 if end_type != Type::Int {
-                    return Err(format!("Type Error: Range end must be an Int, got {:?}", end_type));
+                    return Err(TypeError::from(format!("Range end must be an Int, got {:?}", end_type)));
                 }
 
 
@@ -795,7 +1688,7 @@ impl TypeChecker {
                     Type::Dict => Type::String,
                     Type::Custom(name) if name == "range" => Type::Int,
                     Type::Any => Type::Any,
-                    _ => return Err(format!("Type Error: 'for' loop cannot iterate over type {:?}", iterator_type)),
+                    _ => return Err(TypeError::from(format!("'for' loop cannot iterate over type {:?}", iterator_type))),
                 };
                 let loop_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
                 loop_env.borrow_mut().set(variable.clone(), Self::immutable_info(element_type));
@@ -806,13 +1699,13 @@ impl TypeChecker {
             ASTNode::If { condition, then_block, elif_blocks, else_block } => {
                 let cond_type = Self::check(condition, env, Option::None)?;
                 if cond_type != Type::Bool {
-                    return Err(format!("Type Error: 'if' condition must be a Bool, but got {:?}", cond_type));
+                    return Err(TypeError::from(format!("'if' condition must be a Bool, but got {:?}", cond_type)));
                 }
                 let then_type = Self::check(then_block, env, expected_return_type)?;
                 for (elif_cond, elif_body) in elif_blocks {
                     let elif_cond_type = Self::check(elif_cond, env, Option::None)?;
                     if elif_cond_type != Type::Bool {
-                        return Err(format!("Type Error: 'elif' condition must be a Bool, but got {:?}", elif_cond_type));
+                        return Err(TypeError::from(format!("'elif' condition must be a Bool, but got {:?}", elif_cond_type)));
                     }
                     Self::check(elif_body, env, expected_return_type)?;
                 }
@@ -822,23 +1715,589 @@ impl TypeChecker {
                 Ok(then_type)
             }
 
+            ASTNode::StructDeclaration { name, fields, loc } => {
+                Self::check_user_type_fields(name, fields.iter().map(|field| &field.param_type), env, loc)?;
+                let field_types = fields.iter().map(|field| field.param_type.clone()).collect();
+                let mut env_mut = env.borrow_mut();
+                env_mut.set_user_type(name.clone(), UserType::Struct(fields.clone()));
+                env_mut.set(name.clone(), Self::immutable_info(Type::Function(field_types, Box::new(Type::Custom(name.clone())))));
+                Ok(Type::None)
+            }
+
+            ASTNode::EnumDeclaration { name, variants, loc } => {
+                Self::check_user_type_fields(name, variants.iter().flat_map(|variant| &variant.fields), env, loc)?;
+                env.borrow_mut().set_user_type(name.clone(), UserType::Enum(variants.clone()));
+                Ok(Type::None)
+            }
+
+            ASTNode::Match { value, cases, loc } => {
+                let value_type = Self::check(value, env, Option::None)?;
+                for case in cases {
+                    let case_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
+                    Self::check_pattern(&case.pattern, &value_type, &case_env, loc)?;
+                    if let Some(guard) = &case.guard {
+                        let guard_type = Self::check(guard, &case_env, Option::None)?;
+                        if guard_type != Type::Bool {
+                            return Err(TypeError::at(loc, format!("'case' guard must be a Bool, but got {:?}", guard_type)));
+                        }
+                    }
+                    Self::check(&case.body, &case_env, expected_return_type)?;
+                }
+                if let Some(missing) = Self::missing_match_cases(&value_type, cases, env) {
+                    let warning = Diagnostic::warning(
+                        diagnostics::NON_EXHAUSTIVE_MATCH,
+                        format!("Non-exhaustive match over {:?}: {} not covered", value_type, missing),
+                    ).with_primary(Span::from(*loc), "");
+                    WARNINGS.with(|warnings| warnings.borrow_mut().push(warning));
+                }
+                Ok(Type::None)
+            }
+
             ASTNode::Block(statements) => {
                 for stmt in statements {
-                    Self::check(stmt, env, expected_return_type)?;
+                    if let Err(error) = Self::check(stmt, env, expected_return_type) {
+                        Self::skip_statement(stmt, env, error)?;
+                    }
                 }
                 Ok(Type::None)
             }
 
-            ASTNode::TryCatch { try_block, catch_block, .. } => {
+            ASTNode::TryCatch { try_block, handlers, finally_block, .. } => {
                 Self::check(try_block, env, expected_return_type)?;
-                Self::check(catch_block, env, expected_return_type)?;
+                for handler in handlers {
+                    let catch_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
+                    if let Some(name) = &handler.variable {
+                        catch_env.borrow_mut().set(name.clone(), TypeInfo { var_type: Type::Error, is_mutable: false });
+                    }
+                    Self::check(&handler.body, &catch_env, expected_return_type)?;
+                }
+                if let Some(finally_block) = finally_block {
+                    Self::check(finally_block, env, expected_return_type)?;
+                }
                 Ok(Type::None)
             }
 
+            ASTNode::Break | ASTNode::Continue => Ok(Type::None),
+
+            ASTNode::Lambda { parameters, return_type, body, loc } => {
+                let lambda_env = Rc::new(RefCell::new(TypeEnvironment::new_enclosed(env.clone())));
+                for param in parameters {
+                    lambda_env.borrow_mut().set(param.name.clone(), TypeInfo { var_type: param.param_type.clone(), is_mutable: false });
+                }
+                let param_types = parameters.iter().map(|p| p.param_type.clone()).collect();
+                let body_type = match (&**body, return_type) {
+                    (ASTNode::Block(_), _) => {
+                        let rt = return_type.clone().unwrap_or(Type::Any);
+                        Self::check(body, &lambda_env, Some(&rt))?;
+                        rt
+                    }
+                    (expression, Some(expected)) => {
+                        let found = Self::check(expression, &lambda_env, Option::None)?;
+                        if found != *expected && *expected != Type::Any && !Self::is_assignable(&found, expected) {
+                            return Err(TypeError::at(loc, format!("Lambda declares return type {:?}, but its body has type {:?}", expected, found)));
+                        }
+                        expected.clone()
+                    }
+                    (expression, Option::None) => Self::check(expression, &lambda_env, Option::None)?,
+                };
+                Ok(Type::Function(param_types, Box::new(body_type)))
+            }
+
+            ASTNode::Throw { value, loc } => {
+                match Self::check(value, env, Option::None)? {
+                    Type::Error | Type::String | Type::Custom(_) | Type::Any => Ok(Type::None),
+                    other => Err(TypeError::at(loc, format!("Cannot throw a value of type {:?}; throw an Error, a string, a struct or an enum", other))),
+                }
+            }
+
             _ => {
-                Err(format!("Type checking is not implemented for this node: {:?}", node))
+                Err(format!("Type checking is not implemented for this node: {:?}", node).into())
             }
         }
     }
 
 }
+
+/// A qubit as far as the ownership pass can tell: a whole register (or `Qubit`
+/// parameter), or one qubit of a register.
+#[derive(Debug, Clone, PartialEq)]
+enum QubitRef {
+    Register(String),
+    Qubit(String, QubitIndex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum QubitIndex {
+    Const(i64),
+    // An index variable, which names the same qubit within one statement only.
+    Var(String),
+    Unknown,
+}
+
+impl QubitRef {
+    fn register(&self) -> &str {
+        match self {
+            QubitRef::Register(name) | QubitRef::Qubit(name, _) => name,
+        }
+    }
+
+    // Both certainly contain a common qubit.
+    fn same_qubit(&self, other: &QubitRef) -> bool {
+        if self.register() != other.register() {
+            return false;
+        }
+        match (self, other) {
+            (QubitRef::Qubit(_, a), QubitRef::Qubit(_, b)) => a == b && *a != QubitIndex::Unknown,
+            _ => true,
+        }
+    }
+
+    // Both could contain a common qubit.
+    fn may_share(&self, other: &QubitRef) -> bool {
+        self.register() == other.register() && !matches!(
+            (self, other),
+            (QubitRef::Qubit(_, QubitIndex::Const(a)), QubitRef::Qubit(_, QubitIndex::Const(b))) if a != b
+        )
+    }
+
+    // Whether it names the same qubits in every statement, so that a measurement can be remembered.
+    fn is_stable(&self) -> bool {
+        !matches!(self, QubitRef::Qubit(_, QubitIndex::Var(_) | QubitIndex::Unknown))
+    }
+}
+
+impl std::fmt::Display for QubitRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QubitRef::Register(name) => write!(f, "'{}'", name),
+            QubitRef::Qubit(name, QubitIndex::Const(index)) => write!(f, "'{}[{}]'", name, index),
+            QubitRef::Qubit(name, QubitIndex::Var(index)) => write!(f, "'{}[{}]'", name, index),
+            QubitRef::Qubit(name, QubitIndex::Unknown) => write!(f, "a qubit of '{}'", name),
+        }
+    }
+}
+
+const NO_CLONING: &str = "an unknown quantum state cannot be copied, so a qubit can only be in one place at a time";
+
+/// Qubit ownership, checked once a program type-checks. Qubits cannot be copied, so
+/// a gate or a user-defined call never receives the same qubit twice and qubits are
+/// never put into arrays. Inside a circuit, a qubit measured on every path to a gate
+/// must be reset before the gate uses it, and the qubits the circuit borrows through
+/// its parameters may not be returned. Scripts are free to measure and reuse qubits.
+#[derive(Debug, Clone, Default)]
+struct QubitOwnership {
+    // Names bound to registers and qubits.
+    names: HashMap<String, QubitRef>,
+    // Qubits measured on every path to this point and not reset since.
+    measured: Vec<QubitRef>,
+    // User-defined functions and circuits, which may apply gates to their arguments.
+    // Methods are qualified by their type, and module functions by the alias.
+    callables: HashSet<String>,
+    // The circuit being checked and its quantum parameters.
+    circuit: Option<(String, Vec<String>)>,
+}
+
+impl QubitOwnership {
+    // Starts from everything the checker classified in `env`, which includes the
+    // names brought in by imports.
+    fn new(env: &Rc<RefCell<TypeEnvironment>>) -> Self {
+        QubitOwnership { callables: env.borrow().unitarity.keys().cloned().collect(), ..Default::default() }
+    }
+
+    fn check_sequence(&mut self, statements: &[ASTNode]) -> Result<(), TypeError> {
+        statements.iter().try_for_each(|stmt| self.visit(stmt))
+    }
+
+    fn visit(&mut self, node: &ASTNode) -> Result<(), TypeError> {
+        match node {
+            ASTNode::Program(statements) => self.check_sequence(statements),
+            ASTNode::Block(statements) => {
+                let names = self.names.clone();
+                self.check_sequence(statements)?;
+                self.names = names;
+                Ok(())
+            }
+            ASTNode::QuantumDeclaration { name, .. } => {
+                self.visit_children(node)?;
+                self.bind(name, Some(QubitRef::Register(name.clone())));
+                Ok(())
+            }
+            ASTNode::LetDeclaration { name, value, .. } => {
+                self.visit(value)?;
+                let alias = self.resolve(value);
+                self.bind(name, alias);
+                Ok(())
+            }
+            ASTNode::Assignment { target, value } => {
+                self.visit(value)?;
+                match &**target {
+                    ASTNode::Identifier { name, .. } => {
+                        let alias = self.resolve(value);
+                        self.bind(name, alias);
+                        Ok(())
+                    }
+                    other => self.visit(other),
+                }
+            }
+
+            ASTNode::Apply { gate_expr, arguments, loc } => {
+                self.visit_children(node)?;
+                let qubits = self.distinct_qubits(arguments, loc, "gate application")?;
+                if matches!(&**gate_expr, ASTNode::Gate { name, .. } if name == "reset") {
+                    self.measured.retain(|measured| !qubits.iter().any(|qubit| qubit.may_share(measured)));
+                } else if let Some(qubit) = qubits.iter().find(|qubit| self.measured.iter().any(|measured| measured.same_qubit(qubit))) {
+                    return Err(TypeError::at(loc, format!("{} is used after it was measured; apply Reset to it first", qubit))
+                        .with_code(diagnostics::QUBIT_OWNERSHIP));
+                }
+                Ok(())
+            }
+            ASTNode::FunctionCall { callee, arguments, loc, .. } => {
+                self.visit_children(node)?;
+                if self.is_callable(callee) {
+                    self.distinct_qubits(arguments, loc, "call")?;
+                }
+                Ok(())
+            }
+            ASTNode::Measure(target) => {
+                self.visit(target)?;
+                if let Some(qubit) = self.resolve(target).filter(|qubit| self.circuit.is_some() && qubit.is_stable()) {
+                    self.measured.push(qubit);
+                }
+                Ok(())
+            }
+            ASTNode::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.visit(element)?;
+                    if let Some(qubit) = self.resolve(element) {
+                        let at = match element {
+                            ASTNode::Identifier { loc, .. } | ASTNode::ArrayAccess { loc, .. } => Some(*loc),
+                            _ => Option::None,
+                        };
+                        return Err(TypeError::from(format!("{} cannot be copied into an array (no-cloning)", qubit)).located(at)
+                            .with_code(diagnostics::QUBIT_OWNERSHIP).with_note(NO_CLONING));
+                    }
+                }
+                Ok(())
+            }
+
+            // Only what is measured on every path stays measured.
+            ASTNode::If { condition, then_block, elif_blocks, else_block } => {
+                self.visit(condition)?;
+                let mut exits = vec![self.branch(then_block, self.measured.clone())?];
+                for (elif_condition, elif_block) in elif_blocks {
+                    self.visit(elif_condition)?;
+                    exits.push(self.branch(elif_block, self.measured.clone())?);
+                }
+                if let Some(else_block) = else_block {
+                    self.measured = self.branch(else_block, self.measured.clone())?;
+                }
+                self.keep_common(&exits);
+                Ok(())
+            }
+            ASTNode::While { condition, body } => {
+                self.visit(condition)?;
+                let exit = self.branch(body, self.measured.clone())?;
+                self.keep_common(&[exit]);
+                Ok(())
+            }
+            ASTNode::For { variable, iterator, body } => {
+                self.visit(iterator)?;
+                let element = match self.resolve(iterator) {
+                    Some(QubitRef::Register(register)) => Some(QubitRef::Qubit(register, QubitIndex::Unknown)),
+                    _ => Option::None,
+                };
+                let mut scope = self.clone();
+                scope.bind(variable, element);
+                scope.visit(body)?;
+                self.keep_common(&[scope.measured]);
+                Ok(())
+            }
+            ASTNode::Match { value, cases, .. } => {
+                self.visit(value)?;
+                let mut exits = Vec::new();
+                for case in cases {
+                    if let Some(guard) = &case.guard {
+                        self.visit(guard)?;
+                    }
+                    exits.push(self.branch(&case.body, self.measured.clone())?);
+                }
+                self.keep_common(&exits);
+                Ok(())
+            }
+            // A handler or `Finally` can start anywhere in the `Try`, so they assume nothing is measured.
+            ASTNode::TryCatch { try_block, handlers, finally_block, .. } => {
+                let mut exits = vec![self.branch(try_block, self.measured.clone())?];
+                for handler in handlers {
+                    exits.push(self.branch(&handler.body, Vec::new())?);
+                }
+                if let Some(finally_block) = finally_block {
+                    exits.push(self.branch(finally_block, Vec::new())?);
+                }
+                self.keep_common(&exits);
+                Ok(())
+            }
+
+            ASTNode::FunctionDeclaration { name, parameters, body, .. } => {
+                self.callables.insert(name.clone());
+                self.check_body(Option::None, parameters, body)
+            }
+            ASTNode::CircuitDeclaration { name, parameters, body, .. } => {
+                self.callables.insert(name.clone());
+                self.check_body(Some(name), parameters, body)
+            }
+            ASTNode::ImplBlock { type_name, methods, .. } => {
+                for method in methods {
+                    if let ASTNode::FunctionDeclaration { name, .. } | ASTNode::CircuitDeclaration { name, .. } = method {
+                        self.callables.insert(format!("{}.{}", type_name, name));
+                    }
+                }
+                for method in methods {
+                    if let ASTNode::FunctionDeclaration { parameters, body, .. } = method {
+                        self.check_body(Option::None, parameters, body)?;
+                    } else if let ASTNode::CircuitDeclaration { name, parameters, body, .. } = method {
+                        self.check_body(Some(name), parameters, body)?;
+                    }
+                }
+                Ok(())
+            }
+            ASTNode::Lambda { parameters, body, .. } => self.check_body(Option::None, parameters, body),
+            ASTNode::FromImport { spec: ImportSpec::List(names), .. } => {
+                self.callables.extend(names.iter().cloned());
+                Ok(())
+            }
+            ASTNode::Return(Some(value)) => {
+                self.visit(value)?;
+                if let (Some((circuit, borrowed)), Some(qubit)) = (&self.circuit, self.resolve(value)) {
+                    if borrowed.iter().any(|param| param == qubit.register()) {
+                        return Err(TypeError::from(format!("Circuit '{}' returns {}, which it only borrows from its caller", circuit, qubit))
+                            .with_code(diagnostics::QUBIT_OWNERSHIP));
+                    }
+                }
+                Ok(())
+            }
+            _ => self.visit_children(node),
+        }
+    }
+
+    // `alias.f` and `Type.method` are looked up as written; `value.method` could be
+    // the method of that name on any type.
+    fn is_callable(&self, callee: &ASTNode) -> bool {
+        match callee {
+            ASTNode::Identifier { name, .. } => self.callables.contains(name),
+            ASTNode::MemberAccess { object, member } => match &**object {
+                ASTNode::Identifier { name, .. } if self.callables.contains(&format!("{}.{}", name, member)) => true,
+                _ => self.callables.iter().any(|callable| callable.split_once('.').is_some_and(|(_, method)| method == member)),
+            },
+            _ => false,
+        }
+    }
+
+    fn visit_children(&mut self, node: &ASTNode) -> Result<(), TypeError> {
+        node.children().into_iter().try_for_each(|child| self.visit(child))
+    }
+
+    // Check `body` from the given measured qubits, returning those measured at its end.
+    fn branch(&self, body: &ASTNode, measured: Vec<QubitRef>) -> Result<Vec<QubitRef>, TypeError> {
+        let mut scope = self.clone();
+        scope.measured = measured;
+        scope.visit(body)?;
+        Ok(scope.measured)
+    }
+
+    fn keep_common(&mut self, exits: &[Vec<QubitRef>]) {
+        self.measured.retain(|qubit| exits.iter().all(|exit| exit.contains(qubit)));
+    }
+
+    // A function or circuit body sees the enclosing names, but nothing about what its caller measured.
+    fn check_body(&self, circuit: Option<&str>, parameters: &[Parameter], body: &ASTNode) -> Result<(), TypeError> {
+        let mut scope = QubitOwnership { names: self.names.clone(), callables: self.callables.clone(), ..Default::default() };
+        let mut borrowed = Vec::new();
+        for param in parameters {
+            let quantum = matches!(param.param_type, Type::Qubit | Type::QuantumRegister(_) | Type::GenericRegister(_));
+            scope.bind(&param.name, quantum.then(|| QubitRef::Register(param.name.clone())));
+            if quantum {
+                borrowed.push(param.name.clone());
+            }
+        }
+        scope.circuit = circuit.map(|name| (name.to_string(), borrowed));
+        scope.visit(body)
+    }
+
+    fn bind(&mut self, name: &str, qubit: Option<QubitRef>) {
+        self.measured.retain(|measured| measured.register() != name);
+        match qubit {
+            Some(qubit) => self.names.insert(name.to_string(), qubit),
+            None => self.names.remove(name),
+        };
+    }
+
+    fn resolve(&self, node: &ASTNode) -> Option<QubitRef> {
+        match node {
+            ASTNode::Identifier { name, .. } => self.names.get(name).cloned(),
+            ASTNode::ArrayAccess { array, index, .. } => match self.resolve(array)? {
+                QubitRef::Register(register) => {
+                    let index = match &**index {
+                        ASTNode::IntLiteral(i) => QubitIndex::Const(*i),
+                        ASTNode::Identifier { name, .. } => QubitIndex::Var(name.clone()),
+                        _ => QubitIndex::Unknown,
+                    };
+                    Some(QubitRef::Qubit(register, index))
+                }
+                QubitRef::Qubit(..) => Option::None,
+            },
+            _ => Option::None,
+        }
+    }
+
+    // The qubits among `arguments`, none of which may overlap another.
+    fn distinct_qubits(&self, arguments: &[ASTNode], loc: &Loc, what: &str) -> Result<Vec<QubitRef>, TypeError> {
+        let mut qubits: Vec<QubitRef> = Vec::new();
+        for arg in arguments {
+            let Some(qubit) = self.resolve(arg) else { continue };
+            if let Some(other) = qubits.iter().find(|other| other.same_qubit(&qubit)) {
+                let problem = if *other == qubit {
+                    format!("{} is passed twice", qubit)
+                } else {
+                    format!("{} and {} share a qubit", other, qubit)
+                };
+                return Err(TypeError::at(loc, format!("{} in one {}; a qubit cannot be copied (no-cloning)", problem, what))
+                    .with_code(diagnostics::QUBIT_OWNERSHIP).with_note(NO_CLONING));
+            }
+            qubits.push(qubit);
+        }
+        Ok(qubits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_source(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize()?;
+        TypeChecker::check_program(&Parser::new(tokens).parse()?)
+    }
+
+    #[test]
+    fn test_qubit_ownership_violations() {
+        let rejected = [
+            ("quantum q[2]\napply CNOT(q[1], q[1])\n", "passed twice"),
+            ("quantum q[2]\nlet a = q[0]\napply CNOT(q[0], a)\n", "passed twice"),
+            ("quantum q[2]\nlet pair = [q[0], q[1]]\n", "cannot be copied into an array"),
+            ("circuit f(q: QuantumRegister):\n    let m = measure(q[0])\n    apply Hadamard(q[0])\n", "used after it was measured"),
+            ("circuit f(a: Qubit) -> Qubit:\n    return a\n", "only borrows"),
+        ];
+        for (source, expected) in rejected {
+            let error = check_source(source).expect_err(source);
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn test_qubit_ownership_covers_imported_and_method_calls() {
+        let sources = [
+            "import \"stdlib/qgates.qc\" as gates\nquantum q[2]\ngates.Bell(q[0], q[0])\n",
+            "from \"stdlib/qgates.qc\" import *\nquantum q[2]\nBell(q[0], q[0])\n",
+            "struct P:\n    x: Int\nimpl P:\n    circuit pair(self, a: Qubit, b: Qubit):\n        apply CNOT(a, b)\n\
+             quantum q[2]\nlet p = P(1)\np.pair(q[1], q[1])\n",
+        ];
+        for source in sources {
+            let error = check_source(source).expect_err(source);
+            assert!(error.contains("is passed twice in one call"), "{}: {}", source, error);
+        }
+        check_source("import \"stdlib/qgates.qc\" as gates\nquantum q[2]\ngates.Bell(q[0], q[1])\n").unwrap();
+    }
+
+    #[test]
+    fn test_qubit_ownership_allows_reset_and_distinct_qubits() {
+        let source = "circuit f(q: QuantumRegister):\n    let m = measure(q[0])\n    apply Reset(q[0])\n    apply CNOT(q[0], q[1])\n\
+                      quantum r[2]\nf(r)\nlet m = measure(r[0])\napply Hadamard(r[0])\n";
+        check_source(source).unwrap();
+    }
+
+    #[test]
+    fn test_dagger_and_controlled_need_unitary_circuits() {
+        let rejected = [
+            ("circuit f(q: QuantumRegister):\n    apply Hadamard(q[0])\n    let m = measure(q[1])\nquantum r[2]\napply dagger(f)(r)\n", "[Line 3, Col 22]: 'dagger' at [Line 5, Col 1] needs 'f' to be unitary, but it measures a qubit here"),
+            ("circuit f(q: QuantumRegister):\n    print(\"hi\")\nquantum r[2]\nquantum c[1]\napply controlled(f)(c[0], r)\n", "needs 'f' to be unitary, but it calls 'print' here"),
+            ("circuit f(q: QuantumRegister, flip: Bool):\n    if flip:\n        apply X(q[0])\nquantum r[1]\napply dagger(f)(r, True)\n", "branches on a classical value"),
+            ("circuit f(q: QuantumRegister):\n    apply Reset(q[0])\ncircuit g(q: QuantumRegister):\n    f(q)\nquantum r[1]\napply dagger(g)(r)\n", "calls 'f', which is not unitary"),
+            ("import \"stdlib/math.qc\" as m\nquantum r[1]\napply dagger(m.abs)(r)\n", "needs 'm.abs' to be unitary, but it branches on a classical value"),
+            ("circuit undo(f: func(QuantumRegister) -> None, q: QuantumRegister):\n    apply dagger(f)(q)\n", "needs 'f' to be unitary, but it is not a gate or circuit whose unitarity is known"),
+        ];
+        for (source, expected) in rejected {
+            let error = check_source(source).expect_err(source);
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+        let loops = "circuit f(q: QuantumRegister):\n    for i in 0..2:\n        apply Hadamard(q[i])\n\
+                     circuit g(q: QuantumRegister):\n    f(q)\n    apply dagger(f)(q)\n\
+                     quantum r[2]\nquantum c[1]\napply dagger(g)(r)\napply controlled(g)(c[0], r)\n";
+        check_source(loops).unwrap();
+        check_source("import \"stdlib/qgates.qc\" as gates\nquantum r[3]\napply dagger(gates.GHZ)(r)\n").unwrap();
+    }
+
+    #[test]
+    fn test_gates_of_unknown_unitarity_make_a_body_irreversible() {
+        let source = "circuit g(f: func(Qubit) -> None, c: Qubit, t: Qubit):\n    apply dagger(controlled(f))(c, t)\n\
+                      circuit k(q: Qubit):\n    apply Hadamard(q)\n\
+                      quantum r[2]\napply dagger(g)(k, r[0], r[1])\n";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let errors = TypeChecker::check_program_with_diagnostics(&Parser::new(tokens).parse().unwrap()).unwrap_err();
+        assert!(errors.iter().any(|error| error.message.contains("needs 'g' to be unitary, but it calls 'f' here")), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_reset_is_rejected_under_nested_dagger_and_controlled() {
+        let rejected = [
+            ("apply dagger(reset)(q[0])", "cannot be daggered"),
+            ("apply dagger(controlled(Reset))(q[0], q[1])", "cannot be daggered"),
+            ("apply controlled(dagger(Reset))(q[0], q[1])", "cannot be controlled"),
+            ("apply controlled(controlled(reset))(q[0], q[1], q[2])", "cannot be controlled"),
+        ];
+        for (apply, expected) in rejected {
+            let source = format!("quantum q[3]\n{}\n", apply);
+            let error = check_source(&source).expect_err(&source);
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn test_mut_self_methods_need_a_mutable_receiver() {
+        let declarations = "struct P:\n    x: Float\n\
+                            impl P:\n    func get(self) -> Float:\n        return self.x\n    func bump(mut self):\n        self.x = self.x + 1.0\n";
+        let rejected = [
+            ("let p = P(1.0)\np.bump()\n", "'P.bump' takes 'mut self', but 'p' is immutable"),
+            ("mut p = P(1.0)\nP.bump(p)\n", "must be called on a mutable value"),
+            ("impl P:\n    func clear(self):\n        self.x = 0.0\n", "immutable variable 'self'"),
+        ];
+        for (program, expected) in rejected {
+            let source = format!("{}{}", declarations, program);
+            let error = check_source(&source).expect_err(&source);
+            assert!(error.contains(expected), "{}: {}", source, error);
+        }
+        check_source(&format!("{}let p = P(1.0)\nlet x = p.get()\nmut q = P(1.0)\nq.bump()\n", declarations)).unwrap();
+    }
+
+    #[test]
+    fn test_where_clause_is_checked_through_imports() {
+        for import in ["from \"stdlib/qgates.qc\" import GHZ\n", "import \"stdlib/qgates.qc\" as gates\n"] {
+            let call = if import.starts_with("from") { "GHZ" } else { "gates.GHZ" };
+            let source = format!("{}quantum q[1]\n{}(q)\n", import, call);
+            let error = check_source(&source).expect_err(&source);
+            assert!(error.contains("does not satisfy its 'where' clause with n = 1"), "{}: {}", source, error);
+            check_source(&format!("{}quantum q[3]\n{}(q)\n", import, call)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_non_exhaustive_match_is_a_warning() {
+        let tokens = Lexer::new("match 2 > 1:\n    case True -> print(\"yes\")\n").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let warnings = TypeChecker::check_program_with_diagnostics(&program).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, diagnostics::Severity::Warning);
+        assert_eq!(warnings[0].code, diagnostics::NON_EXHAUSTIVE_MATCH);
+        assert_eq!(warnings[0].message, "Non-exhaustive match over Bool: false not covered");
+        assert_eq!(warnings[0].primary.as_ref().unwrap().span.start.line, 1);
+    }
+}